@@ -1,31 +1,135 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::floors::FloConfig;
+use crate::compiler;
+use crate::host;
+use crate::symbols;
+use crate::types::Type;
 
 const REPO_URL: &str = "https://github.com/2004Scape/Server.git";
 const TEMP_DIR: &str = "2004scape";
-const SCRIPTS_PATH: &str = "2004scape/data/src/scripts";
-const CONFIGS_PATH: &str = "2004scape/data/src";
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ScriptAnalysis {
-    pub triggers: HashSet<String>,
-    pub commands: HashSet<String>,
-    pub types: HashSet<String>,
-    pub configs: HashSet<String>,
-    pub constants: HashSet<String>,
+    pub triggers: BTreeSet<String>,
+    pub commands: BTreeSet<String>,
+    pub types: BTreeSet<String>,
+    pub configs: BTreeSet<String>,
+    pub constants: BTreeSet<String>,
+    /// Every `.flo` entry, with its parsed fields. Missing from reports
+    /// written before floors were exported.
+    #[serde(default)]
+    pub floors: Vec<FloConfig>,
+    /// Delete the clone when the analysis is dropped. Off by default, so a
+    /// later analysis only has to pull instead of cloning again.
+    #[serde(skip)]
+    pub cleanup: bool,
+    /// Where the repo is cloned to; `TEMP_DIR` unless changed.
+    #[serde(skip, default = "default_clone_dir")]
+    pub clone_dir: PathBuf,
+}
+
+fn default_clone_dir() -> PathBuf {
+    PathBuf::from(TEMP_DIR)
+}
+
+/// Names present in one analysis but not the other.
+#[derive(Debug, Default, PartialEq)]
+pub struct SetDiff {
+    pub added: BTreeSet<String>,
+    pub removed: BTreeSet<String>,
+}
+
+impl SetDiff {
+    fn between(baseline: &BTreeSet<String>, current: &BTreeSet<String>) -> Self {
+        Self {
+            added: current.difference(baseline).cloned().collect(),
+            removed: baseline.difference(current).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// How the triggers, commands and types changed since a baseline analysis.
+#[derive(Debug, Default, PartialEq)]
+pub struct AnalysisDiff {
+    pub triggers: SetDiff,
+    pub commands: SetDiff,
+    pub types: SetDiff,
+}
+
+impl AnalysisDiff {
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty() && self.commands.is_empty() && self.types.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("\n=== Changes Since Baseline ===\n");
+        if self.is_empty() {
+            println!("No triggers, commands or types were added or removed");
+            return;
+        }
+        for (label, diff) in [("Triggers", &self.triggers), ("Commands", &self.commands), ("Types", &self.types)] {
+            if diff.is_empty() {
+                continue;
+            }
+            println!("{} (+{} -{})", label, diff.added.len(), diff.removed.len());
+            for name in &diff.added {
+                println!("  + {}", name);
+            }
+            for name in &diff.removed {
+                println!("  - {}", name);
+            }
+        }
+    }
+}
+
+impl Default for ScriptAnalysis {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ScriptAnalysis {
     pub fn new() -> Self {
         Self {
-            triggers: HashSet::new(),
-            commands: HashSet::new(),
-            types: HashSet::new(),
-            configs: HashSet::new(),
-            constants: HashSet::new(),
+            triggers: BTreeSet::new(),
+            commands: BTreeSet::new(),
+            types: BTreeSet::new(),
+            configs: BTreeSet::new(),
+            constants: BTreeSet::new(),
+            floors: Vec::new(),
+            cleanup: false,
+            clone_dir: default_clone_dir(),
+        }
+    }
+
+    /// Saves this analysis as JSON, to serve as a later run's baseline.
+    pub fn write_report(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Loads an analysis saved by `write_report`.
+    pub fn read_report(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// What this analysis added and removed relative to `baseline`.
+    pub fn diff(&self, baseline: &ScriptAnalysis) -> AnalysisDiff {
+        AnalysisDiff {
+            triggers: SetDiff::between(&baseline.triggers, &self.triggers),
+            commands: SetDiff::between(&baseline.commands, &self.commands),
+            types: SetDiff::between(&baseline.types, &self.types),
         }
     }
 
@@ -37,7 +141,7 @@ impl ScriptAnalysis {
     }
 
     fn setup_repository(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let temp_dir = Path::new(TEMP_DIR);
+        let temp_dir = self.clone_dir.as_path();
         let git_dir = temp_dir.join(".git");
 
         if git_dir.exists() {
@@ -45,21 +149,21 @@ impl ScriptAnalysis {
             
             // Check if we have any changes
             let status_output = Command::new("git")
-                .current_dir(TEMP_DIR)
+                .current_dir(temp_dir)
                 .args(&["status", "--porcelain"])
                 .output()?;
 
             if !status_output.stdout.is_empty() {
                 println!("Local changes detected, resetting...");
                 Command::new("git")
-                    .current_dir(TEMP_DIR)
+                    .current_dir(temp_dir)
                     .args(&["reset", "--hard", "HEAD"])
                     .output()?;
             }
 
             // Fetch and check if we're behind
             let fetch_output = Command::new("git")
-                .current_dir(TEMP_DIR)
+                .current_dir(temp_dir)
                 .args(&["fetch", "origin", "main"])
                 .output()?;
 
@@ -70,7 +174,7 @@ impl ScriptAnalysis {
 
             // Check if we need to update
             let rev_list = Command::new("git")
-                .current_dir(TEMP_DIR)
+                .current_dir(temp_dir)
                 .args(&["rev-list", "HEAD..origin/main", "--count"])
                 .output()?;
 
@@ -83,7 +187,7 @@ impl ScriptAnalysis {
                 println!("Updates available, pulling changes...");
                 // Pull latest changes
                 let pull_output = Command::new("git")
-                    .current_dir(TEMP_DIR)
+                    .current_dir(temp_dir)
                     .args(&["pull", "origin", "main"])
                     .output()?;
 
@@ -103,7 +207,8 @@ impl ScriptAnalysis {
             fs::create_dir_all(temp_dir)?;
 
             let clone_output = Command::new("git")
-                .args(&["clone", "--depth", "1", REPO_URL, TEMP_DIR])
+                .args(&["clone", "--depth", "1", REPO_URL])
+                .arg(temp_dir)
                 .output()?;
 
             if !clone_output.status.success() {
@@ -116,13 +221,14 @@ impl ScriptAnalysis {
         Ok(())
     }
 
+    /// Visits the files under `dir` in name order, so reports list floors
+    /// in the same order on every machine.
     fn walk_directory<F>(&mut self, dir: &Path, callback: &mut F) -> Result<(), Box<dyn std::error::Error>> 
     where F: FnMut(&mut Self, &Path) {
         if dir.is_dir() {
-            let entries = fs::read_dir(dir)?;
-            for entry in entries {
-                let entry = entry?;
-                let path = entry.path();
+            let mut paths = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>()?;
+            paths.sort();
+            for path in paths {
                 if path.is_dir() {
                     self.walk_directory(&path, callback)?;
                 } else {
@@ -154,7 +260,8 @@ impl ScriptAnalysis {
                 }
             }
         };
-        self.walk_directory(Path::new(SCRIPTS_PATH), &mut callback)?;
+        let scripts_dir = self.clone_dir.join("data/src/scripts");
+        self.walk_directory(&scripts_dir, &mut callback)?;
         Ok(())
     }
 
@@ -179,7 +286,7 @@ impl ScriptAnalysis {
         ];
         
         for config_type in config_types.iter() {
-            let config_path = Path::new(CONFIGS_PATH).join(config_type);
+            let config_path = self.clone_dir.join("data/src").join(config_type);
             if config_path.exists() {
                 println!("  Analyzing {} configs...", config_type);
                 let mut callback = |analyzer: &mut Self, path: &Path| {
@@ -200,7 +307,9 @@ impl ScriptAnalysis {
 
     fn analyze_script(&mut self, contents: &str) {
         let trigger_pattern = Regex::new(r"\[([\w\d_]+),").unwrap();
-        let command_pattern = Regex::new(r"(?m)^(?:[\t ]*)([\w\d_]+)\(").unwrap();
+        // Any call, wherever it sits: a statement at any indentation, the
+        // right of an `=`, an if or while condition, or an argument.
+        let command_pattern = Regex::new(r"\b([A-Za-z_]\w*)\s*\(").unwrap();
         let type_pattern = Regex::new(r"def_(\w+)").unwrap();
         let gosub_pattern = Regex::new(r"~([\w\d_]+)\(").unwrap();
 
@@ -210,15 +319,17 @@ impl ScriptAnalysis {
             }
         }
 
-        for cap in command_pattern.captures_iter(contents) {
+        let code = code_only(contents);
+        for cap in command_pattern.captures_iter(&code) {
             if let Some(command) = cap.get(1) {
-                if !command.as_str().starts_with("def_") {
+                let sigil = code[..command.start()].chars().next_back();
+                if !matches!(sigil, Some('~' | '$' | '%' | '@')) && is_command_name(command.as_str()) {
                     self.commands.insert(command.as_str().to_string());
                 }
             }
         }
 
-        for cap in gosub_pattern.captures_iter(contents) {
+        for cap in gosub_pattern.captures_iter(&code) {
             if let Some(command) = cap.get(1) {
                 self.commands.insert(format!("gosub_{}", command.as_str()));
             }
@@ -249,6 +360,9 @@ impl ScriptAnalysis {
 
     fn analyze_config(&mut self, contents: &str, config_type: &str) {
         self.configs.insert(config_type.to_string());
+        if config_type == "flo" {
+            self.analyze_floors(contents);
+        }
         
         // Update regex patterns for config analysis
         let patterns = [
@@ -268,6 +382,17 @@ impl ScriptAnalysis {
         }
     }
 
+    /// Keeps each floor's parsed fields; sections that don't parse are
+    /// reported and left out.
+    fn analyze_floors(&mut self, contents: &str) {
+        let parsed = symbols::sections(contents)
+            .and_then(|sections| sections.iter().map(|(name, fields)| FloConfig::parse(name, fields)).collect::<Result<Vec<_>, _>>());
+        match parsed {
+            Ok(floors) => self.floors.extend(floors),
+            Err(e) => println!("    Skipping floors: {}", e),
+        }
+    }
+
     pub fn print_analysis(&self) {
         println!("\n=== RuneScript Analysis Results ===\n");
         
@@ -295,14 +420,158 @@ impl ScriptAnalysis {
         for constant in &self.constants {
             println!("  - {}", constant);
         }
+
+        println!("\nFloors found ({})", self.floors.len());
+        for floor in &self.floors {
+            let colour = floor.colour.map(|colour| format!("{:#08x}", colour)).unwrap_or_else(|| "no colour".to_string());
+            match &floor.texture {
+                Some(texture) => println!("  - {} ({}, texture {})", floor.name, colour, texture),
+                None => println!("  - {} ({})", floor.name, colour),
+            }
+        }
+    }
+}
+
+/// Whether a name followed by `(` calls a command, rather than being a
+/// keyword, a `def_` or `switch_` statement or a cast such as `int(...)`.
+/// As in the parser, a type name that is also a command, such as `stat` or
+/// `coord`, is the command.
+fn is_command_name(name: &str) -> bool {
+    let is_cast = Type::from_name(name).is_some() && !compiler::is_builtin_function(name) && host::signature(name).is_none();
+    !matches!(name, "if" | "while" | "for" | "return") && !name.starts_with("def_") && !name.starts_with("switch_") && !is_cast
+}
+
+/// `contents` with comments and the text of string literals blanked out, so
+/// calls are only matched in code. Interpolations such as `<tostring($x)>`
+/// are code, so they're kept.
+fn code_only(contents: &str) -> String {
+    let mut code = String::with_capacity(contents.len());
+    let mut chars = contents.chars().peekable();
+    let (mut in_string, mut in_interpolation) = (false, false);
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                in_string = false;
+                in_interpolation = false;
+                code.push(c);
+            }
+            '"' if !in_interpolation => {
+                in_string = !in_string;
+                code.push(' ');
+            }
+            '<' if in_string && !in_interpolation => {
+                in_interpolation = true;
+                code.push(' ');
+            }
+            '>' if in_interpolation => {
+                in_interpolation = false;
+                code.push(' ');
+            }
+            '/' if !in_string && chars.peek() == Some(&'/') => {
+                while chars.next_if(|next| *next != '\n').is_some() {}
+            }
+            _ if in_string && !in_interpolation => code.push(' '),
+            _ => code.push(c),
+        }
     }
+    code
 }
 
 impl Drop for ScriptAnalysis {
     fn drop(&mut self) {
-        // Clean up temp directory when done
-        if Path::new(TEMP_DIR).exists() {
-            let _ = fs::remove_dir_all(TEMP_DIR);
+        if self.cleanup && self.clone_dir.exists() {
+            let _ = fs::remove_dir_all(&self.clone_dir);
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn analysis(triggers: &[&str], commands: &[&str], types: &[&str]) -> ScriptAnalysis {
+        let mut analysis = ScriptAnalysis::new();
+        analysis.triggers = set(triggers);
+        analysis.commands = set(commands);
+        analysis.types = set(types);
+        analysis
+    }
+
+    #[test]
+    fn diff_against_a_saved_baseline_lists_what_was_added_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("baseline.json");
+        analysis(&["proc", "opnpc1"], &["mes", "anim"], &["int", "npc"]).write_report(&path).unwrap();
+        let baseline = ScriptAnalysis::read_report(&path).unwrap();
+
+        let current = analysis(&["proc", "opnpc1", "opheld1"], &["mes"], &["int", "npc"]);
+        let diff = current.diff(&baseline);
+        assert_eq!(diff.triggers, SetDiff { added: set(&["opheld1"]), removed: set(&[]) });
+        assert_eq!(diff.commands, SetDiff { added: set(&[]), removed: set(&["anim"]) });
+        assert!(diff.types.is_empty());
+        assert!(!diff.is_empty());
+        assert!(current.diff(&current).is_empty());
+    }
+
+    #[test]
+    fn the_clone_is_kept_unless_cleanup_is_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let clone_dir = dir.path().join("2004scape");
+        fs::create_dir_all(clone_dir.join(".git")).unwrap();
+
+        let mut analysis = ScriptAnalysis::new();
+        analysis.clone_dir = clone_dir.clone();
+        drop(analysis);
+        assert!(clone_dir.join(".git").exists());
+
+        let mut analysis = ScriptAnalysis::new();
+        analysis.clone_dir = clone_dir.clone();
+        analysis.cleanup = true;
+        drop(analysis);
+        assert!(!clone_dir.exists());
+    }
+
+    #[test]
+    fn floors_are_exported_with_their_parsed_fields() {
+        let mut analysis = ScriptAnalysis::new();
+        analysis.analyze_config("[grass]\ncolour=0x35720A\ntexture=grass_tile\n\n[water]\nrgb=3955340\n", "flo");
+        assert!(analysis.configs.contains("flo"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        analysis.write_report(&path).unwrap();
+        let report = ScriptAnalysis::read_report(&path).unwrap();
+        assert_eq!(
+            report.floors,
+            [
+                FloConfig { name: "grass".to_string(), colour: Some(0x35720A), texture: Some("grass_tile".to_string()) },
+                FloConfig { name: "water".to_string(), colour: Some(3955340), texture: None },
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_are_found_at_any_indentation_and_position() {
+        let script = "[proc,nested](int $n)(int)\n\
+            \tdef_int $total = inv_total(inv, coins);\n\
+            if (stat_level(attack) > $n) {\n\
+            \t    while (calc($n + 1) < max($total, 3)) {\n\
+            \t\t\tmes(\"<tostring($n)> left, not_a_call(1)\"); // commented_out(2)\n\
+            \t\t    $n = ~helper(npc_find(coord(0, 50, 50, 0, 0), man, 1, 0));\n\
+              \t  }\n\
+            }\n\
+            def_int $x = int($n);\n\
+            switch_int ($x) {\n    case default : return(stat(attack));\n}\n";
+        let mut analysis = ScriptAnalysis::new();
+        analysis.analyze_script(script);
+        assert_eq!(
+            analysis.commands,
+            set(&["calc", "coord", "gosub_helper", "inv_total", "max", "mes", "npc_find", "stat", "stat_level", "tostring"])
+        );
+        assert_eq!(analysis.triggers, set(&["proc"]));
+        assert_eq!(analysis.types, set(&["int"]));
+    }
+}
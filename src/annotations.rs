@@ -0,0 +1,151 @@
+//! `// @key value` annotations among the comments right above a script's
+//! header, which set options for that script in its source:
+//!
+//! ```text
+//! // Adds two numbers.
+//! // @memoize
+//! // @max_instructions 50_000_000
+//! // @deprecated use add_all
+//! [proc,add](int $a, int $b)(int)
+//! ```
+//!
+//! `memoize` and `max_instructions` work like the same keys under
+//! `[scripts.<name>]` in rsc.toml, which win when both are set.
+
+use std::collections::BTreeMap;
+use crate::config::ScriptOverrides;
+
+/// A script's annotations: each key, without the `@`, with the rest of its
+/// line, empty for a bare `// @memoize`.
+pub type Annotations = BTreeMap<String, String>;
+
+/// The annotations scripts may carry, with what each does.
+pub const KNOWN: &[(&str, &str)] = &[
+    ("memoize", "Cache the script's results by arguments; `@memoize false` turns it off"),
+    ("max_instructions", "Instruction budget for runs that start at the script"),
+    ("deprecated", "Warn at every call of the script, with the rest of the line as advice"),
+];
+
+/// The key and value of a comment line, without its `//`, if it is an
+/// annotation: `@max_instructions 50_000_000` is `max_instructions` and
+/// `50_000_000`.
+pub fn parse(line: &str) -> Option<(String, String)> {
+    let rest = line.trim().strip_prefix('@')?;
+    let (key, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    (!key.is_empty()).then(|| (key.to_string(), value.trim().to_string()))
+}
+
+/// The options `annotations` set, and what is wrong with any that can't be
+/// used: unknown keys and values that don't parse.
+pub fn overrides(annotations: &Annotations) -> (ScriptOverrides, Vec<String>) {
+    let mut overrides = ScriptOverrides::default();
+    let mut problems = Vec::new();
+    for (key, value) in annotations {
+        match key.as_str() {
+            "memoize" => match value.as_str() {
+                "" | "true" => overrides.memoize = Some(true),
+                "false" => overrides.memoize = Some(false),
+                _ => problems.push(format!("@memoize takes true or false, not '{}'", value)),
+            },
+            "max_instructions" => match value.replace('_', "").parse() {
+                Ok(max_instructions) => overrides.max_instructions = Some(max_instructions),
+                Err(_) => problems.push(format!("@max_instructions takes a number, not '{}'", value)),
+            },
+            "deprecated" => {}
+            _ => {
+                let known: Vec<String> = KNOWN.iter().map(|(key, _)| format!("@{}", key)).collect();
+                problems.push(format!("unknown annotation '@{}' (known: {})", key, known.join(", ")));
+            }
+        }
+    }
+    (overrides, problems)
+}
+
+/// Why a `@deprecated` script is, possibly empty; `None` if it isn't.
+pub fn deprecation(annotations: &Annotations) -> Option<&str> {
+    annotations.get("deprecated").map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::config::VmOptions;
+    use crate::test_support;
+    use crate::vm::VM;
+
+    /// top calls middle with 0, 1 and 2; each calls leaf with its argument
+    /// and the next, so leaf sees 1 and 2 twice.
+    fn hierarchy(leaf_annotation: &str) -> String {
+        format!(
+            "[proc,top](int $n)(int)\ndef_int $i = 0;\ndef_int $total = 0;\n\
+            while ($i < $n) {{\n    $total = calc($total + ~middle($i));\n    $i = calc($i + 1);\n}}\nreturn($total);\n\
+            [proc,middle](int $x)(int)\nreturn(calc(~leaf($x) + ~leaf(calc($x + 1))));\n\
+            {}\n[proc,leaf](int $y)(int)\nreturn(calc($y * 2));\n",
+            leaf_annotation
+        )
+    }
+
+    fn leaf_calls(options: &VmOptions, source: &str) -> u64 {
+        let mut vm = VM::with_options(options);
+        for bytecode in test_support::compile(source) {
+            vm.register_script(bytecode);
+        }
+        vm.enable_call_profile();
+        assert_eq!(vm.run_script("top", &[3]), Ok(18));
+        let profile = vm.call_profile().unwrap();
+        profile.scripts().iter().find(|(script, _)| *script == "leaf").map(|(_, stats)| stats.calls).unwrap()
+    }
+
+    #[test]
+    fn memoize_turns_caching_on_or_off_for_one_script() {
+        let cached = VmOptions::default();
+        assert_eq!(leaf_calls(&cached, &hierarchy("")), 4);
+        assert_eq!(leaf_calls(&cached, &hierarchy("// @memoize false")), 6);
+
+        let uncached = VmOptions { memoize: false, ..VmOptions::default() };
+        assert_eq!(leaf_calls(&uncached, &hierarchy("")), 6);
+        assert_eq!(leaf_calls(&uncached, &hierarchy("// @memoize")), 4);
+    }
+
+    #[test]
+    fn max_instructions_bounds_runs_that_start_at_the_script() {
+        let spin = "[proc,spin](int $n)(int)\ndef_int $i = 0;\nwhile ($i < $n) {\n    $i = calc($i + 1);\n}\nreturn($i);\n";
+        let mut vm = test_support::vm(&format!("// @max_instructions 1_000\n{}", spin));
+        assert_eq!(vm.run_script("spin", &[10]), Ok(10));
+        let error = vm.run_script("spin", &[500]).unwrap_err();
+        assert!(error.contains("1000"), "{}", error);
+
+        assert_eq!(test_support::vm(spin).run_script("spin", &[500]), Ok(500));
+    }
+
+    #[test]
+    fn calls_to_a_deprecated_script_are_warned_about_where_they_are() {
+        let mut compiler = Compiler::new();
+        test_support::compile_with(
+            &mut compiler,
+            "// Doubles.\n// @deprecated use twice\n[proc,double](int $n)(int)\nreturn(calc($n * 2));\n\
+            [proc,main](int $n)(int)\ndef_int $a = ~double($n);\nreturn(calc($a + ~double(1)));\n",
+        );
+        assert_eq!(
+            compiler.warnings(),
+            [
+                "call to deprecated script 'double' at line 6: use twice (in script 'main')",
+                "call to deprecated script 'double' at line 7: use twice (in script 'main')",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_bad_values_are_warnings() {
+        let mut compiler = Compiler::new();
+        let bytecode = test_support::compile_with(
+            &mut compiler,
+            "// @colour blue\n// @max_instructions lots\n[proc,one]()(int)\nreturn(1);\n",
+        );
+        assert_eq!(bytecode[0].annotations.get("colour").map(String::as_str), Some("blue"));
+        let warnings = compiler.warnings();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+        assert!(warnings.iter().any(|warning| warning.contains("@max_instructions takes a number, not 'lots'")), "{:?}", warnings);
+        assert!(warnings.iter().any(|warning| warning.contains("unknown annotation '@colour'")), "{:?}", warnings);
+    }
+}
@@ -0,0 +1,217 @@
+//! The player's appearance: a gender and one idkit per body part, chosen
+//! from the `.idk` configs. `setidkit` puts an idkit on the part its config
+//! names and `setgender` changes the gender; the default host keeps the
+//! result in `Appearance`.
+
+use std::collections::BTreeMap;
+use crate::host::HostValue;
+use crate::types::Type;
+
+/// The body parts an idkit can cover, in the engine's order.
+pub const BODYPARTS: &[&str] = &["hair", "jaw", "torso", "arms", "hands", "legs", "feet"];
+
+/// The genders, by the value `setgender` takes.
+pub const GENDERS: &[&str] = &["male", "female"];
+
+/// `setidkit` takes an idkit, which replaces the one worn on its bodypart,
+/// and `setgender` an index into `GENDERS`; both return 0.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "setidkit" => Some((&[Type::IdKit], Type::Int)),
+        "setgender" => Some((&[Type::Int], Type::Int)),
+        _ => None,
+    }
+}
+
+/// One `[name]` section of a `.idk` config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdkConfig {
+    pub name: String,
+    /// Index into `BODYPARTS`.
+    pub bodypart: usize,
+    /// Index into `GENDERS`; an idkit without one suits either.
+    pub gender: Option<usize>,
+}
+
+impl IdkConfig {
+    /// Reads the fields of a `.idk` section:
+    ///
+    /// ```text
+    /// [man_hair_bald]
+    /// bodypart=hair
+    /// gender=male
+    /// ```
+    pub fn parse(name: &str, fields: &[(String, String)]) -> Result<Self, String> {
+        let field = |key: &str| fields.iter().find(|(field, _)| field == key).map(|(_, value)| value.as_str());
+        let bodypart = field("bodypart").ok_or_else(|| format!("idk '{}' has no bodypart", name))?;
+        let bodypart = BODYPARTS
+            .iter()
+            .position(|part| *part == bodypart)
+            .ok_or_else(|| format!("idk '{}': unknown bodypart '{}'; expected one of {}", name, bodypart, BODYPARTS.join(", ")))?;
+        let gender = field("gender")
+            .map(|gender| {
+                GENDERS
+                    .iter()
+                    .position(|known| *known == gender)
+                    .ok_or_else(|| format!("idk '{}': gender '{}' is not male or female", name, gender))
+            })
+            .transpose()?;
+        Ok(IdkConfig { name: name.to_string(), bodypart, gender })
+    }
+}
+
+/// One appearance change made by a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppearanceChange {
+    /// The idkit id and the body part it went on.
+    Idkit { idkit: i32, bodypart: usize },
+    Gender(usize),
+}
+
+/// One player's appearance, kept in memory for runs without a game server.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Appearance {
+    /// Indexed by idk id.
+    configs: Vec<IdkConfig>,
+    gender: usize,
+    /// The idkit on each body part that has one.
+    idkits: BTreeMap<usize, i32>,
+    changes: Vec<AppearanceChange>,
+}
+
+impl Appearance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the idkits, by id. The appearance is kept.
+    pub fn configure(&mut self, configs: Vec<IdkConfig>) {
+        self.configs = configs;
+    }
+
+    /// Index into `GENDERS`; male until `setgender` says otherwise.
+    pub fn gender(&self) -> usize {
+        self.gender
+    }
+
+    /// The idkit on `bodypart`, e.g. "hair", if any.
+    pub fn idkit(&self, bodypart: &str) -> Option<i32> {
+        let part = BODYPARTS.iter().position(|part| *part == bodypart)?;
+        self.idkits.get(&part).copied()
+    }
+
+    /// Every change made so far, in order.
+    pub fn changes(&self) -> &[AppearanceChange] {
+        &self.changes
+    }
+
+    /// Runs `setidkit`: puts `idkit` on its body part, in place of whatever
+    /// was there. An idkit for the other gender is refused.
+    pub fn set_idkit(&mut self, idkit: i32) -> Result<(), String> {
+        let config = usize::try_from(idkit)
+            .ok()
+            .and_then(|index| self.configs.get(index))
+            .ok_or_else(|| format!("{} is not an idkit", idkit))?;
+        if let Some(gender) = config.gender.filter(|gender| *gender != self.gender) {
+            return Err(format!(
+                "idkit '{}' is for {} players, but the player is {}",
+                config.name, GENDERS[gender], GENDERS[self.gender]
+            ));
+        }
+        let bodypart = config.bodypart;
+        println!("[setidkit {}: {}]", BODYPARTS[bodypart], config.name);
+        self.idkits.insert(bodypart, idkit);
+        self.changes.push(AppearanceChange::Idkit { idkit, bodypart });
+        Ok(())
+    }
+
+    /// Runs `setgender`: 0 for male, 1 for female. Idkits for the old
+    /// gender come off.
+    pub fn set_gender(&mut self, gender: i32) -> Result<(), String> {
+        let gender = usize::try_from(gender)
+            .ok()
+            .filter(|gender| *gender < GENDERS.len())
+            .ok_or_else(|| format!("gender {} is not 0 (male) or 1 (female)", gender))?;
+        println!("[setgender {}]", GENDERS[gender]);
+        self.gender = gender;
+        let configs = &self.configs;
+        self.idkits
+            .retain(|_, idkit| configs.get(*idkit as usize).and_then(|config| config.gender).is_none_or(|worn| worn == gender));
+        self.changes.push(AppearanceChange::Gender(gender));
+        Ok(())
+    }
+
+    /// Runs an appearance command for the default host.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        match (name, args) {
+            ("setidkit", [HostValue::Int(idkit)]) => self.set_idkit(*idkit),
+            ("setgender", [HostValue::Int(gender)]) => self.set_gender(*gender),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        }
+        .map(|()| 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,makeover]()(int)\n\
+        setidkit(man_hair_bald);\nsetidkit(boots);\nreturn(0);\n\
+        [proc,wrong_gender]()(int)\nsetidkit(woman_hair_long);\nreturn(0);\n";
+
+    fn symbols() -> SymbolTable {
+        test_support::symbols(&[(
+            "kits.idk",
+            "[man_hair_bald]\nbodypart=hair\ngender=male\n\n[woman_hair_long]\nbodypart=hair\ngender=female\n\n[boots]\nbodypart=feet\n",
+        )])
+    }
+
+    #[test]
+    fn two_idkits_applied_by_a_script_are_recorded_on_both_backends() {
+        let (mut vm, mut evaluator, vm_host, evaluator_host) = test_support::hosted_backends(&symbols(), SCRIPTS);
+        vm.run_script("makeover", &[]).unwrap();
+        evaluator.eval_script("makeover", &[]).unwrap();
+
+        for host in [vm_host, evaluator_host] {
+            let host = host.borrow();
+            let (man_hair_bald, boots) = (0, 2);
+            assert_eq!(
+                host.appearance().changes(),
+                [AppearanceChange::Idkit { idkit: man_hair_bald, bodypart: 0 }, AppearanceChange::Idkit { idkit: boots, bodypart: 6 }]
+            );
+            assert_eq!(host.appearance().idkit("hair"), Some(man_hair_bald));
+            assert_eq!(host.appearance().idkit("feet"), Some(boots));
+            assert_eq!(host.appearance().idkit("jaw"), None);
+        }
+    }
+
+    #[test]
+    fn an_idkit_for_the_other_gender_is_refused_until_the_gender_changes() {
+        let (mut vm, _, host, _) = test_support::hosted_backends(&symbols(), SCRIPTS);
+        let error = vm.run_script("wrong_gender", &[]).unwrap_err();
+        assert!(error.contains("idkit 'woman_hair_long' is for female players, but the player is male"), "{}", error);
+        assert!(host.borrow().appearance().changes().is_empty());
+
+        vm.run_script("makeover", &[]).unwrap();
+        let mut appearance = host.borrow().appearance().clone();
+        appearance.set_gender(1).unwrap();
+        assert_eq!(appearance.idkit("hair"), None);
+        assert_eq!(appearance.idkit("feet"), Some(2));
+        appearance.set_idkit(1).unwrap();
+        assert_eq!(appearance.idkit("hair"), Some(1));
+    }
+
+    #[test]
+    fn bad_idk_configs_and_unknown_idkit_names_are_errors() {
+        let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert!(IdkConfig::parse("cape", &[]).unwrap_err().contains("has no bodypart"));
+        assert!(IdkConfig::parse("cape", &[field("bodypart", "back")]).unwrap_err().contains("unknown bodypart 'back'"));
+        assert!(IdkConfig::parse("cape", &[field("bodypart", "torso"), field("gender", "other")]).is_err());
+
+        let error = test_support::compile_error(&symbols(), "[proc,bad]()(int)\nsetidkit(man_hair_bold);\nreturn(0);\n");
+        assert!(error.to_string().contains("did you mean 'man_hair_bald'?"), "{}", error);
+    }
+}
@@ -0,0 +1,373 @@
+//! `.rscasm`, the textual form of compiled scripts that `rsc compile --emit
+//! bytecode-text` writes and `rsc run --asm` reads; the README describes it.
+
+use std::collections::HashMap;
+use crate::bytecode::{self, ByteCode, Instruction};
+use crate::types::Type;
+
+/// The scripts as `.rscasm` text, which `parse` reads back.
+pub fn emit<'a>(scripts: impl IntoIterator<Item = &'a ByteCode>) -> String {
+    let mut out = String::from("; RuneScript bytecode; see runescript_compiler::asm\n");
+    out.push_str(&format!(".compiler {} {}\n", bytecode::COMPILER_VERSION, bytecode::CODEGEN_REVISION));
+    for script in scripts {
+        out.push('\n');
+        emit_script(script, &mut out);
+    }
+    out
+}
+
+fn emit_script(script: &ByteCode, out: &mut String) {
+    out.push_str(&format!(".script {}\n", script.script_name));
+    for param in &script.params {
+        out.push_str(&format!(".param {}\n", param));
+    }
+    out.push_str(&format!(".required_args {}\n", script.required_args));
+    if let Some(doc) = &script.doc {
+        out.push_str(&format!(".doc {:?}\n", doc));
+    }
+    for (key, value) in &script.annotations {
+        match value.as_str() {
+            "" => out.push_str(&format!(".annotation {}\n", key)),
+            value => out.push_str(&format!(".annotation {} {}\n", key, value)),
+        }
+    }
+    for constant in &script.constants {
+        out.push_str(&format!(".constant {}\n", constant));
+    }
+    for string in &script.strings {
+        out.push_str(&format!(".string {:?}\n", string));
+    }
+    for local in &script.locals {
+        out.push_str(&format!(".local {}\n", local));
+    }
+    for array in &script.arrays {
+        out.push_str(&format!(".array {}\n", array));
+    }
+
+    let mut targets: Vec<usize> = script.instructions.iter().flat_map(Instruction::jump_targets).collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let label = |target: &usize| format!("L{}", targets.binary_search(target).unwrap_or_default());
+    let mut line = None;
+    for ip in 0..=script.instructions.len() {
+        if targets.binary_search(&ip).is_ok() {
+            out.push_str(&format!("{}:\n", label(&ip)));
+        }
+        let Some(instruction) = script.instructions.get(ip) else { break };
+        if let Some(at) = script.line_at(ip).filter(|at| line != Some(*at)) {
+            out.push_str(&format!(".line {}\n", at));
+            line = Some(at);
+        }
+        let text = match instruction {
+            // Every entry, default ones too, so the table keeps its range.
+            Instruction::Switch { low, targets, default } => {
+                let cases: Vec<String> = (*low..)
+                    .zip(targets)
+                    .map(|(value, target)| format!("{} => {}", value, label(target)))
+                    .chain([format!("_ => {}", label(default))])
+                    .collect();
+                format!("Switch({})", cases.join(", "))
+            }
+            _ => match instruction.jump_targets()[..] {
+                [target] => format!("{}({})", variant_name(instruction), label(&target)),
+                _ => format!("{:?}", instruction),
+            },
+        };
+        out.push_str(&format!("    {}\n", text));
+    }
+}
+
+fn variant_name(instruction: &Instruction) -> String {
+    let debug = format!("{:?}", instruction);
+    debug.split(['(', ' ']).next().unwrap_or_default().to_string()
+}
+
+/// Reads scripts written by `emit`, or by hand in the same form. Errors
+/// give the line they are on.
+pub fn parse(text: &str) -> Result<Vec<ByteCode>, String> {
+    let mut scripts = Vec::new();
+    let mut current: Option<Script> = None;
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let at = |message: String| format!("line {}: {}", number, message);
+        if let Some(stamp) = line.strip_prefix(".compiler") {
+            let (compiler, codegen) = stamp.trim().split_once(char::is_whitespace).unwrap_or((stamp.trim(), ""));
+            let codegen = codegen.trim().parse().map_err(|_| at(format!(".compiler takes a version and a revision, not '{}'", stamp.trim())))?;
+            bytecode::check_stamp(compiler, codegen).map_err(|e| at(format!("{}; compile the scripts again", e)))?;
+            continue;
+        }
+        if let Some(name) = line.strip_prefix(".script") {
+            if let Some(script) = current.take() {
+                scripts.push(script.finish()?);
+            }
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(at(".script needs a name".to_string()));
+            }
+            current = Some(Script::new(name));
+            continue;
+        }
+        let Some(script) = current.as_mut() else {
+            return Err(at(format!("'{}' is outside any .script", line)));
+        };
+        if let Some(directive) = line.strip_prefix('.') {
+            script.directive(directive).map_err(at)?;
+        } else if let Some(name) = line.strip_suffix(':').filter(|name| is_label(name)) {
+            if script.labels.insert(name.to_string(), script.pending.len()).is_some() {
+                return Err(at(format!("label '{}' is defined twice", name)));
+            }
+        } else {
+            script.pending.push((number, line.to_string(), script.line));
+        }
+    }
+    if let Some(script) = current {
+        scripts.push(script.finish()?);
+    }
+    Ok(scripts)
+}
+
+/// A script being read: instructions wait in `pending`, with their line in
+/// the text and the source line they're attributed to, until every label
+/// is known.
+struct Script {
+    bytecode: ByteCode,
+    labels: HashMap<String, usize>,
+    pending: Vec<(usize, String, Option<usize>)>,
+    line: Option<usize>,
+}
+
+impl Script {
+    fn new(name: &str) -> Self {
+        Self { bytecode: ByteCode::new(name.to_string()), labels: HashMap::new(), pending: Vec::new(), line: None }
+    }
+
+    fn directive(&mut self, directive: &str) -> Result<(), String> {
+        let (name, value) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+        let value = value.trim();
+        let number = |value: &str| value.parse::<usize>().map_err(|_| format!(".{} takes a number, not '{}'", name, value));
+        let bytecode = &mut self.bytecode;
+        match name {
+            "param" => bytecode.params.push(Type::from_name(value).ok_or_else(|| format!("unknown type '{}'", value))?),
+            "required_args" => bytecode.required_args = number(value)?,
+            "doc" => bytecode.doc = Some(unquote_all(value)?),
+            "annotation" => {
+                let (key, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+                bytecode.annotations.insert(key.to_string(), value.trim().to_string());
+            }
+            "constant" => bytecode.constants.push(value.parse().map_err(|_| format!(".constant takes an int, not '{}'", value))?),
+            "string" => bytecode.strings.push(unquote_all(value)?),
+            "local" => bytecode.locals.push(value.to_string()),
+            "array" => bytecode.arrays.push(value.to_string()),
+            "line" => self.line = Some(number(value)?),
+            _ => return Err(format!("unknown directive '.{}'", name)),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ByteCode, String> {
+        for (number, text, line) in std::mem::take(&mut self.pending) {
+            if let Some(line) = line {
+                self.bytecode.set_line(line);
+            }
+            let instruction = self.instruction(&text).map_err(|message| format!("line {}: {}", number, message))?;
+            self.bytecode.push(instruction);
+        }
+        Ok(self.bytecode)
+    }
+
+    /// Reads an instruction as `Name`, `Name(arg, ...)` or `Switch(value =>
+    /// label, ..., _ => label)`. Builds the serde form of the variant, so
+    /// every instruction reads the same way.
+    fn instruction(&self, text: &str) -> Result<Instruction, String> {
+        let (name, args) = match text.split_once('(') {
+            Some((name, rest)) => {
+                let args = rest.strip_suffix(')').ok_or_else(|| format!("'{}' is missing its ')'", text))?;
+                (name.trim(), split_args(args)?)
+            }
+            None => (text, Vec::new()),
+        };
+        if name == "Switch" {
+            return self.switch(&args);
+        }
+        let mut values = args.iter().map(|arg| self.value(arg)).collect::<Result<Vec<_>, _>>()?;
+        let value = match values.len() {
+            0 => serde_json::Value::String(name.to_string()),
+            1 => serde_json::json!({ name: values.remove(0) }),
+            _ => serde_json::json!({ name: values }),
+        };
+        serde_json::from_value(value).map_err(|e| match e.to_string().starts_with("unknown variant") {
+            true => format!("unknown instruction '{}'", name),
+            false => format!("'{}': {}", text, e),
+        })
+    }
+
+    /// A quoted string, a number, or a label's index.
+    fn value(&self, arg: &str) -> Result<serde_json::Value, String> {
+        if arg.starts_with('"') {
+            return Ok(unquote_all(arg)?.into());
+        }
+        if let Ok(number) = arg.parse::<i64>() {
+            return Ok(number.into());
+        }
+        Ok(self.target(arg)?.into())
+    }
+
+    fn target(&self, label: &str) -> Result<usize, String> {
+        match label.parse() {
+            Ok(index) => Ok(index),
+            Err(_) => self.labels.get(label).copied().ok_or_else(|| format!("undefined label '{}'", label)),
+        }
+    }
+
+    /// Values left out between the cases jump to the default, as in the
+    /// listing `rsc disasm --dump-ir` prints.
+    fn switch(&self, args: &[String]) -> Result<Instruction, String> {
+        let mut cases = Vec::new();
+        let mut default = None;
+        for arg in args {
+            let (value, label) = arg.split_once("=>").ok_or_else(|| format!("expected 'value => label', found '{}'", arg))?;
+            let target = self.target(label.trim())?;
+            match value.trim() {
+                "_" => default = Some(target),
+                value => cases.push((value.parse::<i32>().map_err(|_| format!("'{}' is not a case value", value))?, target)),
+            }
+        }
+        let default = default.ok_or("Switch needs a '_ => label' default")?;
+        let low = cases.iter().map(|(value, _)| *value).min().unwrap_or_default();
+        let high = cases.iter().map(|(value, _)| *value).max().unwrap_or(low - 1);
+        let mut targets = vec![default; (i64::from(high) - i64::from(low) + 1) as usize];
+        for (value, target) in cases.iter().rev() {
+            targets[(value - low) as usize] = *target;
+        }
+        Ok(Instruction::Switch { low, targets, default })
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits an argument list at the commas outside quoted strings.
+fn split_args(args: &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        let end = if rest.starts_with('"') {
+            let (_, after) = unquote(rest)?;
+            rest.len() - after.len()
+        } else {
+            rest.find(',').unwrap_or(rest.len())
+        };
+        parts.push(rest[..end].trim().to_string());
+        rest = rest[end..].trim_start();
+        match rest.strip_prefix(',') {
+            Some(after) => rest = after.trim_start(),
+            None if rest.is_empty() => {}
+            None => return Err(format!("expected ',' before '{}'", rest)),
+        }
+    }
+    Ok(parts)
+}
+
+/// A string quoted as `{:?}` writes it, with nothing after it.
+fn unquote_all(text: &str) -> Result<String, String> {
+    match unquote(text)? {
+        (string, "") => Ok(string),
+        (_, rest) => Err(format!("unexpected '{}' after the string", rest.trim())),
+    }
+}
+
+/// Reads the string quoted as `{:?}` writes it at the start of `text`,
+/// returning it and the text after its closing quote.
+fn unquote(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.strip_prefix('"').ok_or_else(|| format!("expected a quoted string, found '{}'", text))?.char_indices();
+    let mut string = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, chars.as_str())),
+            '\\' => string.push(match chars.next().map(|(_, c)| c) {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some('u') => {
+                    let digits: String = chars.by_ref().map(|(_, c)| c).take_while(|c| *c != '}').skip(1).collect();
+                    u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).ok_or_else(|| format!("bad escape '\\u{{{}}}'", digits))?
+                }
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                other => return Err(format!("bad escape '\\{}'", other.map(String::from).unwrap_or_default())),
+            }),
+            c => string.push(c),
+        }
+    }
+    Err(format!("unterminated string {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::vm::VM;
+
+    const DOUBLE: &str = "[proc,double](int $n)(int)\nreturn(calc($n * 2));\n";
+
+    #[test]
+    fn bytecode_stamped_by_another_build_is_refused() {
+        let text = emit(&test_support::compile(DOUBLE));
+        let stamp = format!(".compiler {} {}\n", bytecode::COMPILER_VERSION, bytecode::CODEGEN_REVISION);
+        assert!(text.contains(&stamp), "{}", text);
+        let instructions = test_support::compile(DOUBLE).remove(0).instructions;
+        assert_eq!(parse(&text).unwrap()[0].instructions, instructions);
+
+        let older = text.replace(&stamp, &format!(".compiler {} {}\n", bytecode::COMPILER_VERSION, bytecode::CODEGEN_REVISION - 1));
+        let error = parse(&older).unwrap_err();
+        assert!(error.starts_with("line 2: compiled by rsc "), "{}", error);
+        assert!(error.ends_with("; compile the scripts again"), "{}", error);
+        let other_version = text.replace(&stamp, &format!(".compiler 0.0.0-old {}\n", bytecode::CODEGEN_REVISION));
+        assert!(parse(&other_version).is_err());
+
+        // Hand-written files may leave the stamp out.
+        assert_eq!(parse(&text.replace(&stamp, "")).unwrap()[0].instructions, instructions);
+    }
+
+    #[test]
+    fn compiled_scripts_round_trip_through_text_and_run_the_same() {
+        let compiled = test_support::compile(include_str!("../data/scripts/fib.rs2"));
+        let text = emit(&compiled);
+        let parsed = parse(&text).unwrap();
+        assert_eq!(emit(&parsed), text);
+        for (original, parsed) in compiled.iter().zip(&parsed) {
+            assert_eq!(serde_json::to_value(original).unwrap(), serde_json::to_value(parsed).unwrap(), "{}", original.script_name);
+        }
+
+        let (mut from_source, mut from_text) = (VM::new(), VM::new());
+        for (original, parsed) in compiled.into_iter().zip(parsed) {
+            from_source.register_script(original);
+            from_text.register_script(parsed);
+        }
+        for (script, args) in [("fib", vec![10]), ("factorial", vec![5]), ("sum_to_n", vec![10]), ("ackermann", vec![2, 3]), ("is_even", vec![7])] {
+            let expected = from_source.run_script(script, &args);
+            assert!(expected.is_ok(), "{}{:?}: {:?}", script, args, expected);
+            assert_eq!(from_text.run_script(script, &args), expected, "{}{:?}", script, args);
+        }
+    }
+
+    #[test]
+    fn labels_resolve_to_the_index_of_the_line_after_them() {
+        let text = ".script pick\n.param int\n.required_args 1\n.local n\n    PushIntLocal(\"arg0\")\n    PopIntLocal(\"n\")\n\
+            ; a comment between instructions\n    PushIntLocal(\"n\")\n    BranchNot(zero)\n    PushConstantInt(1)\n    Return\nzero:\n    PushConstantInt(2)\n    Return\n";
+        let script = parse(text).unwrap().remove(0);
+        assert_eq!(script.instructions[3], Instruction::BranchNot(6));
+        let mut vm = VM::new();
+        vm.register_script(script);
+        assert_eq!(vm.run_script("pick", &[5]), Ok(1));
+        assert_eq!(vm.run_script("pick", &[0]), Ok(2));
+
+        let error = parse(&text.replace("BranchNot(zero)", "BranchNot(nowhere)")).unwrap_err();
+        assert!(error.starts_with("line 9: "), "{}", error);
+    }
+}
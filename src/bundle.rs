@@ -0,0 +1,153 @@
+//! Single-file bundles of a compiled project, written by `rsc bundle` and
+//! run with `rsc run --bundle`, which need no scripts directory or config.
+//!
+//! A bundle is JSON: an envelope with the format version, the compiler
+//! version and codegen revision that wrote it and the script to run, the
+//! VM options and script overrides it was built with, every compiled script
+//! and the config files scripts refer to. Each script and config file
+//! carries the SHA-256 of its contents, checked by `read`, which also
+//! refuses bundles compiled by another build.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::bytecode::{self, ByteCode};
+use crate::config::{Config, ScriptOverrides, VmOptions};
+use crate::symbols::{self, SymbolTable};
+use crate::types::Type;
+
+/// The bundle format this version writes and reads.
+pub const VERSION: u32 = 1;
+
+/// A compiled project and the script to run in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    /// The compiler version that wrote the bundle.
+    pub compiler: String,
+    /// The `CODEGEN_REVISION` of the compiler that wrote the bundle; 0 for
+    /// bundles from before it was recorded.
+    #[serde(default)]
+    pub codegen: u32,
+    /// The script `rsc run --bundle` runs.
+    pub entry: String,
+    /// The entry's declared return type, if any, for printing its result.
+    pub return_type: Option<Type>,
+    pub vm: VmOptions,
+    /// By script name, sorted so the same project always bundles the same.
+    pub script_overrides: BTreeMap<String, ScriptOverrides>,
+    pub scripts: Vec<BundledScript>,
+    /// In the order the symbol table was built from, which its ids follow.
+    pub configs: Vec<ConfigFile>,
+}
+
+/// A compiled script with the hash of its serialized bytecode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledScript {
+    pub sha256: String,
+    pub bytecode: ByteCode,
+}
+
+/// A config file, by its path within its config directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub contents: String,
+}
+
+impl Bundle {
+    /// Bundles `scripts` to run `entry`, with the VM options, script
+    /// overrides and config files `config` names.
+    pub fn new(entry: &str, return_type: Option<Type>, scripts: Vec<ByteCode>, config: &Config) -> io::Result<Self> {
+        let configs = symbols::read_config_files(&config.configs)?
+            .into_iter()
+            .map(|(path, contents)| {
+                let path = config
+                    .configs
+                    .iter()
+                    .find_map(|dir| path.strip_prefix(dir).ok())
+                    .map(Path::to_path_buf)
+                    .unwrap_or(path);
+                ConfigFile { path, sha256: sha256(contents.as_bytes()), contents }
+            })
+            .collect();
+        let scripts = scripts
+            .into_iter()
+            .map(|bytecode| Ok(BundledScript { sha256: script_hash(&bytecode)?, bytecode }))
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            version: VERSION,
+            compiler: bytecode::COMPILER_VERSION.to_string(),
+            codegen: bytecode::CODEGEN_REVISION,
+            entry: entry.to_string(),
+            return_type,
+            vm: config.vm.clone(),
+            script_overrides: config.scripts.iter().map(|(name, overrides)| (name.clone(), overrides.clone())).collect(),
+            scripts,
+            configs,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    /// Loads a bundle saved by `write`, failing if it is of another format
+    /// version, was compiled by another compiler version or codegen
+    /// revision, or anything in it doesn't match its hash.
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let version = serde_json::from_str::<serde_json::Value>(&contents)
+            .map_err(|e| format!("{} is not a bundle: {}", path.display(), e))?
+            .get("version")
+            .and_then(serde_json::Value::as_u64);
+        if version != Some(VERSION as u64) {
+            return Err(format!(
+                "{} is a version {} bundle; this compiler reads version {}",
+                path.display(),
+                version.map_or("unknown".to_string(), |version| version.to_string()),
+                VERSION
+            ));
+        }
+        let bundle: Self = serde_json::from_str(&contents).map_err(|e| format!("{} is not a bundle: {}", path.display(), e))?;
+        bytecode::check_stamp(&bundle.compiler, bundle.codegen)
+            .map_err(|e| format!("{} was {}; bundle it again with this version", path.display(), e))?;
+        bundle.verify()?;
+        Ok(bundle)
+    }
+
+    /// Checks every script and config file against its hash.
+    pub fn verify(&self) -> Result<(), String> {
+        for script in &self.scripts {
+            let name = &script.bytecode.script_name;
+            if script_hash(&script.bytecode).map_err(|e| format!("script '{}': {}", name, e))? != script.sha256 {
+                return Err(format!("script '{}' doesn't match its hash; the bundle is corrupt or was edited", name));
+            }
+        }
+        for config in &self.configs {
+            if sha256(config.contents.as_bytes()) != config.sha256 {
+                return Err(format!("config file {} doesn't match its hash; the bundle is corrupt or was edited", config.path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The symbol table the bundled config files build, as the project's
+    /// config directories did.
+    pub fn symbols(&self) -> io::Result<SymbolTable> {
+        let files: Vec<(PathBuf, String)> = self.configs.iter().map(|config| (config.path.clone(), config.contents.clone())).collect();
+        SymbolTable::from_files(&files)
+    }
+}
+
+fn script_hash(bytecode: &ByteCode) -> io::Result<String> {
+    Ok(sha256(&serde_json::to_vec(bytecode)?))
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -1,4 +1,32 @@
-#[derive(Debug, Clone)]
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use serde::{Deserialize, Serialize};
+use crate::annotations::Annotations;
+use crate::types::Type;
+
+/// The compiler version, stamped on saved bytecode: bundles and `.rscasm`
+/// files.
+pub const COMPILER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Stamped on saved bytecode with `COMPILER_VERSION`. Bump it whenever a
+/// change to codegen or to what an instruction does would make bytecode
+/// saved before it run differently, so builds of the same version refuse
+/// each other's bytecode too.
+pub const CODEGEN_REVISION: u32 = 1;
+
+/// Checks the stamp on saved bytecode against this build, saying which
+/// build wrote it if they differ.
+pub fn check_stamp(compiler: &str, codegen: u32) -> Result<(), String> {
+    if compiler == COMPILER_VERSION && codegen == CODEGEN_REVISION {
+        return Ok(());
+    }
+    Err(format!(
+        "compiled by rsc {} (codegen revision {}), but this is rsc {} (codegen revision {})",
+        compiler, codegen, COMPILER_VERSION, CODEGEN_REVISION
+    ))
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum Instruction {
     // Core language ops (0-99)
@@ -18,11 +46,14 @@ pub enum Instruction {
     Add = 13,                   // Add top two stack values
     Subtract = 14,              // Subtract top two stack values
     Multiply = 15,              // Multiply top two stack values
-    Divide = 16,                // Divide top two stack values
+    Divide = 16,                // Divide top two stack values, truncating toward zero
+    Modulo = 17,                // Remainder of dividing top two stack values; takes the dividend's sign
     Return = 21,                // Return from current script
     Gosub(String) = 22,         // Call a script (without params)
     Jump(usize) = 23,           // Unconditional jump
-    Switch(Vec<(i32, usize)>) = 24, // Switch statement
+    /// Pops a value and jumps to `targets[value - low]`, or to `default`
+    /// for a value outside the table; see `switch_dispatch`.
+    Switch { low: i32, targets: Vec<usize>, default: usize } = 24,
     BranchLessThanOrEquals(usize) = 31, // Branch if less than or equal
     BranchGreaterThanOrEquals(usize) = 32, // Branch if greater than or equal
     BranchNotEquals(usize) = 33, // Branch if not equal
@@ -30,7 +61,7 @@ pub enum Instruction {
     PopIntLocal(String) = 35,   // Pop and store to local integer variable
     PushStringLocal(String) = 36, // Push local string variable
     PopStringLocal(String) = 37, // Pop and store to local string variable
-    JoinString = 38,            // Concatenate strings
+    JoinString(usize) = 38,     // Pop count strings and push them concatenated in order
     PopIntDiscard = 39,         // Pop and discard integer
     PopStringDiscard = 40,      // Pop and discard string
     GosubWithParams(String) = 41, // Call a script with parameters
@@ -39,9 +70,106 @@ pub enum Instruction {
     PushArrayInt(String) = 45,  // Push array element
     PopArrayInt(String) = 46,   // Pop and store to array element
     Abs = 47,                   // Absolute value of top stack value
+    Command(String, String) = 48, // Call a host command; one 'i' or 's' per argument says which stack it's on
+    PackCoord = 49,             // Pop level, x and z and push them packed as a coord
+    CoordX = 50,                // Replace a coord with its x
+    CoordZ = 51,                // Replace a coord with its z
+    CoordLevel = 52,            // Replace a coord with its level
+    CoordDistance = 53,         // Pop two coords and push the Chebyshev distance between them
+    IntToString = 54,           // Pop an int and push it as a string
+    StringToInt = 55,           // Pop a string and push the int it spells
+    CompareStrings = 56,        // Pop two strings and push how they order
+    PushStringConstant(usize) = 57, // Push a string from the script's string pool
+    EnumGetValue(String) = 58,  // Replace a key with its value in the named enum
+    PushVarbit(i32, u8, u8) = 59, // Push bits start..=end of a varp
+    PopVarbit(i32, u8, u8) = 60,  // Pop into bits start..=end of a varp, keeping the rest
+    StringLength = 61,          // Pop a string and push its length in chars
+    Substring = 62,             // Pop end and start, then a string, and push its chars start..end
+    StringIndexOf = 63,         // Pop a string to find and one to search, and push the char index or -1
+    LowerCase = 64,             // Replace a string with its lowercase
+    UpperCase = 65,             // Replace a string with its uppercase
+    Random = 66,                // Replace a bound with a random value below it
+    Min = 67,                   // Pop two values and push the smaller
+    Max = 68,                   // Pop two values and push the larger
+    SetTimer(String) = 69,      // Pop ticks and a timer, and queue the named script to run when it fires
+    ClearTimer = 70,            // Pop a timer and cancel it if pending
+    Rgb = 71,                   // Pop blue, green and red and push the colour they make
+    ColorComponent = 72,        // Pop an index and a colour and push that component of it
+    OcParam(String) = 73,       // Replace an obj with its value of the named param, on the param type's stack
+    NcParam(String) = 74,       // Replace an npc with its value of the named param, on the param type's stack
+    StructParam(String) = 75,   // Replace a struct with its value of the named param, on the param type's stack
+    PackCoord2 = 76,            // Pop x and y and push them packed as a coord2
+    Coord2X = 77,               // Replace a coord2 with its x
+    Coord2Y = 78,               // Replace a coord2 with its y
+    PackCoord3 = 79,            // Pop x, y and z and push them packed as a coord3
+    Coord3X = 80,               // Replace a coord3 with its x
+    Coord3Y = 81,               // Replace a coord3 with its y
+    Coord3Z = 82,               // Replace a coord3 with its z
 }
 
-#[derive(Debug, Clone)]
+impl Instruction {
+    /// The coord command an instruction implements, e.g. `coordx` for
+    /// `CoordX`.
+    pub fn coord_command(&self) -> Option<&'static str> {
+        match self {
+            Instruction::PackCoord => Some("coord"),
+            Instruction::CoordX => Some("coordx"),
+            Instruction::CoordZ => Some("coordz"),
+            Instruction::CoordLevel => Some("coordlevel"),
+            Instruction::CoordDistance => Some("distance"),
+            Instruction::PackCoord2 => Some("coord2"),
+            Instruction::Coord2X => Some("coord2x"),
+            Instruction::Coord2Y => Some("coord2y"),
+            Instruction::PackCoord3 => Some("coord3"),
+            Instruction::Coord3X => Some("coord3x"),
+            Instruction::Coord3Y => Some("coord3y"),
+            Instruction::Coord3Z => Some("coord3z"),
+            _ => None,
+        }
+    }
+
+    /// The instructions this one may jump to, by index.
+    pub fn jump_targets(&self) -> Vec<usize> {
+        match self {
+            Instruction::Branch(target)
+            | Instruction::BranchNot(target)
+            | Instruction::BranchEquals(target)
+            | Instruction::BranchLessThan(target)
+            | Instruction::BranchGreaterThan(target)
+            | Instruction::BranchLessThanOrEquals(target)
+            | Instruction::BranchGreaterThanOrEquals(target)
+            | Instruction::BranchNotEquals(target)
+            | Instruction::Jump(target)
+            | Instruction::JumpWithParams(target) => vec![*target],
+            Instruction::Switch { targets, default, .. } => targets.iter().chain([default]).copied().collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reads or writes state that outlives a script call: varps, varns,
+    /// script variables, arrays, the random number generator or timers.
+    pub fn touches_globals(&self) -> bool {
+        matches!(
+            self,
+            Instruction::PushVarp(_)
+                | Instruction::PopVarp(_)
+                | Instruction::PushVarbit(..)
+                | Instruction::PopVarbit(..)
+                | Instruction::PushVarn(_)
+                | Instruction::PopVarn(_)
+                | Instruction::PushVars(_)
+                | Instruction::PopVars(_)
+                | Instruction::DefineArray(..)
+                | Instruction::PushArrayInt(_)
+                | Instruction::PopArrayInt(_)
+                | Instruction::Random
+                | Instruction::SetTimer(_)
+                | Instruction::ClearTimer
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ByteCode {
     pub instructions: Vec<Instruction>,
     pub script_name: String,
@@ -49,6 +177,19 @@ pub struct ByteCode {
     pub strings: Vec<String>,
     pub locals: Vec<String>,
     pub arrays: Vec<String>,
+    /// Source line (1-based) of each instruction, parallel to `instructions`.
+    pub source_lines: Vec<Option<usize>>,
+    /// Parameters without a default. Calls passing fewer arguments fail,
+    /// unless `vm.lenient_args` is set.
+    pub required_args: usize,
+    /// The declared type of each parameter, in order.
+    pub params: Vec<Type>,
+    /// The script's doc comment, if it has one.
+    pub doc: Option<String>,
+    /// The script's `// @key value` annotations; see `annotations`.
+    pub annotations: Annotations,
+    #[serde(skip)]
+    current_line: Option<usize>,
 }
 
 impl ByteCode {
@@ -60,11 +201,117 @@ impl ByteCode {
             strings: Vec::new(),
             locals: Vec::new(),
             arrays: Vec::new(),
+            source_lines: Vec::new(),
+            required_args: 0,
+            params: Vec::new(),
+            doc: None,
+            annotations: Annotations::new(),
+            current_line: None,
         }
     }
 
     pub fn push(&mut self, instruction: Instruction) {
         self.instructions.push(instruction);
+        self.source_lines.push(self.current_line);
+    }
+
+    /// Attributes instructions pushed from now on to `line`.
+    pub fn set_line(&mut self, line: usize) {
+        self.current_line = Some(line);
+    }
+
+    pub fn line_at(&self, ip: usize) -> Option<usize> {
+        self.source_lines.get(ip).copied().flatten()
+    }
+
+    /// Hash of what the script does: its name, instructions and constant
+    /// pools. Source lines are left out, so moving a script within its file
+    /// keeps the hash. `DefaultHasher::new` uses fixed keys, so the hash is
+    /// the same across runs, though not guaranteed across Rust releases.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.script_name.hash(&mut hasher);
+        self.instructions.hash(&mut hasher);
+        self.constants.hash(&mut hasher);
+        self.strings.hash(&mut hasher);
+        self.locals.hash(&mut hasher);
+        self.arrays.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Like `content_hash`, but without the name, so scripts that compiled
+    /// to the same code under different names hash the same.
+    pub fn body_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.instructions.hash(&mut hasher);
+        self.constants.hash(&mut hasher);
+        self.strings.hash(&mut hasher);
+        self.locals.hash(&mut hasher);
+        self.arrays.hash(&mut hasher);
+        self.required_args.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether `other` behaves the same as this script when called: the same
+    /// code and parameters, whatever either is named.
+    pub fn same_body(&self, other: &ByteCode) -> bool {
+        self.instructions == other.instructions
+            && self.constants == other.constants
+            && self.strings == other.strings
+            && self.locals == other.locals
+            && self.arrays == other.arrays
+            && self.required_args == other.required_args
+            && self.params == other.params
+    }
+
+    /// The instructions as a listing with symbolic labels: each jump target
+    /// gets a label, `L0`, `L1`, ... in order of position, on a line of its
+    /// own before it, and jumps name the label instead of the index.
+    ///
+    /// ```text
+    ///     PushIntLocal("x")
+    ///     PushConstantInt(0)
+    ///     BranchLessThanOrEquals(L0)
+    ///     ...
+    /// L0:
+    ///     Return
+    /// ```
+    pub fn labelled(&self) -> Vec<String> {
+        let mut targets: Vec<usize> = self.instructions.iter().flat_map(Instruction::jump_targets).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        let label = |target: &usize| format!("L{}", targets.binary_search(target).unwrap_or_default());
+
+        let mut lines = Vec::new();
+        // A jump past the last instruction, e.g. out of a loop ending the
+        // script, gets its label at the end.
+        for ip in 0..=self.instructions.len() {
+            if targets.binary_search(&ip).is_ok() {
+                lines.push(format!("{}:", label(&ip)));
+            }
+            let Some(instruction) = self.instructions.get(ip) else { break };
+            let text = match instruction {
+                Instruction::Switch { low, targets, default } => {
+                    let cases: Vec<String> = (*low..)
+                        .zip(targets)
+                        .filter(|(_, target)| *target != default)
+                        .map(|(value, target)| format!("{} => {}", value, label(target)))
+                        .chain([format!("_ => {}", label(default))])
+                        .collect();
+                    format!("Switch({})", cases.join(", "))
+                }
+                _ => match instruction.jump_targets()[..] {
+                    [target] => {
+                        let debug = format!("{:?}", instruction);
+                        let name = debug.split('(').next().unwrap_or_default();
+                        format!("{}({})", name, label(&target))
+                    }
+                    _ => format!("{:?}", instruction),
+                },
+            };
+            lines.push(format!("    {}", text));
+        }
+        lines
     }
 
     pub fn add_constant(&mut self, value: i32) -> usize {
@@ -102,4 +349,159 @@ impl ByteCode {
             self.arrays.len() - 1
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Case values spread over more than this many values per case get a
+/// comparison chain rather than a jump table, which would be mostly gaps.
+const MAX_SWITCH_SPREAD: i64 = 2;
+
+/// The instructions that jump to the target of the first case whose value
+/// equals the one `scrutinee` pushes, or to `default` when none does. Dense
+/// case values, e.g. 0 to 5, get a `Switch` jump table, one lookup whatever
+/// the value; sparse ones, e.g. 1, 100 and 10000, a `BranchEquals` per case,
+/// which pushes the scrutinee again each time. The output's length depends
+/// only on the case values, so a compiler can emit it with placeholder
+/// targets and patch them once the bodies are placed.
+pub fn switch_dispatch(scrutinee: &Instruction, cases: &[(i32, usize)], default: usize) -> Vec<Instruction> {
+    let (Some(low), Some(high)) = (cases.iter().map(|(value, _)| *value).min(), cases.iter().map(|(value, _)| *value).max()) else {
+        return vec![Instruction::Jump(default)];
+    };
+    let span = i64::from(high) - i64::from(low) + 1;
+    if span <= cases.len() as i64 * MAX_SWITCH_SPREAD {
+        let mut targets = vec![default; span as usize];
+        // In reverse, so the first of two cases with one value wins.
+        for (value, target) in cases.iter().rev() {
+            targets[(i64::from(*value) - i64::from(low)) as usize] = *target;
+        }
+        return vec![scrutinee.clone(), Instruction::Switch { low, targets, default }];
+    }
+    let mut instructions = Vec::new();
+    for (value, target) in cases {
+        instructions.extend([scrutinee.clone(), Instruction::PushConstantInt(*value), Instruction::BranchEquals(*target)]);
+    }
+    instructions.push(Instruction::Jump(default));
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::{switch_dispatch, Instruction};
+    use crate::host::{HostValue, RecordingHost};
+    use crate::test_support;
+    use crate::vm::VM;
+
+    const DOUBLE: &str = "[proc,double](int $n)(int)\nreturn(calc($n * 2));\n";
+
+    #[test]
+    fn only_this_builds_stamp_passes() {
+        assert_eq!(super::check_stamp(super::COMPILER_VERSION, super::CODEGEN_REVISION), Ok(()));
+        assert!(super::check_stamp(super::COMPILER_VERSION, super::CODEGEN_REVISION + 1).is_err());
+        assert_eq!(
+            super::check_stamp("0.0.0-old", super::CODEGEN_REVISION),
+            Err(format!(
+                "compiled by rsc 0.0.0-old (codegen revision {}), but this is rsc {} (codegen revision {})",
+                super::CODEGEN_REVISION,
+                super::COMPILER_VERSION,
+                super::CODEGEN_REVISION
+            ))
+        );
+    }
+
+    #[test]
+    fn content_hash_follows_the_code_not_the_compile() {
+        let first = &test_support::compile(DOUBLE)[0];
+        let second = &test_support::compile(DOUBLE)[0];
+        assert_eq!(first.content_hash(), second.content_hash());
+
+        // Moved down the file: only the source lines differ.
+        let moved = &test_support::compile(&format!("\n\n{}", DOUBLE))[0];
+        assert_ne!(moved.source_lines, first.source_lines);
+        assert_eq!(moved.content_hash(), first.content_hash());
+
+        let changed = &test_support::compile(&DOUBLE.replace("* 2", "* 3"))[0];
+        assert_ne!(changed.content_hash(), first.content_hash());
+    }
+
+    #[test]
+    fn repeated_string_literal_is_pooled_once() {
+        let source = "[proc,echo]()(int)\nmes(\"x\");\nmes(\"x\");\nmes(\"x\");\nreturn(0);\n";
+        let bytecode = &test_support::compile(source)[0];
+        assert_eq!(bytecode.strings, ["x"]);
+
+        let host = Rc::new(RefCell::new(RecordingHost::default()));
+        let mut vm = test_support::vm(source);
+        vm.set_host(host.clone());
+        vm.run_script("echo", &[]).unwrap();
+        let x = ("mes".to_string(), vec![HostValue::Str("x".to_string())]);
+        assert_eq!(host.borrow().calls, [x.clone(), x.clone(), x]);
+    }
+
+    #[test]
+    fn an_if_branches_not_to_a_forward_label() {
+        let source = "[proc,warn](int $n)(int)\nif ($n > 3) {\n    mes(\"big\");\n}\nreturn($n);\n";
+        let listing = test_support::compile(source)[0].labelled();
+        let branch = listing.iter().position(|line| line.trim().starts_with("BranchNot(L")).expect("the if should end in a BranchNot");
+        let target = listing[branch].trim().trim_start_matches("BranchNot(").trim_end_matches(')');
+        let label = listing.iter().position(|line| *line == format!("{}:", target)).expect("the label should be placed");
+        assert!(branch < label, "{:#?}", listing);
+        assert!(listing[branch..label].iter().any(|line| line.contains("mes")), "{:#?}", listing);
+        assert!(listing.iter().all(|line| line.ends_with(':') || line.starts_with("    ")), "{:#?}", listing);
+    }
+
+    /// A script returning the index of the case `switch_dispatch` picks for
+    /// its argument, or -1 for the default.
+    fn switch_script(values: &[i32]) -> super::ByteCode {
+        let scrutinee = Instruction::PushIntLocal("arg0".to_string());
+        let length = switch_dispatch(&scrutinee, &values.iter().map(|value| (*value, 0)).collect::<Vec<_>>(), 0).len();
+        let cases: Vec<(i32, usize)> = values.iter().enumerate().map(|(index, value)| (*value, length + 2 * index)).collect();
+        let mut bytecode = super::ByteCode::new("switch".to_string());
+        bytecode.required_args = 1;
+        bytecode.params = vec![crate::types::Type::Int];
+        for instruction in switch_dispatch(&scrutinee, &cases, length + 2 * values.len()) {
+            bytecode.push(instruction);
+        }
+        for result in (0..values.len() as i32).chain([-1]) {
+            bytecode.push(Instruction::PushConstantInt(result));
+            bytecode.push(Instruction::Return);
+        }
+        bytecode
+    }
+
+    #[test]
+    fn a_dense_switch_is_one_jump_table() {
+        let bytecode = switch_script(&[0, 1, 2, 3, 4, 5]);
+        assert!(matches!(&bytecode.instructions[1], Instruction::Switch { low: 0, targets, .. } if targets.len() == 6), "{:?}", bytecode.instructions);
+        assert!(!bytecode.instructions.iter().any(|instruction| matches!(instruction, Instruction::BranchEquals(_))));
+
+        let mut vm = VM::new();
+        vm.register_script(bytecode);
+        for (value, expected) in [(0, 0), (1, 1), (3, 3), (5, 5), (-1, -1), (6, -1), (i32::MIN, -1), (i32::MAX, -1)] {
+            assert_eq!(vm.run_script("switch", &[value]), Ok(expected), "{}", value);
+        }
+    }
+
+    #[test]
+    fn a_sparse_switch_is_a_comparison_chain() {
+        let bytecode = switch_script(&[1, 100, 10000]);
+        assert!(!bytecode.instructions.iter().any(|instruction| matches!(instruction, Instruction::Switch { .. })));
+        let compares = bytecode.instructions.iter().filter(|instruction| matches!(instruction, Instruction::BranchEquals(_))).count();
+        assert_eq!(compares, 3);
+
+        let mut vm = VM::new();
+        vm.register_script(bytecode);
+        for (value, expected) in [(1, 0), (100, 1), (10000, 2), (0, -1), (50, -1), (10001, -1)] {
+            assert_eq!(vm.run_script("switch", &[value]), Ok(expected), "{}", value);
+        }
+    }
+
+    #[test]
+    fn the_first_of_two_cases_with_one_value_wins_and_gaps_go_to_the_default() {
+        let mut vm = VM::new();
+        vm.register_script(switch_script(&[3, 5, 6, 5]));
+        for (value, expected) in [(3, 0), (4, -1), (5, 1), (6, 2), (7, -1)] {
+            assert_eq!(vm.run_script("switch", &[value]), Ok(expected), "{}", value);
+        }
+    }
+}
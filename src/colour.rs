@@ -0,0 +1,85 @@
+//! Colours for interface scripting: 24-bit `0xRRGGBB` values, built with
+//! `rgb` and taken apart with `color_component`.
+
+use crate::types::Type;
+
+/// The largest colour, white.
+pub const MAX: i32 = 0xFF_FFFF;
+
+/// Parameter and return types of the colour commands.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "rgb" => Some((&[Type::Int, Type::Int, Type::Int], Type::Color)),
+        "color_component" => Some((&[Type::Color, Type::Int], Type::Int)),
+        _ => None,
+    }
+}
+
+/// Whether `value` fits in 24 bits.
+pub fn is_valid(value: i32) -> bool {
+    (0..=MAX).contains(&value)
+}
+
+/// Runs `rgb(r, g, b)`; each component must be in 0..=255.
+pub fn rgb(red: i32, green: i32, blue: i32) -> Result<i32, String> {
+    for (name, value) in [("red", red), ("green", green), ("blue", blue)] {
+        if !(0..=255).contains(&value) {
+            return Err(format!("rgb: {} component {} is outside 0..255", name, value));
+        }
+    }
+    Ok(red << 16 | green << 8 | blue)
+}
+
+/// Runs `color_component(colour, index)`: 0 for red, 1 for green, 2 for blue.
+pub fn component(colour: i32, index: i32) -> Result<i32, String> {
+    if !is_valid(colour) {
+        return Err(format!("color_component: {} is not a 24-bit colour", colour));
+    }
+    match index {
+        0..=2 => Ok(colour >> (16 - 8 * index) & 0xFF),
+        _ => Err(format!("color_component: index {} is not 0, 1 or 2", index)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,components](int $r, int $g, int $b)(int)\n\
+        def_colour $c = rgb($r, $g, $b);\n\
+        return(calc(color_component($c, 0) * 1000000 + color_component($c, 1) * 1000 + color_component($c, 2)));\n\
+        [proc,teal]()(colour)\nreturn(rgb(18, 52, 86));\n\
+        [proc,literal]()(int)\ndef_colour $c = 0x123456;\nreturn(color_component($c, 2));\n";
+
+    #[test]
+    fn a_colour_built_from_components_gives_them_back_on_both_backends() {
+        let mut vm = test_support::vm(SCRIPTS);
+        let mut evaluator = test_support::evaluator(SCRIPTS);
+        assert_eq!(vm.run_script("components", &[18, 52, 86]), Ok(18_052_086));
+        assert_eq!(evaluator.eval_script("components", &[18, 52, 86]), Ok(18_052_086));
+        assert_eq!(vm.run_script("teal", &[]), Ok(0x123456));
+        assert_eq!(evaluator.eval_script("teal", &[]), Ok(0x123456));
+        assert_eq!(vm.run_script("literal", &[]), Ok(0x56));
+    }
+
+    #[test]
+    fn an_out_of_range_component_fails_at_runtime_on_both_backends() {
+        let message = "rgb: green component 256 is outside 0..255";
+        let error = test_support::vm(SCRIPTS).run_script("components", &[0, 256, 0]).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = test_support::evaluator(SCRIPTS).eval_script("components", &[0, 256, 0]).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+    }
+
+    #[test]
+    fn colours_and_ints_do_not_mix_without_a_cast() {
+        for source in [
+            "[proc,bad]()(int)\ndef_colour $c = 0x1000000;\nreturn(0);\n",
+            "[proc,bad](int $n)(int)\ndef_colour $c = $n;\nreturn(0);\n",
+            "[proc,bad]()(int)\ndef_colour $c = rgb(1, 2, 3);\nreturn(calc($c + 1));\n",
+        ] {
+            assert!(test_support::try_compile_with(&mut Compiler::new(), source).is_err(), "{}", source);
+        }
+    }
+}
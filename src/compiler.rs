@@ -1,6 +1,20 @@
+use crate::annotations;
 use crate::bytecode::{ByteCode, Instruction};
+use crate::convert;
+use crate::colour;
+use crate::coord;
+use crate::db;
+use crate::error::CompilerError;
+use crate::host;
+use crate::lints;
+use crate::optimize::{ConstantCallFolding, ConstantConditions, LoopInvariantMotion};
+use crate::params::{self, ParamConfig};
 use crate::parser::AstKind;
-use crate::types::Type;
+use crate::random;
+use crate::scheduler;
+use crate::strings;
+use crate::symbols::{self, SymbolTable};
+use crate::types::{Coercion, Type, NULL};
 use std::collections::HashMap;
 
 #[derive(Debug)]
@@ -14,9 +28,29 @@ enum RecursivePattern {
     },
 }
 
+pub const DEFAULT_OPT_LEVEL: u8 = 1;
+
+/// Local the VM sets to the number of arguments a script was called with, so
+/// parameter defaults know which arguments are missing.
+pub const ARG_COUNT_LOCAL: &str = "__argc";
+
 pub struct Compiler {
     scripts: HashMap<String, ByteCode>,
     current_script: Option<String>,  // Track the current script being compiled
+    local_types: HashMap<String, Type>,
+    warnings: Vec<String>,
+    opt_level: u8,
+    strict_conditions: bool,
+    call_folding: ConstantCallFolding,
+    symbols: SymbolTable,
+    /// `@deprecated` scripts seen so far, by name, with their advice.
+    deprecated: HashMap<String, String>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
@@ -24,39 +58,172 @@ impl Compiler {
         Self {
             scripts: HashMap::new(),
             current_script: None,
+            local_types: HashMap::new(),
+            warnings: Vec::new(),
+            opt_level: DEFAULT_OPT_LEVEL,
+            strict_conditions: false,
+            call_folding: ConstantCallFolding::new(),
+            symbols: SymbolTable::new(),
+            deprecated: HashMap::new(),
+        }
+    }
+
+    /// Sets the npc and obj names that bare identifiers in handle-typed
+    /// positions resolve against, e.g. `def_npc $n = man;`.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    /// Resolves handle names in a trigger the way `compile_script` does, for
+    /// backends that run the AST directly.
+    pub fn resolve_symbols(&self, ast: &AstKind) -> Result<AstKind, CompilerError> {
+        self.symbols.resolve(ast).map_err(|message| self.type_error(message))
+    }
+
+    /// Sets the optimisation level for scripts compiled from now on:
+    /// 0 compiles the AST as written, 1 (the default) also hoists
+    /// loop-invariant expressions out of `while` bodies, folds calls to
+    /// pure scripts with constant arguments and removes `if` and `while`
+    /// statements whose condition is constant.
+    pub fn set_opt_level(&mut self, opt_level: u8) {
+        self.opt_level = opt_level;
+    }
+
+    /// With strict conditions, an `if` or `while` condition must be a
+    /// comparison or boolean-typed; a bare int such as `calc($x * 2)` is an
+    /// error rather than true whenever it's nonzero.
+    pub fn set_strict_conditions(&mut self, strict: bool) {
+        self.strict_conditions = strict;
+    }
+
+    /// Makes a trigger's AST available for call folding. Register every
+    /// script before compiling any, so calls to scripts defined later fold too,
+    /// and after `set_symbols`, so handle names are resolved. A trigger whose
+    /// names don't resolve is left out; compiling it reports the error.
+    pub fn add_source(&mut self, node: &AstKind) {
+        self.note_deprecation(node);
+        if let (Some(name), Ok(resolved)) = (node.script_name(), self.symbols.resolve(node)) {
+            self.call_folding.add_script(name, resolved);
         }
     }
 
-    pub fn compile_script(&mut self, name: String, ast: &AstKind) -> ByteCode {
+    /// Type warnings reported so far, e.g. implicit int -> id conversions.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn compile_script(&mut self, name: String, ast: &AstKind) -> Result<ByteCode, CompilerError> {
         let mut bytecode = ByteCode::new(name.clone());
         
         // Set current script name
         self.current_script = Some(name.clone());
+
+        self.check_loop_steps(ast);
+        self.check_annotations(ast);
+        let lowered = match self.resolve_symbols(&ast.lower_loops()) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.current_script = None;
+                return Err(e);
+            }
+        };
+        let ast = &lowered;
+
+        if let Err(e) = self.check_types(ast) {
+            self.current_script = None;
+            return Err(e);
+        }
+        self.check_calc_usage(ast);
         
         match ast {
-            AstKind::Trigger { body, args, .. } => {
+            AstKind::Trigger { body, args, defaults, doc, annotations, .. } => {
                 // Initialize arguments
                 let mut arg_index = 0;
                 let mut param_name = None;
+                bytecode.required_args = defaults.iter().filter(|default| default.is_none()).count();
+                bytecode.params = args
+                    .iter()
+                    .step_by(2)
+                    .filter_map(|arg| match &**arg {
+                        AstKind::Identifier(type_name) => Type::from_name(type_name),
+                        _ => None,
+                    })
+                    .collect();
+                bytecode.doc = doc.clone();
+                bytecode.annotations = annotations.clone();
                 for arg in args.iter().skip(1).step_by(2) {  // Skip type nodes and get variable names
                     if let AstKind::LocalVar(name) = &**arg {
                         let var_name = name.trim_start_matches('$');
-                        bytecode.push(Instruction::PushIntLocal(format!("arg{}", arg_index)));
-                        bytecode.push(Instruction::PopIntLocal(var_name.to_string()));
-                        if param_name.is_none() {
+                        // String arguments arrive in string locals.
+                        let is_string = self.is_string_local(var_name);
+                        let push_arg = if is_string { Instruction::PushStringLocal } else { Instruction::PushIntLocal };
+                        if let Some(Some(default)) = defaults.get(arg_index) {
+                            // Use the default when the caller passed no more
+                            // than arg_index arguments.
+                            bytecode.push(Instruction::PushIntLocal(ARG_COUNT_LOCAL.to_string()));
+                            bytecode.push(Instruction::PushConstantInt(arg_index as i32));
+                            let given_pos = bytecode.instructions.len();
+                            bytecode.push(Instruction::BranchGreaterThan(0)); // Placeholder
+                            self.compile_node(default, &mut bytecode);
+                            let jump_pos = bytecode.instructions.len();
+                            bytecode.push(Instruction::Jump(0)); // Placeholder
+                            bytecode.instructions[given_pos] = Instruction::BranchGreaterThan(bytecode.instructions.len());
+                            bytecode.push(push_arg(format!("arg{}", arg_index)));
+                            bytecode.instructions[jump_pos] = Instruction::Jump(bytecode.instructions.len());
+                        } else {
+                            bytecode.push(push_arg(format!("arg{}", arg_index)));
+                        }
+                        if is_string {
+                            bytecode.push(Instruction::PopStringLocal(var_name.to_string()));
+                        } else {
+                            bytecode.push(Instruction::PopIntLocal(var_name.to_string()));
+                        }
+                        if param_name.is_none() && !is_string {
                             param_name = Some(var_name.to_string());
                         }
                         arg_index += 1;
                     }
                 }
                 
+                let body = if self.opt_level >= 1 {
+                    let before = self.call_folding.folded();
+                    let folded = self.call_folding.run(body);
+                    let count = self.call_folding.folded() - before;
+                    if count > 0 {
+                        println!("Folded {} constant script call(s)", count);
+                    }
+                    let mut conditions = ConstantConditions::new();
+                    let folded = conditions.run(&folded);
+                    if conditions.removed() > 0 {
+                        println!("Removed {} constant condition(s)", conditions.removed());
+                    }
+                    Box::new(folded)
+                } else {
+                    body.clone()
+                };
+
                 // Check if this is a recursive function and transform it if needed
                 let transformed_body = if let Some(param) = param_name {
                     println!("Found parameter '{}' from procedure declaration", param);
-                    self.transform_recursive_to_iterative_with_param(body, param)
+                    self.transform_recursive_to_iterative_with_param(&body, param)
                 } else {
                     println!("No parameter found in procedure declaration");
-                    (**body).clone()
+                    (*body).clone()
+                };
+                // The transform leaves recursion of any other shape as it is.
+                if self.contains_recursive_call(&transformed_body) {
+                    self.warn("calls itself in a way the compiler can't turn into a loop, so it runs recursively, at most max_call_depth calls deep".to_string());
+                }
+                
+                let transformed_body = if self.opt_level >= 1 {
+                    let mut licm = LoopInvariantMotion::new();
+                    let optimized = licm.run(&transformed_body);
+                    if licm.hoisted() > 0 {
+                        println!("Hoisted {} loop-invariant expression(s)", licm.hoisted());
+                    }
+                    optimized
+                } else {
+                    transformed_body
                 };
                 
                 self.compile_node(&transformed_body, &mut bytecode);
@@ -78,7 +245,513 @@ impl Compiler {
         self.current_script = None;
         
         self.scripts.insert(name, bytecode.clone());
-        bytecode
+        Ok(bytecode)
+    }
+
+    /// Remembers a script that is `@deprecated`, so calls to it are warned
+    /// about from then on.
+    fn note_deprecation(&mut self, node: &AstKind) {
+        if let (AstKind::Trigger { annotations, .. }, Some(name)) = (node, node.script_name()) {
+            match annotations::deprecation(annotations) {
+                Some(advice) => self.deprecated.insert(name, advice.to_string()),
+                None => self.deprecated.remove(&name),
+            };
+        }
+    }
+
+    /// Warns about annotations that can't be used and about each call to a
+    /// `@deprecated` script. Runs before optimisation, so calls that get
+    /// folded away are still reported.
+    fn check_annotations(&mut self, ast: &AstKind) {
+        let AstKind::Trigger { annotations, .. } = ast else {
+            return;
+        };
+        for problem in annotations::overrides(annotations).1 {
+            self.warn(problem);
+        }
+        self.note_deprecation(ast);
+        for (callee, line) in lints::script_calls(ast) {
+            let Some(advice) = self.deprecated.get(&callee) else {
+                continue;
+            };
+            let line = line.map(|line| format!(" at line {}", line)).unwrap_or_default();
+            let advice = if advice.is_empty() { String::new() } else { format!(": {}", advice) };
+            self.warn(format!("call to deprecated script '{}'{}{}", callee, line, advice));
+        }
+    }
+
+    /// Warns about `for` loops whose step never changes the loop variable.
+    fn check_loop_steps(&mut self, node: &AstKind) {
+        match node {
+            AstKind::For { step, body, .. } => {
+                if let AstKind::Assignment { target, value } = &**step {
+                    if let AstKind::LocalVar(name) = &**target {
+                        if step_delta(value, name.trim_start_matches('$')) == Some(0) {
+                            self.warn(format!(
+                                "for loop step changes ${} by 0, which is likely an infinite loop",
+                                name.trim_start_matches('$')
+                            ));
+                        }
+                    }
+                }
+                self.check_loop_steps(body);
+            }
+            AstKind::Trigger { body, .. } | AstKind::While { body, .. } => self.check_loop_steps(body),
+            AstKind::If { value, .. } => self.check_loop_steps(value),
+            AstKind::Block(statements) => {
+                for stmt in statements {
+                    self.check_loop_steps(stmt);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Warns about the two ways `calc` is commonly misused: around a lone
+    /// comparison where a value is wanted, as in `$a = calc($b = $c)`, which
+    /// compares rather than assigns; and missing from arithmetic in a
+    /// condition, as in `if ($a + 1 > $b)`. Runs after `check_types`, so
+    /// string `+` is told apart from arithmetic.
+    fn check_calc_usage(&mut self, node: &AstKind) {
+        match node {
+            AstKind::Trigger { body, .. } => self.check_calc_usage(body),
+            AstKind::While { condition, body } => {
+                self.check_condition_calc(condition);
+                self.check_calc_usage(body);
+            }
+            AstKind::If { expression, value, return_statement } => {
+                self.check_condition_calc(expression);
+                self.check_calc_usage(value);
+                self.check_calc_usage(return_statement);
+            }
+            AstKind::Block(statements) => {
+                for stmt in statements {
+                    self.check_calc_usage(stmt);
+                }
+            }
+            AstKind::Define { value, .. } | AstKind::Assignment { value, .. } | AstKind::Return(value) => {
+                self.check_value_calc(value)
+            }
+            node => self.check_value_calc(node),
+        }
+    }
+
+    fn check_value_calc(&mut self, node: &AstKind) {
+        match node {
+            AstKind::FunctionCall { name, arguments } => {
+                if let [argument] = &arguments[..] {
+                    if let AstKind::BinaryExpression { operator, lhs, .. } = &**argument {
+                        if name == "calc" && is_comparison(operator) {
+                            let mut warning = format!("calc() around a '{}' comparison gives 0 or 1, not a number", operator);
+                            if let (AstKind::LocalVar(var), "=") = (&**lhs, operator.as_str()) {
+                                let var = var.trim_start_matches('$');
+                                warning.push_str(&format!("; to assign ${}, write `${} = ...;` on its own", var, var));
+                            }
+                            self.warn(warning);
+                        }
+                    }
+                }
+                for arg in arguments {
+                    self.check_value_calc(arg);
+                }
+            }
+            AstKind::BinaryExpression { lhs, rhs, .. } => {
+                self.check_value_calc(lhs);
+                self.check_value_calc(rhs);
+            }
+            AstKind::ConditionalExpression { lhs, rhs, value } => {
+                self.check_condition_calc(lhs);
+                self.check_value_calc(value);
+                self.check_value_calc(rhs);
+            }
+            AstKind::ScriptCall { arguments, .. } => {
+                for arg in arguments {
+                    self.check_value_calc(arg);
+                }
+            }
+            AstKind::Cast { expression, .. } => self.check_value_calc(expression),
+            _ => {}
+        }
+    }
+
+    fn check_condition_calc(&mut self, condition: &AstKind) {
+        let operands = match condition {
+            AstKind::BinaryExpression { operator, lhs, rhs } if is_comparison(operator) => vec![&**lhs, &**rhs],
+            // A comparison in calc is fine as a whole condition.
+            AstKind::FunctionCall { name, arguments } if name == "calc" => arguments.iter().map(|arg| &**arg).collect(),
+            condition => vec![condition],
+        };
+        for operand in operands {
+            match operand {
+                AstKind::BinaryExpression { operator, .. } if is_arithmetic(operator) && !self.is_string_expression(operand) => {
+                    self.warn(format!("arithmetic '{}' in a condition without calc; wrap it as calc(...)", operator));
+                }
+                operand => self.check_value_calc(operand),
+            }
+        }
+    }
+
+    /// Prints a warning about the current script and collects it in `warnings`.
+    fn warn(&mut self, message: String) {
+        let warning = format!("{} (in script '{}')", message, self.current_script.as_deref().unwrap_or("<unknown>"));
+        println!("Warning: {}", warning);
+        self.warnings.push(warning);
+    }
+
+    /// Checks every conversion in a script against the coercion table before
+    /// any bytecode is emitted. Forbidden conversions are errors; warned ones
+    /// are printed and collected in `warnings`.
+    fn check_types(&mut self, ast: &AstKind) -> Result<(), CompilerError> {
+        self.local_types.clear();
+
+        let AstKind::Trigger { body, args, defaults, .. } = ast else {
+            return self.check_statement(ast, None);
+        };
+
+        for (index, pair) in args.chunks(2).enumerate() {
+            if let [arg_type, arg_name] = pair {
+                if let (AstKind::Identifier(type_name), AstKind::LocalVar(var_name)) = (&**arg_type, &**arg_name) {
+                    if let Some(var_type) = Type::from_name(type_name) {
+                        let var_name = var_name.trim_start_matches('$').to_string();
+                        if let Some(Some(default)) = defaults.get(index) {
+                            self.check_value(default, &var_type, &format!("default of ${}", var_name))?;
+                        }
+                        self.local_types.insert(var_name, var_type);
+                    }
+                }
+            }
+        }
+
+        self.check_statement(body, ast.return_type().as_ref())
+    }
+
+    fn check_statement(&mut self, node: &AstKind, return_type: Option<&Type>) -> Result<(), CompilerError> {
+        match node {
+            AstKind::Define { name, var_type, value } => {
+                let var_name = name.trim_start_matches('$').to_string();
+                self.check_value(value, var_type, &format!("initializer of ${}", var_name))?;
+                self.local_types.insert(var_name, var_type.clone());
+            }
+            AstKind::Assignment { target, value } => match &**target {
+                AstKind::LocalVar(name) => {
+                    let var_name = name.trim_start_matches('$');
+                    match self.local_types.get(var_name).cloned() {
+                        Some(var_type) => self.check_value(value, &var_type, &format!("assignment to ${}", var_name))?,
+                        None => {
+                            self.infer_type(value)?;
+                        }
+                    }
+                }
+                AstKind::Varp { name, .. } => {
+                    if let Some(varp_type) = self.infer_type(target)? {
+                        self.check_value(value, &varp_type, &format!("assignment to %{}", name))?;
+                    }
+                }
+                _ => {
+                    self.infer_type(value)?;
+                }
+            },
+            AstKind::If { expression, value, return_statement } => {
+                self.check_condition(expression)?;
+                self.check_statement(value, return_type)?;
+                self.check_statement(return_statement, return_type)?;
+            }
+            AstKind::While { condition, body } => {
+                self.check_condition(condition)?;
+                self.check_statement(body, return_type)?;
+            }
+            AstKind::Block(statements) => {
+                for stmt in statements {
+                    self.check_statement(stmt, return_type)?;
+                }
+            }
+            AstKind::Return(expr) => match return_type {
+                Some(return_type) => self.check_value(expr, return_type, "return value")?,
+                None => {
+                    self.infer_type(expr)?;
+                }
+            },
+            _ => {
+                self.infer_type(node)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a value stored as `to`: `null` only where `to` is nullable, an
+    /// int literal as a colour only if it fits in 24 bits, and anything else
+    /// by the coercion table.
+    fn check_value(&mut self, value: &AstKind, to: &Type, context: &str) -> Result<(), CompilerError> {
+        if let AstKind::Null = value {
+            if !to.is_nullable() {
+                return Err(self.type_error(format!("{} can't be null; {} is not nullable", context, to)));
+            }
+            return Ok(());
+        }
+        if let (AstKind::NumericLiteral(value), Type::Color) = (value, to) {
+            return self.check_colour_literal(*value, context);
+        }
+        if let Some(value_type) = self.infer_type(value)? {
+            self.check_coercion(&value_type, to, context)?;
+        }
+        Ok(())
+    }
+
+    fn check_colour_literal(&self, value: i32, context: &str) -> Result<(), CompilerError> {
+        if colour::is_valid(value) {
+            return Ok(());
+        }
+        Err(self.type_error(format!(
+            "{} is 0x{:X}, outside the 24-bit colour range 0x000000..0xFFFFFF",
+            context, value as u32
+        )))
+    }
+
+    fn check_condition(&mut self, condition: &AstKind) -> Result<(), CompilerError> {
+        if let Some(condition_type) = self.infer_type(condition)? {
+            if self.strict_conditions && condition_type != Type::Boolean {
+                return Err(self.type_error(format!(
+                    "condition is {}, not a comparison or boolean (strict_conditions is on)",
+                    condition_type
+                )));
+            }
+            self.check_coercion(&condition_type, &Type::Boolean, "condition")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the static type of an expression, or `None` when it can't be
+    /// known yet (script calls, bare identifiers).
+    fn infer_type(&mut self, node: &AstKind) -> Result<Option<Type>, CompilerError> {
+        match node {
+            AstKind::NumericLiteral(_) => Ok(Some(Type::Int)),
+            AstKind::StringLiteral(_) => Ok(Some(Type::String)),
+            AstKind::LocalVar(name) => Ok(self.local_types.get(name.trim_start_matches('$')).cloned()),
+            AstKind::Varp { id, bits: None, .. } => Ok(Some(self.symbols.varp_type(*id))),
+            AstKind::Varp { .. } => Ok(Some(Type::Int)),
+            AstKind::Cast { target, expression } => {
+                if let Some(from) = self.infer_type(expression)? {
+                    if !from.can_cast_to(target) {
+                        return Err(self.type_error(format!(
+                            "cannot cast {} to {}",
+                            from, target
+                        )));
+                    }
+                }
+                Ok(Some(target.clone()))
+            }
+            AstKind::ConditionalExpression { lhs, rhs, value } => {
+                self.check_condition(lhs)?;
+                let value_type = self.infer_type(value)?;
+                let rhs_type = self.infer_type(rhs)?;
+                // Both branches leave their value in the same place, so the
+                // `:` branch must fit the type of the `?` one.
+                if let (Some(value_type), Some(rhs_type)) = (&value_type, &rhs_type) {
+                    self.check_coercion(rhs_type, value_type, "branch after ':'")?;
+                }
+                Ok(value_type.or(rhs_type))
+            }
+            AstKind::BinaryExpression { lhs, rhs, operator } => {
+                let lhs_type = self.infer_type(lhs)?;
+                let rhs_type = self.infer_type(rhs)?;
+                if matches!(**lhs, AstKind::Null) || matches!(**rhs, AstKind::Null) {
+                    if operator != "=" {
+                        return Err(self.type_error(format!("'{}' is not defined for null; null only compares with '='", operator)));
+                    }
+                    if let Some(other) = [lhs_type, rhs_type].into_iter().flatten().find(|ty| !ty.is_nullable()) {
+                        return Err(self.type_error(format!("cannot compare {} with null; {} is not nullable", other, other)));
+                    }
+                    return Ok(Some(Type::Boolean));
+                }
+                let handle = [&lhs_type, &rhs_type].into_iter().flatten().find(|ty| symbols::is_handle(ty)).cloned();
+                if let Some(handle) = handle {
+                    // Handles are ids into different config tables, so they
+                    // only compare for equality with handles of their own type.
+                    if operator != "=" {
+                        return Err(self.type_error(format!(
+                            "'{}' is not defined for {}; handles only compare with '='",
+                            operator, handle
+                        )));
+                    }
+                    if let Some(other) = [lhs_type, rhs_type].into_iter().flatten().find(|ty| *ty != handle) {
+                        return Err(self.type_error(format!(
+                            "cannot compare {} with {}; handles only compare with handles of the same type",
+                            handle, other
+                        )));
+                    }
+                    return Ok(Some(Type::Boolean));
+                }
+                match operator.as_str() {
+                    "=" => {
+                        match (&**lhs, &**rhs, lhs_type, rhs_type) {
+                            (_, AstKind::NumericLiteral(value), Some(Type::Color), _)
+                            | (AstKind::NumericLiteral(value), _, _, Some(Type::Color)) => {
+                                self.check_colour_literal(*value, "operand of '='")?;
+                            }
+                            (_, _, Some(lhs_type), Some(rhs_type)) => {
+                                self.check_coercion(&rhs_type, &lhs_type, "operand of '='")?;
+                            }
+                            _ => {}
+                        }
+                        Ok(Some(Type::Boolean))
+                    }
+                    "+" if lhs_type == Some(Type::String) || rhs_type == Some(Type::String) => {
+                        let context = "operand of string '+'";
+                        for operand_type in [lhs_type, rhs_type].into_iter().flatten() {
+                            self.check_coercion(&operand_type, &Type::String, context)?;
+                        }
+                        Ok(Some(Type::String))
+                    }
+                    _ => {
+                        let context = format!("operand of '{}'", operator);
+                        for operand_type in [lhs_type, rhs_type].into_iter().flatten() {
+                            self.check_coercion(&operand_type, &Type::Int, &context)?;
+                        }
+                        if matches!(operator.as_str(), "+" | "-" | "*" | "/" | "%") {
+                            Ok(Some(Type::Int))
+                        } else {
+                            Ok(Some(Type::Boolean))
+                        }
+                    }
+                }
+            }
+            AstKind::FunctionCall { name, arguments } if name == "enum_getvalue" => {
+                let [enum_name, key] = &arguments[..] else {
+                    return Err(self.type_error(format!(
+                        "enum_getvalue() takes 2 argument(s) but {} were given",
+                        arguments.len()
+                    )));
+                };
+                let AstKind::Identifier(enum_name) = &**enum_name else {
+                    return Err(self.type_error("the first argument of enum_getvalue() must name an enum".to_string()));
+                };
+                let Some(config) = self.symbols.enum_config(enum_name) else {
+                    let mut message = format!("unknown enum '{}'", enum_name);
+                    if let Some(suggestion) = self.symbols.suggest_enum(enum_name) {
+                        message.push_str(&format!("; did you mean '{}'?", suggestion));
+                    }
+                    return Err(self.type_error(message));
+                };
+                let (key_type, value_type) = (config.key_type.clone(), config.value_type.clone());
+                if let Some(found) = self.infer_type(key)? {
+                    self.check_coercion(&found, &key_type, &format!("key of enum '{}'", enum_name))?;
+                }
+                Ok(Some(value_type))
+            }
+            AstKind::FunctionCall { name, arguments } if params::signature(name).is_some() => {
+                // The parser has checked the argument count.
+                let (params, _) = params::signature(name).unwrap_or((&[], Type::Int));
+                self.check_value(&arguments[0], &params[0], &format!("argument of {}()", name))?;
+                let AstKind::Identifier(param) = &*arguments[1] else {
+                    return Err(self.type_error(format!("the second argument of {}() must name a param", name)));
+                };
+                match self.symbols.params().config(param) {
+                    Some(config) => Ok(Some(config.ty.clone())),
+                    None => {
+                        let mut message = format!("unknown param '{}'", param);
+                        if let Some(suggestion) = self.symbols.suggest_param(param) {
+                            message.push_str(&format!("; did you mean '{}'?", suggestion));
+                        }
+                        Err(self.type_error(message))
+                    }
+                }
+            }
+            AstKind::FunctionCall { name, arguments } if name == "db_find" || name == "db_getfield" => {
+                // The parser has checked the argument counts.
+                let (params, return_type) = db::signature(name).unwrap_or((&[], Type::Int));
+                for (arg, param_type) in arguments.iter().zip(params) {
+                    self.check_value(arg, param_type, &format!("argument of {}()", name))?;
+                }
+                // Past a named column, the value and result take its type.
+                let Some(column) = constant_id(&arguments[1]).and_then(|id| self.symbols.db_column(id)).cloned() else {
+                    return Ok(Some(return_type));
+                };
+                if name == "db_getfield" {
+                    if column.ty == Type::String {
+                        return Err(self.type_error(format!("db_getfield() can't read string column '{}' yet", column.name)));
+                    }
+                    return Ok(Some(column.ty));
+                }
+                if let Some(table) = constant_id(&arguments[0]).and_then(|id| self.symbols.db_table(id)) {
+                    if table.column(&column.name).is_none() {
+                        let message = format!("table '{}' has no column '{}'", table.name, column.name);
+                        return Err(self.type_error(message));
+                    }
+                }
+                self.check_value(&arguments[2], &column.ty, &format!("value of column '{}'", column.name))?;
+                Ok(Some(return_type))
+            }
+            AstKind::FunctionCall { name, arguments } => {
+                // Stat and inventory commands go to the host but still have
+                // fixed types.
+                if let Some((params, return_type)) = builtin_signature(name).or_else(|| host::signature(name)) {
+                    for (arg, param_type) in arguments.iter().zip(params) {
+                        self.check_value(arg, param_type, &format!("argument of {}()", name))?;
+                    }
+                    return Ok(Some(return_type));
+                }
+                for arg in arguments {
+                    let arg_type = self.infer_type(arg)?;
+                    // Host commands take any argument types; only the
+                    // built-in math functions need ints.
+                    if let (Some(arg_type), true) = (arg_type, is_builtin_function(name)) {
+                        self.check_coercion(&arg_type, &Type::Int, &format!("argument of {}()", name))?;
+                    }
+                }
+                Ok(Some(Type::Int))
+            }
+            AstKind::ScriptCall { arguments, .. } => {
+                for arg in arguments {
+                    self.infer_type(arg)?;
+                }
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn check_coercion(&mut self, from: &Type, to: &Type, context: &str) -> Result<(), CompilerError> {
+        match from.coercion_to(to) {
+            Coercion::Allowed => Ok(()),
+            Coercion::Warn => {
+                // stat(...) is the stat command, so stats can't be cast to.
+                let fix = if host::signature(to.name()).is_some() {
+                    format!("name the {} instead", to)
+                } else {
+                    format!("use {}(...) to make it explicit", to)
+                };
+                let warning = format!(
+                    "implicit conversion from {} to {} in {}; {} (in script '{}')",
+                    from,
+                    to,
+                    context,
+                    fix,
+                    self.current_script.as_deref().unwrap_or("<unknown>")
+                );
+                println!("Warning: {}", warning);
+                self.warnings.push(warning);
+                Ok(())
+            }
+            Coercion::Explicit => Err(self.type_error(format!(
+                "cannot use {} as {} in {} without a conversion; use {}(...) to convert it",
+                from, to, context, to
+            ))),
+            Coercion::Forbidden => {
+                let mut message = format!("cannot convert {} to {} in {}", from, to, context);
+                if let Some(command) = convert::command_for(from, to) {
+                    message.push_str(&format!("; use {}(...) to convert it", command));
+                }
+                Err(self.type_error(message))
+            }
+        }
+    }
+
+    fn type_error(&self, message: String) -> CompilerError {
+        CompilerError::Type(format!(
+            "{} (in script '{}')",
+            message,
+            self.current_script.as_deref().unwrap_or("<unknown>")
+        ))
     }
 
     fn transform_recursive_to_iterative_with_param(&self, node: &AstKind, param_name: String) -> AstKind {
@@ -122,6 +795,14 @@ impl Compiler {
                     println!("No recursion or base cases found, skipping transformation");
                     return node.clone();
                 }
+                if base_cases.iter().any(|base_case| self.contains_recursive_call(base_case)) {
+                    println!("Found recursion in a base case, skipping transformation");
+                    return node.clone();
+                }
+                if !recursive_expr.as_ref().is_some_and(|expr| is_supported_recursion(expr, &current_script, &param_name)) {
+                    println!("Recursive expression matches no supported pattern, skipping transformation");
+                    return node.clone();
+                }
 
                 println!("Starting transformation to iterative form...");
                 let mut new_statements = Vec::new();
@@ -206,7 +887,7 @@ impl Compiler {
                                 rhs: Box::new(AstKind::NumericLiteral(1)),
                                 operator: "<=".to_string(),
                             }),
-                            value: Box::new(AstKind::LocalVar("acc".to_string())),
+                            value: Box::new(AstKind::Block(Vec::new())),
                             return_statement: Box::new(AstKind::Return(Box::new(AstKind::LocalVar("acc".to_string())))),
                         });
 
@@ -454,7 +1135,7 @@ impl Compiler {
                                     rhs: Box::new(AstKind::NumericLiteral(0)),
                                     operator: "=".to_string(),
                                 }),
-                                value: Box::new(AstKind::NumericLiteral(0)),
+                                value: Box::new(AstKind::Block(Vec::new())),
                                 return_statement: Box::new(AstKind::Return(Box::new(AstKind::NumericLiteral(0)))),
                             });
 
@@ -464,7 +1145,7 @@ impl Compiler {
                                     rhs: Box::new(AstKind::NumericLiteral(1)),
                                     operator: "=".to_string(),
                                 }),
-                                value: Box::new(AstKind::NumericLiteral(1)),
+                                value: Box::new(AstKind::Block(Vec::new())),
                                 return_statement: Box::new(AstKind::Return(Box::new(AstKind::NumericLiteral(1)))),
                             });
 
@@ -474,7 +1155,7 @@ impl Compiler {
                                     rhs: Box::new(AstKind::NumericLiteral(2)),
                                     operator: "=".to_string(),
                                 }),
-                                value: Box::new(AstKind::NumericLiteral(1)),
+                                value: Box::new(AstKind::Block(Vec::new())),
                                 return_statement: Box::new(AstKind::Return(Box::new(AstKind::NumericLiteral(1)))),
                             });
 
@@ -641,13 +1322,13 @@ impl Compiler {
 
     fn contains_recursive_call(&self, node: &AstKind) -> bool {
         match node {
-            AstKind::ScriptCall { script, .. } => {
+            AstKind::ScriptCall { script, arguments } => {
                 if let AstKind::Identifier(script_name) = &**script {
-                    if let Some(current_script) = &self.current_script {
-                        return script_name == current_script;
+                    if self.current_script.as_ref() == Some(script_name) {
+                        return true;
                     }
                 }
-                false
+                arguments.iter().any(|arg| self.contains_recursive_call(arg))
             }
             AstKind::Block(statements) => {
                 statements.iter().any(|stmt| self.contains_recursive_call(stmt))
@@ -671,26 +1352,89 @@ impl Compiler {
                 self.contains_recursive_call(lhs) ||
                 self.contains_recursive_call(rhs)
             }
+            AstKind::ConditionalExpression { lhs, rhs, value } => {
+                self.contains_recursive_call(lhs) ||
+                self.contains_recursive_call(value) ||
+                self.contains_recursive_call(rhs)
+            }
             AstKind::FunctionCall { arguments, .. } => {
                 arguments.iter().any(|arg| self.contains_recursive_call(arg))
             }
+            AstKind::Cast { expression, .. } => self.contains_recursive_call(expression),
+            _ => false,
+        }
+    }
+
+    fn is_string_local(&self, name: &str) -> bool {
+        self.local_types.get(name) == Some(&Type::String)
+    }
+
+    fn is_string_expression(&self, node: &AstKind) -> bool {
+        match node {
+            AstKind::StringLiteral(_) => true,
+            AstKind::LocalVar(name) => self.is_string_local(name.trim_start_matches('$')),
+            AstKind::FunctionCall { name, .. } => {
+                returns_string(name) || param_called(node, &self.symbols).is_some_and(|config| config.ty == Type::String)
+            }
+            AstKind::BinaryExpression { lhs, rhs, operator } if operator == "+" => {
+                self.is_string_expression(lhs) || self.is_string_expression(rhs)
+            }
+            AstKind::ConditionalExpression { rhs, value, .. } => {
+                self.is_string_expression(value) || self.is_string_expression(rhs)
+            }
             _ => false,
         }
     }
 
+    /// Flattens a chain of string `+` into its parts, left to right.
+    fn collect_string_parts<'a>(&self, node: &'a AstKind, parts: &mut Vec<&'a AstKind>) {
+        match node {
+            AstKind::BinaryExpression { lhs, rhs, operator } if operator == "+" && self.is_string_expression(node) => {
+                self.collect_string_parts(lhs, parts);
+                self.collect_string_parts(rhs, parts);
+            }
+            _ => parts.push(node),
+        }
+    }
+
     fn compile_node(&mut self, node: &AstKind, bytecode: &mut ByteCode) {
         match node {
             AstKind::NumericLiteral(n) => {
                 bytecode.push(Instruction::PushConstantInt(*n));
             }
             
+            AstKind::Null => {
+                bytecode.push(Instruction::PushConstantInt(NULL));
+            }
+
             AstKind::StringLiteral(s) => {
-                bytecode.push(Instruction::PushConstantString(s.clone()));
+                let index = bytecode.add_string(s.clone());
+                bytecode.push(Instruction::PushStringConstant(index));
             }
             
             AstKind::LocalVar(name) => {
                 let var_name = name.trim_start_matches('$');
-                bytecode.push(Instruction::PushIntLocal(var_name.to_string()));
+                if self.is_string_local(var_name) {
+                    bytecode.push(Instruction::PushStringLocal(var_name.to_string()));
+                } else {
+                    bytecode.push(Instruction::PushIntLocal(var_name.to_string()));
+                }
+            }
+
+            AstKind::Varp { id, bits, .. } => match bits {
+                Some((start_bit, end_bit)) => bytecode.push(Instruction::PushVarbit(*id, *start_bit, *end_bit)),
+                None => bytecode.push(Instruction::PushVarp(*id)),
+            },
+
+            AstKind::BinaryExpression { operator, .. } if operator == "+" && self.is_string_expression(node) => {
+                // a + b + c joins all three parts with one instruction
+                let mut parts = Vec::new();
+                self.collect_string_parts(node, &mut parts);
+                let count = parts.len();
+                for part in parts {
+                    self.compile_node(part, bytecode);
+                }
+                bytecode.push(Instruction::JoinString(count));
             }
             
             AstKind::BinaryExpression { lhs, rhs, operator } => {
@@ -733,52 +1477,75 @@ impl Compiler {
                     "+" => bytecode.push(Instruction::Add),
                     "-" => bytecode.push(Instruction::Subtract),
                     "*" => bytecode.push(Instruction::Multiply),
+                    "/" => bytecode.push(Instruction::Divide),
+                    "%" => bytecode.push(Instruction::Modulo),
                     _ => panic!("Unsupported operator: {}", operator),
                 }
             }
+
+            AstKind::ConditionalExpression { lhs, rhs, value } => {
+                self.compile_node(lhs, bytecode);
+
+                // Go to the ':' branch when the condition is false
+                let else_index = bytecode.instructions.len();
+                bytecode.push(Instruction::BranchNot(0)); // Placeholder
+
+                self.compile_node(value, bytecode);
+                let end_index = bytecode.instructions.len();
+                bytecode.push(Instruction::Jump(0)); // Placeholder
+
+                bytecode.instructions[else_index] = Instruction::BranchNot(bytecode.instructions.len());
+                self.compile_node(rhs, bytecode);
+                bytecode.instructions[end_index] = Instruction::Jump(bytecode.instructions.len());
+            }
             
             AstKind::Assignment { target, value } => {
                 self.compile_node(value, bytecode);
-                if let AstKind::LocalVar(name) = &**target {
-                    let var_name = name.trim_start_matches('$');
-                    bytecode.push(Instruction::PopIntLocal(var_name.to_string()));
+                match &**target {
+                    AstKind::LocalVar(name) => {
+                        let var_name = name.trim_start_matches('$');
+                        if self.is_string_local(var_name) {
+                            bytecode.push(Instruction::PopStringLocal(var_name.to_string()));
+                        } else {
+                            bytecode.push(Instruction::PopIntLocal(var_name.to_string()));
+                        }
+                    }
+                    AstKind::Varp { id, bits: Some((start_bit, end_bit)), .. } => {
+                        bytecode.push(Instruction::PopVarbit(*id, *start_bit, *end_bit));
+                    }
+                    AstKind::Varp { id, bits: None, .. } => bytecode.push(Instruction::PopVarp(*id)),
+                    _ => {}
                 }
             }
             
-            AstKind::Define { name, value, .. } => {
+            AstKind::Define { name, value, var_type } => {
                 self.compile_node(value, bytecode);
                 let var_name = name.trim_start_matches('$');
-                bytecode.push(Instruction::PopIntLocal(var_name.to_string()));
+                if *var_type == Type::String {
+                    bytecode.push(Instruction::PopStringLocal(var_name.to_string()));
+                } else {
+                    bytecode.push(Instruction::PopIntLocal(var_name.to_string()));
+                }
             }
             
             AstKind::If { expression, value, return_statement } => {
                 // Compile the condition
                 self.compile_node(expression, bytecode);
-                
-                // Add branch instruction
+
+                // Skip the body when the condition is false
                 let jump_index = bytecode.instructions.len();
                 bytecode.push(Instruction::BranchNot(0));  // Placeholder jump target
-                
-                // Compile the return statement if it exists
+
+                // Compile the body, then the return statement if it exists
+                self.compile_node(value, bytecode);
                 if let AstKind::Return(expr) = &**return_statement {
                     self.compile_node(expr, bytecode);
                     bytecode.push(Instruction::Return);
                 }
-                
-                // Add jump instruction to skip else block
-                let else_jump_index = bytecode.instructions.len();
-                bytecode.push(Instruction::Jump(0));  // Placeholder jump target
-                
+
                 // Update the branch target
                 let current_len = bytecode.instructions.len();
                 bytecode.instructions[jump_index] = Instruction::BranchNot(current_len);
-                
-                // Compile the value
-                self.compile_node(value, bytecode);
-                
-                // Update the else jump target
-                let current_len = bytecode.instructions.len();
-                bytecode.instructions[else_jump_index] = Instruction::Jump(current_len);
             }
             
             AstKind::While { condition, body } => {
@@ -802,9 +1569,39 @@ impl Compiler {
                 bytecode.instructions[branch_pos] = Instruction::BranchNot(end_pos);
             }
             
+            AstKind::InvariantWhile { invariants, condition, body } => {
+                // Guard once, compute the invariants, then loop with the test at the bottom.
+                self.compile_node(condition, bytecode);
+                let guard_pos = bytecode.instructions.len();
+                bytecode.push(Instruction::BranchNot(0)); // Placeholder for end of loop
+                
+                for invariant in invariants {
+                    self.compile_node(invariant, bytecode);
+                }
+                
+                let loop_start = bytecode.instructions.len();
+                self.compile_node(body, bytecode);
+                self.compile_node(condition, bytecode);
+                let exit_pos = bytecode.instructions.len();
+                bytecode.push(Instruction::BranchNot(0)); // Placeholder for end of loop
+                bytecode.push(Instruction::Jump(loop_start));
+                
+                let end_pos = bytecode.instructions.len();
+                bytecode.instructions[guard_pos] = Instruction::BranchNot(end_pos);
+                bytecode.instructions[exit_pos] = Instruction::BranchNot(end_pos);
+            }
+            
             AstKind::Block(statements) => {
                 for stmt in statements {
                     self.compile_node(stmt, bytecode);
+                    // A command called for its effect leaves an unused result.
+                    if let AstKind::FunctionCall { name, .. } = stmt {
+                        bytecode.push(if returns_string(name) {
+                            Instruction::PopStringDiscard
+                        } else {
+                            Instruction::PopIntDiscard
+                        });
+                    }
                 }
             }
             
@@ -816,22 +1613,10 @@ impl Compiler {
             AstKind::FunctionCall { name, arguments } => {
                 match name.as_str() {
                     "calc" => {
+                        // Arithmetic and comparisons compile as they do
+                        // anywhere else; a comparison leaves 0 or 1.
                         if let Some(arg) = arguments.first() {
-                            if let AstKind::BinaryExpression { lhs, rhs, operator } = &**arg {
-                                self.compile_node(lhs, bytecode);
-                                self.compile_node(rhs, bytecode);
-                                
-                                match operator.as_str() {
-                                    "+" => bytecode.push(Instruction::Add),
-                                    "-" => bytecode.push(Instruction::Subtract),
-                                    "*" => bytecode.push(Instruction::Multiply),
-                                    "/" => bytecode.push(Instruction::Divide),
-                                    _ => panic!("Unknown operator in calc(): {}", operator),
-                                }
-                            } else {
-                                println!("Non-binary expression in calc(): {:?}", arg);
-                                self.compile_node(arg, bytecode);
-                            }
+                            self.compile_node(arg, bytecode);
                         }
                     }
                     "abs" => {
@@ -840,10 +1625,125 @@ impl Compiler {
                             bytecode.push(Instruction::Abs);
                         }
                     }
-                    _ => panic!("Unknown function: {}", name),
+                    "min" | "max" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(if name == "min" { Instruction::Min } else { Instruction::Max });
+                    }
+                    "coord" | "coordx" | "coordz" | "coordlevel" | "distance" | "coord2" | "coord2x" | "coord2y" | "coord3"
+                    | "coord3x" | "coord3y" | "coord3z" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(match name.as_str() {
+                            "coord" => Instruction::PackCoord,
+                            "coordx" => Instruction::CoordX,
+                            "coordz" => Instruction::CoordZ,
+                            "coordlevel" => Instruction::CoordLevel,
+                            "coord2" => Instruction::PackCoord2,
+                            "coord2x" => Instruction::Coord2X,
+                            "coord2y" => Instruction::Coord2Y,
+                            "coord3" => Instruction::PackCoord3,
+                            "coord3x" => Instruction::Coord3X,
+                            "coord3y" => Instruction::Coord3Y,
+                            "coord3z" => Instruction::Coord3Z,
+                            _ => Instruction::CoordDistance,
+                        });
+                    }
+                    "enum_getvalue" => {
+                        if let [enum_name, key] = &arguments[..] {
+                            if let AstKind::Identifier(enum_name) = &**enum_name {
+                                self.compile_node(key, bytecode);
+                                bytecode.push(Instruction::EnumGetValue(enum_name.clone()));
+                            }
+                        }
+                    }
+                    "tostring" | "toint" | "compare" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(match name.as_str() {
+                            "tostring" => Instruction::IntToString,
+                            "toint" => Instruction::StringToInt,
+                            _ => Instruction::CompareStrings,
+                        });
+                    }
+                    "rgb" | "color_component" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(if name == "rgb" { Instruction::Rgb } else { Instruction::ColorComponent });
+                    }
+                    "random" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(Instruction::Random);
+                    }
+                    "settimer" => {
+                        if let [timer, ticks, callback] = &arguments[..] {
+                            if let AstKind::Identifier(callback) = &**callback {
+                                self.compile_node(timer, bytecode);
+                                self.compile_node(ticks, bytecode);
+                                bytecode.push(Instruction::SetTimer(callback.clone()));
+                            }
+                        }
+                    }
+                    "cleartimer" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(Instruction::ClearTimer);
+                    }
+                    "oc_param" | "nc_param" | "struct_param" => {
+                        if let [owner, param] = &arguments[..] {
+                            if let AstKind::Identifier(param) = &**param {
+                                self.compile_node(owner, bytecode);
+                                bytecode.push(match name.as_str() {
+                                    "oc_param" => Instruction::OcParam(param.clone()),
+                                    "nc_param" => Instruction::NcParam(param.clone()),
+                                    _ => Instruction::StructParam(param.clone()),
+                                });
+                            }
+                        }
+                    }
+                    "append" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(Instruction::JoinString(2));
+                    }
+                    "string_length" | "substring" | "string_indexof" | "lowercase" | "uppercase" => {
+                        for arg in arguments {
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(match name.as_str() {
+                            "string_length" => Instruction::StringLength,
+                            "substring" => Instruction::Substring,
+                            "string_indexof" => Instruction::StringIndexOf,
+                            "lowercase" => Instruction::LowerCase,
+                            _ => Instruction::UpperCase,
+                        });
+                    }
+                    _ => {
+                        let mut signature = String::new();
+                        for arg in arguments {
+                            signature.push(if self.is_string_expression(arg) { 's' } else { 'i' });
+                            self.compile_node(arg, bytecode);
+                        }
+                        bytecode.push(Instruction::Command(name.clone(), signature));
+                    }
                 }
             }
             
+            AstKind::SourceLine(line) => bytecode.set_line(*line),
+            
+            AstKind::Cast { expression, .. } => {
+                // Every type is an i32 at runtime, so a cast emits no code of its own.
+                self.compile_node(expression, bytecode);
+            }
+            
             AstKind::ScriptCall { script, arguments } => {
                 // First compile the arguments in order
                 for arg in arguments {
@@ -864,4 +1764,427 @@ impl Compiler {
             _ => {}
         }
     }
-} 
\ No newline at end of file
+} 
+/// How much `$var = value` changes `$var`, when that is a compile-time constant.
+fn step_delta(value: &AstKind, var: &str) -> Option<i32> {
+    match value {
+        AstKind::LocalVar(name) if name.trim_start_matches('$') == var => Some(0),
+        AstKind::FunctionCall { name, arguments } if name == "calc" && arguments.len() == 1 => {
+            step_delta(&arguments[0], var)
+        }
+        AstKind::BinaryExpression { lhs, rhs, operator } => match operator.as_str() {
+            "+" => match (step_delta(lhs, var), constant_value(rhs)) {
+                (Some(delta), Some(amount)) => delta.checked_add(amount),
+                _ => step_delta(rhs, var).zip(constant_value(lhs)).and_then(|(delta, amount)| delta.checked_add(amount)),
+            },
+            "-" => step_delta(lhs, var).zip(constant_value(rhs)).and_then(|(delta, amount)| delta.checked_sub(amount)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `expr`, the recursive `return` of `script`, has a shape
+/// `transform_recursive_to_iterative_with_param` turns into a loop that
+/// computes the same values, `$n` being `param`:
+///
+/// - `~script(calc($n - 1), calc($n * $acc))`, a tail call with an accumulator
+/// - `calc($n * ~script(calc($n - 1)))` or with `+`, in either order, as in
+///   factorial and sum to n
+/// - `calc(~script(calc($n - 1)) + ~script(calc($n - 2)))`, as in Fibonacci
+fn is_supported_recursion(expr: &AstKind, script: &str, param: &str) -> bool {
+    if let AstKind::ScriptCall { script: called, arguments } = expr {
+        let [n, acc] = &arguments[..] else {
+            return false;
+        };
+        let accumulates = match calc_operation(acc) {
+            Some(("*", lhs, rhs)) => matches!((lhs, rhs), (AstKind::LocalVar(_), AstKind::LocalVar(_))) && is_local(lhs, param) != is_local(rhs, param),
+            _ => false,
+        };
+        return **called == AstKind::Identifier(script.to_string()) && is_decrement(n, param, 1) && accumulates;
+    }
+
+    let calls = |node: &AstKind, step: i32| match node {
+        AstKind::ScriptCall { script: called, arguments } => {
+            **called == AstKind::Identifier(script.to_string()) && matches!(&arguments[..], [arg] if is_decrement(arg, param, step))
+        }
+        _ => false,
+    };
+    match calc_operation(expr) {
+        Some(("+", lhs, rhs)) if (calls(lhs, 1) && calls(rhs, 2)) || (calls(lhs, 2) && calls(rhs, 1)) => true,
+        Some(("+" | "*", lhs, rhs)) => (is_local(lhs, param) && calls(rhs, 1)) || (calls(lhs, 1) && is_local(rhs, param)),
+        _ => false,
+    }
+}
+
+/// The operator and operands of `calc(lhs op rhs)`.
+fn calc_operation(node: &AstKind) -> Option<(&str, &AstKind, &AstKind)> {
+    match node {
+        AstKind::FunctionCall { name, arguments } if name == "calc" => match &arguments[..] {
+            [arg] => match &**arg {
+                AstKind::BinaryExpression { lhs, rhs, operator } => Some((operator.as_str(), &**lhs, &**rhs)),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_local(node: &AstKind, var: &str) -> bool {
+    matches!(node, AstKind::LocalVar(name) if name.trim_start_matches('$') == var)
+}
+
+/// Whether `node` is `calc($var - step)`.
+fn is_decrement(node: &AstKind, var: &str, step: i32) -> bool {
+    matches!(calc_operation(node), Some(("-", lhs, AstKind::NumericLiteral(n))) if is_local(lhs, var) && *n == step)
+}
+
+/// The value of `node` when it is a literal, or `calc` or arithmetic of
+/// literals that doesn't overflow or divide by zero.
+pub fn constant_value(node: &AstKind) -> Option<i32> {
+    match node {
+        AstKind::NumericLiteral(n) => Some(*n),
+        AstKind::FunctionCall { name, arguments } if name == "calc" && arguments.len() == 1 => constant_value(&arguments[0]),
+        AstKind::BinaryExpression { lhs, rhs, operator } => {
+            let (lhs, rhs) = (constant_value(lhs)?, constant_value(rhs)?);
+            match operator.as_str() {
+                "+" => lhs.checked_add(rhs),
+                "-" => lhs.checked_sub(rhs),
+                "*" => lhs.checked_mul(rhs),
+                "/" => lhs.checked_div(rhs),
+                "%" => lhs.checked_rem(rhs),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+pub fn is_comparison(operator: &str) -> bool {
+    matches!(operator, "=" | "<" | ">" | "<=" | ">=")
+}
+
+fn is_arithmetic(operator: &str) -> bool {
+    matches!(operator, "+" | "-" | "*" | "/" | "%")
+}
+
+/// The id of a handle written by name, which `SymbolTable::resolve` turned
+/// into a cast literal.
+fn constant_id(node: &AstKind) -> Option<i32> {
+    match node {
+        AstKind::Cast { expression, .. } => constant_value(expression),
+        _ => None,
+    }
+}
+
+/// Functions the compiler implements itself; any other call goes to the host.
+pub(crate) fn is_builtin_function(name: &str) -> bool {
+    matches!(name, "calc" | "abs" | "enum_getvalue") || builtin_signature(name).is_some()
+}
+
+/// Parameter and return types of the built-in commands that have fixed ones.
+pub(crate) fn builtin_signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "min" | "max" => Some((&[Type::Int, Type::Int], Type::Int)),
+        _ => coord::signature(name)
+            .or_else(|| convert::signature(name))
+            .or_else(|| strings::signature(name))
+            .or_else(|| random::signature(name))
+            .or_else(|| colour::signature(name))
+            .or_else(|| scheduler::signature(name))
+            .or_else(|| params::signature(name)),
+    }
+}
+
+/// The param an `oc_param`, `nc_param` or `struct_param` call reads, if it
+/// names a loaded one.
+pub(crate) fn param_called<'a>(node: &AstKind, symbols: &'a SymbolTable) -> Option<&'a ParamConfig> {
+    match node {
+        AstKind::FunctionCall { name, arguments } if params::signature(name).is_some() => match arguments.get(1).map(|arg| &**arg) {
+            Some(AstKind::Identifier(param)) => symbols.params().config(param),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Built-ins whose result depends only on their arguments, which may be
+/// hoisted, folded and memoized: all but `random`, which advances the
+/// generator, and the timer commands.
+pub(crate) fn is_pure_builtin(name: &str) -> bool {
+    is_builtin_function(name) && !matches!(name, "random" | "settimer" | "cleartimer")
+}
+
+/// Whether a call to `name` leaves its result on the string stack.
+pub(crate) fn returns_string(name: &str) -> bool {
+    matches!(builtin_signature(name).or_else(|| host::signature(name)), Some((_, Type::String)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn allowed_coercion_compiles_without_a_warning() {
+        let mut compiler = Compiler::new();
+        test_support::compile_with(&mut compiler, "[proc,flag]()(boolean)\ndef_int $x = 1;\nreturn($x);\n");
+        assert!(compiler.warnings().is_empty(), "{:?}", compiler.warnings());
+    }
+
+    #[test]
+    fn int_to_id_coercion_compiles_with_a_warning() {
+        let mut compiler = Compiler::new();
+        test_support::compile_with(&mut compiler, "[proc,guard]()(int)\ndef_npc $n = 5;\nreturn(0);\n");
+        assert_eq!(
+            compiler.warnings(),
+            ["implicit conversion from int to npc in initializer of $n; use npc(...) to make it explicit (in script 'guard')"]
+        );
+    }
+
+    #[test]
+    fn string_to_int_is_a_type_error() {
+        let error = test_support::try_compile_with(&mut Compiler::new(), "[proc,count]()(int)\ndef_int $x = \"abc\";\nreturn($x);\n").unwrap_err();
+        assert!(matches!(&error, CompilerError::Type(message) if message.starts_with("cannot convert string to int")), "{:?}", error);
+    }
+
+    #[test]
+    fn constant_division_truncates_like_the_vm() {
+        let minus_seven = || Box::new(AstKind::BinaryExpression {
+            lhs: Box::new(AstKind::NumericLiteral(0)),
+            rhs: Box::new(AstKind::NumericLiteral(7)),
+            operator: "-".to_string(),
+        });
+        let by_two = |operator: &str| AstKind::BinaryExpression { lhs: minus_seven(), rhs: Box::new(AstKind::NumericLiteral(2)), operator: operator.to_string() };
+        assert_eq!(constant_value(&by_two("/")), Some(-3));
+        assert_eq!(constant_value(&by_two("%")), Some(-1));
+    }
+
+    #[test]
+    fn descending_for_loop_sums_down_to_one() {
+        let source = "[proc,sum_down](int $n)(int)\ndef_int $total = 0;\ndef_int $i = 0;\nfor ($i = $n; $i > 0; $i = calc($i - 1)) {\n    $total = calc($total + $i);\n}\nreturn($total);\n";
+        let mut compiler = Compiler::new();
+        test_support::compile_with(&mut compiler, source);
+        assert!(compiler.warnings().is_empty(), "{:?}", compiler.warnings());
+        assert_eq!(test_support::run(source, "sum_down", &[10]), Ok(55));
+        assert_eq!(test_support::run(source, "sum_down", &[0]), Ok(0));
+    }
+
+    #[test]
+    fn zero_step_for_loop_warns() {
+        let mut compiler = Compiler::new();
+        test_support::compile_with(
+            &mut compiler,
+            "[proc,stuck](int $n)(int)\ndef_int $i = 0;\nfor ($i = 0; $i < $n; $i = calc($i + 0)) {\n    mes(\"again\");\n}\nreturn($i);\n",
+        );
+        assert_eq!(compiler.warnings(), ["for loop step changes $i by 0, which is likely an infinite loop (in script 'stuck')"]);
+    }
+
+    #[test]
+    fn calc_comparison_yields_one_or_zero() {
+        let source = "[proc,at_least](int $a, int $b)(int)\nreturn(calc($a >= $b));\n\
+            [proc,mixed](int $a)(int)\nreturn(calc(($a > 2) * 10 + 1));\n";
+        let mut evaluator = test_support::evaluator(source);
+        for (args, expected) in [([5, 3], 1), ([3, 3], 1), ([2, 3], 0)] {
+            assert_eq!(test_support::run(source, "at_least", &args), Ok(expected), "{:?}", args);
+            assert_eq!(evaluator.eval_script("at_least", &args), Ok(expected), "{:?}", args);
+        }
+        assert_eq!(test_support::run(source, "mixed", &[3]), Ok(11));
+        assert_eq!(test_support::run(source, "mixed", &[2]), Ok(1));
+    }
+
+    #[test]
+    fn a_possibly_null_npc_compares_with_null_on_both_backends() {
+        let symbols = test_support::symbols(&[("people.npc", "[man]\n[woman]\n"), ("state.varp", "[target]\ntype=npc\n")]);
+        let source = "[proc,find](int $n)(npc)\nif ($n > 0) {\n    return(woman);\n}\nreturn(null);\n\
+            [proc,found](int $n)(int)\ndef_npc $npc = ~find($n);\nif ($npc = null) {\n    return(0);\n}\nreturn(1);\n\
+            [proc,target]()(npc)\nreturn(%target);\n";
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, source);
+        for (n, handle, found) in [(1, 1, 1), (0, -1, 0)] {
+            assert_eq!(vm.run_script("find", &[n]), Ok(handle));
+            assert_eq!(evaluator.eval_script("find", &[n]), Ok(handle));
+            assert_eq!(vm.run_script("found", &[n]), Ok(found));
+            assert_eq!(evaluator.eval_script("found", &[n]), Ok(found));
+        }
+        // A nullable varp reads as null until it is written.
+        assert_eq!(vm.run_script("target", &[]), Ok(-1));
+        assert_eq!(evaluator.eval_script("target", &[]), Ok(-1));
+    }
+
+    #[test]
+    fn null_is_rejected_where_the_type_is_not_nullable() {
+        let cases = [
+            ("[proc,bad](int $n)(int)\nif ($n = null) {\n    return(1);\n}\nreturn(0);\n", "cannot compare int with null; int is not nullable"),
+            ("[proc,bad](npc $n)(int)\nif ($n < null) {\n    return(1);\n}\nreturn(0);\n", "'<' is not defined for null; null only compares with '='"),
+            ("[proc,bad]()(int)\ndef_string $s = null;\nreturn(0);\n", "can't be null; string is not nullable"),
+            ("[proc,bad]()(int)\nreturn(null);\n", "can't be null; int is not nullable"),
+        ];
+        for (source, message) in cases {
+            let error = test_support::try_compile_with(&mut Compiler::new(), source).unwrap_err();
+            assert!(error.to_string().contains(message), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn strict_conditions_reject_a_bare_int_condition() {
+        let comparison = "[proc,double](int $x)(int)\nif (calc($x * 2) > 0) {\n    return(1);\n}\nreturn(0);\n";
+        let bare = "[proc,double](int $x)(int)\nif (calc($x * 2)) {\n    return(1);\n}\nreturn(0);\n";
+        let bare_while = "[proc,count](int $x)(int)\nwhile ($x) {\n    $x = calc($x - 1);\n}\nreturn($x);\n";
+        for strict in [false, true] {
+            let mut compiler = Compiler::new();
+            compiler.set_strict_conditions(strict);
+            assert!(test_support::try_compile_with(&mut compiler, comparison).is_ok());
+        }
+        assert_eq!(test_support::run(bare, "double", &[3]), Ok(1));
+        assert_eq!(test_support::run(bare, "double", &[0]), Ok(0));
+
+        for source in [bare, bare_while] {
+            let mut compiler = Compiler::new();
+            compiler.set_strict_conditions(true);
+            let error = test_support::try_compile_with(&mut compiler, source).unwrap_err();
+            assert!(error.to_string().contains("condition is int, not a comparison or boolean (strict_conditions is on)"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn command_arguments_may_be_commands_and_script_calls() {
+        let source = "[proc,biggest](int $a, int $b)(int)\nreturn(max(abs($a), ~helper($b)));\n\
+            [proc,helper](int $n)(int)\nreturn(calc($n * 2));\n\
+            [proc,nested](int $a)(int)\nreturn(min(max(abs($a), ~helper(abs($a))), ~helper(min($a, 3))));\n";
+        let mut evaluator = test_support::evaluator(source);
+        for (args, expected) in [([-9, 4], 9), ([2, 4], 8)] {
+            assert_eq!(test_support::run(source, "biggest", &args), Ok(expected), "{:?}", args);
+            assert_eq!(evaluator.eval_script("biggest", &args), Ok(expected), "{:?}", args);
+        }
+        // min(max(5, 10), 6)
+        assert_eq!(test_support::run(source, "nested", &[-5]), Ok(-10));
+        assert_eq!(test_support::run(source, "nested", &[5]), Ok(6));
+        assert_eq!(evaluator.eval_script("nested", &[5]), Ok(6));
+
+        let path = std::path::PathBuf::from("bad.rs2");
+        let tokens = crate::lexer::Lexer::new("[proc,bad]()(int)\nreturn(max(1));\n", &path).tokenize().unwrap();
+        let error = crate::parser::Parser::new(tokens, &path).parse().unwrap_err();
+        assert!(error.to_string().contains("max() takes 2 argument(s) but 1 were given"), "{}", error);
+    }
+
+    /// The warnings from compiling `source`.
+    fn warnings(source: &str) -> Vec<String> {
+        let mut compiler = Compiler::new();
+        test_support::compile_with(&mut compiler, source);
+        compiler.warnings().to_vec()
+    }
+
+    #[test]
+    fn calc_around_a_lone_comparison_warns_with_a_suggestion() {
+        let source = "[proc,same](int $a, int $b)(int)\ndef_int $c = 0;\n$c = calc($a = $b);\nreturn($c);\n";
+        assert_eq!(
+            warnings(source),
+            ["calc() around a '=' comparison gives 0 or 1, not a number; to assign $a, write `$a = ...;` on its own (in script 'same')"]
+        );
+    }
+
+    #[test]
+    fn arithmetic_in_a_condition_without_calc_warns() {
+        let source = "[proc,bigger](int $a, int $b)(int)\nif ($a + 1 > $b) {\n    return(1);\n}\nreturn(0);\n";
+        assert_eq!(warnings(source), ["arithmetic '+' in a condition without calc; wrap it as calc(...) (in script 'bigger')"]);
+    }
+
+    #[test]
+    fn clean_calc_usage_does_not_warn() {
+        let source = "[proc,clean](int $a, int $b, string $s)(int)\n\
+            def_int $i = 0;\n\
+            if (calc($a + 1) > $b) {\n    return(1);\n}\n\
+            if (calc($a = $b)) {\n    return(2);\n}\n\
+            if ($s = \"<$s>!\") {\n    return(3);\n}\n\
+            for ($i = 0; $i < $a; $i = calc($i + 1)) {\n    $b = calc($b + $i);\n}\n\
+            return($b);\n";
+        assert!(warnings(source).is_empty(), "{:?}", warnings(source));
+    }
+
+    #[test]
+    fn a_chained_assignment_sets_every_target_on_both_backends() {
+        let symbols = test_support::symbols(&[("state.varp", "[total]\n")]);
+        let source = "[proc,chain]()(int)\ndef_int $a = 0;\ndef_int $b = 0;\n$a = $b = 5;\nreturn(calc($a * 10 + $b));\n\
+            [proc,through_varp]()(int)\ndef_int $a = 0;\ndef_int $b = 0;\n$a = %total = $b = 7;\nreturn(calc($a * 100 + %total * 10 + $b));\n\
+            [proc,compare](int $x)(int)\nif ($x = 3) {\n    return(1);\n}\nreturn(0);\n";
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, source);
+        assert_eq!(vm.run_script("chain", &[]), Ok(55));
+        assert_eq!(evaluator.eval_script("chain", &[]), Ok(55));
+        assert_eq!(vm.run_script("through_varp", &[]), Ok(777));
+        assert_eq!(evaluator.eval_script("through_varp", &[]), Ok(777));
+        // '=' in a condition still compares, leaving $x alone.
+        assert_eq!(vm.run_script("compare", &[3]), Ok(1));
+        assert_eq!(vm.run_script("compare", &[4]), Ok(0));
+
+        let mut compiler = Compiler::new();
+        compiler.set_symbols(symbols);
+        let chain = &test_support::compile_with(&mut compiler, source)[0];
+        let pops = chain.instructions.iter().filter(|instruction| matches!(instruction, Instruction::PopIntLocal(_))).count();
+        assert_eq!(pops, 4, "{:?}", chain.instructions);
+    }
+
+    #[test]
+    fn a_conditional_expression_assigns_the_chosen_branch_on_both_backends() {
+        let source = "[proc,max](int $a, int $b)(int)\ndef_int $m = $a > $b ? $a : $b;\nreturn($m);\n\
+            [proc,tier](int $n)(int)\ndef_int $t = 0;\n$t = $n < 10 ? 1 : $n < 100 ? 2 : 3;\nreturn($t);\n\
+            [proc,share](int $a, int $b)(int)\ndef_int $r = $b > 0 ? calc($a / $b) : 0;\nreturn($r);\n\
+            [proc,describe](int $n)(int)\ndef_string $s = $n > 0 ? \"positive\" : \"not positive\";\nreturn(string_length($s));\n";
+        let (mut vm, mut evaluator) = test_support::backends_with(&SymbolTable::new(), source);
+        for (script, args, expected) in [
+            ("max", vec![3, 7], 7),
+            ("max", vec![9, 2], 9),
+            ("tier", vec![5], 1),
+            ("tier", vec![50], 2),
+            ("tier", vec![500], 3),
+            ("share", vec![12, 4], 3),
+            // Only the chosen branch runs, so the division never happens.
+            ("share", vec![12, 0], 0),
+            ("describe", vec![1], 8),
+            ("describe", vec![0], 12),
+        ] {
+            assert_eq!(vm.run_script(script, &args), Ok(expected), "{}{:?}", script, args);
+            assert_eq!(evaluator.eval_script(script, &args), Ok(expected), "{}{:?}", script, args);
+        }
+
+        let max = &test_support::compile(source)[0];
+        let branches = max.instructions.iter().filter(|instruction| matches!(instruction, Instruction::BranchNot(_))).count();
+        assert_eq!(branches, 1, "{:?}", max.instructions);
+    }
+
+    #[test]
+    fn a_conditional_expression_with_an_int_and_a_string_branch_is_a_type_error() {
+        let result = test_support::try_compile_with(
+            &mut Compiler::new(),
+            "[proc,mixed](int $n)(int)\ndef_int $m = $n > 0 ? 1 : \"none\";\nreturn($m);\n",
+        );
+        assert!(result.is_err(), "{:?}", result);
+    }
+
+    #[test]
+    fn recursion_that_cant_become_a_loop_is_warned_about_and_runs_recursively() {
+        let source = "[proc,ackermann](int $m, int $n)(int)\nif ($m = 0) {\n    return(calc($n + 1));\n}\n\
+            if ($n = 0) {\n    return(~ackermann(calc($m - 1), 1));\n}\nreturn(~ackermann(calc($m - 1), ~ackermann($m, calc($n - 1))));\n\
+            [proc,factorial](int $n)(int)\nif ($n <= 1) {\n    return(1);\n}\nreturn(calc($n * ~factorial(calc($n - 1))));\n\
+            [proc,fib](int $n)(int)\nif ($n <= 1) {\n    return($n);\n}\nreturn(calc(~fib(calc($n - 1)) + ~fib(calc($n - 2))));\n";
+        let mut compiler = Compiler::new();
+        let bytecode = test_support::compile_with(&mut compiler, source);
+        assert_eq!(compiler.warnings().len(), 1, "{:?}", compiler.warnings());
+        let warning = &compiler.warnings()[0];
+        assert!(warning.contains("ackermann") && warning.contains("calls itself in a way the compiler can't turn into a loop"), "{}", warning);
+
+        // The supported shapes become loops; ackermann keeps its calls.
+        let calls_itself = |index: usize| {
+            let bytecode: &ByteCode = &bytecode[index];
+            bytecode.instructions.iter().any(|instruction| matches!(instruction, Instruction::Gosub(name) | Instruction::GosubWithParams(name) if *name == bytecode.script_name))
+        };
+        assert!(calls_itself(0));
+        assert!(!calls_itself(1) && !calls_itself(2));
+
+        let (mut vm, mut evaluator) = test_support::backends_with(&SymbolTable::new(), source);
+        for (script, args, expected) in [("ackermann", vec![2, 3], 9), ("factorial", vec![5], 120), ("fib", vec![10], 55)] {
+            assert_eq!(vm.run_script(script, &args), Ok(expected), "{}{:?}", script, args);
+            assert_eq!(evaluator.eval_script(script, &args), Ok(expected), "{}{:?}", script, args);
+        }
+    }
+}
@@ -1,19 +1,400 @@
 use std::path::{Path, PathBuf};
 use std::env;
 use std::fs;
-use std::io::{self, Read};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::io;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use crate::entities::Spawn;
+use crate::error::ConfigError;
+use crate::lints::LintSetting;
+use crate::packs;
+
+const PROJECT_RC_FILE: &str = ".rscrc";
+const PROJECT_TOML_FILE: &str = "rsc.toml";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VmOptions {
+    pub max_instructions: usize,
+    pub memoize: bool,
+    /// Deepest chain of nested script calls either backend allows.
+    pub max_call_depth: usize,
+    /// Give required parameters a call leaves out their type's default,
+    /// see `Type::default_value`, rather than failing the call.
+    pub lenient_args: bool,
+    /// After compiling, keep one copy of scripts whose bytecode is the same
+    /// and register the others as aliases of it; see `VM::dedupe_scripts`.
+    pub dedupe_scripts: bool,
+    /// Seed for `random`, given with `rsc run --seed`; unset runs seed from
+    /// the clock. Never saved.
+    #[serde(skip)]
+    pub seed: Option<u64>,
+    /// Players and npcs the default host spawns before a run, given with
+    /// `rsc run --spawn`. Never saved.
+    #[serde(skip)]
+    pub spawns: Vec<Spawn>,
+    /// Keys this version doesn't know about, kept so saving doesn't drop them.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self {
+            max_instructions: 10_000_000,
+            memoize: true,
+            max_call_depth: 200,
+            lenient_args: false,
+            dedupe_scripts: false,
+            seed: None,
+            spawns: Vec::new(),
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_dump_limit() -> usize {
+    200
+}
+
+/// Settings that `Config::set` accepts by exact name, with the description
+/// shown by `Config::keys` and written above each value in `config.toml`.
+const SETTINGS: &[(&str, &str)] = &[
+    ("env_name", "Name of this environment (selected with RSC_ENV)"),
+    ("install_dir", "Where rsc installs itself"),
+    ("scripts_dir", "Directories searched recursively for .rs2 scripts"),
+    ("ignore", "Gitignore-style patterns excluded from script discovery"),
+    ("constants", "Constants files made available to scripts"),
+    ("configs", "Directories searched recursively for .npc and .obj configs"),
+    ("strict_conditions", "Require if/while conditions to be comparisons or booleans"),
+    ("dump_limit", "Most instructions of the bytecode `rsc run` prints before cutting it short"),
+    ("vm.max_instructions", "Instruction budget for a single run"),
+    ("vm.memoize", "Cache script results by arguments"),
+    ("vm.max_call_depth", "Deepest chain of nested script calls a run allows"),
+    ("vm.lenient_args", "Default left-out script arguments by type instead of failing the call"),
+    ("vm.dedupe_scripts", "Store scripts that compile to the same bytecode once, calling them through aliases"),
+];
+
+fn describe(key: &str) -> &'static str {
+    SETTINGS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, description)| *description)
+        .unwrap_or_default()
+}
+
+/// Overlays `overlay` onto `base`, merging objects key by key.
+fn merge_values(base: &mut serde_json::Value, overlay: serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// A setting as reported by `Config::keys`.
+#[derive(Debug, Clone)]
+pub struct ConfigKey {
+    pub key: String,
+    pub description: &'static str,
+    pub value: String,
+}
+
+/// Options from a `[scripts.<name>]` table, applied by the VM when that
+/// script is the entry point of a run. `memoize` also applies whenever the
+/// script is called from another script.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScriptOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_instructions: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memoize: Option<bool>,
+    /// Compiler optimisation level; see `Compiler::set_opt_level`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opt_level: Option<u8>,
+    /// Wall-clock limit for the run, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+impl ScriptOverrides {
+    /// These overrides, with `fallback`'s for the options these leave unset.
+    pub fn or(&self, fallback: &ScriptOverrides) -> ScriptOverrides {
+        ScriptOverrides {
+            max_instructions: self.max_instructions.or(fallback.max_instructions),
+            memoize: self.memoize.or(fallback.memoize),
+            opt_level: self.opt_level.or(fallback.opt_level),
+            timeout: self.timeout.or(fallback.timeout),
+        }
+    }
+
+    /// Lists the options this override sets, e.g. `max_instructions=500`.
+    pub fn describe(&self) -> Vec<String> {
+        let mut set = Vec::new();
+        if let Some(max_instructions) = self.max_instructions {
+            set.push(format!("max_instructions={}", max_instructions));
+        }
+        if let Some(memoize) = self.memoize {
+            set.push(format!("memoize={}", memoize));
+        }
+        if let Some(opt_level) = self.opt_level {
+            set.push(format!("opt_level={}", opt_level));
+        }
+        if let Some(timeout) = self.timeout {
+            set.push(format!("timeout={}ms", timeout));
+        }
+        set
+    }
+}
+
+/// A config file that contributed values on top of the user-level config.
+#[derive(Debug, Clone)]
+pub struct ConfigLayer {
+    pub path: PathBuf,
+    pub keys: Vec<String>,
+}
+
+/// `scripts_dir` may be written as a single path or a list of paths.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrManyPaths {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl From<OneOrManyPaths> for Vec<PathBuf> {
+    fn from(paths: OneOrManyPaths) -> Self {
+        match paths {
+            OneOrManyPaths::One(path) => vec![path],
+            OneOrManyPaths::Many(paths) => paths,
+        }
+    }
+}
+
+fn deserialize_paths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    Ok(OneOrManyPaths::deserialize(deserializer)?.into())
+}
+
+fn deserialize_optional_paths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<PathBuf>>, D::Error> {
+    Ok(Option::<OneOrManyPaths>::deserialize(deserializer)?.map(Into::into))
+}
+
+// A single directory is still written as a plain string so older configs stay readable.
+fn serialize_paths<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+    match paths {
+        [path] => path.serialize(serializer),
+        _ => paths.serialize(serializer),
+    }
+}
+
+/// Strips one pair of matching shell quotes from an RC file value.
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote).and_then(|rest| rest.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    value
+}
+
+/// A configuration problem found by `Config::validate`, with a suggested fix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    pub problem: String,
+    pub fix: String,
+}
+
+impl ConfigProblem {
+    fn new(problem: String, fix: String) -> Self {
+        Self { problem, fix }
+    }
+}
+
+fn is_env_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn check_quotes(value: &str) -> Option<ConfigProblem> {
+    let mut open = None;
+    for c in value.chars() {
+        match open {
+            Some(quote) if c == quote => open = None,
+            None if c == '"' || c == '\'' => open = Some(c),
+            _ => {}
+        }
+    }
+    open.map(|quote| ConfigProblem::new(
+        format!("unclosed {} quote in {}", quote, value),
+        format!("add the missing {} or remove the stray one", quote),
+    ))
+}
+
+/// Checks `$NAME` and `${NAME}` references in an exported value.
+fn check_references(value: &str) -> Option<ConfigProblem> {
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        rest = &rest[index + 1..];
+        let name = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, after)) => {
+                    rest = after;
+                    name
+                }
+                None => {
+                    return Some(ConfigProblem::new(
+                        format!("unclosed ${{ in {}", value),
+                        "close the reference with }".to_string(),
+                    ))
+                }
+            }
+        } else {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let name = &rest[..end];
+            rest = &rest[end..];
+            name
+        };
+        if !is_env_var_name(name) {
+            return Some(ConfigProblem::new(
+                format!("'${}' is not a valid variable reference in {}", name, value),
+                "reference variables as $NAME or ${NAME}".to_string(),
+            ));
+        }
+    }
+    None
+}
+
+/// Splits a command into words the way a shell would for simple quoting:
+/// whitespace separates words unless it is inside single or double quotes.
+fn split_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Settings read from a project-local `rsc.toml`. Every field is optional so
+/// only the keys present in the file override the user-level config.
+#[derive(Debug, Default, Deserialize)]
+struct ProjectToml {
+    #[serde(default, deserialize_with = "deserialize_optional_paths")]
+    scripts_dir: Option<Vec<PathBuf>>,
+    constants: Option<Vec<PathBuf>>,
+    #[serde(default, deserialize_with = "deserialize_optional_paths")]
+    configs: Option<Vec<PathBuf>>,
+    strict_conditions: Option<bool>,
+    dump_limit: Option<usize>,
+    defines: Option<HashMap<String, String>>,
+    vm: Option<ProjectVmToml>,
+    aliases: Option<Vec<String>>,
+    ignore: Option<Vec<String>>,
+    scripts: Option<HashMap<String, ScriptOverrides>>,
+    lints: Option<HashMap<String, LintSetting>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProjectVmToml {
+    max_instructions: Option<usize>,
+    memoize: Option<bool>,
+    max_call_depth: Option<usize>,
+    lenient_args: Option<bool>,
+    dedupe_scripts: Option<bool>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     pub install_dir: PathBuf,
-    pub scripts_dir: PathBuf,
+    #[serde(
+        rename = "scripts_dir",
+        deserialize_with = "deserialize_paths",
+        serialize_with = "serialize_paths"
+    )]
+    pub scripts_dirs: Vec<PathBuf>,
     pub env_name: String,
     #[serde(default)]
     pub aliases: Vec<String>,
     #[serde(default)]
     pub env_vars: HashMap<String, String>,
+    #[serde(default)]
+    pub constants: Vec<PathBuf>,
+    /// Directories whose `.npc` and `.obj` files name the npcs and objs
+    /// scripts may refer to.
+    #[serde(default, deserialize_with = "deserialize_paths")]
+    pub configs: Vec<PathBuf>,
+    /// Reject `if`/`while` conditions that are ints rather than comparisons
+    /// or booleans, e.g. `if (calc($x * 2))`.
+    #[serde(default)]
+    pub strict_conditions: bool,
+    /// How much of the entry script's bytecode `rsc run` prints; `rsc
+    /// disasm` prints all of it.
+    #[serde(default = "default_dump_limit")]
+    pub dump_limit: usize,
+    #[serde(default)]
+    pub defines: HashMap<String, String>,
+    #[serde(default)]
+    pub vm: VmOptions,
+    /// Gitignore-style patterns excluded from script discovery in every root.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Per-script VM overrides keyed by script name.
+    #[serde(default)]
+    pub scripts: HashMap<String, ScriptOverrides>,
+    /// Level and limit of each lint `rsc check` runs, keyed by rule name.
+    #[serde(default)]
+    pub lints: HashMap<String, LintSetting>,
+    /// Environment whose settings this one overlays.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherits: Option<String>,
+    /// Keys this version doesn't know about, kept so saving doesn't drop them.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+    /// The environments merged to build this config, starting with its own.
+    #[serde(skip)]
+    pub env_chain: Vec<String>,
+    #[serde(skip)]
+    pub layers: Vec<ConfigLayer>,
+    /// The one script file to compile, with the files it includes, given
+    /// with `--file`; unset compiles every script found. Never saved.
+    #[serde(skip)]
+    pub file: Option<PathBuf>,
+    /// Let a trigger declared in two files resolve to the later one, given
+    /// with `--allow-duplicates`. Never saved.
+    #[serde(skip)]
+    pub allow_duplicates: bool,
 }
 
 impl Default for Config {
@@ -31,50 +412,582 @@ impl Default for Config {
             base_dir.join(".rsc").join(&env_name)
         };
 
-        let scripts_dir = if let Ok(custom_dir) = env::var("RSC_SCRIPTS_DIR") {
-            PathBuf::from(custom_dir)
+        let scripts_dirs = if let Ok(search_path) = env::var("RSC_SCRIPTS_PATH") {
+            env::split_paths(&search_path).collect()
+        } else if let Ok(custom_dir) = env::var("RSC_SCRIPTS_DIR") {
+            vec![PathBuf::from(custom_dir)]
         } else {
             // First check if there's a local scripts directory
             let local_scripts = Path::new("./data/scripts");
             if local_scripts.is_dir() {
-                local_scripts.to_path_buf()
+                vec![local_scripts.to_path_buf()]
             } else {
-                install_dir.join("scripts")
+                vec![install_dir.join("scripts")]
             }
         };
 
         Config {
             install_dir,
-            scripts_dir,
-            env_name,
+            scripts_dirs,
+            env_name: env_name.clone(),
             aliases: Vec::new(),
             env_vars: HashMap::new(),
+            constants: Vec::new(),
+            configs: Vec::new(),
+            strict_conditions: false,
+            dump_limit: default_dump_limit(),
+            defines: HashMap::new(),
+            vm: VmOptions::default(),
+            ignore: Vec::new(),
+            scripts: HashMap::new(),
+            lints: HashMap::new(),
+            inherits: None,
+            extra: BTreeMap::new(),
+            env_chain: vec![env_name.clone()],
+            layers: Vec::new(),
+            file: None,
+            allow_duplicates: false,
         }
     }
 }
 
 impl Config {
+    /// Loads the user-level config and then merges any project-local
+    /// `.rscrc`/`rsc.toml` files found between the filesystem root (or repo
+    /// boundary) and the current directory, nearest file winning. Writes the
+    /// default user-level config first if the environment has none.
     pub fn load() -> Self {
-        let config_path = Self::get_config_path();
-        if !config_path.exists() {
-            let config = Config::default();
-            config.save().unwrap_or_default();
-            return config;
+        Self::save_default_if_missing();
+        Self::load_unsaved()
+    }
+
+    /// `load`, without writing anything, for reading settings before it is
+    /// known that the command will run, e.g. aliases for `rsc --help`.
+    pub fn load_unsaved() -> Self {
+        let mut config = Self::load_user();
+        if let Ok(current_dir) = env::current_dir() {
+            for path in Self::find_project_files(&current_dir) {
+                if let Err(e) = config.merge_project_file(&path) {
+                    println!("Warning: Failed to read {}: {}", path.display(), e);
+                }
+            }
+        }
+        if let Ok(search_path) = env::var("RSC_SCRIPTS_PATH") {
+            config.scripts_dirs = env::split_paths(&search_path).collect();
+            config.layers.push(ConfigLayer {
+                path: PathBuf::from("$RSC_SCRIPTS_PATH"),
+                keys: vec!["scripts_dir".to_string()],
+            });
+        }
+        config
+    }
+
+    /// Finds the `[scripts.<name>]` overrides for a script, ignoring case.
+    pub fn script_overrides(&self, name: &str) -> Option<&ScriptOverrides> {
+        self.scripts
+            .iter()
+            .find(|(key, _)| key.to_lowercase() == name.to_lowercase())
+            .map(|(_, overrides)| overrides)
+    }
+
+    /// Joins the script roots with the platform path separator, the format
+    /// `RSC_SCRIPTS_PATH` expects.
+    pub fn scripts_search_path(&self) -> String {
+        env::join_paths(&self.scripts_dirs)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Formats the script roots as a comma separated list for messages.
+    pub fn scripts_dirs_display(&self) -> String {
+        self.scripts_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Loads only the user-level config, without project files or
+    /// environment overrides. Use this rather than `load` when the result
+    /// will be changed and saved, so project settings aren't copied into it.
+    pub fn load_user() -> Self {
+        let env_name = Self::current_env();
+        match Self::load_env(&env_name) {
+            Ok(config) => config,
+            Err(ConfigError::EnvNotFound(name)) if name == env_name => Config::default(),
+            Err(e) => {
+                println!("Warning: {}", e);
+                // Fall back to this environment's own settings, ignoring `inherits`.
+                Self::read_env_file(&env_name)
+                    .ok()
+                    .flatten()
+                    .and_then(|value| serde_json::from_value(value).ok())
+                    .unwrap_or_default()
+            }
         }
+    }
+
+    /// Writes the default config for the current environment if it has no
+    /// config file yet.
+    pub fn save_default_if_missing() {
+        if matches!(Self::read_env_file(&Self::current_env()), Ok(None)) {
+            Config::default().save().unwrap_or_default();
+        }
+    }
+
+    /// Loads the user-level config of any environment, overlaying it on the
+    /// environments it `inherits` from. Tables are merged key by key; other
+    /// values, including lists, replace the inherited ones.
+    pub fn load_env(env_name: &str) -> Result<Self, ConfigError> {
+        let mut chain: Vec<String> = Vec::new();
+        let mut values = Vec::new();
+        let mut current = env_name.to_string();
+
+        loop {
+            if chain.contains(&current) {
+                chain.push(current);
+                return Err(ConfigError::InheritanceCycle(chain));
+            }
+            let value = Self::read_env_file(&current)?
+                .ok_or_else(|| ConfigError::EnvNotFound(current.clone()))?;
+            let parent = value.get("inherits").and_then(|parent| parent.as_str()).map(str::to_string);
+            chain.push(current);
+            values.push(value);
+            match parent {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+
+        let mut merged = serde_json::Value::Object(serde_json::Map::new());
+        for value in values.into_iter().rev() {
+            merge_values(&mut merged, value);
+        }
+        merged["env_name"] = serde_json::Value::String(env_name.to_string());
 
-        let mut file = fs::File::open(&config_path).unwrap_or_else(|_| {
-            let config = Config::default();
-            config.save().unwrap_or_default();
-            fs::File::open(&config_path).unwrap()
-        });
+        let mut config: Config = serde_json::from_value(merged).map_err(|e| ConfigError::Parse {
+            path: Self::env_dir(env_name),
+            message: e.to_string(),
+        })?;
+        config.env_chain = chain;
+        Ok(config)
+    }
+
+    /// Reads an environment's `config.toml`, or its `config.json` when there
+    /// is no usable TOML file, without resolving inheritance.
+    fn read_env_file(env_name: &str) -> Result<Option<serde_json::Value>, ConfigError> {
+        let dir = Self::env_dir(env_name);
 
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).unwrap_or_default();
+        let toml_path = dir.join("config.toml");
+        if toml_path.exists() {
+            match fs::read_to_string(&toml_path).map_err(|e| e.to_string())
+                .and_then(|contents| toml::from_str(&contents).map_err(|e| e.to_string()))
+            {
+                Ok(value) => return Ok(Some(value)),
+                Err(e) => println!(
+                    "Warning: Failed to read {}: {}\nFalling back to config.json",
+                    toml_path.display(), e
+                ),
+            }
+        }
 
-        serde_json::from_str(&contents).unwrap_or_default()
+        let json_path = dir.join("config.json");
+        if !json_path.exists() {
+            return Ok(None);
+        }
+        let parse_error = |message: String| ConfigError::Parse {
+            path: json_path.clone(),
+            message,
+        };
+        let contents = fs::read_to_string(&json_path).map_err(|e| parse_error(e.to_string()))?;
+        let value = serde_json::from_str(&contents).map_err(|e| parse_error(e.to_string()))?;
+        Ok(Some(value))
     }
 
+    /// Names of the environments under `~/.rsc` that have a config file.
+    pub fn list_envs() -> Vec<String> {
+        let mut envs: Vec<String> = fs::read_dir(Self::rsc_home())
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| {
+                        let dir = entry.path();
+                        dir.join("config.toml").is_file() || dir.join("config.json").is_file()
+                    })
+                    .filter_map(|entry| entry.file_name().into_string().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+        envs.sort();
+        envs
+    }
+
+    /// Returns project config files from the outermost ancestor down to
+    /// `start`, so applying them in order lets the nearest file win. The walk
+    /// stops at the first directory containing `.git`.
+    pub fn find_project_files(start: &Path) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+
+        for dir in start.ancestors() {
+            // Within one directory rsc.toml is applied after .rscrc.
+            let toml_path = dir.join(PROJECT_TOML_FILE);
+            if toml_path.is_file() {
+                found.push(toml_path);
+            }
+            let rc_path = dir.join(PROJECT_RC_FILE);
+            if rc_path.is_file() {
+                found.push(rc_path);
+            }
+
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+
+        found.reverse();
+        found
+    }
+
+    pub fn merge_project_file(&mut self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        let mut keys = if path.file_name().and_then(|name| name.to_str()) == Some(PROJECT_TOML_FILE) {
+            let project: ProjectToml = toml::from_str(&contents)?;
+            self.merge_project_toml(project, base_dir)
+        } else {
+            self.merge_project_rc(&contents, base_dir)
+        };
+
+        if !keys.is_empty() {
+            keys.sort();
+            self.layers.push(ConfigLayer {
+                path: path.to_path_buf(),
+                keys,
+            });
+        }
+        Ok(())
+    }
+
+    fn merge_project_toml(&mut self, project: ProjectToml, base_dir: &Path) -> Vec<String> {
+        let mut keys = Vec::new();
+
+        if let Some(scripts_dirs) = project.scripts_dir {
+            self.scripts_dirs = scripts_dirs.into_iter().map(|dir| base_dir.join(dir)).collect();
+            keys.push("scripts_dir".to_string());
+        }
+        if let Some(constants) = project.constants {
+            self.constants = constants.into_iter().map(|path| base_dir.join(path)).collect();
+            keys.push("constants".to_string());
+        }
+        if let Some(configs) = project.configs {
+            self.configs = configs.into_iter().map(|dir| base_dir.join(dir)).collect();
+            keys.push("configs".to_string());
+        }
+        if let Some(strict_conditions) = project.strict_conditions {
+            self.strict_conditions = strict_conditions;
+            keys.push("strict_conditions".to_string());
+        }
+        if let Some(dump_limit) = project.dump_limit {
+            self.dump_limit = dump_limit;
+            keys.push("dump_limit".to_string());
+        }
+        if let Some(defines) = project.defines {
+            for (name, value) in defines {
+                keys.push(format!("defines.{}", name));
+                self.defines.insert(name, value);
+            }
+        }
+        if let Some(vm) = project.vm {
+            if let Some(max_instructions) = vm.max_instructions {
+                self.vm.max_instructions = max_instructions;
+                keys.push("vm.max_instructions".to_string());
+            }
+            if let Some(memoize) = vm.memoize {
+                self.vm.memoize = memoize;
+                keys.push("vm.memoize".to_string());
+            }
+            if let Some(max_call_depth) = vm.max_call_depth {
+                self.vm.max_call_depth = max_call_depth;
+                keys.push("vm.max_call_depth".to_string());
+            }
+            if let Some(lenient_args) = vm.lenient_args {
+                self.vm.lenient_args = lenient_args;
+                keys.push("vm.lenient_args".to_string());
+            }
+            if let Some(dedupe_scripts) = vm.dedupe_scripts {
+                self.vm.dedupe_scripts = dedupe_scripts;
+                keys.push("vm.dedupe_scripts".to_string());
+            }
+        }
+        if let Some(aliases) = project.aliases {
+            self.aliases.extend(aliases);
+            keys.push("aliases".to_string());
+        }
+        if let Some(ignore) = project.ignore {
+            self.ignore.extend(ignore);
+            keys.push("ignore".to_string());
+        }
+        if let Some(scripts) = project.scripts {
+            for (name, overrides) in scripts {
+                keys.push(format!("scripts.{}", name));
+                self.scripts.insert(name, overrides);
+            }
+        }
+        if let Some(lints) = project.lints {
+            for (name, setting) in lints {
+                keys.push(format!("lints.{}", name));
+                self.lints.insert(name, setting);
+            }
+        }
+
+        keys
+    }
+
+    fn merge_project_rc(&mut self, contents: &str, base_dir: &Path) -> Vec<String> {
+        let mut keys = Vec::new();
+        let (aliases, env_vars) = Self::parse_rc_file(contents);
+
+        if !aliases.is_empty() {
+            self.aliases.extend(aliases);
+            keys.push("aliases".to_string());
+        }
+
+        for (key, value) in env_vars {
+            let value = unquote(&value).to_string();
+            match key.as_str() {
+                "RSC_SCRIPTS_DIR" => {
+                    self.scripts_dirs = vec![base_dir.join(&value)];
+                    keys.push("scripts_dir".to_string());
+                }
+                "RSC_SCRIPTS_PATH" => {
+                    self.scripts_dirs = env::split_paths(&value).map(|dir| base_dir.join(dir)).collect();
+                    keys.push("scripts_dir".to_string());
+                }
+                "RSC_CONSTANTS" => {
+                    self.constants = env::split_paths(&value).map(|path| base_dir.join(path)).collect();
+                    keys.push("constants".to_string());
+                }
+                "RSC_CONFIGS" => {
+                    self.configs = env::split_paths(&value).map(|dir| base_dir.join(dir)).collect();
+                    keys.push("configs".to_string());
+                }
+                "RSC_MAX_INSTRUCTIONS" => {
+                    if let Ok(max_instructions) = value.parse() {
+                        self.vm.max_instructions = max_instructions;
+                        keys.push("vm.max_instructions".to_string());
+                    }
+                }
+                "RSC_IGNORE" => {
+                    self.ignore.extend(value.split(',').map(|pattern| pattern.trim().to_string()));
+                    keys.push("ignore".to_string());
+                }
+                "RSC_MEMOIZE" => {
+                    if let Ok(memoize) = value.parse() {
+                        self.vm.memoize = memoize;
+                        keys.push("vm.memoize".to_string());
+                    }
+                }
+                _ => {
+                    if let Some(name) = key.strip_prefix("RSC_DEFINE_") {
+                        keys.push(format!("defines.{}", name));
+                        self.defines.insert(name.to_string(), value);
+                    } else {
+                        keys.push(format!("env_vars.{}", key));
+                        self.env_vars.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        keys
+    }
+
+    /// Lists every known setting with its description and current value.
+    /// Entries under `defines`, `env_vars` and `scripts` are listed per name.
+    pub fn keys(&self) -> Vec<ConfigKey> {
+        let mut keys: Vec<ConfigKey> = SETTINGS
+            .iter()
+            .map(|(key, description)| ConfigKey {
+                key: key.to_string(),
+                description,
+                value: self.get(key).unwrap_or_default(),
+            })
+            .collect();
+
+        let mut defines: Vec<_> = self.defines.iter().collect();
+        defines.sort();
+        for (name, value) in defines {
+            keys.push(ConfigKey {
+                key: format!("defines.{}", name),
+                description: "Compile-time define",
+                value: value.clone(),
+            });
+        }
+
+        let mut env_vars: Vec<_> = self.env_vars.iter().collect();
+        env_vars.sort();
+        for (name, value) in env_vars {
+            keys.push(ConfigKey {
+                key: format!("env_vars.{}", name),
+                description: "Environment variable exported by the RC file",
+                value: value.clone(),
+            });
+        }
+
+        let mut scripts: Vec<_> = self.scripts.iter().collect();
+        scripts.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, overrides) in scripts {
+            let options = [
+                ("max_instructions", overrides.max_instructions.map(|value| value.to_string())),
+                ("memoize", overrides.memoize.map(|value| value.to_string())),
+                ("opt_level", overrides.opt_level.map(|value| value.to_string())),
+                ("timeout", overrides.timeout.map(|value| value.to_string())),
+            ];
+            for (option, value) in options {
+                if let Some(value) = value {
+                    keys.push(ConfigKey {
+                        key: format!("scripts.{}.{}", name, option),
+                        description: "Per-script VM override",
+                        value,
+                    });
+                }
+            }
+        }
+
+        let mut lints: Vec<_> = self.lints.iter().collect();
+        lints.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, setting) in lints {
+            let options = [
+                ("level", setting.level.map(|level| level.to_string())),
+                ("max", setting.max.map(|max| max.to_string())),
+            ];
+            for (option, value) in options {
+                if let Some(value) = value {
+                    keys.push(ConfigKey {
+                        key: format!("lints.{}.{}", name, option),
+                        description: "Lint setting for rsc check",
+                        value,
+                    });
+                }
+            }
+        }
+
+        keys
+    }
+
+    /// Returns the current value of a setting in the format `set` accepts.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "env_name" => self.env_name.clone(),
+            "install_dir" => self.install_dir.display().to_string(),
+            "scripts_dir" => self.scripts_search_path(),
+            "ignore" => self.ignore.join(","),
+            "constants" => env::join_paths(&self.constants)
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            "configs" => env::join_paths(&self.configs)
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            "strict_conditions" => self.strict_conditions.to_string(),
+            "dump_limit" => self.dump_limit.to_string(),
+            "vm.max_instructions" => self.vm.max_instructions.to_string(),
+            "vm.memoize" => self.vm.memoize.to_string(),
+            "vm.max_call_depth" => self.vm.max_call_depth.to_string(),
+            "vm.lenient_args" => self.vm.lenient_args.to_string(),
+            "vm.dedupe_scripts" => self.vm.dedupe_scripts.to_string(),
+            _ => {
+                if let Some(name) = key.strip_prefix("defines.") {
+                    return self.defines.get(name).cloned();
+                }
+                if let Some(name) = key.strip_prefix("env_vars.") {
+                    return self.env_vars.get(name).cloned();
+                }
+                return self.keys().into_iter().find(|entry| entry.key == key).map(|entry| entry.value);
+            }
+        };
+        Some(value)
+    }
+
+    /// Sets a single setting from its string form, parsing it for the key's
+    /// type. Path lists use the platform path separator, `ignore` is comma
+    /// separated. Call `save` to persist the change.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        fn invalid(key: &str, value: &str, expected: &str) -> ConfigError {
+            ConfigError::InvalidValue {
+                key: key.to_string(),
+                value: value.to_string(),
+                expected: expected.to_string(),
+            }
+        }
+
+        fn parse<T: std::str::FromStr>(key: &str, value: &str, expected: &str) -> Result<T, ConfigError> {
+            value.trim().parse().map_err(|_| invalid(key, value, expected))
+        }
+
+        match key {
+            "env_name" => self.env_name = value.to_string(),
+            "install_dir" => self.install_dir = PathBuf::from(value),
+            "scripts_dir" => {
+                let dirs: Vec<PathBuf> = env::split_paths(value).filter(|dir| !dir.as_os_str().is_empty()).collect();
+                if dirs.is_empty() {
+                    return Err(invalid(key, value, "at least one directory"));
+                }
+                self.scripts_dirs = dirs;
+            }
+            "ignore" => {
+                self.ignore = value
+                    .split(',')
+                    .map(|pattern| pattern.trim().to_string())
+                    .filter(|pattern| !pattern.is_empty())
+                    .collect();
+            }
+            "constants" => {
+                self.constants = env::split_paths(value).filter(|path| !path.as_os_str().is_empty()).collect();
+            }
+            "configs" => {
+                self.configs = env::split_paths(value).filter(|dir| !dir.as_os_str().is_empty()).collect();
+            }
+            "strict_conditions" => self.strict_conditions = parse(key, value, "true or false")?,
+            "dump_limit" => self.dump_limit = parse(key, value, "a non-negative integer")?,
+            "vm.max_instructions" => self.vm.max_instructions = parse(key, value, "a non-negative integer")?,
+            "vm.memoize" => self.vm.memoize = parse(key, value, "true or false")?,
+            "vm.max_call_depth" => self.vm.max_call_depth = parse(key, value, "a non-negative integer")?,
+            "vm.lenient_args" => self.vm.lenient_args = parse(key, value, "true or false")?,
+            "vm.dedupe_scripts" => self.vm.dedupe_scripts = parse(key, value, "true or false")?,
+            _ => {
+                if let Some(name) = key.strip_prefix("defines.").filter(|name| !name.is_empty()) {
+                    self.defines.insert(name.to_string(), value.to_string());
+                } else if let Some(name) = key.strip_prefix("env_vars.").filter(|name| !name.is_empty()) {
+                    self.env_vars.insert(name.to_string(), value.to_string());
+                } else if let Some((name, option)) = key.strip_prefix("scripts.").and_then(|rest| rest.rsplit_once('.')) {
+                    let mut overrides = self.scripts.get(name).cloned().unwrap_or_default();
+                    match option {
+                        "max_instructions" => overrides.max_instructions = Some(parse(key, value, "a non-negative integer")?),
+                        "memoize" => overrides.memoize = Some(parse(key, value, "true or false")?),
+                        "opt_level" => overrides.opt_level = Some(parse(key, value, "an integer from 0 to 255")?),
+                        "timeout" => overrides.timeout = Some(parse(key, value, "a timeout in milliseconds")?),
+                        _ => return Err(ConfigError::UnknownKey(key.to_string())),
+                    }
+                    self.scripts.insert(name.to_string(), overrides);
+                } else {
+                    return Err(ConfigError::UnknownKey(key.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the config back to the user-level file it is read from:
+    /// `config.toml` when it exists, otherwise `config.json`. Unknown keys
+    /// read from the file are written back unchanged.
     pub fn save(&self) -> io::Result<()> {
+        let toml_path = Self::get_toml_config_path();
+        if toml_path.exists() {
+            let contents = self.to_commented_toml().map_err(io::Error::other)?;
+            return fs::write(&toml_path, contents);
+        }
+
         let config_path = Self::get_config_path();
         fs::create_dir_all(config_path.parent().unwrap())?;
         
@@ -82,47 +995,202 @@ impl Config {
         fs::write(&config_path, contents)
     }
 
-    pub fn get_config_path() -> PathBuf {
-        let env_name = env::var("RSC_ENV").unwrap_or_else(|_| String::from("default"));
+    /// The environment selected with `RSC_ENV`.
+    pub fn current_env() -> String {
+        env::var("RSC_ENV").unwrap_or_else(|_| String::from("default"))
+    }
+
+    /// The directory holding every environment, `~/.rsc`.
+    pub fn rsc_home() -> PathBuf {
         if cfg!(windows) {
-            PathBuf::from(env::var("USERPROFILE").unwrap_or_else(|_| String::from(".")))
-                .join(".rsc")
-                .join(&env_name)
-                .join("config.json")
+            PathBuf::from(env::var("USERPROFILE").unwrap_or_else(|_| String::from("."))).join(".rsc")
         } else {
-            PathBuf::from(env::var("HOME").unwrap_or_else(|_| String::from(".")))
-                .join(".rsc")
-                .join(&env_name)
-                .join("config.json")
+            PathBuf::from(env::var("HOME").unwrap_or_else(|_| String::from("."))).join(".rsc")
         }
     }
 
-    pub fn get_rc_path() -> PathBuf {
-        let env_name = env::var("RSC_ENV").unwrap_or_else(|_| String::from("default"));
-        if cfg!(windows) {
-            PathBuf::from(env::var("USERPROFILE").unwrap_or_else(|_| String::from(".")))
-                .join(".rsc")
-                .join(&env_name)
-                .join("rscrc")
+    pub fn env_dir(env_name: &str) -> PathBuf {
+        Self::rsc_home().join(env_name)
+    }
+
+    pub fn get_config_path() -> PathBuf {
+        Self::env_dir(&Self::current_env()).join("config.json")
+    }
+
+    pub fn get_toml_config_path() -> PathBuf {
+        Self::get_config_path().with_file_name("config.toml")
+    }
+
+    /// Where `rsc update` records the version it installed.
+    pub fn get_installed_path() -> PathBuf {
+        Self::get_config_path().with_file_name("installed.json")
+    }
+
+    /// The user-level config file `load()` reads: `config.toml` when present,
+    /// otherwise the legacy `config.json`.
+    pub fn get_user_config_path() -> PathBuf {
+        let toml_path = Self::get_toml_config_path();
+        if toml_path.exists() {
+            toml_path
         } else {
-            PathBuf::from(env::var("HOME").unwrap_or_else(|_| String::from(".")))
-                .join(".rsc")
-                .join(&env_name)
-                .join("rscrc")
+            Self::get_config_path()
+        }
+    }
+
+    /// Converts the legacy `config.json` and `rscrc` pair into `config.toml`.
+    /// Exports and aliases from the RC file are folded into the matching
+    /// settings. The old files are left untouched.
+    pub fn migrate_to_toml(force: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let toml_path = Self::get_toml_config_path();
+        if toml_path.exists() && !force {
+            return Err(format!(
+                "{} already exists. Use --force to overwrite it.",
+                toml_path.display()
+            ).into());
+        }
+
+        let json_path = Self::get_config_path();
+        let mut config: Config = if json_path.exists() {
+            serde_json::from_str(&fs::read_to_string(&json_path)?)?
+        } else {
+            Config::default()
+        };
+
+        let rc_path = Self::get_rc_path();
+        if rc_path.exists() {
+            let contents = fs::read_to_string(&rc_path)?;
+            let base_dir = rc_path.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+            let aliases = std::mem::take(&mut config.aliases);
+            config.merge_project_rc(&contents, &base_dir);
+            // The JSON config may already list the RC aliases.
+            let mut merged = aliases;
+            for alias in config.aliases.drain(..) {
+                if !merged.contains(&alias) {
+                    merged.push(alias);
+                }
+            }
+            config.aliases = merged;
+        }
+
+        fs::create_dir_all(toml_path.parent().unwrap())?;
+        fs::write(&toml_path, config.to_commented_toml()?)?;
+        Ok(toml_path)
+    }
+
+    /// Renders the config as TOML with a comment describing each setting.
+    pub fn to_commented_toml(&self) -> Result<String, toml::ser::Error> {
+        fn value<T: Serialize + ?Sized>(value: &T) -> Result<String, toml::ser::Error> {
+            toml::Value::try_from(value).map(|value| value.to_string())
+        }
+
+        fn key(name: &str) -> Result<String, toml::ser::Error> {
+            if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                Ok(name.to_string())
+            } else {
+                value(name)
+            }
+        }
+
+        let mut out = String::new();
+        out.push_str("# RuneScript Compiler configuration\n");
+        out.push_str("# Values here are overridden by project .rscrc/rsc.toml files.\n\n");
+
+        out.push_str(&format!("# {}\n", describe("env_name")));
+        out.push_str(&format!("env_name = {}\n\n", value(&self.env_name)?));
+        out.push_str(&format!("# {}\n", describe("install_dir")));
+        out.push_str(&format!("install_dir = {}\n\n", value(&self.install_dir)?));
+        out.push_str(&format!("# {}\n", describe("scripts_dir")));
+        out.push_str(&format!("scripts_dir = {}\n\n", value(&self.scripts_dirs)?));
+        out.push_str(&format!("# {}\n", describe("ignore")));
+        out.push_str(&format!("ignore = {}\n\n", value(&self.ignore)?));
+        out.push_str(&format!("# {}\n", describe("constants")));
+        out.push_str(&format!("constants = {}\n\n", value(&self.constants)?));
+        out.push_str(&format!("# {}\n", describe("configs")));
+        out.push_str(&format!("configs = {}\n\n", value(&self.configs)?));
+        out.push_str(&format!("# {}\n", describe("strict_conditions")));
+        out.push_str(&format!("strict_conditions = {}\n\n", self.strict_conditions));
+        out.push_str(&format!("# {}\n", describe("dump_limit")));
+        out.push_str(&format!("dump_limit = {}\n\n", self.dump_limit));
+        out.push_str("# Shell-style aliases, e.g. \"alias rs-fib='rsc run fib'\"\n");
+        out.push_str(&format!("aliases = {}\n\n", value(&self.aliases)?));
+
+        if !self.extra.is_empty() {
+            out.push_str("# Settings not recognised by this version of rsc, kept as-is\n");
+            for (name, extra) in &self.extra {
+                out.push_str(&format!("{} = {}\n", key(name)?, value(extra)?));
+            }
+            out.push('\n');
+        }
+
+        out.push_str("[vm]\n");
+        out.push_str(&format!("# {}\n", describe("vm.max_instructions")));
+        out.push_str(&format!("max_instructions = {}\n", self.vm.max_instructions));
+        out.push_str(&format!("# {}\n", describe("vm.memoize")));
+        out.push_str(&format!("memoize = {}\n", self.vm.memoize));
+        out.push_str(&format!("# {}\n", describe("vm.max_call_depth")));
+        out.push_str(&format!("max_call_depth = {}\n", self.vm.max_call_depth));
+        out.push_str(&format!("# {}\n", describe("vm.lenient_args")));
+        out.push_str(&format!("lenient_args = {}\n", self.vm.lenient_args));
+        out.push_str(&format!("# {}\n", describe("vm.dedupe_scripts")));
+        out.push_str(&format!("dedupe_scripts = {}\n", self.vm.dedupe_scripts));
+        for (name, extra) in &self.vm.extra {
+            out.push_str(&format!("{} = {}\n", key(name)?, value(extra)?));
+        }
+        out.push('\n');
+
+        out.push_str("# Compile-time defines\n");
+        out.push_str("[defines]\n");
+        let mut defines: Vec<_> = self.defines.iter().collect();
+        defines.sort();
+        for (name, define) in defines {
+            out.push_str(&format!("{} = {}\n", key(name)?, value(define)?));
+        }
+
+        out.push_str("\n# Other environment variables exported by the RC file\n");
+        out.push_str("[env_vars]\n");
+        let mut env_vars: Vec<_> = self.env_vars.iter().collect();
+        env_vars.sort();
+        for (name, env_value) in env_vars {
+            out.push_str(&format!("{} = {}\n", key(name)?, value(env_value)?));
+        }
+
+        let mut scripts: Vec<_> = self.scripts.iter().collect();
+        scripts.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, overrides) in scripts {
+            out.push_str(&format!("\n[scripts.{}]\n", key(name)?));
+            out.push_str(&toml::to_string(overrides)?);
+        }
+
+        let mut lints: Vec<_> = self.lints.iter().collect();
+        lints.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, setting) in lints {
+            out.push_str(&format!("\n[lints.{}]\n", key(name)?));
+            out.push_str(&toml::to_string(setting)?);
         }
+
+        Ok(out)
+    }
+
+    pub fn get_rc_path() -> PathBuf {
+        Self::env_dir(&Self::current_env()).join("rscrc")
     }
 
     pub fn load_rc_file() -> io::Result<String> {
         let rc_path = Self::get_rc_path();
         if !rc_path.exists() {
+            let defaults = Self::default();
+            let scripts_export = match defaults.scripts_dirs.as_slice() {
+                [dir] => format!("export RSC_SCRIPTS_DIR={}", dir.display()),
+                _ => format!("export RSC_SCRIPTS_PATH={}", defaults.scripts_search_path()),
+            };
             let default_rc = format!(
                 "# RuneScript RC File\n\n\
                 # Environment Variables\n\
                 export RSC_DEBUG=false\n\
-                export RSC_SCRIPTS_DIR={}\n\n\
+                {}\n\n\
                 # Aliases\n\
                 alias rs-fib='rsc run fib'\n",
-                Self::default().scripts_dir.display()
+                scripts_export
             );
             fs::create_dir_all(rc_path.parent().unwrap())?;
             fs::write(&rc_path, &default_rc)?;
@@ -138,6 +1206,132 @@ impl Config {
         fs::write(&rc_path, contents)
     }
 
+    /// Checks that the script directories can be read and that the RC file
+    /// and exported variables are well-formed.
+    pub fn validate(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        let rc_path = Self::get_rc_path();
+        let rc_contents = fs::read_to_string(&rc_path).ok();
+
+        // Directories exported by the RC file are checked too, since they
+        // take over whenever that file is sourced.
+        let mut scripts_dirs = self.scripts_dirs.clone();
+        if let Some(contents) = &rc_contents {
+            let (_, env_vars) = Self::parse_rc_file(contents);
+            let exported = match (env_vars.get("RSC_SCRIPTS_PATH"), env_vars.get("RSC_SCRIPTS_DIR")) {
+                (Some(path), _) => env::split_paths(unquote(path)).collect(),
+                (None, Some(dir)) => vec![PathBuf::from(unquote(dir))],
+                (None, None) => Vec::new(),
+            };
+            for dir in exported {
+                if !dir.to_string_lossy().contains('$') && !scripts_dirs.contains(&dir) {
+                    scripts_dirs.push(dir);
+                }
+            }
+        }
+
+        for dir in &scripts_dirs {
+            let fix = format!(
+                "create {} or point RSC_SCRIPTS_DIR at an existing directory (rsc config edit)",
+                dir.display()
+            );
+            if !dir.exists() {
+                problems.push(ConfigProblem::new(format!("scripts_dir {} does not exist", dir.display()), fix));
+            } else if packs::is_pack(dir) {
+                continue;
+            } else if !dir.is_dir() {
+                problems.push(ConfigProblem::new(format!("scripts_dir {} is not a directory or a .zip script pack", dir.display()), fix));
+            } else if let Err(e) = fs::read_dir(dir) {
+                problems.push(ConfigProblem::new(
+                    format!("scripts_dir {} cannot be read: {}", dir.display(), e),
+                    format!("check the permissions on {}", dir.display()),
+                ));
+            }
+        }
+
+        if let Some(contents) = &rc_contents {
+            for mut problem in Self::validate_rc(contents) {
+                problem.problem = format!("{}: {}", rc_path.display(), problem.problem);
+                problems.push(problem);
+            }
+        }
+
+        let mut env_vars: Vec<_> = self.env_vars.iter().collect();
+        env_vars.sort();
+        for (name, value) in env_vars {
+            if !is_env_var_name(name) {
+                problems.push(ConfigProblem::new(
+                    format!("env var name '{}' is not valid", name),
+                    "use letters, digits and underscores, not starting with a digit".to_string(),
+                ));
+            }
+            if let Some(problem) = check_references(value) {
+                problems.push(ConfigProblem::new(format!("env var {}: {}", name, problem.problem), problem.fix));
+            }
+        }
+
+        problems
+    }
+
+    /// Checks each line of an RC file, reporting problems by line number.
+    pub fn validate_rc(contents: &str) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let at_line = |problem: ConfigProblem| ConfigProblem::new(
+                format!("line {}: {}", line_number, problem.problem),
+                problem.fix,
+            );
+
+            if let Some(rest) = line.strip_prefix("export ") {
+                match rest.split_once('=') {
+                    None => problems.push(at_line(ConfigProblem::new(
+                        format!("malformed export '{}'", line),
+                        "write it as export NAME=value".to_string(),
+                    ))),
+                    Some((name, value)) => {
+                        let name = name.trim();
+                        if !is_env_var_name(name) {
+                            problems.push(at_line(ConfigProblem::new(
+                                format!("'{}' is not a valid variable name", name),
+                                "use letters, digits and underscores, not starting with a digit".to_string(),
+                            )));
+                        }
+                        if let Some(problem) = check_quotes(value.trim()).or_else(|| check_references(value)) {
+                            problems.push(at_line(problem));
+                        }
+                    }
+                }
+            } else if let Some(rest) = line.strip_prefix("alias ") {
+                match rest.split_once('=') {
+                    Some((name, command)) if !name.trim().is_empty() && !name.contains(char::is_whitespace) => {
+                        if let Some(problem) = check_quotes(command.trim()) {
+                            problems.push(at_line(problem));
+                        }
+                    }
+                    _ => problems.push(at_line(ConfigProblem::new(
+                        format!("malformed alias '{}'", line),
+                        "write it as alias name='rsc run script'".to_string(),
+                    ))),
+                }
+            } else {
+                problems.push(at_line(ConfigProblem::new(
+                    format!("unrecognised line '{}'", line),
+                    "the RC file only supports export and alias lines; comment it out with #".to_string(),
+                )));
+            }
+        }
+
+        problems
+    }
+
     pub fn parse_rc_file(contents: &str) -> (Vec<String>, HashMap<String, String>) {
         let mut aliases = Vec::new();
         let mut env_vars = HashMap::new();
@@ -160,6 +1354,70 @@ impl Config {
         (aliases, env_vars)
     }
 
+    /// Every alias line that applies here: those from the config and project
+    /// files plus the environment's RC file, without duplicates.
+    pub fn all_aliases(&self) -> Vec<String> {
+        let mut aliases = self.aliases.clone();
+        if let Ok(contents) = fs::read_to_string(Self::get_rc_path()) {
+            for alias in Self::parse_rc_file(&contents).0 {
+                if !aliases.contains(&alias) {
+                    aliases.push(alias);
+                }
+            }
+        }
+        aliases
+    }
+
+    /// Parses `alias name='rsc run fib'` into the alias name and the
+    /// arguments after `rsc`. Aliases for other programs return `None`.
+    pub fn parse_alias(line: &str) -> Option<(String, Vec<String>)> {
+        let (name, command) = line.trim().strip_prefix("alias ")?.split_once('=')?;
+        let mut words = split_words(unquote(command.trim())).into_iter();
+        let program = words.next()?;
+        let program = Path::new(&program).file_stem()?.to_str()?;
+        if program != "rsc" && program != "runescript-compiler" {
+            return None;
+        }
+        Some((name.trim().to_string(), words.collect()))
+    }
+
+    /// Rewrites command line arguments so that an alias given in place of a
+    /// subcommand is replaced by the arguments it stands for. Aliases may
+    /// refer to other aliases; a cycle is an error. Built-in subcommands
+    /// always win over an alias of the same name, with a warning.
+    pub fn expand_aliases(
+        args: &[String],
+        aliases: &[String],
+        subcommands: &[String],
+    ) -> Result<Vec<String>, ConfigError> {
+        let aliases: HashMap<String, Vec<String>> = aliases
+            .iter()
+            .filter_map(|line| Self::parse_alias(line))
+            .collect();
+
+        let mut args = args.to_vec();
+        let mut expanded: Vec<String> = Vec::new();
+        // The first argument after the program name and any global flags.
+        while let Some(index) = args.iter().skip(1).position(|arg| !arg.starts_with('-')).map(|i| i + 1) {
+            let word = &args[index];
+            let Some(replacement) = aliases.get(word) else {
+                break;
+            };
+            if subcommands.contains(word) {
+                println!("Warning: alias '{}' has the same name as a built-in subcommand and is ignored", word);
+                break;
+            }
+            if expanded.contains(word) {
+                expanded.push(word.clone());
+                return Err(ConfigError::AliasCycle(expanded));
+            }
+            expanded.push(word.clone());
+            args.splice(index..=index, replacement.iter().cloned());
+        }
+
+        Ok(args)
+    }
+
     pub fn get_binary_name() -> &'static str {
         if cfg!(windows) {
             "rsc.exe"
@@ -171,4 +1429,79 @@ impl Config {
     pub fn get_binary_path(&self) -> PathBuf {
         self.install_dir.join("bin").join(Self::get_binary_name())
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_project_file_wins_over_user_config() {
+        let root = tempfile::tempdir().unwrap();
+        // Stops the walk here, so nothing above the temp dir is picked up.
+        fs::create_dir(root.path().join(".git")).unwrap();
+        let project = root.path().join("project");
+        let start = project.join("quests").join("cook");
+        fs::create_dir_all(&start).unwrap();
+        fs::write(root.path().join(PROJECT_TOML_FILE), "scripts_dir = \"outer\"\nstrict_conditions = true\n").unwrap();
+        fs::write(project.join(PROJECT_TOML_FILE), "scripts_dir = \"scripts\"\n").unwrap();
+
+        let files = Config::find_project_files(&start);
+        assert_eq!(files, vec![root.path().join(PROJECT_TOML_FILE), project.join(PROJECT_TOML_FILE)]);
+
+        let mut config = Config {
+            scripts_dirs: vec![PathBuf::from("/home/user/scripts")],
+            ..Config::default()
+        };
+        for path in &files {
+            config.merge_project_file(path).unwrap();
+        }
+        assert_eq!(config.scripts_dirs, vec![project.join("scripts")]);
+        // Keys the nearer file doesn't set still come from the outer one.
+        assert!(config.strict_conditions);
+        assert_eq!(config.layers.last().unwrap().path, project.join(PROJECT_TOML_FILE));
+    }
+
+    fn expand(args: &[&str], aliases: &[&str]) -> Result<Vec<String>, ConfigError> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let aliases: Vec<String> = aliases.iter().map(|alias| alias.to_string()).collect();
+        let subcommands = vec!["run".to_string(), "list".to_string(), "help".to_string()];
+        Config::expand_aliases(&args, &aliases, &subcommands)
+    }
+
+    #[test]
+    fn alias_expands_in_place_of_the_subcommand() {
+        let aliases = [
+            "alias f='rsc run fib'",
+            "alias greet=\"rsc run greet --arg 'string:hello world'\"",
+            "alias scripts=/usr/local/bin/rsc list",
+        ];
+        assert_eq!(expand(&["rsc", "f", "10"], &aliases).unwrap(), ["rsc", "run", "fib", "10"]);
+        assert_eq!(expand(&["rsc", "-v", "f", "10"], &aliases).unwrap(), ["rsc", "-v", "run", "fib", "10"]);
+        assert_eq!(
+            expand(&["rsc", "greet"], &aliases).unwrap(),
+            ["rsc", "run", "greet", "--arg", "string:hello world"]
+        );
+        assert_eq!(expand(&["rsc", "scripts"], &aliases).unwrap(), ["rsc", "list"]);
+    }
+
+    #[test]
+    fn aliases_expand_through_other_aliases() {
+        let aliases = ["alias f='rsc run fib'", "alias f10='rsc f 10'"];
+        assert_eq!(expand(&["rsc", "f10", "--backend", "interp"], &aliases).unwrap(), ["rsc", "run", "fib", "10", "--backend", "interp"]);
+    }
+
+    #[test]
+    fn other_programs_and_subcommand_names_are_not_expanded() {
+        let aliases = ["alias ll='ls -l'", "alias run='rsc list'"];
+        assert_eq!(expand(&["rsc", "ll"], &aliases).unwrap(), ["rsc", "ll"]);
+        assert_eq!(expand(&["rsc", "run", "fib"], &aliases).unwrap(), ["rsc", "run", "fib"]);
+    }
+
+    #[test]
+    fn alias_cycle_is_an_error() {
+        let aliases = ["alias a='rsc b'", "alias b='rsc a'"];
+        let error = expand(&["rsc", "a"], &aliases).unwrap_err();
+        assert!(matches!(&error, ConfigError::AliasCycle(chain) if chain == &["a", "b", "a"]), "{}", error);
+    }
+}
\ No newline at end of file
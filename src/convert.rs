@@ -0,0 +1,94 @@
+//! The commands that convert between ints and strings. Ints and strings
+//! live on separate stacks and never convert implicitly, so these are the
+//! only way across.
+
+use crate::types::Type;
+
+/// Parameter and return types of the conversion commands.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "tostring" => Some((&[Type::Int], Type::String)),
+        "toint" => Some((&[Type::String], Type::Int)),
+        "compare" => Some((&[Type::String, Type::String], Type::Int)),
+        _ => None,
+    }
+}
+
+/// The command that converts `from` to `to`, for type errors that reject
+/// the implicit conversion.
+pub fn command_for(from: &Type, to: &Type) -> Option<&'static str> {
+    match (from, to) {
+        (Type::Int | Type::Boolean, Type::String) => Some("tostring"),
+        (Type::String, Type::Int | Type::Boolean) => Some("toint"),
+        _ => None,
+    }
+}
+
+/// Parses a decimal int, with an optional sign, as `toint` does.
+pub fn to_int(text: &str) -> Result<i32, String> {
+    text.parse()
+        .map_err(|_| format!("toint: \"{}\" is not a number", text))
+}
+
+/// Orders two strings the way the engine's `compare` does: the difference
+/// of the first pair of chars that differ, or else of the lengths.
+pub fn compare(a: &str, b: &str) -> i32 {
+    a.chars()
+        .zip(b.chars())
+        .find(|(x, y)| x != y)
+        .map(|(x, y)| x as i32 - y as i32)
+        .unwrap_or(a.chars().count() as i32 - b.chars().count() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::compiler::Compiler;
+    use crate::error::EvalError;
+    use crate::host::{HostValue, RecordingHost};
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,parse](string $text)(int)\nreturn(toint($text));\n\
+        [proc,order]()(int)\nreturn(compare(\"abc\", \"abd\"));\n\
+        [proc,show](int $n)(int)\nmes(tostring($n));\nreturn(0);\n";
+
+    #[test]
+    fn each_conversion_on_both_backends() {
+        let mut vm = test_support::vm(SCRIPTS);
+        let mut evaluator = test_support::evaluator(SCRIPTS);
+        let text = |text: &str| [HostValue::Str(text.to_string())];
+
+        assert_eq!(vm.run_script_with_args("parse", &text("-42")), Ok(-42));
+        assert_eq!(evaluator.eval_script_with_args("parse", &text("-42")), Ok(-42));
+        assert_eq!(vm.run_script("order", &[]), Ok(-1));
+        assert_eq!(evaluator.eval_script("order", &[]), Ok(-1));
+
+        let host = Rc::new(RefCell::new(RecordingHost::default()));
+        vm.set_host(host.clone());
+        vm.run_script("show", &[-7]).unwrap();
+        evaluator.set_host(host.clone());
+        evaluator.eval_script("show", &[-7]).unwrap();
+        let shown = vec![("mes".to_string(), vec![HostValue::Str("-7".to_string())])];
+        assert_eq!(host.borrow().calls, [shown.clone(), shown].concat());
+    }
+
+    #[test]
+    fn toint_of_a_non_number_fails_on_both_backends() {
+        let args = [HostValue::Str("abc".to_string())];
+        let error = test_support::vm(SCRIPTS).run_script_with_args("parse", &args).unwrap_err();
+        assert!(error.contains("toint: \"abc\" is not a number"), "{}", error);
+        assert_eq!(
+            test_support::evaluator(SCRIPTS).eval_script_with_args("parse", &args),
+            Err(EvalError::InvalidConversion("toint: \"abc\" is not a number".to_string()))
+        );
+    }
+
+    #[test]
+    fn implicit_conversion_names_the_command() {
+        let error = test_support::try_compile_with(&mut Compiler::new(), "[proc,count]()(int)\ndef_int $x = \"12\";\nreturn($x);\n").unwrap_err();
+        assert!(error.to_string().contains("toint"), "{}", error);
+        let error = test_support::try_compile_with(&mut Compiler::new(), "[proc,caption]()(int)\ndef_string $s = 12;\nreturn(0);\n").unwrap_err();
+        assert!(error.to_string().contains("tostring"), "{}", error);
+    }
+}
@@ -0,0 +1,168 @@
+//! Coords are packed into one i32 the way the engine stores them:
+//! `level << 28 | x << 14 | z`, with x and z in 0..16384 and level in 0..4.
+//! `coord2` and `coord3` values pack plain 2D and 3D points the same way:
+//! `x << 15 | y` with 15-bit components, and `x << 20 | y << 10 | z` with
+//! 10-bit ones.
+
+use crate::types::Type;
+
+pub const MAX_LEVEL: i32 = 3;
+pub const MAX_XZ: i32 = (1 << 14) - 1;
+/// The largest component of a `coord2`.
+pub const MAX_COORD2: i32 = (1 << 15) - 1;
+/// The largest component of a `coord3`.
+pub const MAX_COORD3: i32 = (1 << 10) - 1;
+
+/// Packs a level and absolute x/z into a coord.
+pub fn pack(level: i32, x: i32, z: i32) -> Result<i32, String> {
+    if !(0..=MAX_LEVEL).contains(&level) {
+        return Err(format!("coord level {} is outside 0..={}", level, MAX_LEVEL));
+    }
+    for (axis, value) in [("x", x), ("z", z)] {
+        if !(0..=MAX_XZ).contains(&value) {
+            return Err(format!("coord {} {} is outside 0..={}", axis, value, MAX_XZ));
+        }
+    }
+    Ok(level << 28 | x << 14 | z)
+}
+
+pub fn x(coord: i32) -> i32 {
+    (coord >> 14) & MAX_XZ
+}
+
+pub fn z(coord: i32) -> i32 {
+    coord & MAX_XZ
+}
+
+pub fn level(coord: i32) -> i32 {
+    (coord >> 28) & MAX_LEVEL
+}
+
+/// Checks each named component is in `0..=max`.
+fn check_components(kind: &str, max: i32, components: &[(&str, i32)]) -> Result<(), String> {
+    for (axis, value) in components {
+        if !(0..=max).contains(value) {
+            return Err(format!("{} {} {} is outside 0..={}", kind, axis, value, max));
+        }
+    }
+    Ok(())
+}
+
+/// Packs x and y into a `coord2`.
+pub fn pack2(x: i32, y: i32) -> Result<i32, String> {
+    check_components("coord2", MAX_COORD2, &[("x", x), ("y", y)])?;
+    Ok(x << 15 | y)
+}
+
+/// Packs x, y and z into a `coord3`.
+pub fn pack3(x: i32, y: i32, z: i32) -> Result<i32, String> {
+    check_components("coord3", MAX_COORD3, &[("x", x), ("y", y), ("z", z)])?;
+    Ok(x << 20 | y << 10 | z)
+}
+
+/// The x and y of a `coord2`.
+pub fn unpack2(coord: i32) -> (i32, i32) {
+    (coord >> 15 & MAX_COORD2, coord & MAX_COORD2)
+}
+
+/// The x, y and z of a `coord3`.
+pub fn unpack3(coord: i32) -> (i32, i32, i32) {
+    (coord >> 20 & MAX_COORD3, coord >> 10 & MAX_COORD3, coord & MAX_COORD3)
+}
+
+/// Chebyshev distance between two coords, ignoring their levels as the
+/// engine's `distance` does.
+pub fn distance(a: i32, b: i32) -> i32 {
+    (x(a) - x(b)).abs().max((z(a) - z(b)).abs())
+}
+
+/// Parameter and return types of the coord commands, which both backends
+/// implement themselves instead of passing them to the host.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "coord" => Some((&[Type::Int, Type::Int, Type::Int], Type::Coord)),
+        "coordx" | "coordz" | "coordlevel" => Some((&[Type::Coord], Type::Int)),
+        "distance" => Some((&[Type::Coord, Type::Coord], Type::Int)),
+        "coord2" => Some((&[Type::Int, Type::Int], Type::Coord2)),
+        "coord2x" | "coord2y" => Some((&[Type::Coord2], Type::Int)),
+        "coord3" => Some((&[Type::Int, Type::Int, Type::Int], Type::Coord3)),
+        "coord3x" | "coord3y" | "coord3z" => Some((&[Type::Coord3], Type::Int)),
+        _ => None,
+    }
+}
+
+/// Runs a coord command on already-evaluated arguments, whose count the
+/// parser has checked against `signature`.
+pub fn call(name: &str, args: &[i32]) -> Result<i32, String> {
+    match (name, args) {
+        ("coord", [level, x, z]) => pack(*level, *x, *z),
+        ("coordx", [coord]) => Ok(x(*coord)),
+        ("coordz", [coord]) => Ok(z(*coord)),
+        ("coordlevel", [coord]) => Ok(level(*coord)),
+        ("distance", [a, b]) => Ok(distance(*a, *b)),
+        ("coord2", [x, y]) => pack2(*x, *y),
+        ("coord2x", [coord]) => Ok(unpack2(*coord).0),
+        ("coord2y", [coord]) => Ok(unpack2(*coord).1),
+        ("coord3", [x, y, z]) => pack3(*x, *y, *z),
+        ("coord3x", [coord]) => Ok(unpack3(*coord).0),
+        ("coord3y", [coord]) => Ok(unpack3(*coord).1),
+        ("coord3z", [coord]) => Ok(unpack3(*coord).2),
+        _ => Err(format!("{}() got {} argument(s)", name, args.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,gap]()(int)\ndef_coord $a = coord(0, 3200, 3200);\ndef_coord $b = coord(0, 3210, 3195);\nreturn(distance($a, $b));\n\
+        [proc,parts](int $level, int $x, int $z)(int)\ndef_coord $c = coord($level, $x, $z);\nreturn(calc(coordlevel($c) * 100000000 + coordx($c) * 10000 + coordz($c)));\n";
+
+    #[test]
+    fn unpacking_reverses_packing() {
+        let coord = pack(2, 3222, 3218).unwrap();
+        assert_eq!((level(coord), x(coord), z(coord)), (2, 3222, 3218));
+        assert_eq!(pack(0, 0, MAX_XZ + 1), Err(format!("coord z {} is outside 0..={}", MAX_XZ + 1, MAX_XZ)));
+    }
+
+    #[test]
+    fn distance_and_components_agree_on_both_backends() {
+        let mut evaluator = test_support::evaluator(SCRIPTS);
+        // Chebyshev: the larger of the x gap (10) and the z gap (5).
+        assert_eq!(test_support::run(SCRIPTS, "gap", &[]), Ok(10));
+        assert_eq!(evaluator.eval_script("gap", &[]), Ok(10));
+
+        let expected = 100_000_000 + 3222 * 10000 + 3218;
+        assert_eq!(test_support::run(SCRIPTS, "parts", &[1, 3222, 3218]), Ok(expected));
+        assert_eq!(evaluator.eval_script("parts", &[1, 3222, 3218]), Ok(expected));
+    }
+
+    const COORD3: &str = "[proc,decompose](int $x, int $y, int $z)(int)\n\
+        def_coord3 $c = coord3($x, $y, $z);\n\
+        return(calc(coord3x($c) * 1000000 + coord3y($c) * 1000 + coord3z($c)));\n\
+        [proc,flat](int $x, int $y)(int)\ndef_coord2 $c = coord2($x, $y);\nreturn(calc(coord2x($c) * 100000 + coord2y($c)));\n";
+
+    #[test]
+    fn a_coord3_decomposes_back_to_its_components_on_both_backends() {
+        let mut evaluator = test_support::evaluator(COORD3);
+        assert_eq!(unpack3(pack3(12, 1023, 7).unwrap()), (12, 1023, 7));
+        assert_eq!(test_support::run(COORD3, "decompose", &[12, 1023, 7]), Ok(13_023_007));
+        assert_eq!(evaluator.eval_script("decompose", &[12, 1023, 7]), Ok(13_023_007));
+        assert_eq!(unpack2(pack2(MAX_COORD2, 0).unwrap()), (MAX_COORD2, 0));
+        assert_eq!(test_support::run(COORD3, "flat", &[300, 5]), Ok(30_000_005));
+        assert_eq!(evaluator.eval_script("flat", &[300, 5]), Ok(30_000_005));
+    }
+
+    #[test]
+    fn out_of_range_components_fail_at_runtime_on_both_backends() {
+        let message = "coord3 y 1024 is outside 0..=1023";
+        let error = test_support::run(COORD3, "decompose", &[0, 1024, 0]).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = test_support::evaluator(COORD3).eval_script("decompose", &[0, 1024, 0]).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+        for (x, y) in [(-1, 0), (0, 32768)] {
+            assert!(test_support::run(COORD3, "flat", &[x, y]).is_err(), "{} {}", x, y);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Line coverage gathered over many VM runs, e.g. a whole `rsc test` run,
+//! from the instruction counts `VM::enable_profiling` records and each
+//! script's instruction-to-source map.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use crate::bytecode::ByteCode;
+
+/// How many times each instruction of each script ran, summed over runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    counts: HashMap<String, Vec<u64>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds one run's counts, as returned by `VM::profile`.
+    pub fn record(&mut self, profile: HashMap<String, Vec<u64>>) {
+        for (script, counts) in profile {
+            let total = self.counts.entry(script).or_default();
+            if total.len() < counts.len() {
+                total.resize(counts.len(), 0);
+            }
+            for (sum, count) in total.iter_mut().zip(counts) {
+                *sum += count;
+            }
+        }
+    }
+
+    /// How many times each line of `bytecode` with code on it ran: the most
+    /// any of the line's instructions ran, so a line is covered if any part
+    /// of it ran. Lines of a script that never ran are all 0.
+    pub fn lines(&self, bytecode: &ByteCode) -> BTreeMap<usize, u64> {
+        let counts = self.counts.get(&bytecode.script_name);
+        let mut lines = BTreeMap::new();
+        for (ip, line) in bytecode.source_lines.iter().enumerate() {
+            let Some(line) = line else {
+                continue;
+            };
+            let count = counts.and_then(|counts| counts.get(ip)).copied().unwrap_or(0);
+            let hits = lines.entry(*line).or_insert(0);
+            *hits = count.max(*hits);
+        }
+        lines
+    }
+}
+
+/// The coverage of one script: its name, its file and the hits of each of
+/// its lines with code, by line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptCoverage {
+    pub script: String,
+    pub path: PathBuf,
+    pub lines: BTreeMap<usize, u64>,
+}
+
+impl ScriptCoverage {
+    pub fn covered(&self) -> usize {
+        self.lines.values().filter(|hits| **hits > 0).count()
+    }
+
+    /// The share of lines that ran, as a percentage; 100 for a script
+    /// without code.
+    pub fn percent(&self) -> f64 {
+        if self.lines.is_empty() {
+            return 100.0;
+        }
+        self.covered() as f64 * 100.0 / self.lines.len() as f64
+    }
+
+    /// The lines with code that never ran.
+    pub fn uncovered(&self) -> Vec<usize> {
+        self.lines.iter().filter(|(_, hits)| **hits == 0).map(|(line, _)| *line).collect()
+    }
+}
+
+/// The report in lcov's tracefile format, one record per file, for CI
+/// tools such as genhtml and codecov. Each script is a function starting
+/// at its first line with code.
+pub fn lcov(scripts: &[ScriptCoverage]) -> String {
+    let mut files: BTreeMap<&PathBuf, Vec<&ScriptCoverage>> = BTreeMap::new();
+    for script in scripts {
+        files.entry(&script.path).or_default().push(script);
+    }
+
+    let mut out = String::new();
+    for (path, scripts) in files {
+        out.push_str("TN:\n");
+        out.push_str(&format!("SF:{}\n", path.display()));
+        for script in &scripts {
+            if let Some(first) = script.lines.keys().next() {
+                out.push_str(&format!("FN:{},{}\n", first, script.script));
+            }
+        }
+        for script in &scripts {
+            let calls = script.lines.values().next().copied().unwrap_or(0);
+            out.push_str(&format!("FNDA:{},{}\n", calls, script.script));
+        }
+        out.push_str(&format!("FNF:{}\n", scripts.len()));
+        out.push_str(&format!("FNH:{}\n", scripts.iter().filter(|script| script.covered() > 0).count()));
+        let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+        for script in &scripts {
+            lines.extend(&script.lines);
+        }
+        for (line, hits) in &lines {
+            out.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        out.push_str(&format!("LF:{}\n", lines.len()));
+        out.push_str(&format!("LH:{}\n", lines.values().filter(|hits| **hits > 0).count()));
+        out.push_str("end_of_record\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const CLAMP: &str = "[proc,clamp](int $a)(int)\nif ($a > 100) {\n    $a = 100;\n}\nreturn($a);\n";
+
+    #[test]
+    fn a_branch_no_run_takes_is_reported_uncovered() {
+        let mut coverage = Coverage::new();
+        // A fresh VM per run, as the differential harness uses.
+        for arg in [5, 7] {
+            let mut vm = test_support::vm(CLAMP);
+            vm.enable_profiling();
+            vm.run_script("clamp", &[arg]).unwrap();
+            coverage.record(vm.profile());
+        }
+
+        let bytecode = &test_support::compile(CLAMP)[0];
+        let clamp = ScriptCoverage { script: "clamp".to_string(), path: PathBuf::from("clamp.rs2"), lines: coverage.lines(bytecode) };
+        assert_eq!(clamp.uncovered(), [3]);
+        assert_eq!(clamp.lines.get(&5), Some(&2));
+        assert!(clamp.percent() < 100.0 && clamp.percent() > 0.0, "{}", clamp.percent());
+
+        let lcov = lcov(&[clamp]);
+        assert!(lcov.starts_with("TN:\nSF:clamp.rs2\n"), "{}", lcov);
+        assert!(lcov.contains("DA:3,0\n") && lcov.contains("DA:5,2\n") && lcov.contains("FNH:1\n"), "{}", lcov);
+        assert!(lcov.ends_with("end_of_record\n"), "{}", lcov);
+    }
+
+    #[test]
+    fn a_script_that_never_ran_has_every_line_uncovered() {
+        let bytecode = &test_support::compile(CLAMP)[0];
+        let lines = Coverage::new().lines(bytecode);
+        assert!(!lines.is_empty());
+        assert!(lines.values().all(|hits| *hits == 0));
+    }
+}
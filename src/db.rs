@@ -0,0 +1,266 @@
+//! Database tables: rows of typed columns loaded from `.csv` and `.tsv`
+//! files, queried with `db_find`, `db_findnext` and `db_getfield`. The
+//! default host implements the queries with `Database`.
+
+use crate::host::HostValue;
+use crate::symbols::SymbolTable;
+use crate::types::{Type, NULL};
+
+/// Parameter and return types of the db commands, which go to the host like
+/// the stat commands. `db_find` also takes a value after these, and
+/// `db_getfield` returns one, of the column's type; see `Compiler::infer_type`.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "db_find" => Some((&[Type::DbTable, Type::DbColumn], Type::DbRow)),
+        "db_findnext" => Some((&[], Type::DbRow)),
+        "db_getfield" => Some((&[Type::DbRow, Type::DbColumn], Type::Int)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbColumn {
+    /// The column's dbcolumn id, shared by columns of the same name in
+    /// other tables.
+    pub id: i32,
+    pub name: String,
+    pub ty: Type,
+}
+
+/// One table, named after its file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbTable {
+    pub name: String,
+    pub columns: Vec<DbColumn>,
+    pub rows: Vec<Vec<HostValue>>,
+}
+
+impl DbTable {
+    pub fn column(&self, name: &str) -> Option<&DbColumn> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    fn column_index(&self, id: i32) -> Option<usize> {
+        self.columns.iter().position(|column| column.id == id)
+    }
+}
+
+/// Parses a table from `contents`, with fields split by `separator`: a
+/// header of `name:type` columns, then one row per line.
+///
+/// ```text
+/// fish:obj,level:int,members:boolean,name:string
+/// raw_shrimps,1,no,Shrimps
+/// ```
+///
+/// Values of npc and obj type are written by name and looked up in
+/// `symbols`; strings are taken as written, without quoting. `column_id`
+/// gives each column name its dbcolumn id.
+pub fn parse(
+    name: &str,
+    contents: &str,
+    separator: char,
+    symbols: &SymbolTable,
+    mut column_id: impl FnMut(&str) -> i32,
+) -> Result<DbTable, String> {
+    let mut lines = contents.lines().map(str::trim_end).filter(|line| !line.trim().is_empty());
+    let header = lines.next().ok_or_else(|| format!("table '{}' has no header", name))?;
+    let mut columns: Vec<DbColumn> = Vec::new();
+    for field in header.split(separator) {
+        let (column, type_name) = field
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("table '{}': expected column:type, found '{}'", name, field.trim()))?;
+        let ty = Type::from_name(type_name).ok_or_else(|| format!("table '{}': unknown type '{}'", name, type_name))?;
+        if columns.iter().any(|existing| existing.name == column) {
+            return Err(format!("table '{}' declares column '{}' twice", name, column));
+        }
+        columns.push(DbColumn { id: column_id(column), name: column.to_string(), ty });
+    }
+
+    let mut rows = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(separator).map(str::trim).collect();
+        if fields.len() != columns.len() {
+            return Err(format!(
+                "table '{}': row '{}' has {} field(s) but the table has {} column(s)",
+                name,
+                line,
+                fields.len(),
+                columns.len()
+            ));
+        }
+        let row = columns
+            .iter()
+            .zip(fields)
+            .map(|(column, text)| parse_value(name, &column.ty, text, symbols))
+            .collect::<Result<_, _>>()?;
+        rows.push(row);
+    }
+    Ok(DbTable { name: name.to_string(), columns, rows })
+}
+
+fn parse_value(table: &str, ty: &Type, text: &str, symbols: &SymbolTable) -> Result<HostValue, String> {
+    symbols
+        .parse_value(ty, text)
+        .ok_or_else(|| format!("table '{}': '{}' is not a valid {}", table, text, ty))
+}
+
+/// The last `db_find`, which `db_findnext` continues.
+#[derive(Debug, Clone, PartialEq)]
+struct Search {
+    table: usize,
+    column: usize,
+    value: HostValue,
+    /// The row to look at next.
+    next: usize,
+}
+
+/// The loaded tables, kept in memory for runs without a game server. A row
+/// handle numbers every row of every table, in table id order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Database {
+    /// Indexed by dbtable id.
+    tables: Vec<DbTable>,
+    search: Option<Search>,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the tables, by id, and forgets any search in progress.
+    pub fn configure(&mut self, tables: Vec<DbTable>) {
+        self.tables = tables;
+        self.search = None;
+    }
+
+    fn table(&self, table: i32) -> Result<&DbTable, String> {
+        usize::try_from(table)
+            .ok()
+            .and_then(|index| self.tables.get(index))
+            .ok_or_else(|| format!("{} is not a table", table))
+    }
+
+    fn row_handle(&self, table: usize, row: usize) -> i32 {
+        (self.tables[..table].iter().map(|table| table.rows.len()).sum::<usize>() + row) as i32
+    }
+
+    /// The table and row index a row handle names.
+    fn row(&self, handle: i32) -> Result<(&DbTable, usize), String> {
+        let mut row = usize::try_from(handle).map_err(|_| format!("{} is not a row", handle))?;
+        for table in &self.tables {
+            if row < table.rows.len() {
+                return Ok((table, row));
+            }
+            row -= table.rows.len();
+        }
+        Err(format!("{} is not a row", handle))
+    }
+
+    /// Runs `db_find`: the first row of `table` whose `column` holds
+    /// `value`, or `null` if none does. Starts the search `db_findnext`
+    /// continues.
+    pub fn find(&mut self, table: i32, column: i32, value: HostValue) -> Result<i32, String> {
+        let config = self.table(table)?;
+        let column = config
+            .column_index(column)
+            .ok_or_else(|| format!("table '{}' has no column {}", config.name, column))?;
+        self.search = Some(Search { table: table as usize, column, value, next: 0 });
+        self.find_next()
+    }
+
+    /// Runs `db_findnext`: the next row matching the last `db_find`, or
+    /// `null` once there are no more.
+    pub fn find_next(&mut self) -> Result<i32, String> {
+        let search = self.search.as_mut().ok_or("no db_find to continue")?;
+        let rows = &self.tables[search.table].rows;
+        match (search.next..rows.len()).find(|&row| rows[row][search.column] == search.value) {
+            Some(row) => {
+                search.next = row + 1;
+                let table = search.table;
+                Ok(self.row_handle(table, row))
+            }
+            None => {
+                search.next = rows.len();
+                Ok(NULL)
+            }
+        }
+    }
+
+    /// Runs `db_getfield`: the int value of `column` in `row`.
+    pub fn get_field(&self, row: i32, column: i32) -> Result<i32, String> {
+        if row == NULL {
+            return Err("the row is null".to_string());
+        }
+        let (table, row) = self.row(row)?;
+        let column = table
+            .column_index(column)
+            .ok_or_else(|| format!("table '{}' has no column {}", table.name, column))?;
+        match &table.rows[row][column] {
+            HostValue::Int(value) => Ok(*value),
+            HostValue::Str(_) => Err(format!("column '{}' holds strings, which can't be read yet", table.columns[column].name)),
+        }
+    }
+
+    /// Runs the db command `name`.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        match (name, args) {
+            ("db_find", [HostValue::Int(table), HostValue::Int(column), value]) => self.find(*table, *column, value.clone()),
+            ("db_findnext", []) => self.find_next(),
+            ("db_getfield", [HostValue::Int(row), HostValue::Int(column)]) => self.get_field(*row, *column),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    const FISHING: &str = "spot:string,level:int,fish:obj\nnet,1,shrimp\nbait,5,sardine\nnet,15,anchovies\n";
+
+    const SCRIPTS: &str = "[proc,net_levels]()(int)\n\
+        def_int $total = 0;\n\
+        def_dbrow $row = db_find(fishing, spot, \"net\");\n\
+        while (1 = 1) {\n    if ($row = null) {\n        return($total);\n    }\n\
+            $total = calc($total * 100 + db_getfield($row, level));\n    $row = db_findnext();\n}\n\
+        return(0);\n\
+        [proc,catches](obj $fish)(int)\n\
+        def_dbrow $row = db_find(fishing, fish, $fish);\n\
+        if ($row = null) {\n    return(0);\n}\n\
+        return(db_getfield($row, level));\n";
+
+    fn symbols() -> SymbolTable {
+        test_support::symbols(&[("items.obj", "[shrimp]\n\n[sardine]\n\n[anchovies]\n\n[shark]\n"), ("fishing.csv", FISHING)])
+    }
+
+    #[test]
+    fn every_match_is_found_in_order_on_both_backends() {
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols(), SCRIPTS);
+        // Levels 1 then 15, the two net rows.
+        assert_eq!(vm.run_script("net_levels", &[]), Ok(115));
+        assert_eq!(evaluator.eval_script("net_levels", &[]), Ok(115));
+        let (sardine, shark) = (1, 3);
+        assert_eq!(vm.run_script("catches", &[sardine]), Ok(5));
+        assert_eq!(evaluator.eval_script("catches", &[sardine]), Ok(5));
+        assert_eq!(vm.run_script("catches", &[shark]), Ok(0));
+        assert_eq!(evaluator.eval_script("catches", &[shark]), Ok(0));
+    }
+
+    #[test]
+    fn tables_columns_and_values_are_checked_against_the_schema() {
+        for source in [
+            "[proc,bad]()(dbrow)\nreturn(db_find(fishin, spot, \"net\"));\n",
+            "[proc,bad]()(dbrow)\nreturn(db_find(fishing, spots, \"net\"));\n",
+            "[proc,bad]()(dbrow)\nreturn(db_find(fishing, level, \"net\"));\n",
+        ] {
+            let mut compiler = Compiler::new();
+            compiler.set_symbols(symbols());
+            assert!(test_support::try_compile_with(&mut compiler, source).is_err(), "{}", source);
+        }
+    }
+}
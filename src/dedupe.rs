@@ -0,0 +1,91 @@
+//! Finding scripts that compiled to the same bytecode under different names,
+//! such as generated helpers, so the VM can store one body for all of them;
+//! see `VM::dedupe_scripts`.
+
+use std::collections::{BTreeMap, HashMap};
+use crate::bytecode::ByteCode;
+
+/// The duplicates among a set of scripts.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dedupe {
+    /// Each duplicate, by name, with the name of the script whose body it
+    /// shares.
+    pub aliases: BTreeMap<String, String>,
+    /// Instructions of the duplicates, no longer stored.
+    pub saved_instructions: usize,
+}
+
+impl Dedupe {
+    /// Each shared body with the names that call it besides its own.
+    pub fn groups(&self) -> BTreeMap<&str, Vec<&str>> {
+        let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (alias, body) in &self.aliases {
+            groups.entry(body.as_str()).or_default().push(alias.as_str());
+        }
+        groups
+    }
+}
+
+/// Groups `scripts` by `ByteCode::body_hash`, checking each match with
+/// `ByteCode::same_body`. Of each group the script first by name keeps its
+/// body and the others become aliases of it.
+pub fn find_duplicates(scripts: &HashMap<String, ByteCode>) -> Dedupe {
+    let mut names: Vec<&String> = scripts.keys().collect();
+    names.sort();
+
+    let mut dedupe = Dedupe::default();
+    let mut bodies: HashMap<u64, Vec<&String>> = HashMap::new();
+    for name in names {
+        let bytecode = &scripts[name];
+        let kept = bodies.entry(bytecode.body_hash()).or_default();
+        match kept.iter().find(|body| scripts[**body].same_body(bytecode)) {
+            Some(body) => {
+                dedupe.aliases.insert(name.clone(), (*body).clone());
+                dedupe.saved_instructions += bytecode.instructions.len();
+            }
+            None => kept.push(name),
+        }
+    }
+    dedupe
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,twice_a](int $n)(int)\nreturn(calc($n * 2));\n\
+        [proc,twice_b](int $n)(int)\nreturn(calc($n * 2));\n\
+        [proc,thrice](int $n)(int)\nreturn(calc($n * 3));\n\
+        [proc,main](int $n)(int)\nreturn(calc(~twice_a($n) + ~twice_b(calc($n + 1)) + ~thrice($n)));\n\
+        [proc,boom_a](int $n)(int)\nreturn(calc($n / 0));\n\
+        [proc,boom_b](int $n)(int)\n\nreturn(calc($n / 0));\n";
+
+    #[test]
+    fn two_identical_procs_share_one_body_under_both_names() {
+        let mut vm = test_support::vm(SCRIPTS);
+        let expected = vm.run_script("main", &[5]).unwrap();
+        assert_eq!(expected, 10 + 12 + 15);
+
+        let dedupe = vm.dedupe_scripts();
+        assert_eq!(dedupe.aliases.len(), 2, "{:?}", dedupe.aliases);
+        assert_eq!(dedupe.groups().get("twice_a"), Some(&vec!["twice_b"]));
+        assert_eq!(dedupe.groups().get("boom_a"), Some(&vec!["boom_b"]));
+        assert!(dedupe.saved_instructions > 0);
+        assert_eq!(vm.stored_scripts(), 4);
+        assert_eq!(vm.alias_of("twice_b"), Some("twice_a"));
+        assert_eq!(vm.alias_of("thrice"), None);
+
+        assert_eq!(vm.run_script("twice_a", &[4]), Ok(8));
+        assert_eq!(vm.run_script("twice_b", &[4]), Ok(8));
+        assert_eq!(vm.run_script("main", &[5]), Ok(expected));
+    }
+
+    #[test]
+    fn an_alias_reports_its_own_name_and_line() {
+        let mut vm = test_support::vm(SCRIPTS);
+        vm.dedupe_scripts();
+        let error = vm.run_script_with_state("boom_b", &[crate::host::HostValue::Int(1)]).unwrap_err();
+        let frame = error.frame.expect("the failing frame should be kept");
+        assert_eq!((frame.script.as_str(), frame.line), ("boom_b", Some(13)));
+    }
+}
@@ -0,0 +1,49 @@
+//! Dialogue: `p_mes` shows the player's chat head saying a line, animated
+//! with a mesanim from the `.mesanim` configs.
+
+use crate::types::Type;
+
+/// `p_mes` has the player say a line of text with the chathead animated by
+/// a mesanim, and returns 0.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "p_mes" => Some((&[Type::MesAnim, Type::String], Type::Int)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::host::{HostValue, RecordingHost};
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,greet](string $name)(int)\n\
+        p_mes(neutral, \"Hello, <$name>.\");\n\
+        p_mes(happy, \"Welcome to <tostring(calc(1 + 1))> worlds!<br>Enjoy.\");\n\
+        return(0);\n";
+
+    #[test]
+    fn a_dialogue_reaches_the_host_as_mesanim_and_text_on_both_backends() {
+        let symbols = test_support::symbols(&[("chat.mesanim", "[neutral]\n\n[happy]\n")]);
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, SCRIPTS);
+        let host = Rc::new(RefCell::new(RecordingHost::default()));
+        vm.set_host(host.clone());
+        evaluator.set_host(host.clone());
+        let args = [HostValue::Str("Zezima".to_string())];
+
+        vm.run_script_with_args("greet", &args).unwrap();
+        evaluator.eval_script_with_args("greet", &args).unwrap();
+        let said = |mesanim: i32, text: &str| ("p_mes".to_string(), vec![HostValue::Int(mesanim), HostValue::Str(text.to_string())]);
+        let dialogue = vec![said(0, "Hello, Zezima."), said(1, "Welcome to 2 worlds!<br>Enjoy.")];
+        assert_eq!(host.borrow().calls, [dialogue.clone(), dialogue].concat());
+    }
+
+    #[test]
+    fn an_unknown_mesanim_is_a_compile_error() {
+        let symbols = test_support::symbols(&[("chat.mesanim", "[neutral]\n\n[happy]\n")]);
+        let error = test_support::compile_error(&symbols, "[proc,bad]()(int)\np_mes(hapy, \"Hi\");\nreturn(0);\n");
+        assert!(error.to_string().contains("did you mean 'happy'?"), "{}", error);
+    }
+}
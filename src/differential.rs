@@ -0,0 +1,470 @@
+use crate::bytecode::ByteCode;
+use crate::compiler::Compiler;
+use crate::config::VmOptions;
+use crate::coverage::Coverage;
+use crate::error::{CompilerError, EvalError};
+use crate::evaluator::Evaluator;
+use crate::host::{HostCall, RecordingHost};
+use crate::parser::AstKind;
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Instruction budget for each harness run. Scripts that loop or recurse
+/// without bound should fail fast on both backends rather than stall the run.
+/// The VM counts every instruction against it but the Evaluator only loop
+/// iterations and script calls, so a call that hits the limit on one backend
+/// only is inconclusive rather than a divergence.
+pub const DEFAULT_MAX_INSTRUCTIONS: usize = 100_000;
+
+/// Argument values tried for every parameter by `Grid::default`.
+pub const DEFAULT_GRID: &[i32] = &[0, 1, 2, 3, 5, 8];
+
+/// Seed both backends draw `random` values from on every run.
+const SEED: u64 = 0;
+
+/// Broad kinds of runtime failure. The VM reports errors as strings and the
+/// Evaluator as `EvalError`, so outcomes are compared by class, not message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    DivisionByZero,
+    IntegerOverflow,
+    UnknownScript,
+    LimitExceeded,
+    Other,
+}
+
+impl ErrorClass {
+    fn of_vm(message: &str) -> Self {
+        if message.contains("Division by zero") {
+            ErrorClass::DivisionByZero
+        } else if message.contains("Integer overflow") {
+            ErrorClass::IntegerOverflow
+        } else if message.contains("not found") {
+            ErrorClass::UnknownScript
+        } else if message.contains("exceeded") {
+            ErrorClass::LimitExceeded
+        } else {
+            ErrorClass::Other
+        }
+    }
+
+    fn of_eval(error: &EvalError) -> Self {
+        match error {
+            EvalError::DivisionByZero => ErrorClass::DivisionByZero,
+            EvalError::IntegerOverflow => ErrorClass::IntegerOverflow,
+            EvalError::UnknownScript { .. } => ErrorClass::UnknownScript,
+            EvalError::LimitExceeded { .. } => ErrorClass::LimitExceeded,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+/// What one backend produced for one call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Value(i32),
+    Error(ErrorClass, String),
+}
+
+impl Outcome {
+    pub fn hit_limit(&self) -> bool {
+        matches!(self, Outcome::Error(ErrorClass::LimitExceeded, _))
+    }
+
+    fn agrees_with(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Value(a), Outcome::Value(b)) => a == b,
+            (Outcome::Error(a, _), Outcome::Error(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Value(value) => write!(f, "{}", value),
+            Outcome::Error(class, message) => write!(f, "{:?} error: {}", class, message),
+        }
+    }
+}
+
+/// What one backend did for one call: its result and, in order, the host
+/// commands it ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub outcome: Outcome,
+    pub calls: Vec<HostCall>,
+}
+
+impl Run {
+    /// Whether both runs had the same result, or failed the same way, and
+    /// ran the same host commands.
+    pub fn agrees_with(&self, other: &Run) -> bool {
+        self.outcome.agrees_with(&other.outcome) && self.calls == other.calls
+    }
+
+    /// Whether exactly one of the runs hit its limits. The backends count
+    /// toward their limits differently, so such a pair says nothing about
+    /// whether they agree.
+    pub fn inconclusive_with(&self, other: &Run) -> bool {
+        self.outcome.hit_limit() != other.outcome.hit_limit()
+    }
+}
+
+/// The first call on which the backends disagreed.
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    pub script: String,
+    pub args: Vec<i32>,
+    pub vm: Run,
+    pub interp: Run,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}{:?} diverged:", self.script, self.args)?;
+        writeln!(f, "  vm:     {}", self.vm.outcome)?;
+        write!(f, "  interp: {}", self.interp.outcome)?;
+        if self.vm.calls != self.interp.calls {
+            write!(f, "\n  vm commands:     {:?}", self.vm.calls)?;
+            write!(f, "\n  interp commands: {:?}", self.interp.calls)?;
+        }
+        Ok(())
+    }
+}
+
+/// What `DifferentialHarness::check_script` found for one script.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptCheck {
+    /// Calls run on both backends.
+    pub compared: usize,
+    /// Of those, calls where only one backend hit its limits.
+    pub inconclusive: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// Produces the argument vectors to try for a script with `arity` parameters.
+/// Implement this to drive the harness with generated inputs, e.g. random
+/// values from a seeded generator.
+pub trait ArgumentSource {
+    fn arguments(&mut self, arity: usize) -> Vec<Vec<i32>>;
+}
+
+/// Every combination of `values` across all parameters.
+pub struct Grid {
+    pub values: Vec<i32>,
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self { values: DEFAULT_GRID.to_vec() }
+    }
+}
+
+impl ArgumentSource for Grid {
+    fn arguments(&mut self, arity: usize) -> Vec<Vec<i32>> {
+        let mut combinations = vec![Vec::new()];
+        for _ in 0..arity {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|prefix| {
+                    self.values.iter().map(move |&value| {
+                        let mut args = prefix.clone();
+                        args.push(value);
+                        args
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+}
+
+/// Runs scripts on both the VM and the Evaluator and compares the results.
+pub struct DifferentialHarness {
+    options: VmOptions,
+    compiler: Compiler,
+    bytecode: Vec<ByteCode>,
+    evaluator: Evaluator,
+    /// Script names with their parameter counts, in registration order.
+    scripts: Vec<(String, usize)>,
+    /// Loaded into every fresh VM as well as the Evaluator.
+    symbols: SymbolTable,
+    /// What the VM runs executed, once `enable_coverage` is called.
+    coverage: Option<Coverage>,
+}
+
+impl DifferentialHarness {
+    pub fn new(options: &VmOptions) -> Self {
+        Self {
+            options: options.clone(),
+            compiler: Compiler::new(),
+            bytecode: Vec::new(),
+            evaluator: Evaluator::with_options(options),
+            scripts: Vec::new(),
+            symbols: SymbolTable::new(),
+            coverage: None,
+        }
+    }
+
+    /// Starts recording which instructions the VM runs execute, summed over
+    /// every later call.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+
+    /// What the VM runs executed so far, if coverage is enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// The compiled scripts, in registration order.
+    pub fn bytecode(&self) -> &[ByteCode] {
+        &self.bytecode
+    }
+
+    /// Sets the names both backends resolve, and the enums and varp
+    /// defaults they use at runtime; see `Compiler::set_symbols`.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.evaluator.load_symbols(&symbols);
+        self.compiler.set_symbols(symbols.clone());
+        self.symbols = symbols;
+    }
+
+    /// See `Compiler::set_strict_conditions`.
+    pub fn set_strict_conditions(&mut self, strict: bool) {
+        self.compiler.set_strict_conditions(strict);
+    }
+
+    /// Makes a trigger available for call folding before it is compiled; see
+    /// `Compiler::add_source`.
+    pub fn add_source(&mut self, node: &AstKind) {
+        self.compiler.add_source(node);
+    }
+
+    /// Compiles a trigger for the VM and registers its AST with the Evaluator.
+    pub fn add_script(&mut self, node: &AstKind, opt_level: u8) -> Result<(), CompilerError> {
+        let (AstKind::Trigger { args, .. }, Some(name)) = (node, node.script_name()) else {
+            return Ok(());
+        };
+
+        self.compiler.set_opt_level(opt_level);
+        self.bytecode.push(self.compiler.compile_script(name.clone(), node)?);
+        self.evaluator.register_script(name.clone(), self.compiler.resolve_symbols(node)?);
+
+        let arity = args.iter().filter(|arg| matches!(***arg, AstKind::LocalVar(_))).count();
+        self.scripts.push((name, arity));
+        Ok(())
+    }
+
+    /// Names and parameter counts of the registered scripts.
+    pub fn scripts(&self) -> &[(String, usize)] {
+        &self.scripts
+    }
+
+    /// Runs one call on both backends. Each VM run starts from a fresh VM, and
+    /// the Evaluator's memo cache and varps are cleared, so state left by
+    /// earlier calls can't hide a difference. Both draw `random` values from
+    /// the same seed.
+    pub fn run(&mut self, script: &str, args: &[i32]) -> (Run, Run) {
+        // Each backend records its host commands so the calls can be compared.
+        let vm_host = Rc::new(RefCell::new(RecordingHost::default()));
+        let mut vm = VM::with_options(&self.options);
+        vm.set_seed(SEED);
+        vm.set_host(vm_host.clone());
+        vm.load_symbols(&self.symbols);
+        for bytecode in &self.bytecode {
+            vm.register_script(bytecode.clone());
+        }
+        if self.options.dedupe_scripts {
+            vm.dedupe_scripts();
+        }
+        if self.coverage.is_some() {
+            vm.enable_profiling();
+        }
+        let vm_outcome = match vm.run_script(script, args) {
+            Ok(value) => Outcome::Value(value),
+            Err(message) => Outcome::Error(ErrorClass::of_vm(&message), message),
+        };
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(vm.profile());
+        }
+
+        let interp_host = Rc::new(RefCell::new(RecordingHost::default()));
+        self.evaluator.set_host(interp_host.clone());
+        self.evaluator.clear_memo_cache();
+        self.evaluator.clear_varps();
+        self.evaluator.set_seed(SEED);
+        let interp_outcome = match self.evaluator.eval_script(script, args) {
+            Ok(value) => Outcome::Value(value),
+            Err(error) => Outcome::Error(ErrorClass::of_eval(&error), error.to_string()),
+        };
+
+        let vm_calls = std::mem::take(&mut vm_host.borrow_mut().calls);
+        let interp_calls = std::mem::take(&mut interp_host.borrow_mut().calls);
+        (
+            Run { outcome: vm_outcome, calls: vm_calls },
+            Run { outcome: interp_outcome, calls: interp_calls },
+        )
+    }
+
+    /// Tries every argument vector `source` gives for `script`, stopping at
+    /// the first one on which the backends disagree. Calls where only one
+    /// backend hit its limits are counted as inconclusive, not divergent.
+    pub fn check_script(&mut self, script: &str, source: &mut dyn ArgumentSource) -> ScriptCheck {
+        let arity = self
+            .scripts
+            .iter()
+            .find(|(name, _)| name == script)
+            .map(|(_, arity)| *arity)
+            .unwrap_or(0);
+
+        let mut check = ScriptCheck::default();
+        for args in source.arguments(arity) {
+            check.compared += 1;
+            let (vm, interp) = self.run(script, &args);
+            if vm.inconclusive_with(&interp) {
+                check.inconclusive += 1;
+            } else if !vm.agrees_with(&interp) {
+                check.divergence = Some(Divergence {
+                    script: script.to_string(),
+                    args,
+                    vm,
+                    interp,
+                });
+                break;
+            }
+        }
+        check
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::HostValue;
+    use crate::test_support;
+
+    /// A harness with every trigger in `source` registered.
+    fn harness(source: &str) -> DifferentialHarness {
+        harness_with(&VmOptions { max_instructions: DEFAULT_MAX_INSTRUCTIONS, ..VmOptions::default() }, source)
+    }
+
+    fn harness_with(options: &VmOptions, source: &str) -> DifferentialHarness {
+        let script = test_support::parse(source);
+        let mut harness = DifferentialHarness::new(options);
+        for node in &script.body {
+            harness.add_source(node);
+        }
+        for node in &script.body {
+            harness.add_script(node, 1).unwrap();
+        }
+        harness
+    }
+
+    /// Runs `script` with each of `calls` on both backends, asserting they
+    /// agree, and returns the VM's runs.
+    fn agree(source: &str, script: &str, calls: &[&[i32]]) -> Vec<Run> {
+        agree_in(&mut harness(source), script, calls)
+    }
+
+    fn agree_in(harness: &mut DifferentialHarness, script: &str, calls: &[&[i32]]) -> Vec<Run> {
+        calls
+            .iter()
+            .map(|args| {
+                let (vm, interp) = harness.run(script, args);
+                assert!(vm.agrees_with(&interp), "{}", Divergence { script: script.to_string(), args: args.to_vec(), vm: vm.clone(), interp });
+                vm
+            })
+            .collect()
+    }
+
+    #[test]
+    fn return_inside_an_if_ends_the_script() {
+        let runs = agree("[proc,sign](int $n)(int)\nif ($n < 0) {\n    return(calc(0 - 1));\n}\nif ($n = 0) {\n    return(0);\n}\nreturn(1);", "sign", &[&[-4], &[0], &[9]]);
+        let outcomes: Vec<Outcome> = runs.into_iter().map(|run| run.outcome).collect();
+        assert_eq!(outcomes, vec![Outcome::Value(-1), Outcome::Value(0), Outcome::Value(1)]);
+    }
+
+    #[test]
+    fn string_locals_and_concatenation() {
+        let runs = agree(
+            "[proc,greet](int $n)(int)\ndef_string $name = \"bob\";\nmes(\"hi \" + $name + \" #<tostring($n)>\");\nreturn($n);",
+            "greet",
+            &[&[3]],
+        );
+        assert_eq!(runs[0].calls, vec![("mes".to_string(), vec![HostValue::Str("hi bob #3".to_string())])]);
+    }
+
+    #[test]
+    fn division_and_modulo_truncate_toward_zero() {
+        let source = "[proc,divide](int $a, int $b)(int)\nreturn(calc($a / $b));\n[proc,modulo](int $a, int $b)(int)\nreturn(calc($a % $b));";
+        for script in ["divide", "modulo"] {
+            let runs = agree(source, script, &[&[7, 2], &[-7, 2], &[7, -2], &[1, 0]]);
+            assert!(matches!(runs[3].outcome, Outcome::Error(ErrorClass::DivisionByZero, _)));
+        }
+        let outcomes: Vec<Outcome> = agree(source, "modulo", &[&[-7, 2]]).into_iter().map(|run| run.outcome).collect();
+        assert_eq!(outcomes, vec![Outcome::Value(-1)]);
+    }
+
+    #[test]
+    fn abs_min_and_max() {
+        let runs = agree(
+            "[proc,spread](int $a, int $b)(int)\nreturn(calc(max(abs($a), abs($b)) - min(abs($a), abs($b))));",
+            "spread",
+            &[&[-9, 4], &[3, -3]],
+        );
+        assert_eq!(runs[0].outcome, Outcome::Value(5));
+        let overflow = agree("[proc,magnitude](int $n)(int)\nreturn(abs($n));", "magnitude", &[&[i32::MIN]]);
+        assert!(matches!(overflow[0].outcome, Outcome::Error(ErrorClass::IntegerOverflow, _)));
+    }
+
+    #[test]
+    fn random_draws_the_same_values_from_the_shared_seed() {
+        let runs = agree("[proc,roll](int $n)(int)\nreturn(calc(random($n) + random($n)));", "roll", &[&[6], &[1000]]);
+        assert!(runs.iter().all(|run| matches!(run.outcome, Outcome::Value(_))));
+    }
+
+    #[test]
+    fn memoized_fib_30_fits_in_a_small_budget_on_both_backends() {
+        // Unmemoized, fib(30) makes over a million calls.
+        let options = VmOptions { max_instructions: 10_000, memoize: true, ..VmOptions::default() };
+        let mut harness = harness_with(&options, include_str!("../data/scripts/fib.rs2"));
+        let runs = agree_in(&mut harness, "fib", &[&[30]]);
+        assert_eq!(runs[0].outcome, Outcome::Value(832_040));
+    }
+
+    #[test]
+    fn script_calling_a_command_is_never_cached() {
+        let runs = agree(
+            "[proc,noisy](int $n)(int)\nmes(\"called\");\nreturn($n);\n[proc,twice]()(int)\nreturn(calc(~noisy(1) + ~noisy(1)));",
+            "twice",
+            &[&[]],
+        );
+        assert_eq!(runs[0].outcome, Outcome::Value(2));
+        assert_eq!(runs[0].calls.len(), 2, "{:?}", runs[0].calls);
+    }
+
+    #[test]
+    fn a_divergence_names_the_call_and_both_results() {
+        // The VM's caller is compiled against an older `k`, which call
+        // folding bakes in; the interpreter calls the `k` registered later.
+        let old = test_support::parse("[proc,k]()(int)\nreturn(1);\n");
+        let new = test_support::parse("[proc,k]()(int)\nreturn(2);\n[proc,caller](int $n)(int)\nreturn(calc(~k + $n));\n");
+        let mut harness = DifferentialHarness::new(&VmOptions { max_instructions: DEFAULT_MAX_INSTRUCTIONS, ..VmOptions::default() });
+        harness.add_source(&old.body[0]);
+        harness.add_source(&new.body[1]);
+        harness.add_script(&new.body[1], 1).unwrap();
+        harness.add_source(&new.body[0]);
+        harness.add_script(&new.body[0], 1).unwrap();
+
+        let check = harness.check_script("caller", &mut Grid { values: vec![0, 5] });
+        let divergence = check.divergence.expect("the backends should disagree");
+        assert_eq!(divergence.args, [0]);
+        assert_eq!(divergence.to_string(), "caller[0] diverged:\n  vm:     1\n  interp: 2");
+    }
+}
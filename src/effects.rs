@@ -0,0 +1,158 @@
+//! Visual effects: `anim` plays a seq on the player and `spotanim_pl` a
+//! spotanim over them, named by the `.seq` and `.spotanim` configs. The
+//! default host checks each against the configs and records it in
+//! `Effects`.
+
+use crate::host::HostValue;
+use crate::types::Type;
+
+/// The largest delay, in client cycles, and spotanim height the engine
+/// sends; it packs them into 16 bits each.
+pub const MAX_DELAY: i32 = 0xFFFF;
+pub const MAX_HEIGHT: i32 = 0xFFFF;
+
+/// `anim` takes a seq and a delay, and `spotanim_pl` a spotanim, a height
+/// and a delay; both return 0.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "anim" => Some((&[Type::Animation, Type::Int], Type::Int)),
+        "spotanim_pl" => Some((&[Type::Spotanim, Type::Int, Type::Int], Type::Int)),
+        _ => None,
+    }
+}
+
+/// One effect played by a script, with its seq or spotanim id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    Anim { seq: i32, delay: i32 },
+    SpotanimPl { spotanim: i32, height: i32, delay: i32 },
+}
+
+/// The seqs and spotanims, and every effect played, in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Effects {
+    /// Seq names, indexed by id.
+    seqs: Vec<String>,
+    /// Spotanim names, indexed by id.
+    spotanims: Vec<String>,
+    played: Vec<Effect>,
+}
+
+impl Effects {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the seq and spotanim names, by id. Effects already played are
+    /// kept.
+    pub fn configure(&mut self, seqs: Vec<String>, spotanims: Vec<String>) {
+        self.seqs = seqs;
+        self.spotanims = spotanims;
+    }
+
+    /// Every effect played so far, in order.
+    pub fn played(&self) -> &[Effect] {
+        &self.played
+    }
+
+    /// The name of the seq or spotanim `id`, checking it is loaded.
+    fn name<'a>(names: &'a [String], kind: &str, id: i32) -> Result<&'a str, String> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|index| names.get(index))
+            .map(String::as_str)
+            .ok_or_else(|| format!("{} is not a {}", id, kind))
+    }
+
+    fn check(what: &str, value: i32, max: i32) -> Result<(), String> {
+        if !(0..=max).contains(&value) {
+            return Err(format!("{} {} is outside 0..={}", what, value, max));
+        }
+        Ok(())
+    }
+
+    /// Checks `effect` against the configs, then records it and prints it.
+    pub fn play(&mut self, effect: Effect) -> Result<(), String> {
+        match &effect {
+            Effect::Anim { seq, delay } => {
+                let name = Self::name(&self.seqs, "seq", *seq)?;
+                Self::check("delay", *delay, MAX_DELAY)?;
+                println!("[anim {}, delay {}]", name, delay);
+            }
+            Effect::SpotanimPl { spotanim, height, delay } => {
+                let name = Self::name(&self.spotanims, "spotanim", *spotanim)?;
+                Self::check("height", *height, MAX_HEIGHT)?;
+                Self::check("delay", *delay, MAX_DELAY)?;
+                println!("[spotanim {}, height {}, delay {}]", name, height, delay);
+            }
+        }
+        self.played.push(effect);
+        Ok(())
+    }
+
+    /// Runs an effect command for the default host.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        let effect = match (name, args) {
+            ("anim", [HostValue::Int(seq), HostValue::Int(delay)]) => Effect::Anim { seq: *seq, delay: *delay },
+            ("spotanim_pl", [HostValue::Int(spotanim), HostValue::Int(height), HostValue::Int(delay)]) => {
+                Effect::SpotanimPl { spotanim: *spotanim, height: *height, delay: *delay }
+            }
+            _ => return Err(format!("unexpected arguments {:?}", args)),
+        };
+        self.play(effect).map(|()| 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+    use crate::host::CommandRegistry;
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,wave](int $height)(int)\n\
+        anim(human_wave, 0);\nspotanim_pl(sparkle, $height, 10);\nanim(human_idle, 30);\nreturn(0);\n";
+
+    fn symbols() -> SymbolTable {
+        test_support::symbols(&[("anims.seq", "[human_idle]\n\n[human_wave]\n"), ("gfx.spotanim", "[sparkle]\n")])
+    }
+
+    #[test]
+    fn an_effect_script_records_its_events_in_order_on_both_backends() {
+        let symbols = symbols();
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, SCRIPTS);
+        let host = Rc::new(RefCell::new(CommandRegistry::new()));
+        vm.set_host(host.clone());
+        vm.load_symbols(&symbols);
+        evaluator.set_host(host.clone());
+
+        vm.run_script("wave", &[92]).unwrap();
+        evaluator.eval_script("wave", &[92]).unwrap();
+        let (human_idle, human_wave, sparkle) = (0, 1, 0);
+        let wave = [
+            Effect::Anim { seq: human_wave, delay: 0 },
+            Effect::SpotanimPl { spotanim: sparkle, height: 92, delay: 10 },
+            Effect::Anim { seq: human_idle, delay: 30 },
+        ];
+        assert_eq!(host.borrow().effects().played(), [wave.clone(), wave].concat());
+    }
+
+    #[test]
+    fn an_out_of_range_height_fails_and_is_not_recorded() {
+        let symbols = symbols();
+        let (mut vm, _) = test_support::backends_with(&symbols, SCRIPTS);
+        let host = Rc::new(RefCell::new(CommandRegistry::new()));
+        vm.set_host(host.clone());
+        vm.load_symbols(&symbols);
+        assert!(vm.run_script("wave", &[65536]).is_err());
+        assert_eq!(host.borrow().effects().played(), [Effect::Anim { seq: 1, delay: 0 }]);
+    }
+
+    #[test]
+    fn an_unknown_seq_is_a_compile_error_with_a_suggestion() {
+        let error = test_support::compile_error(&symbols(), "[proc,bad]()(int)\nanim(human_wavee, 0);\nreturn(0);\n");
+        assert!(error.to_string().contains("did you mean 'human_wave'?"), "{}", error);
+    }
+}
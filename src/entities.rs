@@ -0,0 +1,262 @@
+//! Players and npcs in the world, referenced by scripts through `playeruid`
+//! and `npcuid` values. The default host keeps them in `Entities`; tests and
+//! `rsc run --spawn` put them there.
+
+use std::collections::BTreeMap;
+use crate::coord;
+use crate::host::HostValue;
+use crate::symbols::SymbolTable;
+use crate::types::{Type, NULL};
+
+/// `npc_find` gives the uid of the closest npc of a type to a coord,
+/// `npc_uid` and `player_uid` those of the active npc and player, and
+/// `npc_coord` where an npc stands.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "npc_find" => Some((&[Type::Coord, Type::Npc], Type::NpcUid)),
+        "npc_uid" => Some((&[], Type::NpcUid)),
+        "player_uid" => Some((&[], Type::PlayerUid)),
+        "npc_coord" => Some((&[Type::NpcUid], Type::Coord)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Npc {
+    /// The npc config id.
+    pub npc: i32,
+    pub name: String,
+    pub coord: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Player {
+    pub name: String,
+    pub coord: i32,
+}
+
+/// The spawned entities. Every spawn gets a new uid, never reused, so a
+/// uid kept after its entity despawns stays invalid instead of naming
+/// whatever spawned next.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entities {
+    next_uid: i32,
+    npcs: BTreeMap<i32, Npc>,
+    players: BTreeMap<i32, Player>,
+    /// The npc `npc_uid` returns, set by `npc_find`.
+    active_npc: Option<i32>,
+    /// The player `player_uid` returns: the first spawned, unless changed.
+    active_player: Option<i32>,
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn next_uid(&mut self) -> i32 {
+        let uid = self.next_uid;
+        self.next_uid += 1;
+        uid
+    }
+
+    /// Spawns an npc of config id `npc` and returns its uid.
+    pub fn spawn_npc(&mut self, npc: i32, name: &str, coord: i32) -> i32 {
+        let uid = self.next_uid();
+        self.npcs.insert(uid, Npc { npc, name: name.to_string(), coord });
+        uid
+    }
+
+    /// Spawns a player and returns its uid. The first player spawned
+    /// becomes the active one.
+    pub fn spawn_player(&mut self, name: &str, coord: i32) -> i32 {
+        let uid = self.next_uid();
+        self.players.insert(uid, Player { name: name.to_string(), coord });
+        self.active_player.get_or_insert(uid);
+        uid
+    }
+
+    /// Spawns `spawn`, looking its npc up in `symbols`, and returns its uid.
+    pub fn spawn(&mut self, spawn: &Spawn, symbols: &SymbolTable) -> Result<i32, String> {
+        if !spawn.npc {
+            return Ok(self.spawn_player(&spawn.name, spawn.coord));
+        }
+        let npc = symbols
+            .lookup(&Type::Npc, &spawn.name)
+            .ok_or_else(|| format!("can't spawn unknown npc '{}'", spawn.name))?;
+        Ok(self.spawn_npc(npc, &spawn.name, spawn.coord))
+    }
+
+    /// Removes the player or npc `uid`; returns whether it was spawned.
+    pub fn despawn(&mut self, uid: i32) -> bool {
+        self.npcs.remove(&uid).is_some() || self.players.remove(&uid).is_some()
+    }
+
+    pub fn npc(&self, uid: i32) -> Option<&Npc> {
+        self.npcs.get(&uid)
+    }
+
+    pub fn player(&self, uid: i32) -> Option<&Player> {
+        self.players.get(&uid)
+    }
+
+    /// Makes `uid` the player `player_uid` returns.
+    pub fn set_active_player(&mut self, uid: i32) -> Result<(), String> {
+        self.spawned_player(uid)?;
+        self.active_player = Some(uid);
+        Ok(())
+    }
+
+    fn spawned_npc(&self, uid: i32) -> Result<&Npc, String> {
+        if uid == NULL {
+            return Err("the npc uid is null".to_string());
+        }
+        self.npcs.get(&uid).ok_or_else(|| format!("npc uid {} is not spawned; it may have despawned", uid))
+    }
+
+    fn spawned_player(&self, uid: i32) -> Result<&Player, String> {
+        if uid == NULL {
+            return Err("the player uid is null".to_string());
+        }
+        self.players.get(&uid).ok_or_else(|| format!("player uid {} is not spawned; it may have despawned", uid))
+    }
+
+    /// Runs `npc_find`: the closest npc of config id `npc` on the level of
+    /// `coord`, the earliest spawned on a tie, or `null` if there is none.
+    /// The npc found becomes the active npc.
+    pub fn find_npc(&mut self, coord: i32, npc: i32) -> i32 {
+        let found = self
+            .npcs
+            .iter()
+            .filter(|(_, spawned)| spawned.npc == npc && coord::level(spawned.coord) == coord::level(coord))
+            .min_by_key(|(uid, spawned)| (coord::distance(spawned.coord, coord), **uid))
+            .map(|(uid, _)| *uid);
+        self.active_npc = found;
+        found.unwrap_or(NULL)
+    }
+
+    /// Runs `npc_uid`: the npc the last `npc_find` found.
+    pub fn active_npc(&self) -> Result<i32, String> {
+        let uid = self.active_npc.ok_or("there is no active npc; find one with npc_find first")?;
+        self.spawned_npc(uid)?;
+        Ok(uid)
+    }
+
+    /// Runs `player_uid`: the active player.
+    pub fn active_player(&self) -> Result<i32, String> {
+        let uid = self.active_player.ok_or("no player is spawned")?;
+        self.spawned_player(uid)?;
+        Ok(uid)
+    }
+
+    /// Runs `npc_coord`.
+    pub fn npc_coord(&self, uid: i32) -> Result<i32, String> {
+        Ok(self.spawned_npc(uid)?.coord)
+    }
+
+    /// Runs an entity command for the default host.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        match (name, args) {
+            ("npc_find", [HostValue::Int(coord), HostValue::Int(npc)]) => Ok(self.find_npc(*coord, *npc)),
+            ("npc_uid", []) => self.active_npc(),
+            ("player_uid", []) => self.active_player(),
+            ("npc_coord", [HostValue::Int(uid)]) => self.npc_coord(*uid),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        }
+    }
+}
+
+/// An entity to spawn before a run, given as `npc:NAME:COORD` or
+/// `player:NAME:COORD` with the coord written `level_x_z`, e.g.
+/// `npc:man:0_3222_3218`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spawn {
+    /// Whether this is an npc, named by its config, or a player.
+    pub npc: bool,
+    pub name: String,
+    pub coord: i32,
+}
+
+impl Spawn {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let expected = || format!("expected npc:NAME:COORD or player:NAME:COORD, got '{}'", text);
+        let mut fields = text.splitn(3, ':');
+        let (Some(kind), Some(name), Some(coord)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(expected());
+        };
+        let npc = match kind {
+            "npc" => true,
+            "player" => false,
+            _ => return Err(expected()),
+        };
+        let parts: Vec<i32> = coord
+            .split('_')
+            .map(|part| part.parse().map_err(|_| format!("'{}' is not a coord; write it as level_x_z", coord)))
+            .collect::<Result<_, _>>()?;
+        let [level, x, z] = parts[..] else {
+            return Err(format!("'{}' is not a coord; write it as level_x_z", coord));
+        };
+        Ok(Spawn { npc, name: name.to_string(), coord: coord::pack(level, x, z)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+    use crate::host::CommandRegistry;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,nearest_man](coord $from)(coord)\n\
+        def_npcuid $uid = npc_find($from, man);\nreturn(npc_coord($uid));\n\
+        [proc,found]()(coord)\nreturn(npc_coord(npc_uid()));\n\
+        [proc,me]()(playeruid)\nreturn(player_uid());\n";
+
+    #[test]
+    fn an_npc_spawned_through_the_host_is_found_by_a_script_until_it_despawns() {
+        let symbols = test_support::symbols(&[("people.npc", "[goblin]\n\n[man]\n")]);
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, SCRIPTS);
+        let host = Rc::new(RefCell::new(CommandRegistry::new()));
+        vm.set_host(host.clone());
+        evaluator.set_host(host.clone());
+
+        let here = coord::pack(0, 3222, 3218).unwrap();
+        let near = coord::pack(0, 3225, 3218).unwrap();
+        let far = coord::pack(0, 3240, 3218).unwrap();
+        let upstairs = coord::pack(1, 3222, 3218).unwrap();
+        let (goblin, man) = (0, 1);
+        let player = {
+            let mut host = host.borrow_mut();
+            let entities = host.entities_mut();
+            entities.spawn_npc(man, "man", far);
+            entities.spawn_npc(goblin, "goblin", here);
+            entities.spawn_npc(man, "man", upstairs);
+            entities.spawn_npc(man, "man", near);
+            entities.spawn_player("Zezima", here)
+        };
+
+        assert_eq!(vm.run_script("nearest_man", &[here]), Ok(near));
+        assert_eq!(evaluator.eval_script("nearest_man", &[here]), Ok(near));
+        assert_eq!(vm.run_script("found", &[]), Ok(near));
+        assert_eq!(vm.run_script("me", &[]), Ok(player));
+        assert_eq!(evaluator.eval_script("me", &[]), Ok(player));
+
+        let found = host.borrow().entities().active_npc().unwrap();
+        assert!(host.borrow_mut().entities_mut().despawn(found));
+        let message = format!("npc uid {} is not spawned; it may have despawned", found);
+        let error = vm.run_script("found", &[]).unwrap_err();
+        assert!(error.contains(&message), "{}", error);
+        let error = evaluator.eval_script("found", &[]).unwrap_err();
+        assert!(error.to_string().contains(&message), "{}", error);
+    }
+
+    #[test]
+    fn spawns_parse_from_the_command_line_form() {
+        let spawn = Spawn::parse("npc:man:0_3222_3218").unwrap();
+        assert_eq!(spawn, Spawn { npc: true, name: "man".to_string(), coord: coord::pack(0, 3222, 3218).unwrap() });
+        assert!(!Spawn::parse("player:Zezima:1_3200_3200").unwrap().npc);
+        assert!(Spawn::parse("npc:man").is_err());
+        assert!(Spawn::parse("npc:man:3222_3218").is_err());
+    }
+}
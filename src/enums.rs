@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use crate::symbols::{self, SymbolTable};
+use crate::types::Type;
+
+/// One `[name]` section of a `.enum` config: a map from keys of one type to
+/// values of another, read by scripts with `enum_getvalue`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumConfig {
+    pub name: String,
+    pub key_type: Type,
+    pub value_type: Type,
+    /// Returned for keys without an entry; without one, such a lookup fails.
+    pub default: Option<i32>,
+    pub entries: HashMap<i32, i32>,
+}
+
+impl EnumConfig {
+    pub fn get(&self, key: i32) -> Result<i32, String> {
+        self.entries
+            .get(&key)
+            .copied()
+            .or(self.default)
+            .ok_or_else(|| format!("enum_getvalue: key {} is not in enum '{}' and it has no default", key, self.name))
+    }
+}
+
+/// Parses the sections of a `.enum` file:
+///
+/// ```text
+/// [weapon_damage]
+/// inputtype=obj
+/// outputtype=int
+/// default=1
+/// val=bronze_sword,5
+/// ```
+///
+/// Keys and values of npc and obj type are written by name and looked up in
+/// `symbols`; other types are written as ints.
+pub fn parse(contents: &str, symbols: &SymbolTable) -> Result<Vec<EnumConfig>, String> {
+    symbols::sections(contents)?
+        .into_iter()
+        .map(|(name, fields)| build(name, fields, symbols))
+        .collect()
+}
+
+fn build(name: String, fields: Vec<(String, String)>, symbols: &SymbolTable) -> Result<EnumConfig, String> {
+    let field_type = |field: &str| -> Result<Type, String> {
+        let (_, type_name) = fields
+            .iter()
+            .find(|(key, _)| key == field)
+            .ok_or_else(|| format!("enum '{}' has no {}", name, field))?;
+        match Type::from_name(type_name) {
+            Some(Type::String) => Err(format!("enum '{}': string {}s aren't supported yet", name, field)),
+            Some(ty) => Ok(ty),
+            None => Err(format!("enum '{}': unknown type '{}'", name, type_name)),
+        }
+    };
+    let key_type = field_type("inputtype")?;
+    let value_type = field_type("outputtype")?;
+
+    let mut default = None;
+    let mut entries = HashMap::new();
+    for (key, value) in &fields {
+        match key.as_str() {
+            "default" => default = Some(parse_value(&name, &value_type, value, symbols)?),
+            "val" => {
+                let (entry_key, entry_value) = value
+                    .split_once(',')
+                    .ok_or_else(|| format!("enum '{}': expected val=key,value, found 'val={}'", name, value))?;
+                entries.insert(
+                    parse_value(&name, &key_type, entry_key.trim(), symbols)?,
+                    parse_value(&name, &value_type, entry_value.trim(), symbols)?,
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(EnumConfig { name, key_type, value_type, default, entries })
+}
+
+fn parse_value(name: &str, ty: &Type, text: &str, symbols: &SymbolTable) -> Result<i32, String> {
+    if let Some(id) = symbols.lookup(ty, text) {
+        return Ok(id);
+    }
+    match (ty, text) {
+        (Type::Boolean, "true" | "yes") => Ok(1),
+        (Type::Boolean, "false" | "no") => Ok(0),
+        _ => text.parse().map_err(|_| format!("enum '{}': '{}' is not a valid {}", name, text, ty)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,damage](obj $weapon)(int)\nreturn(enum_getvalue(weapon_damage, $weapon));\n\
+        [proc,price](obj $item)(int)\nreturn(enum_getvalue(shop_price, $item));\n";
+
+    const ENUMS: &str = "[weapon_damage]\ninputtype=obj\noutputtype=int\ndefault=1\nval=bronze_sword,5\nval=iron_sword,8\n\n\
+        [shop_price]\ninputtype=obj\noutputtype=int\nval=logs,4\n";
+
+    #[test]
+    fn present_and_missing_keys_on_both_backends() {
+        let symbols = test_support::symbols(&[("items.obj", "[bronze_sword]\n[iron_sword]\n[logs]\n"), ("lookups.enum", ENUMS)]);
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, SCRIPTS);
+        let (bronze_sword, iron_sword, logs) = (0, 1, 2);
+
+        assert_eq!(vm.run_script("damage", &[iron_sword]), Ok(8));
+        assert_eq!(evaluator.eval_script("damage", &[iron_sword]), Ok(8));
+        assert_eq!(vm.run_script("damage", &[bronze_sword]), Ok(5));
+        // Logs aren't a weapon, so they get the declared default.
+        assert_eq!(vm.run_script("damage", &[logs]), Ok(1));
+        assert_eq!(evaluator.eval_script("damage", &[logs]), Ok(1));
+
+        assert_eq!(vm.run_script("price", &[logs]), Ok(4));
+        let message = "enum_getvalue: key 0 is not in enum 'shop_price' and it has no default";
+        let error = vm.run_script("price", &[bronze_sword]).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = evaluator.eval_script("price", &[bronze_sword]).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::token::Token;
+use crate::types::Type;
 use std::fmt;
 use std::path::PathBuf;
 use std::error::Error;
@@ -9,10 +10,40 @@ pub enum CompilerError {
     IO(std::io::Error),
     LexingError(LexingError),
     Syntax(SyntaxError),
+    SyntaxErrors(SyntaxErrors),
+    Type(String),
+    /// A missing included file or an include cycle.
+    Include(String),
+    /// Two files, or one file twice, declaring the same trigger.
+    DuplicateScript { name: String, first: PathBuf, second: PathBuf },
 }
 
 impl Error for CompilerError {}
 
+impl From<std::io::Error> for CompilerError {
+    fn from(err: std::io::Error) -> Self {
+        CompilerError::IO(err)
+    }
+}
+
+impl From<LexingError> for CompilerError {
+    fn from(err: LexingError) -> Self {
+        CompilerError::LexingError(err)
+    }
+}
+
+impl From<SyntaxError> for CompilerError {
+    fn from(err: SyntaxError) -> Self {
+        CompilerError::Syntax(err)
+    }
+}
+
+impl From<SyntaxErrors> for CompilerError {
+    fn from(errs: SyntaxErrors) -> Self {
+        CompilerError::SyntaxErrors(errs)
+    }
+}
+
 impl fmt::Display for CompilerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -20,6 +51,12 @@ impl fmt::Display for CompilerError {
             CompilerError::FileNotFound(err) => writeln!(f, "FileNotFoundError: {}", err),
             CompilerError::LexingError(err) => writeln!(f, "LexingError: {}", err),
             CompilerError::Syntax(err) => writeln!(f, "SyntaxError: {}", err),
+            CompilerError::SyntaxErrors(errs) => write!(f, "{}", errs),
+            CompilerError::Type(err) => writeln!(f, "TypeError: {}", err),
+            CompilerError::Include(err) => writeln!(f, "IncludeError: {}", err),
+            CompilerError::DuplicateScript { name, first, second } => {
+                writeln!(f, "DuplicateScriptError: '{}' is declared in both {} and {}", name, first.display(), second.display())
+            }
         }
     }
 }
@@ -56,6 +93,28 @@ pub struct SyntaxError{
 
 impl Error for SyntaxError {}
 
+/// Every syntax error found in one file by a recovering parse.
+#[derive(Debug)]
+pub struct SyntaxErrors {
+    pub errors: Vec<SyntaxError>,
+    /// More errors followed the last one collected.
+    pub truncated: bool,
+}
+
+impl Error for SyntaxErrors {}
+
+impl fmt::Display for SyntaxErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for err in &self.errors {
+            write!(f, "{}", err)?;
+        }
+        if self.truncated {
+            writeln!(f, "... and more")?;
+        }
+        Ok(())
+    }
+}
+
 impl SyntaxError {
     pub fn from_token(path: PathBuf, token: &Token, message: String) -> Self {
         Self {
@@ -94,3 +153,210 @@ impl fmt::Display for SyntaxError {
         )
     }
 }
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownKey(String),
+    EnvNotFound(String),
+    InheritanceCycle(Vec<String>),
+    AliasCycle(Vec<String>),
+    Parse {
+        path: PathBuf,
+        message: String,
+    },
+    InvalidValue {
+        key: String,
+        value: String,
+        expected: String,
+    },
+}
+
+impl Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnknownKey(key) => write!(f, "Unknown config key '{}'", key),
+            ConfigError::EnvNotFound(name) => write!(f, "Environment '{}' has no config.toml or config.json", name),
+            ConfigError::InheritanceCycle(chain) => {
+                write!(f, "Environment inheritance cycle: {}", chain.join(" -> "))
+            }
+            ConfigError::AliasCycle(chain) => write!(f, "Recursive alias: {}", chain.join(" -> ")),
+            ConfigError::Parse { path, message } => write!(f, "Failed to read {}: {}", path.display(), message),
+            ConfigError::InvalidValue { key, value, expected } => {
+                write!(f, "Invalid value '{}' for '{}': expected {}", value, key, expected)
+            }
+        }
+    }
+}
+
+/// Runtime errors from the tree-walking `Evaluator`. Messages match the VM's
+/// where both backends can fail the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    UndefinedVariable(String),
+    DivisionByZero,
+    IntegerOverflow,
+    UnknownScript {
+        name: String,
+        suggestion: Option<String>,
+    },
+    UnknownFunction(String),
+    MissingArgument(String),
+    UnknownOperator(String),
+    InvalidAssignmentTarget,
+    InvalidCallTarget,
+    ArgumentCount {
+        script: String,
+        /// Parameters without a default.
+        min: usize,
+        max: usize,
+        found: usize,
+    },
+    /// A coord command got a level or x/z outside the packable range.
+    InvalidCoord(String),
+    /// `toint` got a string that isn't a number.
+    InvalidConversion(String),
+    /// `enum_getvalue` named an enum that isn't loaded, or a key it lacks
+    /// with no default.
+    EnumLookup(String),
+    /// A param lookup named a param that isn't loaded, or one its owner
+    /// lacks with no default.
+    ParamLookup(String),
+    /// A value written to a varbit doesn't fit in its bits.
+    InvalidVarbitValue(String),
+    /// `substring` got indices outside its string.
+    InvalidStringIndex(String),
+    /// `random` got a negative bound.
+    InvalidRandomBound(String),
+    /// `settimer` got a delay of less than one tick.
+    InvalidTimerDelay(String),
+    /// `rgb` got a component outside 0..255, or `color_component` a bad
+    /// colour or index.
+    InvalidColour(String),
+    /// A host command reported a failure.
+    Host {
+        command: String,
+        message: String,
+    },
+    /// A parameter list that isn't a sequence of `type $name` pairs.
+    MalformedSignature(String),
+    /// A parameter whose declared type has no runtime value yet.
+    UnsupportedParameterType {
+        script: String,
+        param: String,
+        param_type: Type,
+    },
+    LimitExceeded {
+        script: String,
+        limit: EvalLimit,
+        steps: usize,
+        depth: usize,
+    },
+}
+
+/// Which of the `Evaluator`'s limits was hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EvalLimit {
+    /// Loop iterations plus script calls in one run.
+    Steps(usize),
+    /// Nested script calls.
+    CallDepth(usize),
+}
+
+impl Error for EvalError {}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UndefinedVariable(name) => write!(f, "Undefined variable: ${}", name),
+            EvalError::DivisionByZero => write!(f, "Division by zero"),
+            EvalError::IntegerOverflow => write!(f, "Integer overflow"),
+            EvalError::UnknownScript { name, suggestion } => {
+                write!(f, "Script '{}' not found", name)?;
+                if let Some(suggestion) = suggestion {
+                    write!(f, ". Did you mean '{}'?", suggestion)?;
+                }
+                Ok(())
+            }
+            EvalError::UnknownFunction(name) => write!(f, "Unknown function: {}", name),
+            EvalError::MissingArgument(function) => write!(f, "{} requires one argument", function),
+            EvalError::InvalidCoord(message)
+            | EvalError::InvalidConversion(message)
+            | EvalError::EnumLookup(message)
+            | EvalError::ParamLookup(message)
+            | EvalError::InvalidVarbitValue(message)
+            | EvalError::InvalidStringIndex(message)
+            | EvalError::InvalidRandomBound(message)
+            | EvalError::InvalidTimerDelay(message)
+            | EvalError::InvalidColour(message) => {
+                write!(f, "{}", message)
+            }
+            EvalError::UnknownOperator(operator) => write!(f, "Unknown operator: {}", operator),
+            EvalError::InvalidAssignmentTarget => write!(f, "Invalid assignment target"),
+            EvalError::InvalidCallTarget => write!(f, "Invalid script call target"),
+            EvalError::ArgumentCount { script, min, max, found } => {
+                if min == max {
+                    write!(f, "Script '{}' takes {} argument(s) but {} were given", script, max, found)
+                } else {
+                    write!(f, "Script '{}' takes {} to {} arguments but {} were given", script, min, max, found)
+                }
+            }
+            EvalError::Host { command, message } => write!(f, "{}: {}", command, message),
+            EvalError::MalformedSignature(script) => {
+                write!(f, "Script '{}' has a malformed parameter list", script)
+            }
+            EvalError::UnsupportedParameterType { script, param, param_type } => write!(
+                f,
+                "Parameter ${} of script '{}' has type {}, which can't be passed as an argument yet",
+                param, script, param_type
+            ),
+            EvalError::LimitExceeded { script, limit, steps, depth } => {
+                match limit {
+                    EvalLimit::Steps(max) => write!(f, "Execution exceeded maximum step count ({})", max)?,
+                    EvalLimit::CallDepth(max) => write!(f, "Execution exceeded maximum call depth ({})", max)?,
+                }
+                write!(f, " in script '{}' after {} steps at call depth {}", script, steps, depth)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::session;
+
+    /// Lexes `source` the way a call site would, relying on `?` alone.
+    fn lex(source: &str) -> Result<usize, CompilerError> {
+        Ok(Lexer::new(source, &PathBuf::from("broken.rs2")).tokenize()?.len())
+    }
+
+    #[test]
+    fn a_lexing_failure_propagates_through_the_question_mark_as_a_lexing_error() {
+        let error = lex("[proc,broken]()\nmes(\"unterminated);\n").unwrap_err();
+        assert!(matches!(error, CompilerError::LexingError(_)), "{:?}", error);
+        assert!(error.to_string().starts_with("LexingError: "), "{}", error);
+        assert!(lex("[proc,fine]()\nmes(\"done\");\n").is_ok());
+    }
+
+    #[test]
+    fn each_stage_of_reading_a_file_keeps_its_own_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.rs2");
+        let error = session::process_rs2_file(&missing, 10).unwrap_err();
+        assert!(matches!(error, CompilerError::IO(_)), "{:?}", error);
+        assert!(error.to_string().starts_with("IOError: "), "{}", error);
+
+        let unterminated = dir.path().join("unterminated.rs2");
+        std::fs::write(&unterminated, "[proc,broken]()\nmes(\"unterminated);\n").unwrap();
+        let error = session::process_rs2_file(&unterminated, 10).unwrap_err();
+        assert!(matches!(error, CompilerError::LexingError(_)), "{:?}", error);
+
+        let unbalanced = dir.path().join("unbalanced.rs2");
+        std::fs::write(&unbalanced, "[proc,broken]()\nmes(\"hi\";\n").unwrap();
+        let error = session::process_rs2_file(&unbalanced, 10).unwrap_err();
+        assert!(matches!(error, CompilerError::SyntaxErrors(_)), "{:?}", error);
+    }
+}
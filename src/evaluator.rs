@@ -1,128 +1,353 @@
 use std::collections::HashMap;
+use crate::annotations;
+use crate::config::{ScriptOverrides, VmOptions};
+use crate::convert;
+use crate::colour;
+use crate::compiler;
+use crate::coord;
+use crate::enums::EnumConfig;
+use crate::error::{EvalError, EvalLimit};
+use crate::host::{self, Host, HostContext, HostValue};
+use crate::optimize::Purity;
+use crate::params::{self, Params};
 use crate::parser::AstKind;
+use crate::random::Rng;
+use crate::scheduler::Timers;
+use crate::strings;
+use crate::symbols::SymbolTable;
+use crate::types::{Type, NULL};
+use crate::varbits;
+use crate::vm::MemoKey;
+
+/// The result of evaluating an expression.
+pub type Value = i32;
+
 
 pub struct Evaluator {
-    pub variables: HashMap<String, i32>,
+    pub variables: HashMap<String, Value>,
+    /// String locals, kept apart from ints as the VM keeps them.
+    pub string_variables: HashMap<String, String>,
     scripts: HashMap<String, AstKind>,
+    strict: bool,
+    /// Loop iterations and script calls allowed in one run.
+    max_steps: usize,
+    /// Nested script calls allowed. Each call recurses on the native stack,
+    /// so this keeps deep recursion from overflowing it.
+    max_depth: usize,
+    steps: usize,
+    depth: usize,
+    current_script: String,
+    /// Set by `return` and cleared when the script it belongs to finishes;
+    /// blocks and loops stop as soon as it is set.
+    returned: Option<Value>,
+    memoize: bool,
+    /// See `VmOptions::lenient_args`.
+    lenient_args: bool,
+    /// Per-script `memoize` overrides, keyed by lowercased name as in the VM.
+    memoize_overrides: HashMap<String, bool>,
+    memo_cache: HashMap<MemoKey, Value>,
+    purity: Purity,
+    host: Host,
+    enums: HashMap<String, EnumConfig>,
+    /// Params read by `oc_param`, `nc_param` and `struct_param`.
+    params: Params,
+    /// Values of `%name` varps, which like the VM's outlive a script call.
+    varps: HashMap<i32, Value>,
+    varp_defaults: HashMap<i32, Value>,
+    rng: Rng,
+    /// String arguments for the next `eval_script`, by position; see
+    /// `eval_script_with_args`.
+    entry_string_args: HashMap<usize, String>,
+    /// Timers set by `settimer`. Only the VM has a `Scheduler` to fire
+    /// them; see `VM::timers`.
+    timers: Timers,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Evaluator {
     pub fn new() -> Self {
+        Self::with_options(&VmOptions::default())
+    }
+
+    /// Takes its step limit from `max_instructions` and its depth limit from
+    /// `max_call_depth`, so both backends share one set of defaults.
+    pub fn with_options(options: &VmOptions) -> Self {
         Self {
             variables: HashMap::new(),
+            string_variables: HashMap::new(),
             scripts: HashMap::new(),
+            strict: false,
+            max_steps: options.max_instructions,
+            max_depth: options.max_call_depth,
+            steps: 0,
+            depth: 0,
+            current_script: String::new(),
+            returned: None,
+            memoize: options.memoize,
+            lenient_args: options.lenient_args,
+            memoize_overrides: HashMap::new(),
+            memo_cache: HashMap::new(),
+            purity: Purity::new(),
+            host: Host::default(),
+            enums: HashMap::new(),
+            params: Params::default(),
+            varps: HashMap::new(),
+            varp_defaults: HashMap::new(),
+            rng: options.seed.map(Rng::new).unwrap_or_default(),
+            entry_string_args: HashMap::new(),
+            timers: Timers::default(),
+        }
+    }
+
+    /// See `VM::set_seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn timers(&self) -> &Timers {
+        &self.timers
+    }
+
+    /// Applies the `memoize` setting of per-script overrides; the other
+    /// settings only concern the VM.
+    pub fn set_script_overrides(&mut self, overrides: &HashMap<String, ScriptOverrides>) {
+        self.memoize_overrides = overrides
+            .iter()
+            .filter_map(|(name, overrides)| Some((name.to_lowercase(), overrides.memoize?)))
+            .collect();
+    }
+
+    /// Makes an enum available to `enum_getvalue`; see `VM::register_enum`.
+    pub fn register_enum(&mut self, config: EnumConfig) {
+        self.enums.insert(config.name.clone(), config);
+    }
+
+    /// See `VM::load_symbols`.
+    pub fn load_symbols(&mut self, symbols: &SymbolTable) {
+        for config in symbols.enums() {
+            self.register_enum(config.clone());
         }
+        self.params = symbols.params().clone();
+        self.varp_defaults.extend(symbols.varp_defaults());
+        self.host.0.load_symbols(symbols);
+    }
+
+    fn get_varp(&self, id: i32) -> Value {
+        self.varps.get(&id).or_else(|| self.varp_defaults.get(&id)).copied().unwrap_or(0)
+    }
+
+    /// Replaces the default `CommandRegistry` as the target of host commands.
+    pub fn set_host(&mut self, host: impl HostContext + 'static) {
+        self.host = Host(Box::new(host));
+    }
+
+    /// Forgets every memoized result.
+    pub fn clear_memo_cache(&mut self) {
+        self.memo_cache.clear();
+    }
+
+    /// Resets every varp to 0, as in a fresh VM.
+    pub fn clear_varps(&mut self) {
+        self.varps.clear();
+    }
+
+    /// Follows the VM: results are cached when memoization is enabled for the
+    /// script and the script is pure.
+    fn memoize_script(&mut self, name: &str) -> bool {
+        let annotated = || match self.scripts.get(name) {
+            Some(AstKind::Trigger { annotations, .. }) => annotations::overrides(annotations).0.memoize,
+            _ => None,
+        };
+        let enabled = self
+            .memoize_overrides
+            .get(&name.to_lowercase())
+            .copied()
+            .or_else(annotated)
+            .unwrap_or(self.memoize);
+        enabled && self.purity.is_pure(name, &self.scripts)
+    }
+
+    /// In strict mode, reading a local that was never assigned is an error
+    /// instead of yielding 0.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
     }
 
     pub fn register_script(&mut self, name: String, ast: AstKind) {
+        self.purity.clear();
         self.scripts.insert(name, ast);
     }
 
-    pub fn eval(&mut self, ast: &AstKind) -> i32 {
+    pub fn eval(&mut self, ast: &AstKind) -> Result<Value, EvalError> {
         match ast {
-            AstKind::NumericLiteral(n) => *n,
-            AstKind::StringLiteral(_) => 0,
+            AstKind::NumericLiteral(n) => Ok(*n),
+            AstKind::Null => Ok(NULL),
+            AstKind::StringLiteral(_) => Ok(0),
 
             AstKind::LocalVar(name) => {
                 let var_name = name.trim_start_matches('$');
-                self.variables.get(var_name).unwrap_or(&0).clone()
+                match self.variables.get(var_name) {
+                    Some(value) => Ok(*value),
+                    None if self.strict => Err(EvalError::UndefinedVariable(var_name.to_string())),
+                    None => Ok(0),
+                }
+            },
+
+            AstKind::Varp { id, bits, .. } => {
+                let value = self.get_varp(*id);
+                Ok(match bits {
+                    Some((start_bit, end_bit)) => varbits::read(value, *start_bit, *end_bit),
+                    None => value,
+                })
             },
 
             AstKind::BinaryExpression { lhs, rhs, operator } => {
-                let left = self.eval(lhs);
-                let right = self.eval(rhs);
+                let left = self.eval(lhs)?;
+                let right = self.eval(rhs)?;
                 match operator.as_str() {
-                    "+" => left + right,
-                    "-" => left - right,
-                    "*" => left * right,
-                    "/" => left / right,
-                    "<=" => if left <= right { 1 } else { 0 },
-                    ">=" => if left >= right { 1 } else { 0 },
-                    "<" => if left < right { 1 } else { 0 },
-                    ">" => if left > right { 1 } else { 0 },
-                    "=" => if left == right { 1 } else { 0 },
-                    _ => panic!("Unknown operator: {}", operator),
+                    "+" => left.checked_add(right).ok_or(EvalError::IntegerOverflow),
+                    "-" => left.checked_sub(right).ok_or(EvalError::IntegerOverflow),
+                    "*" => left.checked_mul(right).ok_or(EvalError::IntegerOverflow),
+                    "/" => {
+                        if right == 0 {
+                            return Err(EvalError::DivisionByZero);
+                        }
+                        left.checked_div(right).ok_or(EvalError::IntegerOverflow)
+                    },
+                    "%" => {
+                        if right == 0 {
+                            return Err(EvalError::DivisionByZero);
+                        }
+                        left.checked_rem(right).ok_or(EvalError::IntegerOverflow)
+                    },
+                    "<=" => Ok(if left <= right { 1 } else { 0 }),
+                    ">=" => Ok(if left >= right { 1 } else { 0 }),
+                    "<" => Ok(if left < right { 1 } else { 0 }),
+                    ">" => Ok(if left > right { 1 } else { 0 }),
+                    "=" => Ok(if left == right { 1 } else { 0 }),
+                    _ => Err(EvalError::UnknownOperator(operator.clone())),
+                }
+            },
+
+            AstKind::ConditionalExpression { lhs, rhs, value } => {
+                if self.eval(lhs)? != 0 {
+                    self.eval(value)
+                } else {
+                    self.eval(rhs)
                 }
             },
 
             AstKind::Assignment { target, value } => {
                 if let AstKind::LocalVar(name) = &**target {
                     let var_name = name.trim_start_matches('$');
-                    let val = self.eval(value);
+                    if self.string_variables.contains_key(var_name) {
+                        let text = self.eval_string(value)?;
+                        self.string_variables.insert(var_name.to_string(), text);
+                        return Ok(0);
+                    }
+                    let val = self.eval(value)?;
                     self.variables.insert(var_name.to_string(), val);
-                    val
+                    Ok(val)
+                } else if let AstKind::Varp { id, bits, .. } = &**target {
+                    let val = self.eval(value)?;
+                    let varp = match bits {
+                        Some((start_bit, end_bit)) => {
+                            let current = self.get_varp(*id);
+                            varbits::write(current, *start_bit, *end_bit, val)
+                                .map_err(|e| EvalError::InvalidVarbitValue(format!("{} in varp {}", e, id)))?
+                        }
+                        None => val,
+                    };
+                    self.varps.insert(*id, varp);
+                    Ok(val)
                 } else {
-                    panic!("Invalid assignment target");
+                    Err(EvalError::InvalidAssignmentTarget)
                 }
             },
 
-            AstKind::Define { name, var_type: _, value } => {
-                let val = self.eval(value);
+            AstKind::Define { name, var_type, value } => {
                 let var_name = name.trim_start_matches('$');
+                if *var_type == Type::String {
+                    let text = self.eval_string(value)?;
+                    self.string_variables.insert(var_name.to_string(), text);
+                    return Ok(0);
+                }
+                let val = self.eval(value)?;
                 self.variables.insert(var_name.to_string(), val);
-                val
+                Ok(val)
             },
 
             AstKind::If { expression, value, return_statement } => {
-                let condition = self.eval(expression);
-                if condition != 0 {
-                    self.eval(return_statement)
-                } else {
-                    self.eval(value)
+                if self.eval(expression)? != 0 {
+                    self.eval(value)?;
+                    if self.returned.is_none() {
+                        self.eval(return_statement)?;
+                    }
                 }
+                Ok(0)
             },
 
             AstKind::While { condition, body } => {
                 let mut last_value = 0;
-                while self.eval(condition) != 0 {
-                    last_value = self.eval(body);
+                while self.returned.is_none() && self.eval(condition)? != 0 {
+                    self.step()?;
+                    last_value = self.eval(body)?;
+                }
+                Ok(last_value)
+            },
+
+            AstKind::InvariantWhile { invariants, condition, body } => {
+                let mut last_value = 0;
+                if self.eval(condition)? != 0 {
+                    for invariant in invariants {
+                        self.eval(invariant)?;
+                    }
+                    loop {
+                        self.step()?;
+                        last_value = self.eval(body)?;
+                        if self.returned.is_some() || self.eval(condition)? == 0 {
+                            break;
+                        }
+                    }
                 }
-                last_value
+                Ok(last_value)
             },
 
+            AstKind::For { .. } => self.eval(&ast.lower_loops()),
+
             AstKind::Block(statements) => {
                 let mut last_value = 0;
                 for stmt in statements {
-                    match stmt {
-                        AstKind::Return(expr) => return self.eval(expr),
-                        AstKind::If { .. } => {
-                            let result = self.eval(stmt);
-                            if result != 0 {
-                                return result;
-                            }
-                        },
-                        _ => { last_value = self.eval(stmt); }
+                    last_value = self.eval(stmt)?;
+                    if self.returned.is_some() {
+                        break;
                     }
                 }
-                last_value
+                Ok(last_value)
             },
 
             AstKind::Return(expr) => {
-                self.eval(expr)
+                let value = self.eval(expr)?;
+                self.returned = Some(value);
+                Ok(value)
             },
 
-            AstKind::FunctionCall { name, arguments } => {
-                match name.as_str() {
-                    "calc" => {
-                        if let Some(arg) = arguments.first() {
-                            self.eval(arg)
-                        } else {
-                            panic!("calc requires one argument");
-                        }
-                    },
-                    _ => panic!("Unknown function: {}", name),
-                }
-            },
+            AstKind::FunctionCall { name, arguments } => self.call_function(name, arguments),
 
             AstKind::ScriptCall { script, arguments } => {
                 if let AstKind::Identifier(script_name) = &**script {
                     let mut arg_values = Vec::new();
                     for arg in arguments {
-                        arg_values.push(self.eval(arg));
+                        arg_values.push(self.eval(arg)?);
                     }
                     self.eval_script(script_name, &arg_values)
                 } else {
-                    panic!("Invalid script call target");
+                    Err(EvalError::InvalidCallTarget)
                 }
             },
 
@@ -130,38 +355,568 @@ impl Evaluator {
                 self.eval(body)
             },
 
-            _ => 0,
+            AstKind::Cast { expression, .. } => self.eval(expression),
+
+            _ => Ok(0),
         }
     }
 
-    pub fn eval_script(&mut self, name: &str, args: &[i32]) -> i32 {
-        let script = if let Some(s) = self.scripts.get(name) {
-            s.clone()
-        } else {
-            panic!("Script not found: {}", name);
-        };
-
-        let old_vars = self.variables.clone();
-        self.variables.clear();
-        
-        if let AstKind::Trigger { args: script_args, .. } = &script {
-            // Zip parameter names with argument values and insert into variables
-            for (param, &value) in script_args.iter()
-                .filter_map(|arg| if let AstKind::LocalVar(name) = &**arg {
-                    Some(name.trim_start_matches('$'))
+    /// Runs a function call: `calc`, `abs` and the other built-in commands,
+    /// or else a host command. Kept out of `eval`, whose stack frame each
+    /// nested script call repeats, so deep recursion needs less stack.
+    fn call_function(&mut self, name: &str, arguments: &[Box<AstKind>]) -> Result<Value, EvalError> {
+        match name {
+            "calc" => {
+                if let Some(arg) = arguments.first() {
+                    self.eval(arg)
+                } else {
+                    Err(EvalError::MissingArgument(name.to_string()))
+                }
+            },
+            "abs" => {
+                if let Some(arg) = arguments.first() {
+                    self.eval(arg)?.checked_abs().ok_or(EvalError::IntegerOverflow)
                 } else {
-                    None
+                    Err(EvalError::MissingArgument(name.to_string()))
+                }
+            },
+            "rgb" => {
+                let [red, green, blue] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                let (red, green, blue) = (self.eval(red)?, self.eval(green)?, self.eval(blue)?);
+                colour::rgb(red, green, blue).map_err(EvalError::InvalidColour)
+            },
+            "color_component" => {
+                let [value, index] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                let (value, index) = (self.eval(value)?, self.eval(index)?);
+                colour::component(value, index).map_err(EvalError::InvalidColour)
+            },
+            "min" | "max" => {
+                let [a, b] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                let (a, b) = (self.eval(a)?, self.eval(b)?);
+                Ok(if name == "min" { a.min(b) } else { a.max(b) })
+            },
+            "coord" | "coordx" | "coordz" | "coordlevel" | "distance" | "coord2" | "coord2x" | "coord2y" | "coord3"
+            | "coord3x" | "coord3y" | "coord3z" => {
+                let mut args = Vec::new();
+                for arg in arguments {
+                    args.push(self.eval(arg)?);
+                }
+                coord::call(name, &args).map_err(EvalError::InvalidCoord)
+            },
+            "toint" => {
+                let text = match arguments.first() {
+                    Some(arg) => self.eval_string(arg)?,
+                    None => return Err(EvalError::MissingArgument(name.to_string())),
+                };
+                convert::to_int(&text).map_err(EvalError::InvalidConversion)
+            },
+            "enum_getvalue" => {
+                let [enum_name, key] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                let AstKind::Identifier(enum_name) = &**enum_name else {
+                    return Err(EvalError::EnumLookup("enum_getvalue: the first argument must name an enum".to_string()));
+                };
+                let key = self.eval(key)?;
+                let config = self.enums.get(enum_name).ok_or_else(|| {
+                    EvalError::EnumLookup(format!("enum_getvalue: enum '{}' is not loaded", enum_name))
+                })?;
+                config.get(key).map_err(EvalError::EnumLookup)
+            },
+            "oc_param" | "nc_param" | "struct_param" => match self.param_lookup(name, arguments)? {
+                HostValue::Int(value) => Ok(value),
+                HostValue::Str(_) => Err(EvalError::ParamLookup(format!("{}: the param is a string", name))),
+            },
+            "compare" => {
+                let [a, b] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                Ok(convert::compare(&self.eval_string(a)?, &self.eval_string(b)?))
+            },
+            "random" => match arguments.first() {
+                Some(arg) => {
+                    let bound = self.eval(arg)?;
+                    self.rng.random(bound).map_err(EvalError::InvalidRandomBound)
+                }
+                None => Err(EvalError::MissingArgument(name.to_string())),
+            },
+            "settimer" => {
+                let [timer, ticks, callback] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                let AstKind::Identifier(callback) = &**callback else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                let (timer, ticks) = (self.eval(timer)?, self.eval(ticks)?);
+                self.timers.set(timer, ticks, callback).map_err(EvalError::InvalidTimerDelay)?;
+                Ok(0)
+            },
+            "cleartimer" => match arguments.first() {
+                Some(timer) => {
+                    let timer = self.eval(timer)?;
+                    self.timers.clear(timer);
+                    Ok(0)
+                }
+                None => Err(EvalError::MissingArgument(name.to_string())),
+            },
+            "string_length" => match arguments.first() {
+                Some(arg) => Ok(strings::length(&self.eval_string(arg)?)),
+                None => Err(EvalError::MissingArgument(name.to_string())),
+            },
+            "string_indexof" => {
+                let [text, find] = arguments else {
+                    return Err(EvalError::MissingArgument(name.to_string()));
+                };
+                Ok(strings::index_of(&self.eval_string(text)?, &self.eval_string(find)?))
+            },
+            _ => {
+                let args = self.host_args(arguments)?;
+                match self.host.0.call(name, &args) {
+                    Some(result) => result.map_err(|message| EvalError::Host {
+                        command: name.to_string(),
+                        message,
+                    }),
+                    None => Err(EvalError::UnknownFunction(name.to_string())),
+                }
+            },
+        }
+    }
+
+    /// Like `eval_script`, but the arguments may be strings, for scripts with
+    /// `string` parameters; see `VM::run_script_with_args`.
+    pub fn eval_script_with_args(&mut self, name: &str, args: &[HostValue]) -> Result<Value, EvalError> {
+        let mut ints = Vec::new();
+        for (index, arg) in args.iter().enumerate() {
+            match arg {
+                HostValue::Int(value) => ints.push(*value),
+                HostValue::Str(text) => {
+                    ints.push(0);
+                    self.entry_string_args.insert(index, text.clone());
+                }
+            }
+        }
+        let result = self.eval_script(name, &ints);
+        self.entry_string_args.clear();
+        result
+    }
+
+    pub fn eval_script(&mut self, name: &str, args: &[Value]) -> Result<Value, EvalError> {
+        let script = match self.scripts.get(name) {
+            Some(s) => s.clone(),
+            None => {
+                return Err(EvalError::UnknownScript {
+                    name: name.to_string(),
+                    suggestion: self.suggest_script(name),
                 })
-                .zip(args.iter()) {
-                self.variables.insert(param.to_string(), value);
+            }
+        };
+
+        let params = parameters(name, &script)?;
+        let required = params.iter().filter(|param| param.default.is_none()).count();
+        if (args.len() < required && !self.lenient_args) || args.len() > params.len() {
+            return Err(EvalError::ArgumentCount {
+                script: name.to_string(),
+                min: required,
+                max: params.len(),
+                found: args.len(),
+            });
+        }
+
+        // Only `eval_script_with_args` passes strings, so a script call
+        // can't fill a string parameter yet.
+        let mut string_args = std::mem::take(&mut self.entry_string_args);
+        let unfilled = params
+            .iter()
+            .take(args.len())
+            .enumerate()
+            .find(|(index, param)| param.is_string && !string_args.contains_key(index));
+        if let Some((_, param)) = unfilled {
+            return Err(EvalError::UnsupportedParameterType {
+                script: name.to_string(),
+                param: param.name.clone(),
+                param_type: Type::String,
+            });
+        }
+
+        let memo_key: MemoKey = (name.to_string(), args.to_vec());
+        let memoize = self.memoize_script(name) && string_args.is_empty();
+        if memoize {
+            if let Some(&cached) = self.memo_cache.get(&memo_key) {
+                return Ok(cached);
+            }
+        }
+
+        if self.depth == 0 {
+            self.steps = 0;
+        }
+        // Limits are reported against the calling script.
+        self.step()?;
+        if self.depth >= self.max_depth {
+            return Err(self.limit_exceeded(EvalLimit::CallDepth(self.max_depth)));
+        }
+        let old_script = std::mem::replace(&mut self.current_script, name.to_string());
+
+        let old_vars = std::mem::take(&mut self.variables);
+        let old_strings = std::mem::take(&mut self.string_variables);
+        for (index, (param, &value)) in params.iter().zip(args).enumerate() {
+            match string_args.remove(&index) {
+                Some(text) if param.is_string => {
+                    self.string_variables.insert(param.name.clone(), text);
+                }
+                _ => {
+                    self.variables.insert(param.name.clone(), value);
+                }
             }
         }
         
-        let result = match &script {
+        self.depth += 1;
+        let result = self.bind_defaults(&params[args.len()..]).and_then(|_| match &script {
             AstKind::Trigger { body, .. } => self.eval(body),
             _ => self.eval(&script),
-        };
+        });
+        // A script that ends without `return` yields its last statement's value.
+        let result = result.map(|last_value| self.returned.take().unwrap_or(last_value));
+        self.returned = None;
+        self.depth -= 1;
         self.variables = old_vars;
+        self.string_variables = old_strings;
+        self.current_script = old_script;
+        if let (true, Ok(value)) = (memoize, &result) {
+            self.memo_cache.insert(memo_key, *value);
+        }
         result
     }
-} 
\ No newline at end of file
+
+    /// Evaluates the defaults of parameters the caller left out, in order, so
+    /// a default can refer to the parameters before it. With `lenient_args`,
+    /// required ones get their type's default.
+    fn bind_defaults(&mut self, missing: &[Param]) -> Result<(), EvalError> {
+        for param in missing {
+            match &param.default {
+                Some(default) if param.is_string => {
+                    let text = self.eval_string(default)?;
+                    self.string_variables.insert(param.name.clone(), text);
+                }
+                Some(default) => {
+                    let value = self.eval(default)?;
+                    self.variables.insert(param.name.clone(), value);
+                }
+                // Only reached with lenient_args.
+                None if param.is_string => {
+                    self.string_variables.insert(param.name.clone(), String::new());
+                }
+                None => {
+                    self.variables.insert(param.name.clone(), param.param_type.default_value());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `ast` is a string, judged as the compiler does: a literal, a
+    /// string local or a `+` involving either.
+    fn is_string_expression(&self, ast: &AstKind) -> bool {
+        match ast {
+            AstKind::StringLiteral(_) => true,
+            AstKind::LocalVar(name) => self.string_variables.contains_key(name.trim_start_matches('$')),
+            AstKind::FunctionCall { name, arguments } if params::signature(name).is_some() => {
+                matches!(arguments.get(1).map(|arg| &**arg), Some(AstKind::Identifier(param))
+                    if self.params.config(param).is_some_and(|config| config.ty == Type::String))
+            },
+            AstKind::FunctionCall { name, .. } => compiler::returns_string(name),
+            AstKind::BinaryExpression { lhs, rhs, operator } if operator == "+" => {
+                self.is_string_expression(lhs) || self.is_string_expression(rhs)
+            },
+            AstKind::ConditionalExpression { rhs, value, .. } => {
+                self.is_string_expression(value) || self.is_string_expression(rhs)
+            },
+            _ => false,
+        }
+    }
+
+    /// Evaluates the arguments of a host command, each on whichever side
+    /// its expression's type says.
+    fn host_args(&mut self, arguments: &[Box<AstKind>]) -> Result<Vec<HostValue>, EvalError> {
+        let mut args = Vec::new();
+        for arg in arguments {
+            args.push(if self.is_string_expression(arg) {
+                HostValue::Str(self.eval_string(arg)?)
+            } else {
+                HostValue::Int(self.eval(arg)?)
+            });
+        }
+        Ok(args)
+    }
+
+    /// Runs `oc_param`, `nc_param` or `struct_param`, whose second argument
+    /// names the param.
+    fn param_lookup(&mut self, name: &str, arguments: &[Box<AstKind>]) -> Result<HostValue, EvalError> {
+        let [owner, param] = arguments else {
+            return Err(EvalError::MissingArgument(name.to_string()));
+        };
+        let AstKind::Identifier(param) = &**param else {
+            return Err(EvalError::ParamLookup(format!("{}: the second argument must name a param", name)));
+        };
+        let owner = self.eval(owner)?;
+        self.params.get(name, owner, param).map_err(EvalError::ParamLookup)
+    }
+
+    /// Evaluates an expression of type string: literals, string locals,
+    /// string commands and `+` chains of them.
+    pub fn eval_string(&mut self, ast: &AstKind) -> Result<String, EvalError> {
+        match ast {
+            AstKind::StringLiteral(text) => Ok(text.clone()),
+            AstKind::LocalVar(name) => {
+                let var_name = name.trim_start_matches('$');
+                match self.string_variables.get(var_name) {
+                    Some(text) => Ok(text.clone()),
+                    None if self.strict => Err(EvalError::UndefinedVariable(var_name.to_string())),
+                    None => Ok(String::new()),
+                }
+            },
+            AstKind::BinaryExpression { lhs, rhs, operator } if operator == "+" => {
+                Ok(self.eval_string(lhs)? + &self.eval_string(rhs)?)
+            },
+            AstKind::ConditionalExpression { lhs, rhs, value } => {
+                if self.eval(lhs)? != 0 {
+                    self.eval_string(value)
+                } else {
+                    self.eval_string(rhs)
+                }
+            },
+            AstKind::FunctionCall { name, arguments } if name == "tostring" => match arguments.first() {
+                Some(arg) => Ok(self.eval(arg)?.to_string()),
+                None => Err(EvalError::MissingArgument(name.clone())),
+            },
+            AstKind::FunctionCall { name, arguments } if name == "substring" => {
+                let [text, start, end] = &arguments[..] else {
+                    return Err(EvalError::MissingArgument(name.clone()));
+                };
+                let text = self.eval_string(text)?;
+                let (start, end) = (self.eval(start)?, self.eval(end)?);
+                strings::substring(&text, start, end).map_err(EvalError::InvalidStringIndex)
+            },
+            AstKind::FunctionCall { name, arguments } if params::signature(name).is_some() => {
+                Ok(self.param_lookup(name, arguments)?.to_string())
+            }
+            AstKind::FunctionCall { name, arguments } if host::signature(name).is_some_and(|(_, ty)| ty == Type::String) => {
+                let args = self.host_args(arguments)?;
+                match self.host.0.call_string(name, &args) {
+                    Some(result) => result.map_err(|message| EvalError::Host {
+                        command: name.clone(),
+                        message,
+                    }),
+                    None => Err(EvalError::UnknownFunction(name.clone())),
+                }
+            }
+            AstKind::FunctionCall { name, arguments } if name == "append" => {
+                let [a, b] = &arguments[..] else {
+                    return Err(EvalError::MissingArgument(name.clone()));
+                };
+                Ok(self.eval_string(a)? + &self.eval_string(b)?)
+            },
+            AstKind::FunctionCall { name, arguments } if name == "lowercase" || name == "uppercase" => {
+                let text = match arguments.first() {
+                    Some(arg) => self.eval_string(arg)?,
+                    None => return Err(EvalError::MissingArgument(name.clone())),
+                };
+                Ok(if name == "lowercase" { text.to_lowercase() } else { text.to_uppercase() })
+            },
+            _ => Ok(self.eval(ast)?.to_string()),
+        }
+    }
+
+    /// Counts one loop iteration or script call against the step limit.
+    fn step(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(self.limit_exceeded(EvalLimit::Steps(self.max_steps)));
+        }
+        Ok(())
+    }
+
+    fn limit_exceeded(&self, limit: EvalLimit) -> EvalError {
+        EvalError::LimitExceeded {
+            script: self.current_script.clone(),
+            limit,
+            steps: self.steps,
+            depth: self.depth,
+        }
+    }
+
+    /// The registered script with the closest name, if any is close enough to
+    /// be a likely typo.
+    fn suggest_script(&self, name: &str) -> Option<String> {
+        self.scripts
+            .keys()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    }
+}
+
+/// One declared parameter of a script.
+struct Param {
+    name: String,
+    param_type: Type,
+    is_string: bool,
+    default: Option<AstKind>,
+}
+
+/// Reads a trigger's parameter list from its `(type, name)` pairs.
+fn parameters(script: &str, ast: &AstKind) -> Result<Vec<Param>, EvalError> {
+    let AstKind::Trigger { args, defaults, .. } = ast else {
+        return Ok(Vec::new());
+    };
+    if !args.len().is_multiple_of(2) {
+        return Err(EvalError::MalformedSignature(script.to_string()));
+    }
+
+    let mut params = Vec::new();
+    for (index, pair) in args.chunks(2).enumerate() {
+        let (AstKind::Identifier(type_name), AstKind::LocalVar(name)) = (&*pair[0], &*pair[1]) else {
+            return Err(EvalError::MalformedSignature(script.to_string()));
+        };
+        let param_type = Type::from_name(type_name)
+            .ok_or_else(|| EvalError::MalformedSignature(script.to_string()))?;
+        let name = name.trim_start_matches('$').to_string();
+        let default = defaults.get(index).cloned().flatten();
+        params.push(Param { name, is_string: param_type == Type::String, param_type, default });
+    }
+    Ok(params)
+}
+
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{evaluator, evaluator_with};
+
+    fn call(name: &str, arguments: Vec<AstKind>) -> AstKind {
+        AstKind::FunctionCall { name: name.to_string(), arguments: arguments.into_iter().map(Box::new).collect() }
+    }
+
+    #[test]
+    fn undefined_variable_is_an_error_only_when_strict() {
+        let source = "[proc,read]()(int)\nreturn($missing);";
+        assert_eq!(evaluator(source).eval_script("read", &[]), Ok(0));
+
+        let mut strict = evaluator(source);
+        strict.set_strict(true);
+        assert_eq!(strict.eval_script("read", &[]), Err(EvalError::UndefinedVariable("missing".to_string())));
+    }
+
+    #[test]
+    fn arithmetic_errors() {
+        let mut evaluator = evaluator(
+            "[proc,divide](int $a, int $b)(int)\nreturn(calc($a / $b));\n\
+             [proc,modulo](int $a, int $b)(int)\nreturn(calc($a % $b));\n\
+             [proc,add](int $a, int $b)(int)\nreturn(calc($a + $b));",
+        );
+        assert_eq!(evaluator.eval_script("divide", &[1, 0]), Err(EvalError::DivisionByZero));
+        assert_eq!(evaluator.eval_script("modulo", &[1, 0]), Err(EvalError::DivisionByZero));
+        assert_eq!(evaluator.eval_script("add", &[i32::MAX, 1]), Err(EvalError::IntegerOverflow));
+        assert_eq!(evaluator.eval_script("divide", &[i32::MIN, -1]), Err(EvalError::IntegerOverflow));
+    }
+
+    #[test]
+    fn unknown_script_suggests_a_close_name() {
+        let mut evaluator = evaluator("[proc,factorial](int $n)(int)\nreturn($n);");
+        assert_eq!(
+            evaluator.eval_script("factorail", &[1]),
+            Err(EvalError::UnknownScript { name: "factorail".to_string(), suggestion: Some("factorial".to_string()) })
+        );
+    }
+
+    #[test]
+    fn two_parameters_bind_in_order_and_their_count_is_checked() {
+        let mut evaluator = evaluator("[proc,pair](int $a, int $b)(int)\nreturn(calc($a - $b));");
+        assert_eq!(evaluator.eval_script("pair", &[10, 3]), Ok(7));
+        assert_eq!(
+            evaluator.eval_script("pair", &[1]),
+            Err(EvalError::ArgumentCount { script: "pair".to_string(), min: 2, max: 2, found: 1 })
+        );
+        assert_eq!(
+            evaluator.eval_script("pair", &[1, 2, 3]),
+            Err(EvalError::ArgumentCount { script: "pair".to_string(), min: 2, max: 2, found: 3 })
+        );
+    }
+
+    #[test]
+    fn unknown_function_missing_argument_and_unknown_operator() {
+        let mut evaluator = Evaluator::new();
+        assert_eq!(evaluator.eval(&call("no_such_command", vec![])), Err(EvalError::UnknownFunction("no_such_command".to_string())));
+        assert_eq!(evaluator.eval(&call("calc", vec![])), Err(EvalError::MissingArgument("calc".to_string())));
+
+        let shifted = AstKind::BinaryExpression {
+            lhs: Box::new(AstKind::NumericLiteral(1)),
+            rhs: Box::new(AstKind::NumericLiteral(2)),
+            operator: "<<".to_string(),
+        };
+        assert_eq!(evaluator.eval(&shifted), Err(EvalError::UnknownOperator("<<".to_string())));
+    }
+
+    #[test]
+    fn invalid_assignment_and_call_targets() {
+        let mut evaluator = Evaluator::new();
+        let assignment = AstKind::Assignment {
+            target: Box::new(AstKind::NumericLiteral(1)),
+            value: Box::new(AstKind::NumericLiteral(2)),
+        };
+        assert_eq!(evaluator.eval(&assignment), Err(EvalError::InvalidAssignmentTarget));
+
+        let call = AstKind::ScriptCall { script: Box::new(AstKind::NumericLiteral(1)), arguments: vec![] };
+        assert_eq!(evaluator.eval(&call), Err(EvalError::InvalidCallTarget));
+    }
+
+    #[test]
+    fn infinite_loop_hits_the_step_limit() {
+        let options = VmOptions { max_instructions: 10_000, ..VmOptions::default() };
+        let mut evaluator = evaluator_with(&options, "[proc,spin]()(int)\ndef_int $i = 0;\nwhile (1 = 1) {\n    $i = calc($i + 1);\n}\nreturn($i);");
+        match evaluator.eval_script("spin", &[]) {
+            Err(EvalError::LimitExceeded { script, limit: EvalLimit::Steps(10_000), steps, .. }) => {
+                assert_eq!(script, "spin");
+                assert_eq!(steps, 10_001);
+            }
+            other => panic!("expected the step limit, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fib_25_completes_within_the_default_limits() {
+        let mut evaluator = evaluator(include_str!("../data/scripts/fib.rs2"));
+        assert_eq!(evaluator.eval_script("fib", &[25]), Ok(75_025));
+    }
+
+    #[test]
+    fn unbounded_recursion_hits_the_call_depth_limit() {
+        // Kept shallow: the default depth needs more native stack than a
+        // test thread has in a debug build.
+        let options = VmOptions { max_call_depth: 20, ..VmOptions::default() };
+        let mut evaluator = evaluator_with(&options, "[proc,forever](int $n)(int)\nreturn(~forever(calc($n + 1)));");
+        match evaluator.eval_script("forever", &[0]) {
+            Err(EvalError::LimitExceeded { script, limit: EvalLimit::CallDepth(20), .. }) => assert_eq!(script, "forever"),
+            other => panic!("expected the call depth limit, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Floors: the `.flo` configs that colour and texture map tiles, named in
+//! scripts as `flo` handles and read with `flo_colour`.
+
+use serde::{Deserialize, Serialize};
+use crate::colour;
+use crate::host::HostValue;
+use crate::types::Type;
+
+/// Parameter and return types of the floor lookups, which go to the host
+/// like the db commands.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "flo_colour" => Some((&[Type::Flo], Type::Color)),
+        _ => None,
+    }
+}
+
+/// One `[name]` section of a `.flo` config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FloConfig {
+    pub name: String,
+    /// A 24-bit colour, from `colour=` or the engine's `rgb=`.
+    pub colour: Option<i32>,
+    /// The texture's name, for floors drawn with one.
+    pub texture: Option<String>,
+}
+
+impl FloConfig {
+    /// Reads the fields of a `.flo` section:
+    ///
+    /// ```text
+    /// [grass]
+    /// colour=0x35720A
+    /// texture=grass_tile
+    /// ```
+    pub fn parse(name: &str, fields: &[(String, String)]) -> Result<Self, String> {
+        let field = |key: &str| fields.iter().find(|(field, _)| field == key).map(|(_, value)| value.as_str());
+        let colour = field("colour")
+            .or_else(|| field("rgb"))
+            .map(|text| {
+                parse_colour(text).ok_or_else(|| format!("flo '{}': colour '{}' is not a 24-bit colour such as 0xFF981F", name, text))
+            })
+            .transpose()?;
+        Ok(FloConfig {
+            name: name.to_string(),
+            colour,
+            texture: field("texture").map(str::to_string),
+        })
+    }
+}
+
+/// Reads a colour written in hex, `0xRRGGBB`, or decimal.
+fn parse_colour(text: &str) -> Option<i32> {
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(digits) => i32::from_str_radix(digits, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    colour::is_valid(value).then_some(value)
+}
+
+/// Runs `flo_colour` against `floors`, indexed by flo id.
+pub fn call(floors: &[FloConfig], name: &str, args: &[HostValue]) -> Result<i32, String> {
+    match (name, args) {
+        ("flo_colour", [HostValue::Int(flo)]) => {
+            let config = usize::try_from(*flo)
+                .ok()
+                .and_then(|index| floors.get(index))
+                .ok_or_else(|| format!("{} is not a flo", flo))?;
+            config.colour.ok_or_else(|| format!("flo '{}' has no colour", config.name))
+        }
+        _ => Err(format!("unexpected arguments {:?}", args)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    const FLO: &str = "[grass]\ncolour=0x35720A\ntexture=grass_tile\n\n[water]\nrgb=3955340\n\n[void]\ntexture=black\n";
+
+    const SCRIPTS: &str = "[proc,grass_colour]()(colour)\nreturn(flo_colour(grass));\n\
+        [proc,water_id]()(int)\nreturn(int(flo(water)));\n\
+        [proc,void_colour]()(colour)\nreturn(flo_colour(void));\n";
+
+    fn symbols() -> SymbolTable {
+        test_support::symbols(&[("floors.flo", FLO)])
+    }
+
+    #[test]
+    fn a_fixture_flo_file_parses_every_field() {
+        assert_eq!(
+            symbols().floors(),
+            [
+                FloConfig { name: "grass".to_string(), colour: Some(0x35720A), texture: Some("grass_tile".to_string()) },
+                FloConfig { name: "water".to_string(), colour: Some(3955340), texture: None },
+                FloConfig { name: "void".to_string(), colour: None, texture: Some("black".to_string()) },
+            ]
+        );
+        let error = FloConfig::parse("lava", &[("colour".to_string(), "0x1000000".to_string())]).unwrap_err();
+        assert_eq!(error, "flo 'lava': colour '0x1000000' is not a 24-bit colour such as 0xFF981F");
+    }
+
+    #[test]
+    fn a_script_looks_up_a_floor_on_both_backends() {
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols(), SCRIPTS);
+        assert_eq!(vm.run_script("grass_colour", &[]), Ok(0x35720A));
+        assert_eq!(evaluator.eval_script("grass_colour", &[]), Ok(0x35720A));
+        assert_eq!(vm.run_script("water_id", &[]), Ok(1));
+        assert_eq!(evaluator.eval_script("water_id", &[]), Ok(1));
+        let error = vm.run_script("void_colour", &[]).unwrap_err();
+        assert!(error.contains("flo 'void' has no colour"), "{}", error);
+        assert!(evaluator.eval_script("void_colour", &[]).is_err());
+    }
+
+    #[test]
+    fn a_floor_is_not_a_colour_without_the_lookup() {
+        let mut compiler = Compiler::new();
+        compiler.set_symbols(symbols());
+        let source = "[proc,bad]()(colour)\nreturn(colour(grass));\n";
+        assert!(test_support::try_compile_with(&mut compiler, source).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::appearance::{self, Appearance};
+use crate::db::{self, Database};
+use crate::dialogue;
+use crate::effects::{self, Effects};
+use crate::entities::{self, Entities};
+use crate::floors::{self, FloConfig};
+use crate::interfaces::{self, Interfaces};
+use crate::inv::{self, Inventories};
+use crate::stats::{self, PlayerStats};
+use crate::symbols::SymbolTable;
+use crate::types::Type;
+
+/// An argument passed to a host command, or to a script run with
+/// `rsc run --arg`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostValue {
+    Int(i32),
+    Str(String),
+}
+
+impl fmt::Display for HostValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HostValue::Int(value) => write!(f, "{}", value),
+            HostValue::Str(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// One command call: the command name and its arguments.
+pub type HostCall = (String, Vec<HostValue>);
+
+/// Parameter and return types of the host commands with fixed ones, which
+/// the default host implements: the stat, appearance, inventory, dialogue,
+/// effect, interface, db, floor and entity commands. The compiler checks
+/// calls against them and resolves config names passed to them, but the
+/// commands run in the host, which owns the player's state; each family's
+/// module lists its own.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    stats::signature(name)
+        .or_else(|| appearance::signature(name))
+        .or_else(|| inv::signature(name))
+        .or_else(|| dialogue::signature(name))
+        .or_else(|| effects::signature(name))
+        .or_else(|| interfaces::signature(name))
+        .or_else(|| db::signature(name))
+        .or_else(|| floors::signature(name))
+        .or_else(|| entities::signature(name))
+}
+
+/// Commands provided by whatever embeds the compiler rather than by the
+/// language, e.g. `mes`. Both the VM and the Evaluator send every function
+/// call other than `calc` and `abs` here, so one host serves both backends.
+pub trait HostContext {
+    /// Runs `name` and returns its int result, or `None` if the host has no
+    /// command by that name.
+    fn call(&mut self, name: &str, args: &[HostValue]) -> Option<Result<i32, String>>;
+
+    /// Runs `name`, a command whose signature returns a string, e.g.
+    /// `if_gettext`. Hosts without such commands can leave the default,
+    /// which has none.
+    fn call_string(&mut self, _name: &str, _args: &[HostValue]) -> Option<Result<String, String>> {
+        None
+    }
+
+    /// Receives the loaded configs before any script runs, for hosts that
+    /// keep their own game state. Does nothing by default.
+    fn load_symbols(&mut self, _symbols: &SymbolTable) {}
+}
+
+/// Lets one host be shared, e.g. by a VM and an Evaluator in the same test,
+/// while the caller keeps a handle to inspect it afterwards.
+impl<T: HostContext> HostContext for Rc<RefCell<T>> {
+    fn call(&mut self, name: &str, args: &[HostValue]) -> Option<Result<i32, String>> {
+        self.borrow_mut().call(name, args)
+    }
+
+    fn call_string(&mut self, name: &str, args: &[HostValue]) -> Option<Result<String, String>> {
+        self.borrow_mut().call_string(name, args)
+    }
+
+    fn load_symbols(&mut self, symbols: &SymbolTable) {
+        self.borrow_mut().load_symbols(symbols)
+    }
+}
+
+/// Holds a backend's host; lets the backends keep deriving `Debug`.
+pub(crate) struct Host(pub(crate) Box<dyn HostContext>);
+
+impl Default for Host {
+    fn default() -> Self {
+        Host(Box::new(CommandRegistry::new()))
+    }
+}
+
+impl fmt::Debug for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Host")
+    }
+}
+
+type Command = Box<dyn FnMut(&[HostValue]) -> Result<i32, String>>;
+
+/// The default host: `mes` prints its message and records it in `messages`,
+/// `p_mes` prints its line after the mesanim's name and records both in
+/// `dialogue`, `anim` and `spotanim_pl` are checked and recorded in
+/// `Effects`, the `if_` commands work on the `Interfaces` state, the stat,
+/// appearance, inventory, db and entity commands work on an in-memory
+/// `PlayerStats`, `Appearance`, `Inventories`, `Database` and `Entities`,
+/// `flo_colour` reads the loaded floors, and further commands can be added
+/// with `register`.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: HashMap<String, Command>,
+    messages: Vec<String>,
+    /// Each `p_mes` line with its mesanim id.
+    dialogue: Vec<(i32, String)>,
+    /// Mesanim names by id, for printing `p_mes` lines.
+    mesanims: Vec<String>,
+    effects: Effects,
+    interfaces: Interfaces,
+    stats: PlayerStats,
+    appearance: Appearance,
+    inventories: Inventories,
+    database: Database,
+    /// Indexed by flo id.
+    floors: Vec<FloConfig>,
+    entities: Entities,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<F>(&mut self, name: &str, command: F)
+    where
+        F: FnMut(&[HostValue]) -> Result<i32, String> + 'static,
+    {
+        self.commands.insert(name.to_string(), Box::new(command));
+    }
+
+    /// Every message passed to `mes`, in order.
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    /// Every `p_mes` call, in order, as (mesanim id, text).
+    pub fn dialogue(&self) -> &[(i32, String)] {
+        &self.dialogue
+    }
+
+    /// Every `anim` and `spotanim_pl` call, in order.
+    pub fn effects(&self) -> &Effects {
+        &self.effects
+    }
+
+    pub fn interfaces(&self) -> &Interfaces {
+        &self.interfaces
+    }
+
+    pub fn stats(&self) -> &PlayerStats {
+        &self.stats
+    }
+
+    pub fn stats_mut(&mut self) -> &mut PlayerStats {
+        &mut self.stats
+    }
+
+    pub fn appearance(&self) -> &Appearance {
+        &self.appearance
+    }
+
+    pub fn inventories(&self) -> &Inventories {
+        &self.inventories
+    }
+
+    pub fn inventories_mut(&mut self) -> &mut Inventories {
+        &mut self.inventories
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    pub fn entities(&self) -> &Entities {
+        &self.entities
+    }
+
+    pub fn entities_mut(&mut self) -> &mut Entities {
+        &mut self.entities
+    }
+}
+
+impl HostContext for CommandRegistry {
+    fn call(&mut self, name: &str, args: &[HostValue]) -> Option<Result<i32, String>> {
+        if let Some(command) = self.commands.get_mut(name) {
+            return Some(command(args));
+        }
+        match name {
+            "mes" => {
+                let message: String = args.iter().map(|arg| arg.to_string()).collect();
+                println!("{}", message);
+                self.messages.push(message);
+                Some(Ok(0))
+            }
+            "p_mes" => {
+                let [HostValue::Int(mesanim), HostValue::Str(text)] = args else {
+                    return Some(Err(format!("expected a mesanim and a string, got {} argument(s)", args.len())));
+                };
+                match self.mesanims.get(*mesanim as usize) {
+                    Some(name) => println!("[{}] {}", name, text),
+                    None => println!("[mesanim {}] {}", mesanim, text),
+                }
+                self.dialogue.push((*mesanim, text.clone()));
+                Some(Ok(0))
+            }
+            "stat" | "stat_base" | "stat_advance" => Some(self.stats.call(name, args)),
+            _ if appearance::signature(name).is_some() => Some(self.appearance.call(name, args)),
+            _ if effects::signature(name).is_some() => Some(self.effects.call(name, args)),
+            _ if interfaces::signature(name).is_some() => Some(self.interfaces.call(name, args)),
+            _ if inv::signature(name).is_some() => Some(self.inventories.call(name, args)),
+            _ if db::signature(name).is_some() => Some(self.database.call(name, args)),
+            _ if floors::signature(name).is_some() => Some(floors::call(&self.floors, name, args)),
+            _ if entities::signature(name).is_some() => Some(self.entities.call(name, args)),
+            _ => None,
+        }
+    }
+
+    fn call_string(&mut self, name: &str, args: &[HostValue]) -> Option<Result<String, String>> {
+        match name {
+            "if_gettext" => Some(self.interfaces.call_string(name, args)),
+            _ => None,
+        }
+    }
+
+    fn load_symbols(&mut self, symbols: &SymbolTable) {
+        self.interfaces.configure(symbols.names(&Type::Interface), symbols.components().to_vec());
+        self.inventories.configure(symbols.invs().to_vec(), symbols.stackable_objs().clone());
+        self.mesanims = symbols.mesanims().to_vec();
+        self.appearance.configure(symbols.idkits().to_vec());
+        self.effects.configure(symbols.names(&Type::Animation), symbols.names(&Type::Spotanim));
+        self.database.configure(symbols.db_tables().to_vec());
+        self.floors = symbols.floors().to_vec();
+    }
+}
+
+/// Records every command call and answers each with 0, or an empty string, so two runs can be
+/// compared by the calls they made.
+#[derive(Debug, Default)]
+pub struct RecordingHost {
+    pub calls: Vec<HostCall>,
+}
+
+impl HostContext for RecordingHost {
+    fn call(&mut self, name: &str, args: &[HostValue]) -> Option<Result<i32, String>> {
+        self.calls.push((name.to_string(), args.to_vec()));
+        Some(Ok(0))
+    }
+
+    fn call_string(&mut self, name: &str, args: &[HostValue]) -> Option<Result<String, String>> {
+        self.calls.push((name.to_string(), args.to_vec()));
+        Some(Ok(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// Answers `award_xp` with the sum of its arguments and every other
+    /// command with 0, recording each call.
+    #[derive(Default)]
+    struct MockHost {
+        calls: Vec<HostCall>,
+    }
+
+    impl HostContext for MockHost {
+        fn call(&mut self, name: &str, args: &[HostValue]) -> Option<Result<i32, String>> {
+            self.calls.push((name.to_string(), args.to_vec()));
+            let ints = args.iter().map(|arg| match arg {
+                HostValue::Int(value) => *value,
+                HostValue::Str(_) => 0,
+            });
+            Some(Ok(if name == "award_xp" { ints.sum() } else { 0 }))
+        }
+    }
+
+    #[test]
+    fn both_backends_call_a_mock_command_with_the_same_arguments() {
+        let source = "[proc,reward](int $n)(int)\ndef_int $total = award_xp(calc($n * 10), 5);\nmes(\"xp awarded\");\nreturn($total);\n";
+
+        let vm_host = Rc::new(RefCell::new(MockHost::default()));
+        let mut vm = test_support::vm(source);
+        vm.set_host(vm_host.clone());
+        assert_eq!(vm.run_script("reward", &[4]), Ok(45));
+
+        let interp_host = Rc::new(RefCell::new(MockHost::default()));
+        let mut evaluator = test_support::evaluator(source);
+        evaluator.set_host(interp_host.clone());
+        assert_eq!(evaluator.eval_script("reward", &[4]), Ok(45));
+
+        let expected: Vec<HostCall> = vec![
+            ("award_xp".to_string(), vec![HostValue::Int(40), HostValue::Int(5)]),
+            ("mes".to_string(), vec![HostValue::Str("xp awarded".to_string())]),
+        ];
+        assert_eq!(vm_host.borrow().calls, expected);
+        assert_eq!(interp_host.borrow().calls, expected);
+    }
+}
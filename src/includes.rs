@@ -0,0 +1,146 @@
+//! `// include: lib/math.rs2` directives, which name the script files whose
+//! procs a file calls. They go in the comments at the top of the file,
+//! before its first trigger, with paths relative to the including file.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use crate::packs;
+
+/// The paths a file's leading `// include:` comments name, as written.
+pub fn directives(source: &str) -> Vec<String> {
+    source
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with("//"))
+        .filter_map(|line| line.strip_prefix("//")?.trim().strip_prefix("include:"))
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+/// The files `path` includes, resolved against its directory.
+pub fn includes_of(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let source = packs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    directives(&source)
+        .into_iter()
+        .map(|include| {
+            let target = normalize(&dir.join(&include));
+            if packs::is_script(&target) {
+                Ok(target)
+            } else {
+                Err(format!("{}: included file '{}' not found", path.display(), include))
+            }
+        })
+        .collect()
+}
+
+/// `roots` and every file they include, directly or not. Each file comes
+/// once, after the files it includes; an include cycle is an error naming
+/// the files in it.
+pub fn expand(roots: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut expansion = Expansion::default();
+    for root in roots {
+        expansion.visit(root)?;
+    }
+    Ok(expansion.files)
+}
+
+#[derive(Default)]
+struct Expansion {
+    files: Vec<PathBuf>,
+    /// Canonical paths of `files`, so a file reached by two paths is kept once.
+    done: HashSet<PathBuf>,
+    /// The files whose includes are being visited, outermost first, by
+    /// canonical path and path as reached.
+    stack: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Expansion {
+    fn visit(&mut self, path: &Path) -> Result<(), String> {
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if self.done.contains(&canonical) {
+            return Ok(());
+        }
+        if let Some(start) = self.stack.iter().position(|(visiting, _)| *visiting == canonical) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .map(|(_, reached)| reached.display().to_string())
+                .chain([path.display().to_string()])
+                .collect();
+            return Err(format!("include cycle: {}", cycle.join(" -> ")));
+        }
+        self.stack.push((canonical.clone(), path.to_path_buf()));
+        for include in includes_of(path)? {
+            self.visit(&include)?;
+        }
+        self.stack.pop();
+        self.done.insert(canonical);
+        self.files.push(path.to_path_buf());
+        Ok(())
+    }
+}
+
+/// Drops the `.` and `dir/..` parts of `path`, so `day1/../lib/math.rs2`
+/// reads as `lib/math.rs2`.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normal.components().next_back(), Some(Component::Normal(_))) => {
+                normal.pop();
+            }
+            _ => normal.push(component),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes each (path, contents) under a temp directory.
+    fn files(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn only_leading_include_comments_are_directives() {
+        let source = "// day one\n// include: ../lib/math.rs2\n\n//include:util.rs2\n[proc,main]()(int)\n// include: late.rs2\nreturn(0);\n";
+        assert_eq!(directives(source), ["../lib/math.rs2", "util.rs2"]);
+    }
+
+    #[test]
+    fn transitive_includes_come_once_before_the_files_including_them() {
+        let dir = files(&[
+            ("day1/main.rs2", "// include: ../lib/math.rs2\n// include: ../lib/util.rs2\n[proc,main]()(int)\nreturn(~square(2));\n"),
+            ("lib/math.rs2", "// include: util.rs2\n[proc,square](int $n)(int)\nreturn(calc($n * $n));\n"),
+            ("lib/util.rs2", "[proc,one]()(int)\nreturn(1);\n"),
+        ]);
+        let expanded = expand(&[dir.path().join("day1/main.rs2")]).unwrap();
+        let relative: Vec<_> = expanded.iter().map(|path| path.strip_prefix(dir.path()).unwrap().to_path_buf()).collect();
+        assert_eq!(relative, [PathBuf::from("lib/util.rs2"), PathBuf::from("lib/math.rs2"), PathBuf::from("day1/main.rs2")]);
+    }
+
+    #[test]
+    fn a_cycle_or_a_missing_include_is_an_error() {
+        let dir = files(&[
+            ("a.rs2", "// include: b.rs2\n[proc,a]()(int)\nreturn(0);\n"),
+            ("b.rs2", "// include: a.rs2\n[proc,b]()(int)\nreturn(0);\n"),
+            ("lonely.rs2", "// include: gone.rs2\n[proc,lonely]()(int)\nreturn(0);\n"),
+        ]);
+        let (a, b) = (dir.path().join("a.rs2"), dir.path().join("b.rs2"));
+        assert_eq!(expand(std::slice::from_ref(&a)), Err(format!("include cycle: {} -> {} -> {}", a.display(), b.display(), a.display())));
+        let error = expand(&[dir.path().join("lonely.rs2")]).unwrap_err();
+        assert!(error.ends_with("lonely.rs2: included file 'gone.rs2' not found"), "{}", error);
+    }
+}
@@ -0,0 +1,273 @@
+//! Interfaces and their components, declared by `.if` configs: each file is
+//! an interface named after the file, and each `[name]` section in it a
+//! component, named `interface:name` in scripts. The `if_` commands open
+//! and close interfaces and change their components; the default host
+//! keeps their state in `Interfaces`.
+
+use crate::colour;
+use crate::host::HostValue;
+use crate::types::Type;
+
+/// `if_open` and `if_close` open and close an interface; the other `if_`
+/// commands set or read the text and colour of one of its components,
+/// which it must have open.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "if_open" => Some((&[Type::Interface], Type::Int)),
+        "if_close" => Some((&[], Type::Int)),
+        "if_settext" => Some((&[Type::Component, Type::String], Type::Int)),
+        "if_setcolour" => Some((&[Type::Component, Type::Color], Type::Int)),
+        "if_gettext" => Some((&[Type::Component], Type::String)),
+        _ => None,
+    }
+}
+
+/// One `[name]` section of a `.if` config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentConfig {
+    /// The full `interface:component` name.
+    pub name: String,
+    /// The interface id.
+    pub interface: i32,
+    /// What `if_gettext` reads before any `if_settext`; empty by default.
+    pub text: String,
+}
+
+impl ComponentConfig {
+    /// Reads the fields of a `.if` section:
+    ///
+    /// ```text
+    /// [title]
+    /// text=The Bank of Gielinor
+    /// ```
+    pub fn parse(name: &str, interface: i32, fields: &[(String, String)]) -> Self {
+        let text = fields
+            .iter()
+            .find(|(key, _)| key == "text")
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        ComponentConfig { name: name.to_string(), interface, text }
+    }
+}
+
+/// One interface command run by a script, with interface and component ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterfaceEvent {
+    Open { interface: i32 },
+    Close { interface: i32 },
+    SetText { component: i32, text: String },
+    SetColour { component: i32, colour: i32 },
+}
+
+/// The state of one component while its interface is open.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ComponentState {
+    text: String,
+    colour: i32,
+}
+
+/// The player's interfaces, kept in memory for runs without a client: one
+/// open at a time, whose components start as their configs declare them
+/// each time it opens.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Interfaces {
+    /// Interface names, indexed by id.
+    names: Vec<String>,
+    /// Indexed by component id.
+    components: Vec<ComponentConfig>,
+    open: Option<i32>,
+    /// Indexed by component id; only the open interface's are used.
+    state: Vec<ComponentState>,
+    events: Vec<InterfaceEvent>,
+}
+
+impl Interfaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the interfaces and components, by id, and closes any open one.
+    /// Events already recorded are kept.
+    pub fn configure(&mut self, names: Vec<String>, components: Vec<ComponentConfig>) {
+        self.names = names;
+        self.state = vec![ComponentState::default(); components.len()];
+        self.components = components;
+        self.open = None;
+    }
+
+    /// Every interface command run so far, in order.
+    pub fn events(&self) -> &[InterfaceEvent] {
+        &self.events
+    }
+
+    /// The interface that is open, if any.
+    pub fn open_interface(&self) -> Option<i32> {
+        self.open
+    }
+
+    fn interface_name(&self, interface: i32) -> Result<&str, String> {
+        usize::try_from(interface)
+            .ok()
+            .and_then(|index| self.names.get(index))
+            .map(String::as_str)
+            .ok_or_else(|| format!("{} is not an interface", interface))
+    }
+
+    /// The config of `component`, checking its interface is open.
+    fn open_component(&self, component: i32) -> Result<&ComponentConfig, String> {
+        let config = usize::try_from(component)
+            .ok()
+            .and_then(|index| self.components.get(index))
+            .ok_or_else(|| format!("{} is not a component", component))?;
+        if self.open != Some(config.interface) {
+            return Err(format!("{}: interface '{}' is not open", config.name, self.interface_name(config.interface)?));
+        }
+        Ok(config)
+    }
+
+    /// Runs `if_open`: closes the open interface, if any, and opens
+    /// `interface` with its components as configured.
+    pub fn open(&mut self, interface: i32) -> Result<(), String> {
+        let name = self.interface_name(interface)?.to_string();
+        if self.open.is_some() {
+            self.close();
+        }
+        for (config, state) in self.components.iter().zip(&mut self.state) {
+            if config.interface == interface {
+                *state = ComponentState { text: config.text.clone(), colour: 0 };
+            }
+        }
+        println!("[if_open {}]", name);
+        self.open = Some(interface);
+        self.events.push(InterfaceEvent::Open { interface });
+        Ok(())
+    }
+
+    /// Runs `if_close`; does nothing if no interface is open.
+    pub fn close(&mut self) {
+        if let Some(interface) = self.open.take() {
+            println!("[if_close {}]", self.names[interface as usize]);
+            self.events.push(InterfaceEvent::Close { interface });
+        }
+    }
+
+    pub fn set_text(&mut self, component: i32, text: &str) -> Result<(), String> {
+        println!("[if_settext {}: {}]", self.open_component(component)?.name, text);
+        self.state[component as usize].text = text.to_string();
+        self.events.push(InterfaceEvent::SetText { component, text: text.to_string() });
+        Ok(())
+    }
+
+    pub fn set_colour(&mut self, component: i32, colour: i32) -> Result<(), String> {
+        let name = &self.open_component(component)?.name;
+        if !colour::is_valid(colour) {
+            return Err(format!("{} is not a 24-bit colour", colour));
+        }
+        println!("[if_setcolour {}: {:#08x}]", name, colour);
+        self.state[component as usize].colour = colour;
+        self.events.push(InterfaceEvent::SetColour { component, colour });
+        Ok(())
+    }
+
+    /// Runs `if_gettext`: the component's text, as last set or configured.
+    pub fn text(&self, component: i32) -> Result<&str, String> {
+        self.open_component(component)?;
+        Ok(&self.state[component as usize].text)
+    }
+
+    /// Runs an interface command that returns an int for the default host.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        match (name, args) {
+            ("if_open", [HostValue::Int(interface)]) => self.open(*interface),
+            ("if_close", []) => {
+                self.close();
+                Ok(())
+            }
+            ("if_settext", [HostValue::Int(component), HostValue::Str(text)]) => self.set_text(*component, text),
+            ("if_setcolour", [HostValue::Int(component), HostValue::Int(colour)]) => self.set_colour(*component, *colour),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        }
+        .map(|()| 0)
+    }
+
+    /// Runs an interface command that returns a string for the default host.
+    pub fn call_string(&mut self, name: &str, args: &[HostValue]) -> Result<String, String> {
+        match (name, args) {
+            ("if_gettext", [HostValue::Int(component)]) => self.text(*component).map(str::to_string),
+            _ => Err(format!("unexpected arguments {:?}", args)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,bank]()(int)\n\
+        if_open(bank_main);\n\
+        if_settext(bank_main:status, if_gettext(bank_main:title));\n\
+        if_settext(bank_main:title, \"Welcome\");\n\
+        if_setcolour(bank_main:title, 0xff0000);\n\
+        if_settext(bank_main:status, if_gettext(bank_main:title));\n\
+        return(0);\n\
+        [proc,closed]()(int)\nif_settext(bank_main:title, \"Welcome\");\nreturn(0);\n";
+
+    fn symbols() -> SymbolTable {
+        test_support::symbols(&[("bank_main.if", "[title]\ntext=The Bank of Gielinor\n\n[status]\n")])
+    }
+
+    #[test]
+    fn a_script_opens_an_interface_sets_text_and_reads_it_back() {
+        let (mut vm, mut evaluator, vm_host, evaluator_host) = test_support::hosted_backends(&symbols(), SCRIPTS);
+        vm.run_script("bank", &[]).unwrap();
+        evaluator.eval_script("bank", &[]).unwrap();
+
+        let (bank_main, title, status) = (0, 0, 1);
+        let expected = [
+            InterfaceEvent::Open { interface: bank_main },
+            InterfaceEvent::SetText { component: status, text: "The Bank of Gielinor".to_string() },
+            InterfaceEvent::SetText { component: title, text: "Welcome".to_string() },
+            InterfaceEvent::SetColour { component: title, colour: 0xff0000 },
+            InterfaceEvent::SetText { component: status, text: "Welcome".to_string() },
+        ];
+        for host in [vm_host, evaluator_host] {
+            let host = host.borrow();
+            assert_eq!(host.interfaces().events(), expected);
+            assert_eq!(host.interfaces().open_interface(), Some(bank_main));
+            assert_eq!(host.interfaces().text(status), Ok("Welcome"));
+        }
+    }
+
+    #[test]
+    fn changing_a_component_of_a_closed_interface_is_an_error() {
+        let (mut vm, mut evaluator, vm_host, _) = test_support::hosted_backends(&symbols(), SCRIPTS);
+        let error = vm.run_script("closed", &[]).unwrap_err();
+        assert!(error.contains("bank_main:title: interface 'bank_main' is not open"), "{}", error);
+        assert!(evaluator.eval_script("closed", &[]).is_err());
+        assert!(vm_host.borrow().interfaces().events().is_empty());
+    }
+
+    #[test]
+    fn opening_another_interface_closes_the_open_one_and_resets_its_text() {
+        let mut interfaces = Interfaces::new();
+        let components = vec![ComponentConfig::parse("bank_main:title", 0, &[("text".to_string(), "Bank".to_string())])];
+        interfaces.configure(vec!["bank_main".to_string(), "shop_main".to_string()], components);
+        interfaces.open(0).unwrap();
+        interfaces.set_text(0, "Changed").unwrap();
+        interfaces.open(1).unwrap();
+        assert_eq!(interfaces.text(0), Err("bank_main:title: interface 'bank_main' is not open".to_string()));
+        interfaces.open(0).unwrap();
+        assert_eq!(interfaces.text(0), Ok("Bank"));
+        assert_eq!(interfaces.events()[2], InterfaceEvent::Close { interface: 0 });
+        assert!(interfaces.open(2).is_err());
+    }
+
+    #[test]
+    fn an_unknown_component_or_a_component_for_an_interface_is_a_compile_error() {
+        let error = test_support::compile_error(&symbols(), "[proc,a]()(int)\nif_settext(bank_main:titel, \"x\");\nreturn(0);\n");
+        assert!(error.to_string().contains("did you mean 'bank_main:title'?"), "{}", error);
+        test_support::compile_error(&symbols(), "[proc,b]()(int)\nif_open(bank_main:title);\nreturn(0);\n");
+    }
+}
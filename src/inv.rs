@@ -0,0 +1,245 @@
+//! Inventories: the `.inv` configs that declare them, and the inventory
+//! commands the default host implements with `Inventories`.
+
+use std::collections::{HashMap, HashSet};
+use crate::host::HostValue;
+use crate::types::{Type, NULL};
+
+/// One `[name]` section of a `.inv` config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvConfig {
+    pub name: String,
+    /// Number of slots.
+    pub size: i32,
+    /// Every obj stacks in this inventory, stackable or not, as in a bank.
+    pub stackall: bool,
+}
+
+impl InvConfig {
+    /// Reads the fields of a `.inv` section. `size` defaults to 1, as in
+    /// the engine.
+    pub fn parse(name: &str, fields: &[(String, String)]) -> Result<Self, String> {
+        let mut config = InvConfig {
+            name: name.to_string(),
+            size: 1,
+            stackall: false,
+        };
+        for (key, value) in fields {
+            match key.as_str() {
+                "size" => {
+                    config.size = value
+                        .parse()
+                        .ok()
+                        .filter(|size| *size > 0)
+                        .ok_or_else(|| format!("inv '{}': size '{}' is not a positive number", name, value))?;
+                }
+                "stackall" => config.stackall = parse_flag(value).ok_or_else(|| format!("inv '{}': stackall '{}' is not yes or no", name, value))?,
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A yes/no config field.
+pub(crate) fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "yes" | "true" => Some(true),
+        "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// `inv_add` and `inv_del` change how many of an obj an inventory holds,
+/// `inv_total` counts them, `inv_size` is its number of slots and
+/// `inv_getobj` the obj in one slot, or `null`.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "inv_add" | "inv_del" => Some((&[Type::Inv, Type::Obj, Type::Int], Type::Int)),
+        "inv_total" => Some((&[Type::Inv, Type::Obj], Type::Int)),
+        "inv_size" => Some((&[Type::Inv], Type::Int)),
+        "inv_getobj" => Some((&[Type::Inv, Type::Int], Type::Obj)),
+        _ => None,
+    }
+}
+
+/// One player's inventories, kept in memory for runs without a game server.
+/// Each slot holds an obj and its count; objs that stack share one slot,
+/// others take a slot each.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inventories {
+    /// Indexed by inv id.
+    configs: Vec<InvConfig>,
+    stackable: HashSet<i32>,
+    slots: HashMap<i32, Vec<Option<(i32, i32)>>>,
+}
+
+impl Inventories {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the inventories, by id, and the objs that stack anywhere.
+    /// Contents are kept.
+    pub fn configure(&mut self, configs: Vec<InvConfig>, stackable: HashSet<i32>) {
+        self.configs = configs;
+        self.stackable = stackable;
+    }
+
+    fn config(&self, inv: i32) -> Result<&InvConfig, String> {
+        usize::try_from(inv)
+            .ok()
+            .and_then(|index| self.configs.get(index))
+            .ok_or_else(|| format!("{} is not an inventory", inv))
+    }
+
+    fn slots(&mut self, inv: i32) -> Result<&mut Vec<Option<(i32, i32)>>, String> {
+        let size = self.config(inv)?.size as usize;
+        Ok(self.slots.entry(inv).or_insert_with(|| vec![None; size]))
+    }
+
+    /// Whether `obj` shares one slot per inventory in `inv`.
+    fn stacks(&self, inv: i32, obj: i32) -> Result<bool, String> {
+        Ok(self.config(inv)?.stackall || self.stackable.contains(&obj))
+    }
+
+    pub fn size(&self, inv: i32) -> Result<i32, String> {
+        Ok(self.config(inv)?.size)
+    }
+
+    pub fn total(&mut self, inv: i32, obj: i32) -> Result<i32, String> {
+        Ok(self.slots(inv)?.iter().flatten().filter(|(slot_obj, _)| *slot_obj == obj).map(|(_, count)| count).sum())
+    }
+
+    /// The obj in `slot`, or `null` if it is empty.
+    pub fn get_obj(&mut self, inv: i32, slot: i32) -> Result<i32, String> {
+        let slots = self.slots(inv)?;
+        let entry = usize::try_from(slot)
+            .ok()
+            .and_then(|index| slots.get(index))
+            .ok_or_else(|| format!("slot {} is outside 0..{}", slot, slots.len()))?;
+        Ok(entry.map_or(NULL, |(obj, _)| obj))
+    }
+
+    /// Adds `count` of `obj`, failing without changing anything if there is
+    /// no room for all of them.
+    pub fn add(&mut self, inv: i32, obj: i32, count: i32) -> Result<(), String> {
+        if count < 0 {
+            return Err(format!("count {} is negative", count));
+        }
+        let name = self.config(inv)?.name.clone();
+        let stacks = self.stacks(inv, obj)?;
+        let slots = self.slots(inv)?;
+        if stacks {
+            if let Some((_, total)) = slots.iter_mut().flatten().find(|(slot_obj, _)| *slot_obj == obj) {
+                *total = total
+                    .checked_add(count)
+                    .ok_or_else(|| format!("stack of {} in inventory '{}' would overflow", obj, name))?;
+                return Ok(());
+            }
+            let empty = slots.iter_mut().find(|slot| slot.is_none()).ok_or_else(|| format!("inventory '{}' is full", name))?;
+            *empty = Some((obj, count));
+            return Ok(());
+        }
+        let free = slots.iter().filter(|slot| slot.is_none()).count();
+        if free < count as usize {
+            return Err(format!("inventory '{}' has room for {} more, not {}", name, free, count));
+        }
+        for slot in slots.iter_mut().filter(|slot| slot.is_none()).take(count as usize) {
+            *slot = Some((obj, 1));
+        }
+        Ok(())
+    }
+
+    /// Removes up to `count` of `obj`, from the first slots holding it.
+    pub fn delete(&mut self, inv: i32, obj: i32, count: i32) -> Result<(), String> {
+        if count < 0 {
+            return Err(format!("count {} is negative", count));
+        }
+        let mut remaining = count;
+        for slot in self.slots(inv)?.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            if let Some((slot_obj, total)) = slot {
+                if *slot_obj != obj {
+                    continue;
+                }
+                let taken = remaining.min(*total);
+                *total -= taken;
+                remaining -= taken;
+                if *total == 0 {
+                    *slot = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs an inventory command for the default host; the parser and type
+    /// checker have already checked the argument count and types.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        let ints: Vec<i32> = args
+            .iter()
+            .map(|arg| match arg {
+                HostValue::Int(value) => Ok(*value),
+                HostValue::Str(text) => Err(format!("\"{}\" is not an int", text)),
+            })
+            .collect::<Result<_, _>>()?;
+        match (name, &ints[..]) {
+            ("inv_add", [inv, obj, count]) => self.add(*inv, *obj, *count).map(|()| 0),
+            ("inv_del", [inv, obj, count]) => self.delete(*inv, *obj, *count).map(|()| 0),
+            ("inv_total", [inv, obj]) => self.total(*inv, *obj),
+            ("inv_size", [inv]) => self.size(*inv),
+            ("inv_getobj", [inv, slot]) => self.get_obj(*inv, *slot),
+            _ => Err(format!("{}() got {} argument(s)", name, args.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support;
+
+    const OBJS: &str = "[coins]\nstackable=yes\n\n[logs]\n";
+
+    const SCRIPTS: &str = "[proc,fill]()(int)\n\
+        inv_add(bag, coins, 100);\ninv_add(bag, coins, 50);\ninv_add(bag, logs, 2);\n\
+        return(calc(inv_total(bag, coins) * 10 + inv_total(bag, logs)));\n\
+        [proc,spend]()(int)\ninv_del(bag, coins, 30);\nreturn(inv_total(bag, coins));\n\
+        [proc,one_more]()(int)\ninv_add(bag, logs, 1);\nreturn(inv_total(bag, logs));\n\
+        [proc,slot](int $slot)(obj)\nreturn(inv_getobj(bag, $slot));\n\
+        [proc,size]()(int)\nreturn(inv_size(bag));\n";
+
+    #[test]
+    fn filling_an_inventory_stacks_and_then_overflows_on_both_backends() {
+        let symbols = test_support::symbols(&[("items.obj", OBJS), ("player.inv", "[bag]\nsize=3\n")]);
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, SCRIPTS);
+        let (coins, logs) = (0, 1);
+
+        // The coins share a slot and each log takes one, filling all three.
+        assert_eq!(vm.run_script("fill", &[]), Ok(1502));
+        assert_eq!(evaluator.eval_script("fill", &[]), Ok(1502));
+        assert_eq!(vm.run_script("size", &[]), Ok(3));
+        for (slot, obj) in [(0, coins), (1, logs), (2, logs)] {
+            assert_eq!(vm.run_script("slot", &[slot]), Ok(obj));
+            assert_eq!(evaluator.eval_script("slot", &[slot]), Ok(obj));
+        }
+        assert_eq!(vm.run_script("spend", &[]), Ok(120));
+        assert_eq!(evaluator.eval_script("spend", &[]), Ok(120));
+
+        let message = "inventory 'bag' has room for 0 more, not 1";
+        let error = vm.run_script("one_more", &[]).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = evaluator.eval_script("one_more", &[]).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+    }
+
+    #[test]
+    fn unknown_inventories_and_objs_are_compile_errors() {
+        let symbols = test_support::symbols(&[("items.obj", OBJS), ("player.inv", "[bag]\nsize=3\n")]);
+        for source in ["[proc,bad]()(int)\nreturn(inv_size(bank));\n", "[proc,bad]()(int)\nreturn(inv_total(bag, gold));\n"] {
+            test_support::compile_error(&symbols, source);
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::iter::from_fn;
 use std::path::PathBuf;
 use crate::error::LexingError;
 use crate::token::{Kind, Token};
+use crate::types::Type;
 
 pub struct Lexer<'a> {
     source_code: &'a str,
@@ -52,8 +53,65 @@ impl<'a> Lexer<'a> {
     }
 
     pub fn tokenize(&mut self) -> Result<Vec<Token>, LexingError> {
+        let source = self.source_code;
+        let mut tokens = self.lex(source)?;
+
+        let eof_token = Token {
+            line: self.line,
+            position: self.position,
+            kind: Kind::EOF,
+            value: "EndOfFile".to_string(),
+        };
+        tokens.push(eof_token);
+
+        Ok(tokens)
+    }
+
+    /// Tokenizes only `source[start_byte..end_byte]`, for editors re-lexing an
+    /// edited region. `line` and `position` must describe where `start_byte`
+    /// sits in the file (see `location_at`) so tokens carry the same
+    /// coordinates a full `tokenize` would give them. No EOF token is added.
+    ///
+    /// The range should start at a token boundary; a range beginning inside a
+    /// token or comment lexes that fragment on its own.
+    pub fn tokenize_range(
+        &mut self,
+        start_byte: usize,
+        end_byte: usize,
+        line: usize,
+        position: usize,
+    ) -> Result<Vec<Token>, LexingError> {
+        let source = self.source_code;
+        if start_byte > end_byte
+            || end_byte > source.len()
+            || !source.is_char_boundary(start_byte)
+            || !source.is_char_boundary(end_byte)
+        {
+            return Err(LexingError::new(
+                self.file_name.clone(),
+                format!("Invalid byte range {}..{}", start_byte, end_byte),
+                line,
+                position,
+            ));
+        }
+
+        self.line = line;
+        self.position = position;
+        self.lex(&source[start_byte..end_byte])
+    }
+
+    /// Returns the `(line, position)` the lexer tracks at `byte_offset`, for
+    /// use as the starting coordinates of `tokenize_range`.
+    pub fn location_at(&self, byte_offset: usize) -> (usize, usize) {
+        let prefix = &self.source_code[..byte_offset.min(self.source_code.len())];
+        let line = prefix.matches('\n').count();
+        let line_start = prefix.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        (line, prefix[line_start..].chars().count())
+    }
+
+    fn lex(&mut self, source: &str) -> Result<Vec<Token>, LexingError> {
         let mut tokens = Vec::new();
-        let mut iter = self.source_code.chars().peekable();
+        let mut iter = source.chars().peekable();
 
         while let Some(ch) = iter.next() {
             self.position += 1;
@@ -87,6 +145,31 @@ impl<'a> Lexer<'a> {
                     let token = self.create_token(Kind::ScriptCall, "~".to_string());
                     tokens.push(token);
                 },
+                '"' => {
+                    let mut value = String::new();
+                    let mut terminated = false;
+                    for c in iter.by_ref() {
+                        if c == '"' {
+                            terminated = true;
+                            break;
+                        }
+                        if c == '\n' {
+                            break;
+                        }
+                        value.push(c);
+                    }
+                    if !terminated {
+                        return Err(LexingError::new(
+                            self.file_name.clone(),
+                            "Unterminated string literal".to_string(),
+                            self.line,
+                            self.position,
+                        ));
+                    }
+                    self.position += value.chars().count() + 1;
+                    let token = self.create_token(Kind::StringLiteral, value);
+                    tokens.push(token);
+                },
                 '(' => {
                     let token = self.create_token(Kind::LParen, ch.to_string());
                     tokens.push(token);
@@ -99,6 +182,21 @@ impl<'a> Lexer<'a> {
                     let token = self.create_token(Kind::LocalVar, ch.to_string());
                     tokens.push(token);
                 },
+                '=' if iter.peek() == Some(&'=') => {
+                    iter.next();  // consume the second '='
+                    self.position += 1;
+                    if iter.peek() == Some(&'=') {
+                        return Err(LexingError::new(
+                            self.file_name.clone(),
+                            "'===' is not an operator; use '=' to compare values".to_string(),
+                            self.line,
+                            self.position,
+                        ));
+                    }
+                    // C-style equality, parsed as an alias of '='.
+                    let token = self.create_token(Kind::ComparisonOperator, "==".to_string());
+                    tokens.push(token);
+                },
                 '=' => {
                     let mut is_comparison = false;
                     for i in (0..tokens.len()).rev() {
@@ -106,7 +204,7 @@ impl<'a> Lexer<'a> {
                             is_comparison = true;
                             break;
                         }
-                        if tokens[i].kind == Kind::Def || tokens[i].kind == Kind::LocalVar {
+                        if matches!(tokens[i].kind, Kind::Def | Kind::LocalVar | Kind::GameVar) {
                             break;
                         }
                     }
@@ -141,7 +239,11 @@ impl<'a> Lexer<'a> {
                         tokens.push(token);
                     }
                 },
-                '+' | '-' | '*' => {
+                '%' if iter.peek().is_some_and(|next| next.is_alphabetic() || *next == '_') => {
+                    let token = self.create_token(Kind::GameVar, ch.to_string());
+                    tokens.push(token);
+                },
+                '+' | '-' | '*' | '%' => {
                     let token = self.create_token(Kind::BinaryOperator, ch.to_string());
                     tokens.push(token);
                 },
@@ -152,9 +254,11 @@ impl<'a> Lexer<'a> {
                                 // Single-line comment
                                 iter.next(); // consume the second '/'
                                 self.position += 1;
-                                let comment: String = iter.by_ref()
-                                    .take_while(|&c| c != '\n')
-                                    .collect();
+                                // The newline is left for the main loop to count.
+                                let mut comment = String::new();
+                                while let Some(c) = iter.next_if(|&c| c != '\n') {
+                                    comment.push(c);
+                                }
                                 self.position += comment.len();
                                 let token = self.create_token(Kind::SingleLineComment, comment);
                                 tokens.push(token);
@@ -224,17 +328,34 @@ impl<'a> Lexer<'a> {
                     let token = self.create_token(Kind::Comma, ch.to_string());
                     tokens.push(token);
                 },
+                '?' => {
+                    let token = self.create_token(Kind::Question, ch.to_string());
+                    tokens.push(token);
+                },
+                ':' => {
+                    // Only reached on its own; `interface:component` is read
+                    // as one identifier below.
+                    let token = self.create_token(Kind::Colon, ch.to_string());
+                    tokens.push(token);
+                },
                 '_' => {
                     let token = self.create_token(Kind::Underscore, ch.to_string());
                     tokens.push(token);
                 },
                 c => {
                     if c.is_alphabetic() || c == '_' {
-                        let ident: String = iter::once(ch)
+                        let mut ident: String = iter::once(ch)
                             .chain(from_fn(|| iter.by_ref().next_if(|s| s.is_alphanumeric() || *s == '_')))
                             .collect::<String>()
                             .parse()
                             .unwrap();
+                        // `interface:component` names a component.
+                        let mut lookahead = iter.clone();
+                        if lookahead.next() == Some(':') && lookahead.peek().is_some_and(|s| s.is_alphabetic() || *s == '_') {
+                            iter.next();
+                            ident.push(':');
+                            ident.extend(from_fn(|| iter.next_if(|s| s.is_alphanumeric() || *s == '_')));
+                        }
 
                         self.position += ident.len();
 
@@ -248,6 +369,22 @@ impl<'a> Lexer<'a> {
                                 tokens.push(token);
                             },
                         }
+                    } else if c == '0' && matches!(iter.peek(), Some('x' | 'X')) {
+                        // Hex literal, e.g. 0xFF0000; above 0x7FFFFFFF it
+                        // wraps to a negative int, as in Java.
+                        iter.next();
+                        let digits: String = from_fn(|| iter.next_if(|c| c.is_ascii_hexdigit())).collect();
+                        let value = u32::from_str_radix(&digits, 16).map_err(|_| {
+                            LexingError::new(
+                                self.file_name.clone(),
+                                format!("Invalid hex literal 0x{}", digits),
+                                self.line,
+                                self.position,
+                            )
+                        })?;
+                        self.position += digits.len() + 2;
+                        let token = self.create_token(Kind::Number, (value as i32).to_string());
+                        tokens.push(token);
                     } else if c.is_ascii_digit() {
                         let number: String = iter::once(ch)
                             .chain(from_fn(|| iter.by_ref().next_if(|s| s.is_ascii_digit())))
@@ -270,31 +407,56 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        let eof_token = Token {
-            line: self.line,
-            position: self.position,
-            kind: Kind::EOF,
-            value: "EndOfFile".to_string(),
-        };
-        tokens.push(eof_token);
-
         Ok(tokens)
     }
 
     pub fn get_keyword_token(&self, ident: &String) -> Result<Kind, LexingError> {
         match ident.as_str() {
             "proc" | "clientscript" | "label" | "debugproc" => Ok(Kind::Trigger),
-            "def_int" | "def_string" | "def_coord" | "def_loc" | 
-            "def_obj" | "def_npc" | "def_boolean" | "def_namedobj" |
-            "def_playeruid" | "def_npcuid" | "def_stat" | "def_component" |
-            "def_interface" | "def_inv" | "def_enum" | "def_struct" |
-            "def_param" | "def_dbtable" | "def_dbrow" | "def_dbcolumn" |
-            "def_varp" | "def_mesanim" => Ok(Kind::Def),
+            keyword if Type::from_def_keyword(keyword).is_some() => Ok(Kind::Def),
             "if" => Ok(Kind::If),
             "while" => Ok(Kind::While),
+            "for" => Ok(Kind::For),
             "return" => Ok(Kind::Return),
             "calc" => Ok(Kind::Command),
             _ => Ok(Kind::Identifier),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "[proc,a]()(int)\nreturn(1);\n\n/* the next one */\n[proc,b](int $n)(int)\ndef_string $s = \"ß and ü\";\nreturn(calc($n + 1));\n";
+
+    /// Lexes `SOURCE[start..end]` on its own and checks it gives the same
+    /// tokens, at the same coordinates, as that region of the whole file.
+    fn assert_range_matches_full(start: usize, end: usize) {
+        let path = PathBuf::from("test.rs2");
+        let full = Lexer::new(SOURCE, &path).tokenize().unwrap();
+        let mut lexer = Lexer::new(SOURCE, &path);
+        let (line, position) = lexer.location_at(start);
+        let range = lexer.tokenize_range(start, end, line, position).unwrap();
+
+        assert!(!range.is_empty());
+        let first = full.iter().position(|token| *token == range[0]).unwrap();
+        assert_eq!(range, full[first..first + range.len()]);
+    }
+
+    #[test]
+    fn tokenizing_a_range_matches_the_full_file() {
+        let second = SOURCE.find("[proc,b]").unwrap();
+        assert_range_matches_full(second, SOURCE.len());
+
+        let string_line = SOURCE.find("def_string").unwrap();
+        assert_range_matches_full(string_line, SOURCE.find("return(calc").unwrap());
+    }
+
+    #[test]
+    fn a_range_off_a_char_boundary_is_an_error() {
+        let path = PathBuf::from("test.rs2");
+        let inside = SOURCE.find('ß').unwrap() + 1;
+        assert!(Lexer::new(SOURCE, &path).tokenize_range(0, inside, 1, 0).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+pub mod error;
+pub mod annotations;
+pub mod lexer;
+pub mod parser;
+pub mod token;
+pub mod evaluator;
+pub mod analysis;
+pub mod config;
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod types;
+pub mod coord;
+pub mod colour;
+pub mod convert;
+pub mod strings;
+pub mod random;
+pub mod scheduler;
+pub mod triggers;
+pub mod symbols;
+pub mod enums;
+pub mod params;
+pub mod varbits;
+pub mod stats;
+pub mod appearance;
+pub mod inv;
+pub mod interfaces;
+pub mod dialogue;
+pub mod effects;
+pub mod db;
+pub mod floors;
+pub mod includes;
+pub mod packs;
+pub mod lints;
+pub mod coverage;
+pub mod profiler;
+pub mod dedupe;
+pub mod entities;
+pub mod optimize;
+pub mod differential;
+pub mod session;
+pub mod bundle;
+pub mod asm;
+pub mod update;
+pub mod host;
+
+#[cfg(test)]
+mod test_support;
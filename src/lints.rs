@@ -0,0 +1,537 @@
+//! Lints: opinionated checks on parsed scripts, run by `rsc check`. Unlike
+//! compile errors they never stop a build; each rule has a code in the
+//! `L0xxx` range and a level a project can change in `rsc.toml`:
+//!
+//! ```toml
+//! [lints.nested-if]
+//! level = "deny"
+//! max = 2
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::compiler;
+use crate::parser::AstKind;
+
+/// What a lint's findings do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Not reported.
+    Allow,
+    /// Reported, without failing `rsc check`.
+    Warn,
+    /// Reported, and `rsc check` fails.
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A project's settings for one rule, under `[lints.<rule>]`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LintSetting {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<Level>,
+    /// The rule's limit, for the rules that have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<usize>,
+}
+
+/// One check. `check` is given each trigger in turn, with the limit the
+/// project set or the rule's own.
+pub trait Rule {
+    fn code(&self) -> &'static str;
+    /// The name the rule is configured by, e.g. `nested-if`.
+    fn name(&self) -> &'static str;
+    fn default_level(&self) -> Level;
+    /// The limit used when the project doesn't set `max`, for rules that
+    /// take one.
+    fn default_max(&self) -> Option<usize> {
+        None
+    }
+    fn check(&self, trigger: &AstKind, max: usize, found: &mut Vec<Finding>);
+}
+
+/// Something a rule found: the source line it's on, if known, and what to
+/// say about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// A finding from a rule that isn't allowed, ready to print.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub code: &'static str,
+    pub level: Level,
+    pub path: PathBuf,
+    pub script: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = if self.level == Level::Deny { "error" } else { "warning" };
+        writeln!(f, "{}[{}]: {} (in script '{}')", level, self.code, self.message, self.script)?;
+        match self.line {
+            Some(line) => writeln!(f, "  --> {}:{}", self.path.display(), line),
+            None => writeln!(f, "  --> {}", self.path.display()),
+        }
+    }
+}
+
+/// Every rule, in code order.
+pub fn rules() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(NestedIf),
+        Box::new(LongScript),
+        Box::new(MagicNumber),
+        Box::new(SelfComparison),
+        Box::new(AssignmentInCondition),
+    ]
+}
+
+/// Runs every rule that isn't allowed over the triggers of one file.
+/// Settings naming no rule are an error, so a typo doesn't silently leave a
+/// rule at its default.
+pub fn check(path: &Path, nodes: &[AstKind], settings: &HashMap<String, LintSetting>) -> Result<Vec<Lint>, String> {
+    let rules = rules();
+    if let Some(unknown) = settings.keys().find(|name| !rules.iter().any(|rule| rule.name() == name.as_str())) {
+        let names: Vec<_> = rules.iter().map(|rule| rule.name()).collect();
+        return Err(format!("unknown lint '{}'; expected one of {}", unknown, names.join(", ")));
+    }
+
+    let mut lints = Vec::new();
+    for rule in &rules {
+        let setting = settings.get(rule.name());
+        let level = setting.and_then(|setting| setting.level).unwrap_or(rule.default_level());
+        if level == Level::Allow {
+            continue;
+        }
+        let max = setting.and_then(|setting| setting.max).or(rule.default_max()).unwrap_or_default();
+        for trigger in nodes {
+            let Some(script) = trigger.script_name() else {
+                continue;
+            };
+            let mut found = Vec::new();
+            rule.check(trigger, max, &mut found);
+            lints.extend(found.into_iter().map(|finding| Lint {
+                code: rule.code(),
+                level,
+                path: path.to_path_buf(),
+                script: script.clone(),
+                line: finding.line,
+                message: finding.message,
+            }));
+        }
+    }
+    lints.sort_by_key(|lint| lint.line);
+    Ok(lints)
+}
+
+/// Where in a script a node sits.
+#[derive(Debug, Clone, Copy, Default)]
+struct Place<'a> {
+    /// The source line of the enclosing statement, once one is known.
+    line: Option<usize>,
+    /// How many `if`s enclose the node, counting the node itself.
+    if_depth: usize,
+    /// Whether the node is part of an `if`, `while` or `for` condition.
+    in_condition: bool,
+    parent: Option<&'a AstKind>,
+}
+
+/// Calls `visit` on `node` and every node under it, with where each sits.
+fn walk<'a>(node: &'a AstKind, place: Place<'a>, visit: &mut dyn FnMut(&'a AstKind, Place<'a>)) {
+    let place = match node {
+        AstKind::If { .. } => Place { if_depth: place.if_depth + 1, ..place },
+        _ => place,
+    };
+    visit(node, place);
+    let child = Place { parent: Some(node), ..place };
+    let condition = Place { in_condition: true, ..child };
+    match node {
+        AstKind::Block(statements) => {
+            let mut line = place.line;
+            for statement in statements {
+                match statement {
+                    AstKind::SourceLine(number) => line = Some(*number),
+                    _ => walk(statement, Place { line, ..child }, visit),
+                }
+            }
+        }
+        AstKind::Trigger { body, .. } => walk(body, child, visit),
+        AstKind::If { expression, value, return_statement } => {
+            walk(expression, condition, visit);
+            walk(value, child, visit);
+            // The parser lifts a block's `return` out of it, leaving its
+            // line marker last in the block.
+            let line = match &**value {
+                AstKind::Block(statements) => statements.iter().rev().find_map(|statement| match statement {
+                    AstKind::SourceLine(line) => Some(*line),
+                    _ => None,
+                }),
+                _ => None,
+            };
+            walk(return_statement, Place { line: line.or(place.line), ..child }, visit);
+        }
+        AstKind::While { condition: test, body } => {
+            walk(test, condition, visit);
+            walk(body, child, visit);
+        }
+        AstKind::InvariantWhile { invariants, condition: test, body } => {
+            for invariant in invariants {
+                walk(invariant, child, visit);
+            }
+            walk(test, condition, visit);
+            walk(body, child, visit);
+        }
+        AstKind::For { init, condition: test, step, body } => {
+            walk(init, child, visit);
+            walk(test, condition, visit);
+            walk(step, child, visit);
+            walk(body, child, visit);
+        }
+        AstKind::BinaryExpression { lhs, rhs, .. } => {
+            walk(lhs, child, visit);
+            walk(rhs, child, visit);
+        }
+        AstKind::ConditionalExpression { lhs, rhs, value } => {
+            walk(lhs, child, visit);
+            walk(value, child, visit);
+            walk(rhs, child, visit);
+        }
+        AstKind::Define { value, .. } | AstKind::Return(value) => walk(value, child, visit),
+        AstKind::Assignment { target, value } => {
+            walk(target, child, visit);
+            walk(value, child, visit);
+        }
+        AstKind::FunctionCall { arguments, .. } | AstKind::ScriptCall { arguments, .. } => {
+            for argument in arguments {
+                walk(argument, child, visit);
+            }
+        }
+        AstKind::Cast { expression, .. } => walk(expression, child, visit),
+        _ => {}
+    }
+}
+
+/// Every script `trigger` calls, by name, with the line of each call, in
+/// source order.
+pub fn script_calls(trigger: &AstKind) -> Vec<(String, Option<usize>)> {
+    let mut calls = Vec::new();
+    walk(trigger, Place::default(), &mut |node, place| {
+        if let AstKind::ScriptCall { script, .. } = node {
+            if let AstKind::Identifier(name) = &**script {
+                calls.push((name.clone(), place.line));
+            }
+        }
+    });
+    calls
+}
+
+/// The first source line of a trigger's body, for findings about the
+/// whole script.
+fn first_line(trigger: &AstKind) -> Option<usize> {
+    match trigger {
+        AstKind::Trigger { body, .. } => match &**body {
+            AstKind::Block(statements) => statements.iter().find_map(|statement| match statement {
+                AstKind::SourceLine(line) => Some(*line),
+                _ => None,
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A variable as written, e.g. `$count` or `%quest_stage`; `None` for
+/// anything else.
+fn variable(node: &AstKind) -> Option<String> {
+    match node {
+        AstKind::LocalVar(name) => Some(format!("${}", name)),
+        AstKind::GameVar(name) | AstKind::Varp { name, .. } => Some(format!("%{}", name)),
+        _ => None,
+    }
+}
+
+/// Whether `node` reads `var` anywhere in it.
+fn reads(node: &AstKind, var: &AstKind) -> bool {
+    let mut found = false;
+    walk(node, Place::default(), &mut |child, _| found |= child == var);
+    found
+}
+
+/// L0001: an `if` inside more than `max` others.
+struct NestedIf;
+
+impl Rule for NestedIf {
+    fn code(&self) -> &'static str {
+        "L0001"
+    }
+
+    fn name(&self) -> &'static str {
+        "nested-if"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn default_max(&self) -> Option<usize> {
+        Some(3)
+    }
+
+    fn check(&self, trigger: &AstKind, max: usize, found: &mut Vec<Finding>) {
+        // Only the first `if` past the limit is reported, not every one
+        // inside it.
+        walk(trigger, Place::default(), &mut |node, place| {
+            if matches!(node, AstKind::If { .. }) && place.if_depth == max + 1 {
+                found.push(Finding {
+                    line: place.line,
+                    message: format!("if nested {} deep, more than {}; return early or move the inner checks into a proc", place.if_depth, max),
+                });
+            }
+        });
+    }
+}
+
+/// L0002: a script of more than `max` statements, counting those in blocks.
+struct LongScript;
+
+impl LongScript {
+    /// The statements in `node`'s blocks and bodies, at any depth.
+    fn statements(node: &AstKind) -> usize {
+        match node {
+            AstKind::Trigger { body, .. } => Self::statements(body),
+            AstKind::Block(statements) => statements.iter().map(Self::body).sum(),
+            AstKind::If { value, return_statement, .. } => {
+                Self::body(value) + usize::from(matches!(**return_statement, AstKind::Return(_)))
+            }
+            AstKind::While { body, .. } | AstKind::InvariantWhile { body, .. } | AstKind::For { body, .. } => Self::body(body),
+            _ => 0,
+        }
+    }
+
+    /// The statements in a block, or in a body written without braces.
+    fn body(node: &AstKind) -> usize {
+        match node {
+            AstKind::SourceLine(_) => 0,
+            AstKind::Block(_) => Self::statements(node),
+            statement => 1 + Self::statements(statement),
+        }
+    }
+}
+
+impl Rule for LongScript {
+    fn code(&self) -> &'static str {
+        "L0002"
+    }
+
+    fn name(&self) -> &'static str {
+        "long-script"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn default_max(&self) -> Option<usize> {
+        Some(50)
+    }
+
+    fn check(&self, trigger: &AstKind, max: usize, found: &mut Vec<Finding>) {
+        let count = Self::statements(trigger);
+        if count > max {
+            found.push(Finding {
+                line: first_line(trigger),
+                message: format!("{} statements, more than {}; split it into procs", count, max),
+            });
+        }
+    }
+}
+
+/// L0003: a number other than 0 or 1 used without a name. The value a
+/// local is defined with is named by the local, so it doesn't count.
+struct MagicNumber;
+
+impl Rule for MagicNumber {
+    fn code(&self) -> &'static str {
+        "L0003"
+    }
+
+    fn name(&self) -> &'static str {
+        "magic-number"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Allow
+    }
+
+    fn check(&self, trigger: &AstKind, _max: usize, found: &mut Vec<Finding>) {
+        walk(trigger, Place::default(), &mut |node, place| {
+            if let AstKind::NumericLiteral(value) = node {
+                if !matches!(value, 0 | 1) && !matches!(place.parent, Some(AstKind::Define { .. })) {
+                    found.push(Finding {
+                        line: place.line,
+                        message: format!("magic number {}; name it with a def_int", value),
+                    });
+                }
+            }
+        });
+    }
+}
+
+/// L0004: a comparison of a variable with itself, which is always true or
+/// always false.
+struct SelfComparison;
+
+impl Rule for SelfComparison {
+    fn code(&self) -> &'static str {
+        "L0004"
+    }
+
+    fn name(&self) -> &'static str {
+        "self-comparison"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn check(&self, trigger: &AstKind, _max: usize, found: &mut Vec<Finding>) {
+        walk(trigger, Place::default(), &mut |node, place| {
+            if let AstKind::BinaryExpression { lhs, rhs, operator } = node {
+                if let (true, true, Some(var)) = (compiler::is_comparison(operator), lhs == rhs, variable(lhs)) {
+                    found.push(Finding {
+                        line: place.line,
+                        message: format!("{} {} {} compares {} with itself", var, operator, var, var),
+                    });
+                }
+            }
+        });
+    }
+}
+
+/// L0005: a condition that reads like an assignment, `$x = calc($x + 1)`.
+/// In a condition `=` compares, so this is almost always false rather than
+/// updating `$x`.
+struct AssignmentInCondition;
+
+impl Rule for AssignmentInCondition {
+    fn code(&self) -> &'static str {
+        "L0005"
+    }
+
+    fn name(&self) -> &'static str {
+        "assignment-in-condition"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn check(&self, trigger: &AstKind, _max: usize, found: &mut Vec<Finding>) {
+        walk(trigger, Place::default(), &mut |node, place| {
+            if !place.in_condition {
+                return;
+            }
+            if let AstKind::BinaryExpression { lhs, rhs, operator } = node {
+                if let (true, Some(var)) = (operator == "=", variable(lhs)) {
+                    if lhs != rhs && reads(rhs, lhs) {
+                        found.push(Finding {
+                            line: place.line,
+                            message: format!(
+                                "{} = ... compares {} with a value computed from it; '=' in a condition doesn't assign, so set {} before the condition",
+                                var, var, var
+                            ),
+                        });
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    /// The codes and lines of what `rule` alone finds in `source`, set to
+    /// warn with `max` as its limit.
+    fn only(rule: &str, max: Option<usize>, source: &str) -> Vec<(&'static str, Option<usize>)> {
+        let settings: HashMap<String, LintSetting> = rules()
+            .iter()
+            .map(|other| {
+                let level = if other.name() == rule { Level::Warn } else { Level::Allow };
+                (other.name().to_string(), LintSetting { level: Some(level), max })
+            })
+            .collect();
+        check(Path::new("test.rs2"), &test_support::parse(source).body, &settings)
+            .unwrap()
+            .into_iter()
+            .map(|lint| (lint.code, lint.line))
+            .collect()
+    }
+
+    #[test]
+    fn nested_if_fires_past_its_depth() {
+        let deep = "[proc,deep](int $n)(int)\nif ($n > 0) {\n    if ($n > 1) {\n        if ($n > 2) {\n            mes(\"deep\");\n        }\n    }\n}\nreturn(0);\n";
+        assert_eq!(only("nested-if", Some(2), deep), [("L0001", Some(4))]);
+        assert!(only("nested-if", Some(3), deep).is_empty());
+    }
+
+    #[test]
+    fn long_script_fires_past_its_statement_count() {
+        let long = "[proc,long]()(int)\nmes(\"a\");\nmes(\"b\");\nmes(\"c\");\nreturn(0);\n";
+        assert_eq!(only("long-script", Some(3), long).iter().map(|(code, _)| *code).collect::<Vec<_>>(), ["L0002"]);
+        assert!(only("long-script", Some(4), long).is_empty());
+    }
+
+    #[test]
+    fn magic_number_fires_outside_a_def_value() {
+        let magic = "[proc,magic](int $n)(int)\nreturn(calc($n * 42));\n";
+        let named = "[proc,named](int $n)(int)\ndef_int $answer = 42;\nreturn(calc($n * $answer + 1));\n";
+        assert_eq!(only("magic-number", None, magic), [("L0003", Some(2))]);
+        assert!(only("magic-number", None, named).is_empty());
+        // Allowed unless a project turns it on.
+        assert!(check(Path::new("test.rs2"), &test_support::parse(magic).body, &HashMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn self_comparison_fires_on_a_variable_compared_with_itself() {
+        let same = "[proc,same](int $n)(int)\nif ($n = $n) {\n    return(1);\n}\nreturn(0);\n";
+        let other = "[proc,other](int $n, int $m)(int)\nif ($n = $m) {\n    return(1);\n}\nreturn(0);\n";
+        assert_eq!(only("self-comparison", None, same), [("L0004", Some(2))]);
+        assert!(only("self-comparison", None, other).is_empty());
+    }
+
+    #[test]
+    fn assignment_in_condition_fires_on_a_comparison_with_itself_updated() {
+        let bumped = "[proc,bumped](int $i)(int)\nwhile ($i = calc($i + 1)) {\n    mes(\"never\");\n}\nreturn($i);\n";
+        let plain = "[proc,plain](int $i)(int)\n$i = calc($i + 1);\nif ($i = 3) {\n    return(1);\n}\nreturn(0);\n";
+        assert_eq!(only("assignment-in-condition", None, bumped), [("L0005", Some(2))]);
+        assert!(only("assignment-in-condition", None, plain).is_empty());
+    }
+
+    #[test]
+    fn an_unknown_rule_in_the_settings_is_an_error() {
+        let settings = HashMap::from([("nested-ifs".to_string(), LintSetting::default())]);
+        let error = check(Path::new("test.rs2"), &[], &settings).unwrap_err();
+        assert!(error.starts_with("unknown lint 'nested-ifs'; expected one of nested-if, "), "{}", error);
+    }
+}
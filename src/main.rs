@@ -1,30 +1,52 @@
 extern crate core;
 
-use crate::error::CompilerError;
-use crate::lexer::Lexer;
-use crate::parser::{Parser, Script, AstKind};
-use crate::compiler::Compiler;
-use crate::vm::VM;
-use crate::config::Config;
+use runescript_compiler::analysis;
+use runescript_compiler::asm;
+use runescript_compiler::error::CompilerError;
+use runescript_compiler::parser::{AstKind, DEFAULT_MAX_ERRORS};
+use runescript_compiler::bytecode::ByteCode;
+use runescript_compiler::bundle::Bundle;
+use runescript_compiler::compiler::Compiler;
+use runescript_compiler::evaluator::Evaluator;
+use runescript_compiler::entities::Spawn;
+use runescript_compiler::host::{CommandRegistry, HostValue};
+use runescript_compiler::includes;
+use runescript_compiler::coverage::{self, ScriptCoverage};
+use runescript_compiler::profiler::CallProfile;
+use runescript_compiler::lints::{self, Level};
+use runescript_compiler::packs;
+use runescript_compiler::vm::{DebugAction, Debugger, Pause, VM};
+use runescript_compiler::config::Config;
+use runescript_compiler::differential::{self, DifferentialHarness, Divergence, Grid, Outcome};
+use runescript_compiler::session::{get_rs2_files, opt_level, parse_scripts, process_rs2_file, CompileSession, Event};
+use runescript_compiler::symbols::SymbolTable;
+use runescript_compiler::triggers::DispatchManifest;
+use runescript_compiler::types::Type;
+use runescript_compiler::update::{Install, SystemRunner, Updated, Updater, Version};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
-use clap::{Parser as ClapParser, Subcommand};
-
-mod error;
-mod lexer;
-mod parser;
-mod token;
-mod evaluator;
-mod analysis;
-mod config;
-mod bytecode;
-mod compiler;
-mod vm;
-mod types;
+use std::io::{self, BufRead, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::path::{Path, PathBuf};
+use clap::{CommandFactory, Parser as ClapParser, Subcommand, ValueEnum};
 
 #[derive(ClapParser)]
 #[command(author, version, about = "RuneScript Compiler")]
 struct Cli {
+    /// Print extra detail, such as files skipped by ignore patterns
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Stop reporting syntax errors in a file after this many
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_ERRORS)]
+    max_errors: usize,
+    /// Compile only this script file and the files it includes, instead of every script found
+    #[arg(long, global = true, value_name = "PATH")]
+    file: Option<PathBuf>,
+    /// Let a script declared again in a later file replace the earlier one, with a warning, instead of failing
+    #[arg(long, global = true)]
+    allow_duplicates: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -33,10 +55,44 @@ struct Cli {
 enum Commands {
     /// Run a RuneScript file with arguments
     Run {
-        /// Name of the script to run (without .rs2 extension)
-        script_name: String,
+        /// Name of the script to run (without .rs2 extension); with --bundle, the first argument
+        #[arg(required_unless_present = "bundle")]
+        script_name: Option<String>,
         /// Arguments to pass to the script
         args: Vec<i32>,
+        /// A typed argument, `int:N` or `string:TEXT`, in place of the positional ones (repeatable)
+        #[arg(long = "arg", value_name = "TYPE:VALUE", value_parser = parse_typed_arg)]
+        typed_args: Vec<HostValue>,
+        /// How to print the result
+        #[arg(long, value_enum, default_value_t = ResultFormat::Dec)]
+        result_format: ResultFormat,
+        /// Print the result of a script returning boolean as 0 or 1 instead of false or true
+        #[arg(long)]
+        bool_as_int: bool,
+        /// Execute compiled bytecode on the VM, or walk the AST directly
+        #[arg(long, value_enum, default_value_t = Backend::Vm)]
+        backend: Backend,
+        /// Pause in the debugger each time execution reaches this source line (VM only, repeatable)
+        #[arg(long = "break-at", value_name = "LINE")]
+        break_at: Vec<usize>,
+        /// On a runtime error, print the failing frame's locals, stack and source line (VM only)
+        #[arg(long)]
+        dump_locals_on_error: bool,
+        /// Seed for random(), so runs with the same seed draw the same values
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Spawn an npc or player before running, `npc:NAME:COORD` or `player:NAME:COORD` with the coord as level_x_z (repeatable)
+        #[arg(long = "spawn", value_name = "KIND:NAME:COORD", value_parser = Spawn::parse)]
+        spawns: Vec<Spawn>,
+        /// Run the script on both the VM and the interpreter, failing if their results or host commands differ
+        #[arg(long, conflicts_with_all = ["backend", "break_at", "dump_locals_on_error"])]
+        compare_engines: bool,
+        /// Run the entry script of a bundle written by `rsc bundle`, without the scripts or config
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["backend", "break_at", "dump_locals_on_error", "compare_engines"])]
+        bundle: Option<PathBuf>,
+        /// Run the script from `.rscasm` text written by `rsc compile --emit bytecode-text` instead of compiling the sources
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["backend", "break_at", "dump_locals_on_error", "compare_engines", "bundle"])]
+        asm: Option<PathBuf>,
     },
     /// Run AOC script with data file
     Aoc {
@@ -44,12 +100,104 @@ enum Commands {
         script_name: String,
         /// Path to data file relative to scripts directory
         data_file: String,
+        /// Report the instructions, time and calls each script took, and which scripts called which
+        #[arg(long)]
+        profile: bool,
+        /// With --profile, also write the call stacks in flamegraph's folded format to this file
+        #[arg(long, value_name = "PATH", requires = "profile")]
+        profile_out: Option<PathBuf>,
+    },
+    /// Run a script and report the source lines that executed the most instructions
+    #[command(name = "profile-hot")]
+    ProfileHot {
+        /// Name of the script to run (without .rs2 extension)
+        script_name: String,
+        /// Arguments to pass to the script
+        args: Vec<i32>,
+        /// Number of lines to report
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Check scripts by comparing backends
+    Test {
+        /// Run each script on both the VM and the interpreter over a grid of
+        /// small arguments and report the first call where they disagree
+        #[arg(long)]
+        differential: bool,
+        /// Only check this script (default: every script)
+        script_name: Option<String>,
+        /// Argument values to combine for each parameter
+        #[arg(long, value_delimiter = ',', allow_hyphen_values = true)]
+        values: Vec<i32>,
+        /// Report which script lines the test run executed, listing the ones it never reached
+        #[arg(long)]
+        coverage: bool,
+        /// With --coverage, also write the report in lcov format to this file
+        #[arg(long, value_name = "PATH", requires = "coverage")]
+        lcov: Option<PathBuf>,
+    },
+    /// Print the full compiled bytecode of a script
+    Disasm {
+        /// Name of the script to disassemble (without .rs2 extension)
+        script_name: String,
+        /// Print a listing with symbolic labels for jump targets instead of indices
+        #[arg(long)]
+        dump_ir: bool,
+    },
+    /// Compile every script, reporting the first error
+    Compile {
+        /// Also write this artifact
+        #[arg(long, value_enum)]
+        emit: Option<Emit>,
+        /// Where to write the artifact (default: dispatch.json, or scripts.rscasm for bytecode-text)
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Write one file with every compiled script and the config files, runnable with `rsc run --bundle`
+    Bundle {
+        /// Name of the script the bundle runs (without .rs2 extension)
+        script_name: String,
+        /// Where to write the bundle (default: <script_name>.rsx)
+        #[arg(long, short, value_name = "PATH")]
+        out: Option<PathBuf>,
+    },
+    /// Run the lints configured under [lints] in rsc.toml over every script
+    Check,
+    /// List the scripts found in the configured script directories
+    List,
+    /// Print a script's doc comment, the `//` lines right above its header
+    Doc {
+        /// Name of the script (without .rs2 extension)
+        script_name: String,
     },
+    /// Print which script files include which
+    Graph,
     /// Analyze the 2004Scape codebase
     #[command(name = "2004")]
-    Analyze2004,
-    /// Update the RuneScript Compiler to the latest version
-    Update,
+    Analyze2004 {
+        /// Print the triggers, commands and types added or removed since
+        /// this saved report
+        #[arg(long, value_name = "PATH")]
+        baseline: Option<PathBuf>,
+        /// Save this analysis as a JSON report, for use as a later baseline
+        #[arg(long, value_name = "PATH")]
+        report: Option<PathBuf>,
+        /// Delete the cloned repository afterwards instead of keeping it
+        /// for the next run to update
+        #[arg(long)]
+        clean: bool,
+    },
+    /// Update the RuneScript Compiler to the latest released version, or to the one given
+    Update {
+        /// Install this release, e.g. v1.2.0, even if it is older than the installed one
+        #[arg(long, value_name = "VERSION", value_parser = parse_version)]
+        version: Option<Version>,
+        /// Print the installed and latest versions without changing anything
+        #[arg(long, conflicts_with = "version")]
+        check: bool,
+    },
+    /// Show the resolved configuration and where each value came from
+    Doctor,
     /// Manage RuneScript configuration
     Config {
         #[command(subcommand)]
@@ -57,6 +205,91 @@ enum Commands {
     },
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Backend {
+    /// Compile to bytecode and run it on the VM
+    Vm,
+    /// Evaluate the parsed AST with the tree-walking interpreter
+    Interp,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Emit {
+    /// The triggers by kind and subject, category or `_`, with script ids and
+    /// the order to try them in, as JSON for an engine to route events with
+    Dispatch,
+    /// Every script's bytecode as `.rscasm` text, one instruction per line
+    /// with labels for jump targets, which `asm::parse` reads back
+    BytecodeText,
+}
+
+impl Emit {
+    fn default_output(self) -> &'static str {
+        match self {
+            Emit::Dispatch => "dispatch.json",
+            Emit::BytecodeText => "scripts.rscasm",
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ResultFormat {
+    /// Plain decimal, e.g. 1000000
+    Dec,
+    /// Hexadecimal of the 32-bit value, e.g. 0xFF
+    Hex,
+    /// Decimal with thousands separators, e.g. 1,000,000
+    Grouped,
+}
+
+impl ResultFormat {
+    fn format(self, value: i32) -> String {
+        match self {
+            ResultFormat::Dec => value.to_string(),
+            // Negative values show their two's complement bits, as flags would be read.
+            ResultFormat::Hex => format!("0x{:X}", value as u32),
+            ResultFormat::Grouped => {
+                let digits = value.unsigned_abs().to_string();
+                let mut grouped = String::new();
+                for (i, digit) in digits.chars().enumerate() {
+                    if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                        grouped.push(',');
+                    }
+                    grouped.push(digit);
+                }
+                if value < 0 {
+                    grouped.insert(0, '-');
+                }
+                grouped
+            }
+        }
+    }
+}
+
+/// How `rsc run` prints a script's result.
+#[derive(Clone, Copy)]
+struct ResultStyle {
+    format: ResultFormat,
+    bool_as_int: bool,
+}
+
+impl ResultStyle {
+    /// The VM and interpreter return booleans as ints, so the script's
+    /// declared return type decides whether `value` is printed as one.
+    fn format(self, value: i32, return_type: Option<&Type>) -> String {
+        match (return_type, value) {
+            (Some(Type::Boolean), 0 | 1) if !self.bool_as_int => (value == 1).to_string(),
+            _ => self.format.format(value),
+        }
+    }
+}
+
+/// The debugging aids `rsc run` sets up on the VM.
+struct DebugOptions {
+    break_at: Vec<usize>,
+    dump_locals_on_error: bool,
+}
+
 #[derive(Subcommand)]
 enum ConfigCommands {
     /// Edit the RC file for the current environment
@@ -66,153 +299,760 @@ enum ConfigCommands {
     /// Initialize a new RC file with defaults
     Init,
     /// List all environment variables and aliases
-    List,
+    List {
+        /// Show the resolved settings of another environment instead
+        #[arg(long)]
+        env: Option<String>,
+    },
+    /// Check that the scripts directories and RC file are usable
+    Validate,
+    /// List the environments under ~/.rsc
+    Envs,
+    /// Convert config.json and the RC file into config.toml
+    Migrate {
+        /// Overwrite an existing config.toml
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-fn get_rs2_files(config: &Config) -> Result<Vec<PathBuf>, CompilerError> {
-    let scripts_path = &config.scripts_dir;
+/// Loads the npc and obj names from the configured config directories.
+fn load_symbols(config: &Config) -> Result<SymbolTable, CompilerError> {
+    Ok(SymbolTable::load(&config.configs)?)
+}
 
-    if !scripts_path.exists() {
-        return Err(CompilerError::FileNotFound(format!(
-            "Scripts directory not found: {}\n\nTo fix this:\n1. Create the directory\n2. Add your .rs2 files there\n3. Or set RSC_SCRIPTS_DIR in your RC file (rsc config edit)",
-            scripts_path.display()
-        )));
+/// Applies `vm.dedupe_scripts` once every script is registered, reporting
+/// which scripts now share a body.
+fn dedupe_scripts(vm: &mut VM, config: &Config) {
+    if !config.vm.dedupe_scripts {
+        return;
     }
-
-    if !scripts_path.is_dir() {
-        return Err(CompilerError::FileNotFound(format!(
-            "Expected {} to be a directory",
-            scripts_path.display()
-        )));
+    let dedupe = vm.dedupe_scripts();
+    if dedupe.aliases.is_empty() {
+        return;
+    }
+    let groups = dedupe.groups();
+    println!(
+        "Deduplicated {} script(s) into {} shared bodies, saving {} instructions",
+        dedupe.aliases.len() + groups.len(),
+        groups.len(),
+        dedupe.saved_instructions
+    );
+    for (body, aliases) in groups {
+        println!("  {} <- {}", body, aliases.join(", "));
     }
+}
 
-    let mut found_scripts: Vec<PathBuf> = Vec::new();
-    let files = fs::read_dir(scripts_path).map_err(|e| {
-        CompilerError::FileNotFound(format!(
-            "Cannot access scripts directory: {}\nError: {}",
-            scripts_path.display(), e
-        ))
-    })?;
+/// Parses an `rsc update --version` value, `v1.2.0` or `1.2.0`.
+fn parse_version(value: &str) -> Result<Version, String> {
+    Version::parse(value).ok_or_else(|| format!("expected a version like v1.2.0, got '{}'", value))
+}
 
-    for entry in files {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-            if path.extension().and_then(|ext| ext.to_str()) == Some("rs2") {
-                found_scripts.push(path);
-            }
-        }
+/// Parses a `--arg` value: `int:N` or `string:TEXT`.
+fn parse_typed_arg(value: &str) -> Result<HostValue, String> {
+    match value.split_once(':') {
+        Some(("int", number)) => number
+            .parse()
+            .map(HostValue::Int)
+            .map_err(|_| format!("'{}' is not an int", number)),
+        Some(("string", text)) => Ok(HostValue::Str(text.to_string())),
+        _ => Err(format!("expected int:N or string:TEXT, got '{}'", value)),
     }
+}
 
-    if found_scripts.is_empty() {
-        return Err(CompilerError::FileNotFound(format!(
-            "No .rs2 files found in: {}\n\nTo fix this:\n1. Add your RuneScript (.rs2) files to this directory\n2. Or set RSC_SCRIPTS_DIR in your RC file (rsc config edit)\n3. Example script path: {}/example.rs2",
-            scripts_path.display(),
-            scripts_path.display()
-        )));
+/// The arguments for `rsc run`: the `--arg` ones if given, else the
+/// positional ints. Giving both is an error.
+fn script_args(args: Vec<i32>, typed_args: Vec<HostValue>) -> Vec<HostValue> {
+    if typed_args.is_empty() {
+        return args.into_iter().map(HostValue::Int).collect();
     }
+    if !args.is_empty() {
+        println!("Error: pass arguments either positionally or with --arg, not both");
+        std::process::exit(1);
+    }
+    typed_args
+}
 
-    Ok(found_scripts)
+fn display_args(args: &[HostValue]) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| match arg {
+            HostValue::Int(value) => value.to_string(),
+            HostValue::Str(text) => format!("{:?}", text),
+        })
+        .collect();
+    format!("[{}]", args.join(", "))
 }
 
-fn process_rs2_file(path_buf: &PathBuf) -> Result<Script, CompilerError> {
-    let source_code = fs::read_to_string(path_buf)
-        .map_err(|e| CompilerError::IO(e))?;
-    
-    let tokens = Lexer::new(&source_code, path_buf)
-        .tokenize()
-        .map_err(|e| CompilerError::LexingError(e))?;
-        
-    let mut parser = Parser::new(tokens, path_buf);
-    parser.parse()
-        .map_err(|e| CompilerError::Syntax(e))
+/// Checks `args` against the parameters `script` declares: their number,
+/// allowing for defaults, and that strings go to `string` parameters and
+/// ints to the rest. With `lenient`, as for `vm.lenient_args`, too few is
+/// fine.
+fn check_args(script: &AstKind, args: &[HostValue], lenient: bool) -> Result<(), String> {
+    let AstKind::Trigger { args: params, defaults, .. } = script else {
+        return Ok(());
+    };
+    let params: Vec<(Type, &str)> = params
+        .chunks(2)
+        .filter_map(|pair| match (&*pair[0], pair.get(1).map(|name| &**name)) {
+            (AstKind::Identifier(type_name), Some(AstKind::LocalVar(name))) => Some((Type::from_name(type_name)?, name.as_str())),
+            _ => None,
+        })
+        .collect();
+    let required = (0..params.len())
+        .find(|&index| defaults.get(index).is_some_and(Option::is_some))
+        .unwrap_or(params.len());
+    if (args.len() < required && !lenient) || args.len() > params.len() {
+        let expected = if required == params.len() {
+            required.to_string()
+        } else {
+            format!("{} to {}", required, params.len())
+        };
+        let name = script.script_name().unwrap_or_default();
+        return Err(format!("'{}' takes {} arguments, got {}", name, expected, args.len()));
+    }
+    for ((param_type, param), arg) in params.iter().zip(args) {
+        match arg {
+            HostValue::Str(_) if *param_type != Type::String => {
+                return Err(format!("${} is {}, not a string; pass it as int:N", param.trim_start_matches('$'), param_type));
+            }
+            HostValue::Int(_) if *param_type == Type::String => {
+                return Err(format!("${} is a string; pass it as string:TEXT", param.trim_start_matches('$')));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The default host, with the players and npcs given by `rsc run --spawn`
+/// spawned.
+fn default_host(config: &Config, symbols: &SymbolTable) -> Result<CommandRegistry, String> {
+    let mut host = CommandRegistry::new();
+    for spawn in &config.vm.spawns {
+        host.entities_mut().spawn(spawn, symbols)?;
+    }
+    Ok(host)
 }
 
-fn run_script(script_name: &str, args: &[i32], config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+fn run_script(script_name: &str, args: &[HostValue], result_style: ResultStyle, debug: &DebugOptions, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting script execution...");
     
     // Load and register all scripts
+    let symbols = load_symbols(config)?;
+    let mut vm = VM::with_options(&config.vm);
+    vm.set_script_overrides(&config.scripts);
+    vm.set_host(default_host(config, &symbols)?);
+    vm.load_symbols(&symbols);
     let mut compiler = Compiler::new();
-    let mut vm = VM::new();
+    compiler.set_symbols(symbols);
+    compiler.set_strict_conditions(config.strict_conditions);
     
     let mut found_script = false;
-    let scripts = match get_rs2_files(config) {
-        Ok(scripts) => scripts,
+    let mut arg_error = None;
+    let mut return_type = None;
+    let mut session = CompileSession::new(config, compiler);
+    session.set_verbose(verbose);
+    session.set_max_errors(max_errors);
+    session.subscribe(print_progress);
+    let compiled = match session.run() {
+        Ok(compiled) => compiled,
         Err(CompilerError::FileNotFound(msg)) => {
             println!("Error: {}", msg);
             println!("\nCurrent configuration:");
             println!("  Environment: {}", config.env_name);
-            println!("  Scripts directory: {}", config.scripts_dir.display());
+            println!("  Scripts directories: {}", config.scripts_dirs_display());
             println!("\nTo change the scripts directory:");
             println!("1. Edit your RC file: rsc config edit");
             println!("2. Add: export RSC_SCRIPTS_DIR=/path/to/your/scripts");
+            println!("   or: export RSC_SCRIPTS_PATH=/path/to/lib:/path/to/day1");
             return Ok(());
         }
         Err(e) => return Err(Box::new(e)),
     };
 
-    println!("Found {} script files", scripts.len());
+    // Register scripts and check if target exists
+    let mut names = Vec::new();
+    for script in compiled {
+        let name = &script.bytecode.script_name;
+        names.push(name.clone());
+        // Print bytecode instructions for debugging
+        if name.to_lowercase() == script_name.to_lowercase() {
+            print_bytecode(name, &script.bytecode, config.dump_limit);
+            found_script = true;
+            arg_error = check_args(&script.node, args, config.vm.lenient_args).err();
+            return_type = script.node.return_type();
+        }
+        println!("Registering script: {}", name);
+        vm.register_script(script.bytecode);
+    }
+    dedupe_scripts(&mut vm, config);
+
+    if !found_script {
+        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
+        println!("\nAvailable scripts:");
+        for name in names {
+            println!("  {}", name);
+        }
+        return Ok(());
+    }
+    if let Some(message) = arg_error {
+        println!("Error: {}", message);
+        return Ok(());
+    }
 
-    // First pass to register scripts and check if target exists
-    for path in &scripts {
-        println!("Processing script: {}", path.display());
-        let script = process_rs2_file(path)?;
-        for node in &script.body {
-            if let AstKind::Trigger { name, .. } = node {
-                if let AstKind::Identifier(script_name_found) = &**name {
-                    println!("Compiling script: {}", script_name_found);
-                    let bytecode = compiler.compile_script(script_name_found.clone(), node);
-                    
-                    // Print bytecode instructions for debugging
-                    if script_name_found.to_lowercase() == script_name.to_lowercase() {
-                        println!("\nBytecode for script '{}':", script_name_found);
-                        for (i, instruction) in bytecode.instructions.iter().enumerate() {
-                            println!("{:04}: {:?}", i, instruction);
-                        }
-                        println!();
-                        found_script = true;
+    for &line in &debug.break_at {
+        match vm.add_breakpoint(line) {
+            Some(resolved) if resolved == line => println!("Breakpoint set at line {}", line),
+            Some(resolved) => println!("Line {} has no code; breakpoint set at line {}", line, resolved),
+            None => println!("Warning: no code on or after line {}; breakpoint ignored", line),
+        }
+    }
+    if !debug.break_at.is_empty() {
+        vm.set_debugger(ConsoleDebugger);
+    }
+
+    println!("\nExecuting {} with args: {}", script_name, display_args(args));
+    // Run the specified script
+    match vm.run_script_with_state(script_name, args) {
+        Ok(result) => println!("Result: {}", result_style.format(result, return_type.as_ref())),
+        Err(e) => {
+            println!("Error executing script: {}", e);
+            if let (true, Some(frame)) = (debug.dump_locals_on_error, &e.frame) {
+                print!("\n{}", frame);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads debugger commands from stdin whenever the VM pauses.
+struct ConsoleDebugger;
+
+impl Debugger for ConsoleDebugger {
+    fn pause(&mut self, at: &Pause) -> DebugAction {
+        println!("\nPaused in '{}' at line {} (instruction {})", at.script, at.line, at.ip);
+        let stdin = io::stdin();
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+            let mut command = String::new();
+            // End of input resumes the run rather than waiting forever.
+            if stdin.lock().read_line(&mut command).unwrap_or(0) == 0 {
+                return DebugAction::Continue;
+            }
+            match command.trim() {
+                "c" | "continue" => return DebugAction::Continue,
+                "s" | "step" => return DebugAction::Step,
+                "q" | "quit" => return DebugAction::Abort,
+                "l" | "locals" => {
+                    let mut locals: Vec<_> = at.locals.iter().filter(|(name, _)| !name.starts_with("__")).collect();
+                    locals.sort();
+                    for (name, value) in locals {
+                        println!("  ${} = {}", name, value);
                     }
-                    
-                    println!("Registering script: {}", script_name_found);
-                    vm.register_script(bytecode);
                 }
+                "stack" => println!("  {:?}", at.stack),
+                _ => println!("Commands: c(ontinue), s(tep), l(ocals), stack, q(uit)"),
             }
         }
     }
+}
 
-    if !found_script {
-        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dir.display());
-        println!("\nAvailable scripts:");
-        for path in &scripts {
-            if let Ok(script) = process_rs2_file(path) {
-                if let Some(AstKind::Trigger { name, .. }) = script.body.get(0) {
-                    if let AstKind::Identifier(name) = &**name {
-                        println!("  {}", name);
-                    }
+/// Prints a `CompileSession`'s progress. Compiler warnings aren't repeated,
+/// since the compiler prints them as it finds them.
+fn print_progress(event: &Event) {
+    match event {
+        Event::FileDiscovered { .. } | Event::DiagnosticEmitted { .. } => {}
+        Event::Parsed { file, duration } => println!("Parsed {} in {:.2?}", file.display(), duration),
+        Event::Compiled { script, instructions } => println!("Compiled script: {} ({} instructions)", script, instructions),
+        Event::Finished { summary } => println!(
+            "Compiled {} script(s) from {} file(s) in {:.2?}, with {} warning(s)",
+            summary.scripts, summary.files, summary.duration, summary.diagnostics
+        ),
+    }
+}
+
+/// Prints the first `limit` instructions of a script's bytecode, then how
+/// many were left out.
+fn print_bytecode(name: &str, bytecode: &ByteCode, limit: usize) {
+    println!("\nBytecode for script '{}':", name);
+    for (i, instruction) in bytecode.instructions.iter().enumerate().take(limit) {
+        println!("{:04}: {:?}", i, instruction);
+    }
+    let hidden = bytecode.instructions.len().saturating_sub(limit);
+    if hidden > 0 {
+        println!("... ({} more; `rsc disasm {}` prints them all)", hidden, name);
+    }
+    println!();
+}
+
+/// Compiles every script and prints the whole bytecode of `script_name`,
+/// or with `dump_ir` its listing with labelled jumps.
+fn disasm(script_name: &str, dump_ir: bool, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut compiler = Compiler::new();
+    compiler.set_symbols(load_symbols(config)?);
+    compiler.set_strict_conditions(config.strict_conditions);
+
+    let mut session = CompileSession::new(config, compiler);
+    session.set_verbose(verbose);
+    session.set_max_errors(max_errors);
+    session.subscribe(print_progress);
+    let compiled = match session.run() {
+        Ok(compiled) => compiled,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let Some(script) = compiled.iter().find(|script| script.bytecode.script_name.eq_ignore_ascii_case(script_name)) else {
+        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
+        return Ok(());
+    };
+    let name = &script.bytecode.script_name;
+    if dump_ir {
+        println!("IR for script '{}':", name);
+        for line in script.bytecode.labelled() {
+            println!("{}", line);
+        }
+    } else {
+        print_bytecode(name, &script.bytecode, usize::MAX);
+    }
+    Ok(())
+}
+
+fn compile_all(emit: Option<Emit>, output: Option<&Path>, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut compiler = Compiler::new();
+    compiler.set_symbols(load_symbols(config)?);
+    compiler.set_strict_conditions(config.strict_conditions);
+
+    let mut session = CompileSession::new(config, compiler);
+    session.set_verbose(verbose);
+    session.set_max_errors(max_errors);
+    session.subscribe(print_progress);
+    let compiled = match session.run() {
+        Ok(compiled) => compiled,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let names: Vec<&str> = compiled.iter().map(|script| script.bytecode.script_name.as_str()).collect();
+    let Some(emit) = emit else {
+        return Ok(());
+    };
+    let artifact = match emit {
+        Emit::Dispatch => serde_json::to_string_pretty(&DispatchManifest::new(names.iter().copied()))?,
+        Emit::BytecodeText => asm::emit(compiled.iter().map(|script| &script.bytecode)),
+    };
+    let output = output.unwrap_or_else(|| Path::new(emit.default_output()));
+    fs::write(output, artifact)?;
+    println!("Compiled {} scripts; wrote {}", names.len(), output.display());
+    Ok(())
+}
+
+/// Compiles every script and writes them to `out` as a bundle that runs
+/// `script_name`.
+fn write_bundle(script_name: &str, out: &Path, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut compiler = Compiler::new();
+    compiler.set_symbols(load_symbols(config)?);
+    compiler.set_strict_conditions(config.strict_conditions);
+
+    let mut session = CompileSession::new(config, compiler);
+    session.set_verbose(verbose);
+    session.set_max_errors(max_errors);
+    session.subscribe(print_progress);
+    let compiled = match session.run() {
+        Ok(compiled) => compiled,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let Some(entry) = compiled.iter().find(|script| script.bytecode.script_name.eq_ignore_ascii_case(script_name)) else {
+        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
+        return Ok(());
+    };
+    let entry_name = entry.bytecode.script_name.clone();
+    let return_type = entry.node.return_type();
+    let bundle = Bundle::new(&entry_name, return_type, compiled.into_iter().map(|script| script.bytecode).collect(), config)?;
+    bundle.write(out)?;
+    println!(
+        "Bundled {} scripts and {} config files running '{}' into {}",
+        bundle.scripts.len(),
+        bundle.configs.len(),
+        entry_name,
+        out.display()
+    );
+    Ok(())
+}
+
+/// Runs the entry script of the bundle at `path`, once its hashes check
+/// out, with the VM options it was built with. Only `--seed` and `--spawn`
+/// come from this run.
+fn run_bundle(path: &Path, args: &[HostValue], result_style: ResultStyle, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let bundle = match Bundle::read(path) {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            println!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let symbols = bundle.symbols()?;
+    let mut options = bundle.vm.clone();
+    options.seed = config.vm.seed;
+    options.spawns = config.vm.spawns.clone();
+    let mut vm = VM::with_options(&options);
+    vm.set_script_overrides(&bundle.script_overrides.iter().map(|(name, overrides)| (name.clone(), overrides.clone())).collect());
+    vm.set_host(default_host(config, &symbols)?);
+    vm.load_symbols(&symbols);
+    for script in bundle.scripts {
+        vm.register_script(script.bytecode);
+    }
+    if options.dedupe_scripts {
+        vm.dedupe_scripts();
+    }
+
+    match vm.run_script_with_state(&bundle.entry, args) {
+        Ok(result) => println!("Result: {}", result_style.format(result, bundle.return_type.as_ref())),
+        Err(e) => println!("Error executing script: {}", e),
+    }
+    Ok(())
+}
+
+/// Runs `script_name` from the `.rscasm` scripts at `path`, with the
+/// project's config files and VM options but none of its sources. The text
+/// doesn't record return types, so the result prints as an int.
+fn run_asm(path: &Path, script_name: &str, args: &[HostValue], result_style: ResultStyle, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let scripts = match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| asm::parse(&text)) {
+        Ok(scripts) => scripts,
+        Err(e) => {
+            println!("Error: {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let Some(entry) = scripts.iter().find(|script| script.script_name.eq_ignore_ascii_case(script_name)) else {
+        println!("Error: Script '{}' not found in {}", script_name, path.display());
+        std::process::exit(1);
+    };
+    let entry = entry.script_name.clone();
+
+    let symbols = load_symbols(config)?;
+    let mut vm = VM::with_options(&config.vm);
+    vm.set_script_overrides(&config.scripts);
+    vm.set_host(default_host(config, &symbols)?);
+    vm.load_symbols(&symbols);
+    for script in scripts {
+        vm.register_script(script);
+    }
+    dedupe_scripts(&mut vm, config);
+
+    match vm.run_script_with_state(&entry, args) {
+        Ok(result) => println!("Result: {}", result_style.format(result, None)),
+        Err(e) => println!("Error executing script: {}", e),
+    }
+    Ok(())
+}
+
+/// Runs a script with the tree-walking `Evaluator`, bypassing the compiler,
+/// as a reference to compare the VM against.
+fn run_interpreted(script_name: &str, args: &[HostValue], result_style: ResultStyle, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut evaluator = Evaluator::with_options(&config.vm);
+    evaluator.set_script_overrides(&config.scripts);
+    let symbols = load_symbols(config)?;
+    evaluator.set_host(default_host(config, &symbols)?);
+    evaluator.load_symbols(&symbols);
+
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let mut found_script = false;
+    let mut arg_error = None;
+    let mut return_type = None;
+    for script in parse_scripts(&scripts, config, max_errors)? {
+        for node in script.body {
+            if let Some(name) = node.script_name() {
+                if name == script_name {
+                    found_script = true;
+                    arg_error = check_args(&node, args, config.vm.lenient_args).err();
+                    return_type = node.return_type();
                 }
+                let resolved = symbols
+                    .resolve(&node)
+                    .map_err(|message| CompilerError::Type(format!("{} (in script '{}')", message, name)))?;
+                evaluator.register_script(name, resolved);
             }
         }
+    }
+
+    if !found_script {
+        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
+        return Ok(());
+    }
+    if let Some(message) = arg_error {
+        println!("Error: {}", message);
         return Ok(());
     }
 
-    println!("\nExecuting {} with args: {:?}", script_name, args);
-    // Run the specified script
-    match vm.run_script(script_name, args) {
-        Ok(result) => println!("Result: {}", result),
+    println!("Interpreting {} with args: {}", script_name, display_args(args));
+    match evaluator.eval_script_with_args(script_name, args) {
+        Ok(result) => println!("Result: {}", result_style.format(result, return_type.as_ref())),
         Err(e) => println!("Error executing script: {}", e),
     }
     Ok(())
 }
 
-fn run_aoc(script_name: &str, data_file: &str, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+/// `rsc run --compare-engines`: runs one call on the VM and the interpreter
+/// through a `DifferentialHarness` and exits with 1 if they disagree.
+fn compare_engines(script_name: &str, args: &[HostValue], result_style: ResultStyle, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(args) = args
+        .iter()
+        .map(|arg| match arg {
+            HostValue::Int(value) => Some(*value),
+            _ => None,
+        })
+        .collect::<Option<Vec<i32>>>()
+    else {
+        println!("Error: --compare-engines only passes int arguments");
+        std::process::exit(1);
+    };
+
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let mut harness = DifferentialHarness::new(&config.vm);
+    harness.set_symbols(load_symbols(config)?);
+    harness.set_strict_conditions(config.strict_conditions);
+    let parsed = parse_scripts(&scripts, config, max_errors)?;
+    for node in parsed.iter().flat_map(|script| &script.body) {
+        harness.add_source(node);
+    }
+    let mut target = None;
+    for node in parsed.iter().flat_map(|script| &script.body) {
+        if let Some(name) = node.script_name() {
+            harness.add_script(node, opt_level(config, &name))?;
+            if name.eq_ignore_ascii_case(script_name) {
+                target = Some((name, node.return_type()));
+            }
+        }
+    }
+    let Some((name, return_type)) = target else {
+        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
+        return Ok(());
+    };
+
+    println!("\nComparing engines on {} with args: {:?}", name, args);
+    let (vm, interp) = harness.run(&name, &args);
+    if vm.inconclusive_with(&interp) {
+        let (engine, run) = if vm.outcome.hit_limit() { ("vm", &vm) } else { ("interpreter", &interp) };
+        println!("\nInconclusive: only the {} hit its limits, which the engines count differently: {}", engine, run.outcome);
+        return Ok(());
+    }
+    if !vm.agrees_with(&interp) {
+        let divergence = Divergence { script: name, args, vm, interp };
+        println!("\nEngines disagree: {}", divergence);
+        std::process::exit(1);
+    }
+    match vm.outcome {
+        Outcome::Value(value) => println!("\nEngines agree. Result: {}", result_style.format(value, return_type.as_ref())),
+        Outcome::Error(_, message) => println!("\nEngines agree. Both failed: {}", message),
+    }
+    Ok(())
+}
+
+/// Runs every script (or just `script_name`) on both backends over a grid of
+/// arguments and exits nonzero at the first divergence.
+/// `coverage` is `Some` to report coverage, with the file to write an lcov
+/// report to, if any.
+fn run_differential(script_name: Option<&str>, values: &[i32], coverage: Option<Option<&Path>>, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let mut options = config.vm.clone();
+    options.max_instructions = options.max_instructions.min(differential::DEFAULT_MAX_INSTRUCTIONS);
+    let mut harness = DifferentialHarness::new(&options);
+    harness.set_symbols(load_symbols(config)?);
+    harness.set_strict_conditions(config.strict_conditions);
+    if coverage.is_some() {
+        harness.enable_coverage();
+    }
+    let parsed = parse_scripts(&scripts, config, max_errors)?;
+    for node in parsed.iter().flat_map(|script| &script.body) {
+        harness.add_source(node);
+    }
+    let mut script_files = HashMap::new();
+    for (path, script) in scripts.iter().zip(&parsed) {
+        for node in &script.body {
+            if let Some(name) = node.script_name() {
+                harness.add_script(node, opt_level(config, &name))?;
+                script_files.insert(name, path.clone());
+            }
+        }
+    }
+
+    let targets: Vec<(String, usize)> = harness
+        .scripts()
+        .iter()
+        .filter(|(name, _)| script_name.is_none_or(|wanted| name.eq_ignore_ascii_case(wanted)))
+        .cloned()
+        .collect();
+    if targets.is_empty() {
+        println!("Error: Script '{}' not found in {}", script_name.unwrap_or_default(), config.scripts_dirs_display());
+        return Ok(());
+    }
+
+    let mut grid = if values.is_empty() { Grid::default() } else { Grid { values: values.to_vec() } };
+    let mut report = Vec::new();
+    let mut divergence = None;
+    for (name, _) in &targets {
+        let check = harness.check_script(name, &mut grid);
+        let diverged = check.divergence.is_some();
+        report.push((name.clone(), check.compared, check.inconclusive, !diverged));
+        if diverged {
+            divergence = check.divergence;
+            break;
+        }
+    }
+
+    println!("\nDifferential test (vm vs interp, values {:?}):", grid.values);
+    for (name, compared, inconclusive, agreed) in &report {
+        let status = if *agreed { "ok" } else { "DIVERGED" };
+        match inconclusive {
+            0 => println!("  {:<24} {:>4} calls  {}", name, compared, status),
+            _ => println!("  {:<24} {:>4} calls  {} ({} inconclusive: one engine hit its limits)", name, compared, status, inconclusive),
+        }
+    }
+    if let (Some(lcov_path), Some(coverage)) = (coverage, harness.coverage()) {
+        // Scripts not under test count only if a tested script called them.
+        let reported: Vec<ScriptCoverage> = harness
+            .bytecode()
+            .iter()
+            .filter_map(|bytecode| {
+                let path = script_files.get(&bytecode.script_name)?;
+                Some(ScriptCoverage { script: bytecode.script_name.clone(), path: path.clone(), lines: coverage.lines(bytecode) })
+            })
+            .filter(|script| targets.iter().any(|(name, _)| *name == script.script) || script.covered() > 0)
+            .collect();
+        print_coverage(&reported);
+        if let Some(lcov_path) = lcov_path {
+            fs::write(lcov_path, coverage::lcov(&reported))?;
+            println!("\nWrote lcov report to {}", lcov_path.display());
+        }
+    }
+    if let Some(divergence) = divergence {
+        println!("\n{}", divergence);
+        std::process::exit(1);
+    }
+    println!("\nAll {} scripts agree.", report.len());
+    Ok(())
+}
+
+/// Prints each script's share of lines run, then the source of every script
+/// with lines that never ran, marked like gcov: each line's hits, `#####`
+/// for a line that never ran and `-` for one without code.
+fn print_coverage(scripts: &[ScriptCoverage]) {
+    println!("\nCoverage:");
+    for script in scripts {
+        println!("  {:<24} {:>5.1}%  ({}/{} lines)", script.script, script.percent(), script.covered(), script.lines.len());
+    }
+
+    let mut sources: HashMap<&PathBuf, Vec<String>> = HashMap::new();
+    for script in scripts.iter().filter(|script| !script.uncovered().is_empty()) {
+        let (Some(&first), Some(&last)) = (script.lines.keys().next(), script.lines.keys().next_back()) else {
+            continue;
+        };
+        let source = sources.entry(&script.path).or_insert_with(|| {
+            packs::read_to_string(&script.path).map(|text| text.lines().map(str::to_string).collect()).unwrap_or_default()
+        });
+        println!("\n{} ({}):", script.script, script.path.display());
+        for line in first..=last {
+            let hits = match script.lines.get(&line) {
+                Some(0) => "#####".to_string(),
+                Some(hits) => hits.to_string(),
+                None => "-".to_string(),
+            };
+            let text = source.get(line - 1).map(String::as_str).unwrap_or_default();
+            println!("  {:>9}:{:>5}: {}", hits, line, text);
+        }
+    }
+}
+
+/// Prints the scripts of a `--profile` run, most self instructions first,
+/// then the calls between them.
+fn print_call_profile(profile: &CallProfile) {
+    println!("\nProfile:");
+    println!(
+        "  {:<24} {:>8} {:>12} {:>12} {:>10} {:>10}",
+        "script", "calls", "self instr", "incl instr", "self ms", "incl ms"
+    );
+    for (script, stats) in profile.scripts() {
+        println!(
+            "  {:<24} {:>8} {:>12} {:>12} {:>10.3} {:>10.3}",
+            script,
+            stats.calls,
+            stats.self_instructions,
+            stats.inclusive_instructions,
+            stats.self_time.as_secs_f64() * 1000.0,
+            stats.inclusive_time.as_secs_f64() * 1000.0,
+        );
+    }
+
+    let edges = profile.edges();
+    if !edges.is_empty() {
+        println!("\nCalls:");
+        for (caller, callee, count) in edges {
+            println!("  {} -> {}  {}", caller, callee, count);
+        }
+    }
+}
+
+/// `profile` is `Some` to report a call profile, with the file to write
+/// folded stacks to, if any.
+fn run_aoc(script_name: &str, data_file: &str, profile: Option<Option<&Path>>, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
     println!("Starting AOC script execution...");
     
     // Load and register all scripts
+    let symbols = load_symbols(config)?;
+    let mut vm = VM::with_options(&config.vm);
+    if profile.is_some() {
+        vm.enable_call_profile();
+    }
+    vm.set_script_overrides(&config.scripts);
+    vm.load_symbols(&symbols);
     let mut compiler = Compiler::new();
-    let mut vm = VM::new();
+    compiler.set_symbols(symbols);
+    compiler.set_strict_conditions(config.strict_conditions);
     
-    // Load scripts
-    let scripts = match get_rs2_files(config) {
-        Ok(scripts) => scripts,
+    let mut session = CompileSession::new(config, compiler);
+    session.set_verbose(verbose);
+    session.set_max_errors(max_errors);
+    session.subscribe(print_progress);
+    let compiled = match session.run() {
+        Ok(compiled) => compiled,
         Err(CompilerError::FileNotFound(msg)) => {
             println!("Error: {}", msg);
             return Ok(());
@@ -220,22 +1060,12 @@ fn run_aoc(script_name: &str, data_file: &str, config: &Config) -> Result<(), Bo
         Err(e) => return Err(Box::new(e)),
     };
 
-    // First pass to register scripts
     let mut found_script = false;
-    for path in &scripts {
-        let script = process_rs2_file(path)?;
-        for node in &script.body {
-            if let AstKind::Trigger { name, .. } = node {
-                if let AstKind::Identifier(script_name_found) = &**name {
-                    let bytecode = compiler.compile_script(script_name_found.clone(), node);
-                    if script_name_found.to_lowercase() == script_name.to_lowercase() {
-                        found_script = true;
-                    }
-                    vm.register_script(bytecode);
-                }
-            }
-        }
+    for script in compiled {
+        found_script |= script.bytecode.script_name.eq_ignore_ascii_case(script_name);
+        vm.register_script(script.bytecode);
     }
+    dedupe_scripts(&mut vm, config);
 
     if !found_script {
         println!("Error: Script '{}' not found", script_name);
@@ -273,50 +1103,575 @@ fn run_aoc(script_name: &str, data_file: &str, config: &Config) -> Result<(), Bo
     left_list.sort();
     right_list.sort();
 
+    // The first Ctrl-C stops the pair being run and keeps the summary of
+    // the ones before it; a second quits without waiting.
+    let cancel = vm.cancel_flag();
+    ctrlc::set_handler(move || {
+        if cancel.swap(true, Ordering::SeqCst) {
+            std::process::exit(CANCELLED_EXIT_CODE);
+        }
+        eprintln!("\nCancelling; press Ctrl-C again to quit now");
+    })?;
+
     // Calculate distances between sorted pairs
-    let mut total_distance = 0;
-    let mut line_count = 0;
+    let pairs: Vec<(i32, i32)> = left_list.into_iter().zip(right_list).collect();
+    let mut stdout = io::stdout();
+    let summary = run_pairs(&mut vm, script_name, &pairs, &mut stdout)?;
+    summary.print(&mut stdout)?;
+
+    if let (Some(stacks_path), Some(call_profile)) = (profile, vm.call_profile()) {
+        print_call_profile(call_profile);
+        if let Some(stacks_path) = stacks_path {
+            fs::write(stacks_path, call_profile.folded_stacks())?;
+            println!("\nWrote folded stacks to {}", stacks_path.display());
+        }
+    }
+
+    if summary.cancelled_at.is_some() {
+        std::process::exit(CANCELLED_EXIT_CODE);
+    }
+    Ok(())
+}
+
+/// How far `rsc aoc` got through its pairs.
+#[derive(Debug, PartialEq)]
+struct PairSummary {
+    pairs: usize,
+    processed: usize,
+    total: i32,
+    /// The pair, counting from 1, that was running when the VM was
+    /// cancelled.
+    cancelled_at: Option<usize>,
+}
+
+impl PairSummary {
+    fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(pair) = self.cancelled_at {
+            writeln!(out, "\nCancelled at pair {} of {}", pair, self.pairs)?;
+        }
+        writeln!(out, "\nProcessed {} pairs", self.processed)?;
+        match self.cancelled_at {
+            Some(_) => writeln!(out, "Partial total distance: {}", self.total)?,
+            None => writeln!(out, "Total distance: {}", self.total)?,
+        }
+        if self.processed > 0 {
+            writeln!(out, "Average distance: {}", self.total / self.processed as i32)?;
+        }
+        Ok(())
+    }
+}
 
-    for (left, right) in left_list.iter().zip(right_list.iter()) {
-        match vm.run_script(script_name, &[*left, *right]) {
+/// Runs `script_name` on each pair, printing each result, until the pairs
+/// run out or the VM is cancelled.
+fn run_pairs(vm: &mut VM, script_name: &str, pairs: &[(i32, i32)], out: &mut dyn Write) -> io::Result<PairSummary> {
+    let mut summary = PairSummary { pairs: pairs.len(), processed: 0, total: 0, cancelled_at: None };
+    for (index, (left, right)) in pairs.iter().enumerate() {
+        match vm.run_script_with_state(script_name, &[HostValue::Int(*left), HostValue::Int(*right)]) {
             Ok(result) => {
-                total_distance += result;
-                line_count += 1;
-                println!("Pair {}: {} {} -> {}", line_count, left, right, result);
+                summary.total += result;
+                summary.processed += 1;
+                writeln!(out, "Pair {}: {} {} -> {}", summary.processed, left, right, result)?;
             }
-            Err(e) => println!("Error processing pair {}: {}", line_count + 1, e),
+            Err(e) if e.is_cancelled() => {
+                summary.cancelled_at = Some(index + 1);
+                break;
+            }
+            Err(e) => writeln!(out, "Error processing pair {}: {}", summary.processed + 1, e)?,
         }
     }
+    Ok(summary)
+}
 
-    println!("\nProcessed {} pairs", line_count);
-    println!("Total distance: {}", total_distance);
-    if line_count > 0 {
-        println!("Average distance: {}", total_distance / line_count);
+fn profile_hot(script_name: &str, args: &[i32], top: usize, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let symbols = load_symbols(config)?;
+    let mut vm = VM::with_options(&config.vm);
+    vm.set_script_overrides(&config.scripts);
+    vm.load_symbols(&symbols);
+    let mut compiler = Compiler::new();
+    compiler.set_symbols(symbols);
+    compiler.set_strict_conditions(config.strict_conditions);
+    vm.enable_profiling();
+
+    let mut session = CompileSession::new(config, compiler);
+    session.set_verbose(verbose);
+    session.set_max_errors(max_errors);
+    session.subscribe(print_progress);
+    let compiled = match session.run() {
+        Ok(compiled) => compiled,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    // Remember which file each script came from so lines can be reported.
+    let mut script_files = HashMap::new();
+    for script in compiled {
+        script_files.insert(script.bytecode.script_name.clone(), script.path);
+        vm.register_script(script.bytecode);
     }
+    dedupe_scripts(&mut vm, config);
 
+    if !script_files.contains_key(script_name) {
+        println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
+        return Ok(());
+    }
+
+    let result = vm.run_script(script_name, args);
+
+    let mut line_counts: HashMap<(PathBuf, Option<usize>), u64> = HashMap::new();
+    for (name, counts) in vm.profile() {
+        let Some(path) = script_files.get(&name) else {
+            continue;
+        };
+        for (ip, count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            *line_counts.entry((path.clone(), vm.line_at(&name, ip))).or_insert(0) += count;
+        }
+    }
+    let total: u64 = line_counts.values().sum();
+    let mut hot: Vec<_> = line_counts.into_iter().collect();
+    hot.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    println!();
+    match result {
+        Ok(value) => println!("Result: {}", value),
+        Err(e) => println!("Error executing script: {}", e),
+    }
+    println!("\nHottest lines ({} instructions executed):", total);
+    let mut sources: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for ((path, line), count) in hot.into_iter().take(top) {
+        let percent = count as f64 * 100.0 / total.max(1) as f64;
+        let Some(line) = line else {
+            println!("  {:>10} {:>5.1}%  {}:? (generated code)", count, percent, path.display());
+            continue;
+        };
+        let lines = sources.entry(path.clone()).or_insert_with(|| {
+            fs::read_to_string(&path)
+                .map(|source| source.lines().map(str::to_string).collect())
+                .unwrap_or_default()
+        });
+        let text = lines.get(line - 1).map(|text| text.trim()).unwrap_or("");
+        println!("  {:>10} {:>5.1}%  {}:{}  {}", count, percent, path.display(), line, text);
+    }
+    Ok(())
+}
+
+fn list_scripts(config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Searched script directories:");
+    for dir in &config.scripts_dirs {
+        println!(
+            "  {}{}",
+            dir.display(),
+            if dir.is_dir() || packs::is_pack(dir) { "" } else { " (missing)" }
+        );
+    }
+
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("\nError: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    println!("\nScripts:");
+    for path in &scripts {
+        match process_rs2_file(path, max_errors) {
+            Ok(script) => {
+                for node in &script.body {
+                    if let Some(name) = node.script_name() {
+                        let summary = match node {
+                            AstKind::Trigger { doc: Some(doc), .. } => format!(" - {}", doc.lines().next().unwrap_or_default()),
+                            _ => String::new(),
+                        };
+                        match config.script_overrides(&name) {
+                            Some(overrides) => println!(
+                                "  {} ({}) [overrides: {}]{}",
+                                name,
+                                path.display(),
+                                overrides.describe().join(", "),
+                                summary
+                            ),
+                            None => println!("  {} ({}){}", name, path.display(), summary),
+                        }
+                    }
+                }
+            }
+            Err(e) => println!("  <error> ({}): {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+fn print_doc(script_name: &str, config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    for script in parse_scripts(&scripts, config, max_errors)? {
+        for node in &script.body {
+            if node.script_name().is_some_and(|name| name.eq_ignore_ascii_case(script_name)) {
+                let AstKind::Trigger { doc, annotations, .. } = node else {
+                    continue;
+                };
+                match doc {
+                    Some(doc) => println!("{}", doc),
+                    None => println!("'{}' has no doc comment", script_name),
+                }
+                for (key, value) in annotations {
+                    println!("{}", format!("@{} {}", key, value).trim_end());
+                }
+                return Ok(());
+            }
+        }
+    }
+    println!("Error: Script '{}' not found in {}", script_name, config.scripts_dirs_display());
     Ok(())
 }
 
+/// Runs the lints over every script and prints what they find, failing if
+/// a denied lint fires.
+fn check_scripts(config: &Config, verbose: bool, max_errors: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let mut lints = Vec::new();
+    for (path, script) in scripts.iter().zip(parse_scripts(&scripts, config, max_errors)?) {
+        match lints::check(path, &script.body, &config.lints) {
+            Ok(found) => lints.extend(found),
+            Err(message) => {
+                println!("Error: {}", message);
+                std::process::exit(2);
+            }
+        }
+    }
+    for lint in &lints {
+        print!("{}", lint);
+    }
+    let denied = lints.iter().filter(|lint| lint.level == Level::Deny).count();
+    println!("{} warning(s), {} error(s) in {} file(s)", lints.len() - denied, denied, scripts.len());
+    if denied > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_include_graph(config: &Config, verbose: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let scripts = match get_rs2_files(config, verbose) {
+        Ok(scripts) => scripts,
+        Err(CompilerError::FileNotFound(msg)) => {
+            println!("Error: {}", msg);
+            return Ok(());
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let mut edges = 0;
+    for path in &scripts {
+        for include in includes::includes_of(path)? {
+            println!("{} -> {}", path.display(), include.display());
+            edges += 1;
+        }
+    }
+    if edges == 0 {
+        println!("No script includes another");
+    }
+    Ok(())
+}
+
+fn print_config_layers(config: &Config) {
+    println!("\nConfig files:");
+    println!("  {} (user)", Config::get_user_config_path().display());
+    for layer in &config.layers {
+        println!("  {}", layer.path.display());
+        for key in &layer.keys {
+            println!("    {}", key);
+        }
+    }
+}
+
+fn run_doctor(config: &Config) {
+    println!("Environment: {}", config.env_name);
+    if config.env_chain.len() > 1 {
+        println!("Inherits: {}", config.env_chain[1..].join(" -> "));
+    }
+    println!("Install directory: {}", config.install_dir.display());
+    println!("\nScripts directories:");
+    for dir in &config.scripts_dirs {
+        println!(
+            "  {}{}",
+            dir.display(),
+            if dir.is_dir() || packs::is_pack(dir) { "" } else { " (missing)" }
+        );
+    }
+
+    println!("\nIgnore patterns:");
+    for pattern in &config.ignore {
+        println!("  {}", pattern);
+    }
+
+    println!("\nConstants files:");
+    for path in &config.constants {
+        println!(
+            "  {}{}",
+            path.display(),
+            if path.is_file() { "" } else { " (missing)" }
+        );
+    }
+
+    println!("\nConfig directories:");
+    for dir in &config.configs {
+        println!(
+            "  {}{}",
+            dir.display(),
+            if dir.is_dir() { "" } else { " (missing)" }
+        );
+    }
+
+    println!("\nStrict conditions: {}", config.strict_conditions);
+    println!("Bytecode dump limit: {}", config.dump_limit);
+
+    println!("\nDefines:");
+    let mut defines: Vec<_> = config.defines.iter().collect();
+    defines.sort();
+    for (name, value) in defines {
+        println!("  {}={}", name, value);
+    }
+
+    println!("\nVM options:");
+    println!("  max_instructions={}", config.vm.max_instructions);
+    println!("  memoize={}", config.vm.memoize);
+    println!("  max_call_depth={}", config.vm.max_call_depth);
+    println!("  lenient_args={}", config.vm.lenient_args);
+    println!("  dedupe_scripts={}", config.vm.dedupe_scripts);
+
+    print_config_layers(config);
+}
+
+/// Exit code used when compilation or execution hits an internal panic.
+const INTERNAL_ERROR_EXIT_CODE: i32 = 101;
+
+/// Exit code of a run stopped with Ctrl-C, the one shells report for SIGINT.
+const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Where the most recent panic happened, recorded by the hook installed in
+/// `run_guarded` so the friendly report can still point at the faulty code.
+static PANIC_LOCATION: Mutex<Option<String>> = Mutex::new(None);
+
+/// Runs a compile/execute step, converting any panic into an internal error
+/// report that names the script instead of printing a raw backtrace. Set
+/// `RUST_BACKTRACE` to get the default panic output as well.
+fn run_guarded<F>(script_name: &str, f: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnOnce() -> Result<(), Box<dyn std::error::Error>>,
+{
+    let show_backtrace = std::env::var_os("RUST_BACKTRACE").is_some();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Some(location) = info.location() {
+            if let Ok(mut slot) = PANIC_LOCATION.lock() {
+                *slot = Some(format!("{}:{}", location.file(), location.line()));
+            }
+        }
+        if show_backtrace {
+            default_hook(info);
+        }
+    }));
+
+    let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+    let _ = panic::take_hook();
+
+    match outcome {
+        Ok(Err(e)) => {
+            // Print lexing and collected syntax errors the way they format
+            // themselves, including the "... and more" notice, rather than as
+            // a debug dump.
+            match e.downcast_ref::<CompilerError>() {
+                Some(err @ CompilerError::SyntaxErrors(_)) => {
+                    eprint!("{}", err);
+                    std::process::exit(1);
+                }
+                Some(CompilerError::LexingError(err)) => {
+                    eprint!("{}", err);
+                    std::process::exit(1);
+                }
+                _ => {}
+            }
+            Err(e)
+        }
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            let location = PANIC_LOCATION.lock().ok().and_then(|slot| slot.clone());
+
+            eprintln!("\nInternal compiler error while processing script '{}': {}", script_name, message);
+            if let Some(location) = location {
+                eprintln!("  --> {}", location);
+            }
+            eprintln!("\nThis is a bug in the RuneScript Compiler, not in your script.");
+            eprintln!("Please report it at https://github.com/tyler27/runescript-compiler/issues");
+            eprintln!("and include the script that triggered it.");
+            std::process::exit(INTERNAL_ERROR_EXIT_CODE);
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
-    let config = Config::load();
+    // Nothing is written until the arguments parse, so `rsc --help` in a
+    // fresh HOME leaves it untouched.
+    let mut config = Config::load_unsaved();
+    let subcommands: Vec<String> = Cli::command()
+        .get_subcommands()
+        .flat_map(|command| std::iter::once(command.get_name()).chain(command.get_all_aliases()))
+        .chain(std::iter::once("help"))
+        .map(str::to_string)
+        .collect();
+    let args: Vec<String> = std::env::args().collect();
+    let args = match Config::expand_aliases(&args, &config.all_aliases(), &subcommands) {
+        Ok(args) => args,
+        Err(e) => {
+            println!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let cli = Cli::parse_from(args);
+    Config::save_default_if_missing();
+    if let Commands::Run { seed, spawns, .. } = &cli.command {
+        config.vm.seed = *seed;
+        config.vm.spawns = spawns.clone();
+    }
+    config.file = cli.file.clone();
+    config.allow_duplicates = cli.allow_duplicates;
 
     match cli.command {
-        Commands::Run { script_name, args } => {
-            run_script(&script_name, &args, &config)?;
+        Commands::Run { bundle: Some(bundle), script_name, args, typed_args, result_format, bool_as_int, .. } => {
+            // The bundle names the script, so a first positional is an argument.
+            let args = match script_name.map(|first| first.parse::<i32>()).transpose() {
+                Ok(first) => first.into_iter().chain(args).collect(),
+                Err(e) => {
+                    println!("Error: arguments to a bundle are ints: {}", e);
+                    std::process::exit(2);
+                }
+            };
+            let args = script_args(args, typed_args);
+            let result_style = ResultStyle { format: result_format, bool_as_int };
+            let name = bundle.display().to_string();
+            run_guarded(&name, || run_bundle(&bundle, &args, result_style, &config))?;
+        }
+        Commands::Run { script_name: None, .. } => unreachable!("clap requires a script name without --bundle"),
+        Commands::Run { script_name: Some(script_name), asm: Some(asm), args, typed_args, result_format, bool_as_int, .. } => {
+            let args = script_args(args, typed_args);
+            let result_style = ResultStyle { format: result_format, bool_as_int };
+            run_guarded(&script_name, || run_asm(&asm, &script_name, &args, result_style, &config))?;
+        }
+        Commands::Run { script_name: Some(script_name), args, typed_args, result_format, bool_as_int, compare_engines: true, .. } => {
+            let args = script_args(args, typed_args);
+            let result_style = ResultStyle { format: result_format, bool_as_int };
+            run_guarded(&script_name, || compare_engines(&script_name, &args, result_style, &config, cli.verbose, cli.max_errors))?;
         }
-        Commands::Aoc { script_name, data_file } => {
-            run_aoc(&script_name, &data_file, &config)?;
+        Commands::Run { script_name: Some(script_name), args, typed_args, result_format, bool_as_int, backend: Backend::Vm, break_at, dump_locals_on_error, .. } => {
+            let args = script_args(args, typed_args);
+            let result_style = ResultStyle { format: result_format, bool_as_int };
+            let debug = DebugOptions { break_at, dump_locals_on_error };
+            run_guarded(&script_name, || run_script(&script_name, &args, result_style, &debug, &config, cli.verbose, cli.max_errors))?;
         }
-        Commands::Analyze2004 => {
+        Commands::Run { break_at, .. } if !break_at.is_empty() => {
+            println!("Error: --break-at needs the vm backend");
+            std::process::exit(1);
+        }
+        Commands::Run { dump_locals_on_error: true, .. } => {
+            println!("Error: --dump-locals-on-error needs the vm backend");
+            std::process::exit(1);
+        }
+        Commands::Run { script_name: Some(script_name), args, typed_args, result_format, bool_as_int, backend: Backend::Interp, .. } => {
+            let args = script_args(args, typed_args);
+            let result_style = ResultStyle { format: result_format, bool_as_int };
+            run_guarded(&script_name, || run_interpreted(&script_name, &args, result_style, &config, cli.verbose, cli.max_errors))?;
+        }
+        Commands::Aoc { script_name, data_file, profile, profile_out } => {
+            let profile = profile.then_some(profile_out.as_deref());
+            run_guarded(&script_name, || run_aoc(&script_name, &data_file, profile, &config, cli.verbose, cli.max_errors))?;
+        }
+        Commands::Analyze2004 { baseline, report, clean } => {
             println!("Analyzing 2004Scape codebase...");
             let mut analyzer = analysis::ScriptAnalysis::new();
+            analyzer.cleanup = clean;
             match analyzer.analyze_repository() {
                 Ok(_) => analyzer.print_analysis(),
-                Err(e) => println!("Error analyzing 2004Scape codebase: {}", e),
+                Err(e) => {
+                    println!("Error analyzing 2004Scape codebase: {}", e);
+                    return Ok(());
+                }
+            }
+            if let Some(baseline) = baseline {
+                match analysis::ScriptAnalysis::read_report(&baseline) {
+                    Ok(previous) => analyzer.diff(&previous).print(),
+                    Err(e) => println!("Error loading baseline: {}", e),
+                }
             }
+            if let Some(report) = report {
+                match analyzer.write_report(&report) {
+                    Ok(()) => println!("\nReport written to {}", report.display()),
+                    Err(e) => println!("Error writing report: {}", e),
+                }
+            }
+        }
+        Commands::ProfileHot { script_name, args, top } => {
+            run_guarded(&script_name, || profile_hot(&script_name, &args, top, &config, cli.verbose, cli.max_errors))?;
+        }
+        // The differential run is the only test run, so --coverage alone runs it.
+        Commands::Test { differential, script_name, values, coverage, lcov } if differential || coverage => {
+            let name = script_name.clone().unwrap_or_else(|| "differential".to_string());
+            let coverage = coverage.then_some(lcov.as_deref());
+            run_guarded(&name, || run_differential(script_name.as_deref(), &values, coverage, &config, cli.verbose, cli.max_errors))?;
+        }
+        Commands::Test { .. } => {
+            println!("Error: choose a test mode, e.g. rsc test --differential or rsc test --coverage");
+            std::process::exit(2);
+        }
+        Commands::Disasm { script_name, dump_ir } => {
+            disasm(&script_name, dump_ir, &config, cli.verbose, cli.max_errors)?;
+        }
+        Commands::Compile { emit, output } => {
+            compile_all(emit, output.as_deref(), &config, cli.verbose, cli.max_errors)?;
+        }
+        Commands::Bundle { script_name, out } => {
+            let out = out.unwrap_or_else(|| PathBuf::from(format!("{}.rsx", script_name)));
+            write_bundle(&script_name, &out, &config, cli.verbose, cli.max_errors)?;
         }
-        Commands::Update => {
+        Commands::Check => {
+            check_scripts(&config, cli.verbose, cli.max_errors)?;
+        }
+        Commands::List => {
+            list_scripts(&config, cli.verbose, cli.max_errors)?;
+        }
+        Commands::Doc { script_name } => {
+            print_doc(&script_name, &config, cli.verbose, cli.max_errors)?;
+        }
+        Commands::Graph => {
+            print_include_graph(&config, cli.verbose)?;
+        }
+        Commands::Doctor => {
+            run_doctor(&config);
+        }
+        Commands::Update { version, check } => {
             // Get the current directory
             let current_dir = std::env::current_dir()?;
             let install_script = if cfg!(windows) {
@@ -330,59 +1685,52 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 return Ok(());
             }
 
-            println!("Updating RuneScript Compiler ({} environment)...", config.env_name);
-            
-            // Check if git is initialized and has a remote
-            let has_git = std::process::Command::new("git")
-                .args(["rev-parse", "--git-dir"])
-                .output()
-                .map(|output| output.status.success())
-                .unwrap_or(false);
-
-            let has_remote = if has_git {
-                std::process::Command::new("git")
-                    .args(["remote", "get-url", "origin"])
-                    .output()
-                    .map(|output| output.status.success())
-                    .unwrap_or(false)
-            } else {
-                false
-            };
-
-            // Only try to pull if we have a git repo with a remote
-            if has_git && has_remote {
-                println!("Pulling latest changes from git...");
-                if let Ok(status) = std::process::Command::new("git")
-                    .args(["pull", "origin", "main"])
-                    .status()
-                {
-                    if !status.success() {
-                        println!("Warning: Failed to pull latest changes. Continuing with local version...");
+            let mut updater = Updater::new(SystemRunner, Config::get_installed_path());
+            if check {
+                match updater.check() {
+                    Ok(check) => {
+                        println!("Installed: {}", check.installed);
+                        println!("Latest:    {}", check.latest);
+                        if !check.fetched {
+                            println!("No origin remote is configured, so only tags already fetched were checked.");
+                        }
+                        if check.update_available() {
+                            println!("Run rsc update to install {}", check.latest);
+                        } else {
+                            println!("Up to date");
+                        }
+                    }
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        std::process::exit(1);
                     }
                 }
-            } else {
-                println!("No git repository found or no remote configured. Using local version...");
+                return Ok(());
             }
 
-            // Run the installation script with environment variables
-            println!("Rebuilding and reinstalling...");
-            if cfg!(windows) {
-                std::process::Command::new("powershell")
-                    .args(["-ExecutionPolicy", "Bypass", "-File", install_script])
-                    .env("RSC_ENV", &config.env_name)
-                    .env("RSC_INSTALL_DIR", config.install_dir.to_str().unwrap())
-                    .env("RSC_SCRIPTS_DIR", config.scripts_dir.to_str().unwrap())
-                    .status()?;
+            println!("Updating RuneScript Compiler ({} environment)...", config.env_name);
+            let envs = vec![
+                ("RSC_ENV", config.env_name.clone()),
+                ("RSC_INSTALL_DIR", config.install_dir.to_string_lossy().into_owned()),
+                ("RSC_SCRIPTS_PATH", config.scripts_search_path()),
+            ];
+            let install = if cfg!(windows) {
+                Install {
+                    program: "powershell".to_string(),
+                    args: vec!["-ExecutionPolicy".to_string(), "Bypass".to_string(), "-File".to_string(), install_script.to_string()],
+                    envs,
+                }
             } else {
-                std::process::Command::new("sh")
-                    .arg(install_script)
-                    .env("RSC_ENV", &config.env_name)
-                    .env("RSC_INSTALL_DIR", config.install_dir.to_str().unwrap())
-                    .env("RSC_SCRIPTS_DIR", config.scripts_dir.to_str().unwrap())
-                    .status()?;
+                Install { program: "sh".to_string(), args: vec![install_script.to_string()], envs }
+            };
+            match updater.update(version, &install) {
+                Ok(Updated::Installed(installed)) => println!("Update complete! Installed {}", installed.version),
+                Ok(Updated::UpToDate(version)) => println!("Already up to date at {}", version),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    std::process::exit(1);
+                }
             }
-            
-            println!("Update complete!");
         }
         Commands::Config { command } => {
             match command {
@@ -419,7 +1767,57 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         println!("Use 'rsc config edit' to modify it.");
                     }
                 }
-                ConfigCommands::List => {
+                ConfigCommands::Migrate { force } => {
+                    match Config::migrate_to_toml(force) {
+                        Ok(path) => {
+                            println!("Wrote {}", path.display());
+                            println!("config.json and rscrc were left in place; config.toml now takes precedence.");
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ConfigCommands::List { env: Some(env_name) } => {
+                    match Config::load_env(&env_name) {
+                        Ok(env_config) => {
+                            println!("Environment: {}", env_name);
+                            if env_config.env_chain.len() > 1 {
+                                println!("Inherits: {}", env_config.env_chain[1..].join(" -> "));
+                            }
+                            println!("\nSettings:");
+                            for key in env_config.keys() {
+                                println!("  {} = {}", key.key, key.value);
+                            }
+                        }
+                        Err(e) => println!("Error: {}", e),
+                    }
+                }
+                ConfigCommands::Validate => {
+                    let problems = config.validate();
+                    if problems.is_empty() {
+                        println!("Configuration for environment '{}' is valid", config.env_name);
+                    } else {
+                        println!("Found {} problem(s) in environment '{}':", problems.len(), config.env_name);
+                        for problem in &problems {
+                            println!("  - {}", problem.problem);
+                            println!("    fix: {}", problem.fix);
+                        }
+                        std::process::exit(1);
+                    }
+                }
+                ConfigCommands::Envs => {
+                    let current = Config::current_env();
+                    for env_name in Config::list_envs() {
+                        let marker = if env_name == current { " (current)" } else { "" };
+                        match Config::load_env(&env_name) {
+                            Ok(env_config) if env_config.env_chain.len() > 1 => {
+                                println!("  {}{}  inherits {}", env_name, marker, env_config.env_chain[1..].join(" -> "));
+                            }
+                            Ok(_) => println!("  {}{}", env_name, marker),
+                            Err(e) => println!("  {}{}  error: {}", env_name, marker, e),
+                        }
+                    }
+                }
+                ConfigCommands::List { env: None } => {
                     let contents = Config::load_rc_file()?;
                     let (aliases, env_vars) = Config::parse_rc_file(&contents);
                     
@@ -433,6 +1831,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     for alias in aliases {
                         println!("  {}", alias);
                     }
+
+                    print_config_layers(&config);
                 }
             }
         }
@@ -441,3 +1841,71 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runescript_compiler::lexer::Lexer;
+    use runescript_compiler::parser::Parser;
+
+    #[test]
+    fn hex_format_prints_uppercase_digits() {
+        assert_eq!(ResultFormat::Hex.format(255), "0xFF");
+        assert_eq!(ResultFormat::Hex.format(-1), "0xFFFFFFFF");
+    }
+
+    #[test]
+    fn grouped_format_separates_thousands() {
+        assert_eq!(ResultFormat::Grouped.format(1_000_000), "1,000,000");
+        assert_eq!(ResultFormat::Grouped.format(999), "999");
+        assert_eq!(ResultFormat::Grouped.format(-12_345), "-12,345");
+    }
+
+    #[test]
+    fn cancelling_mid_pair_keeps_the_summary_of_the_pairs_before_it() {
+        let source = "[proc,distance](int $a, int $b)(int)\nif ($a = 3) {\n    mes(\"cancel\");\n}\nreturn(calc($b - $a));\n";
+        let path = PathBuf::from("aoc.rs2");
+        let tokens = Lexer::new(source, &path).tokenize().unwrap();
+        let script = &Parser::new(tokens, &path).parse().unwrap().body[0];
+        let mut vm = VM::new();
+        vm.register_script(Compiler::new().compile_script("distance".to_string(), script).unwrap());
+        // Cancelled from inside the third pair's run, as Ctrl-C would.
+        let cancel = vm.cancel_flag();
+        vm.register_command("mes", move |_, _| {
+            cancel.store(true, Ordering::SeqCst);
+            Ok(0)
+        });
+
+        let mut out = Vec::new();
+        let summary = run_pairs(&mut vm, "distance", &[(1, 4), (2, 7), (3, 9), (4, 5)], &mut out).unwrap();
+        assert_eq!(summary, PairSummary { pairs: 4, processed: 2, total: 8, cancelled_at: Some(3) });
+        summary.print(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Pair 1: 1 4 -> 3\nPair 2: 2 7 -> 5\n\nCancelled at pair 3 of 4\n\nProcessed 2 pairs\nPartial total distance: 8\nAverage distance: 4\n"
+        );
+        // Later runs stop too until the flag is cleared.
+        assert!(vm.run_script("distance", &[1, 2]).is_err());
+        vm.cancel_flag().store(false, Ordering::SeqCst);
+        assert_eq!(vm.run_script("distance", &[1, 2]), Ok(1));
+    }
+
+    #[test]
+    fn a_command_failing_with_the_cancelled_message_is_not_a_cancellation() {
+        let source = "[proc,distance](int $a, int $b)(int)\nif ($a = 3) {\n    mes(\"fail\");\n}\nreturn(calc($b - $a));\n";
+        let path = PathBuf::from("aoc.rs2");
+        let tokens = Lexer::new(source, &path).tokenize().unwrap();
+        let script = &Parser::new(tokens, &path).parse().unwrap().body[0];
+        let mut vm = VM::new();
+        vm.register_script(Compiler::new().compile_script("distance".to_string(), script).unwrap());
+        vm.register_command("mes", |_, _| Err(runescript_compiler::vm::CANCELLED.to_string()));
+
+        let mut out = Vec::new();
+        let summary = run_pairs(&mut vm, "distance", &[(1, 4), (3, 9), (4, 5)], &mut out).unwrap();
+        assert_eq!(summary, PairSummary { pairs: 3, processed: 2, total: 4, cancelled_at: None });
+        assert!(String::from_utf8(out).unwrap().contains("Error processing pair 2: mes: Execution cancelled."));
+        let error = vm.run_script_with_state("distance", &[HostValue::Int(3), HostValue::Int(9)]).unwrap_err();
+        assert!(error.message.ends_with(runescript_compiler::vm::CANCELLED));
+        assert!(!error.is_cancelled());
+    }
+}
@@ -0,0 +1,642 @@
+use crate::compiler::{constant_value, is_comparison, is_pure_builtin};
+use crate::config::VmOptions;
+use crate::evaluator::Evaluator;
+use crate::parser::AstKind;
+use crate::types::Type;
+use std::collections::{HashMap, HashSet};
+
+/// Step limit for evaluating one folded call. Anything that runs longer is
+/// left for the VM, so a non-terminating script can't hang the compiler.
+pub const FOLD_MAX_STEPS: usize = 10_000;
+
+/// Hoists loop-invariant expressions out of `while` bodies.
+///
+/// A loop is only considered when it is free of script calls and returns, so
+/// nothing it does can change state the pass can't see. Candidates are taken
+/// from the top-level statements of the body, which run on every iteration,
+/// and must only read locals the loop never writes. Each hoisted expression is
+/// bound to a `__licm<N>` local, and the loop becomes an
+/// `AstKind::InvariantWhile`, which computes the bindings after the condition
+/// first holds so a loop that never runs never evaluates them.
+pub struct LoopInvariantMotion {
+    next_temp: usize,
+    hoisted: usize,
+}
+
+impl Default for LoopInvariantMotion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoopInvariantMotion {
+    pub fn new() -> Self {
+        Self {
+            next_temp: 0,
+            hoisted: 0,
+        }
+    }
+
+    /// Number of expressions hoisted so far.
+    pub fn hoisted(&self) -> usize {
+        self.hoisted
+    }
+
+    pub fn run(&mut self, node: &AstKind) -> AstKind {
+        match node {
+            AstKind::Block(statements) => {
+                AstKind::Block(statements.iter().map(|stmt| self.run(stmt)).collect())
+            }
+            AstKind::If { expression, value, return_statement } => AstKind::If {
+                expression: expression.clone(),
+                value: Box::new(self.run(value)),
+                return_statement: return_statement.clone(),
+            },
+            AstKind::While { condition, body } => {
+                // Inner loops first, so their invariants can't be mistaken for ours.
+                let body = self.run(body);
+                self.hoist(condition, body)
+            }
+            _ => node.clone(),
+        }
+    }
+
+    fn hoist(&mut self, condition: &AstKind, body: AstKind) -> AstKind {
+        let unchanged = |body: AstKind| AstKind::While {
+            condition: Box::new(condition.clone()),
+            body: Box::new(body),
+        };
+
+        if has_side_effects(condition) || has_side_effects(&body) {
+            return unchanged(body);
+        }
+
+        let mut written = HashSet::new();
+        collect_writes(&body, &mut written);
+
+        let AstKind::Block(statements) = body else {
+            return unchanged(body);
+        };
+
+        let mut invariants = Vec::new();
+        let mut seen: Vec<(String, String)> = Vec::new();
+        let statements = statements
+            .into_iter()
+            .map(|stmt| match stmt {
+                AstKind::Assignment { target, value } => AstKind::Assignment {
+                    target,
+                    value: Box::new(self.replace_invariants(*value, &written, &mut invariants, &mut seen)),
+                },
+                AstKind::Define { name, var_type, value } => AstKind::Define {
+                    name,
+                    var_type,
+                    value: Box::new(self.replace_invariants(*value, &written, &mut invariants, &mut seen)),
+                },
+                stmt => stmt,
+            })
+            .collect();
+
+        if invariants.is_empty() {
+            return unchanged(AstKind::Block(statements));
+        }
+
+        AstKind::InvariantWhile {
+            invariants,
+            condition: Box::new(condition.clone()),
+            body: Box::new(AstKind::Block(statements)),
+        }
+    }
+
+    /// Replaces the largest invariant subexpressions of `node` with temporaries.
+    fn replace_invariants(
+        &mut self,
+        node: AstKind,
+        written: &HashSet<String>,
+        invariants: &mut Vec<AstKind>,
+        seen: &mut Vec<(String, String)>,
+    ) -> AstKind {
+        if is_worth_hoisting(&node) && is_invariant(&node, written) {
+            // Identical expressions share one temporary.
+            let key = format!("{:?}", node);
+            if let Some((_, temp)) = seen.iter().find(|(existing, _)| *existing == key) {
+                return AstKind::LocalVar(temp.clone());
+            }
+
+            let temp = format!("__licm{}", self.next_temp);
+            self.next_temp += 1;
+            self.hoisted += 1;
+            invariants.push(AstKind::Define {
+                name: temp.clone(),
+                var_type: Type::Int,
+                value: Box::new(node),
+            });
+            seen.push((key, temp.clone()));
+            return AstKind::LocalVar(temp);
+        }
+
+        match node {
+            AstKind::BinaryExpression { lhs, rhs, operator } => AstKind::BinaryExpression {
+                lhs: Box::new(self.replace_invariants(*lhs, written, invariants, seen)),
+                rhs: Box::new(self.replace_invariants(*rhs, written, invariants, seen)),
+                operator,
+            },
+            AstKind::FunctionCall { name, arguments } => AstKind::FunctionCall {
+                name,
+                arguments: arguments
+                    .into_iter()
+                    .map(|arg| Box::new(self.replace_invariants(*arg, written, invariants, seen)))
+                    .collect(),
+            },
+            AstKind::Cast { target, expression } => AstKind::Cast {
+                target,
+                expression: Box::new(self.replace_invariants(*expression, written, invariants, seen)),
+            },
+            node => node,
+        }
+    }
+}
+
+/// Removes `if` and `while` statements whose condition is a constant.
+///
+/// `if (false)` and `while (false)` are dropped, and the body of `if (true)`,
+/// with its `return` if it has one, takes the place of the `if`. A condition
+/// is constant when it is a literal, `calc` of literals or a comparison of
+/// them, e.g. `if (1 > 0)` or `while (0)`. One that would fail at run time,
+/// such as `calc(1 / 0)`, is left alone so the error still surfaces.
+#[derive(Debug, Default)]
+pub struct ConstantConditions {
+    removed: usize,
+}
+
+impl ConstantConditions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of `if` and `while` statements removed or inlined so far.
+    pub fn removed(&self) -> usize {
+        self.removed
+    }
+
+    pub fn run(&mut self, node: &AstKind) -> AstKind {
+        match node {
+            AstKind::Block(statements) => {
+                let mut folded = Vec::with_capacity(statements.len());
+                for stmt in statements {
+                    match self.run(stmt) {
+                        // An inlined `if (true)` body joins the enclosing block,
+                        // and a removed statement leaves nothing behind.
+                        AstKind::Block(inlined) if matches!(stmt, AstKind::If { .. } | AstKind::While { .. }) => {
+                            folded.extend(inlined)
+                        }
+                        stmt => folded.push(stmt),
+                    }
+                }
+                AstKind::Block(folded)
+            }
+            AstKind::If { expression, value, return_statement } => match constant_condition(expression) {
+                Some(true) => {
+                    self.removed += 1;
+                    let mut inlined = match self.run(value) {
+                        AstKind::Block(statements) => statements,
+                        value => vec![value],
+                    };
+                    if let AstKind::Return(_) = &**return_statement {
+                        inlined.push((**return_statement).clone());
+                    }
+                    AstKind::Block(inlined)
+                }
+                Some(false) => {
+                    self.removed += 1;
+                    AstKind::Block(Vec::new())
+                }
+                None => AstKind::If {
+                    expression: expression.clone(),
+                    value: Box::new(self.run(value)),
+                    return_statement: return_statement.clone(),
+                },
+            },
+            AstKind::While { condition, .. } if constant_condition(condition) == Some(false) => {
+                self.removed += 1;
+                AstKind::Block(Vec::new())
+            }
+            AstKind::While { condition, body } => AstKind::While {
+                condition: condition.clone(),
+                body: Box::new(self.run(body)),
+            },
+            _ => node.clone(),
+        }
+    }
+}
+
+/// Whether `condition` always holds or never does, when that is known at
+/// compile time.
+fn constant_condition(condition: &AstKind) -> Option<bool> {
+    match condition {
+        AstKind::BinaryExpression { lhs, rhs, operator } if is_comparison(operator) => {
+            let (lhs, rhs) = (constant_value(lhs)?, constant_value(rhs)?);
+            Some(match operator.as_str() {
+                "=" => lhs == rhs,
+                "<" => lhs < rhs,
+                ">" => lhs > rhs,
+                "<=" => lhs <= rhs,
+                _ => lhs >= rhs,
+            })
+        }
+        _ => constant_value(condition).map(|value| value != 0),
+    }
+}
+
+/// Literals and plain variable reads cost as much as reading a temporary.
+fn is_worth_hoisting(node: &AstKind) -> bool {
+    matches!(node, AstKind::BinaryExpression { .. } | AstKind::FunctionCall { .. })
+}
+
+fn is_invariant(node: &AstKind, written: &HashSet<String>) -> bool {
+    match node {
+        AstKind::NumericLiteral(_) | AstKind::Null => true,
+        AstKind::LocalVar(name) => !written.contains(name.trim_start_matches('$')),
+        AstKind::BinaryExpression { lhs, rhs, .. } => is_invariant(lhs, written) && is_invariant(rhs, written),
+        AstKind::FunctionCall { name, arguments } => {
+            is_pure_builtin(name) && arguments.iter().all(|arg| is_invariant(arg, written))
+        }
+        AstKind::Cast { expression, .. } => is_invariant(expression, written),
+        _ => false,
+    }
+}
+
+/// Script calls may touch globals and returns leave the loop early; either
+/// makes moving code across iterations unsafe.
+fn has_side_effects(node: &AstKind) -> bool {
+    match node {
+        AstKind::ScriptCall { .. } | AstKind::Return(_) => true,
+        AstKind::FunctionCall { name, arguments } => {
+            !is_pure_builtin(name) || arguments.iter().any(|arg| has_side_effects(arg))
+        }
+        AstKind::BinaryExpression { lhs, rhs, .. } => has_side_effects(lhs) || has_side_effects(rhs),
+        AstKind::ConditionalExpression { lhs, rhs, value } => {
+            has_side_effects(lhs) || has_side_effects(value) || has_side_effects(rhs)
+        }
+        AstKind::Assignment { value, .. } | AstKind::Define { value, .. } => has_side_effects(value),
+        AstKind::Cast { expression, .. } => has_side_effects(expression),
+        AstKind::Block(statements) => statements.iter().any(has_side_effects),
+        AstKind::If { expression, value, return_statement } => {
+            has_side_effects(expression) || has_side_effects(value) || has_side_effects(return_statement)
+        }
+        AstKind::While { condition, body } => has_side_effects(condition) || has_side_effects(body),
+        AstKind::InvariantWhile { invariants, condition, body } => {
+            invariants.iter().any(has_side_effects) || has_side_effects(condition) || has_side_effects(body)
+        }
+        _ => false,
+    }
+}
+
+fn collect_writes(node: &AstKind, written: &mut HashSet<String>) {
+    match node {
+        AstKind::Assignment { target, .. } => {
+            if let AstKind::LocalVar(name) = &**target {
+                written.insert(name.trim_start_matches('$').to_string());
+            }
+        }
+        AstKind::Define { name, .. } => {
+            written.insert(name.trim_start_matches('$').to_string());
+        }
+        AstKind::Block(statements) => {
+            for stmt in statements {
+                collect_writes(stmt, written);
+            }
+        }
+        AstKind::If { value, return_statement, .. } => {
+            collect_writes(value, written);
+            collect_writes(return_statement, written);
+        }
+        AstKind::While { body, .. } => collect_writes(body, written),
+        AstKind::InvariantWhile { invariants, body, .. } => {
+            for invariant in invariants {
+                collect_writes(invariant, written);
+            }
+            collect_writes(body, written);
+        }
+        _ => {}
+    }
+}
+
+/// Decides which scripts are pure: their result depends on nothing but their
+/// arguments, so a call can be folded or its result cached. A script is pure
+/// when it calls no functions besides `calc`/`abs`, reads and writes no varps,
+/// and only calls scripts that are pure too. Verdicts are cached; call `clear` when the scripts change.
+#[derive(Debug, Default)]
+pub struct Purity {
+    verdicts: HashMap<String, bool>,
+}
+
+impl Purity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.verdicts.clear();
+    }
+
+    pub fn is_pure(&mut self, name: &str, scripts: &HashMap<String, AstKind>) -> bool {
+        if let Some(&pure) = self.verdicts.get(name) {
+            return pure;
+        }
+        let Some(ast) = scripts.get(name) else {
+            return false;
+        };
+        // Recursive calls are assumed pure while the script is being checked.
+        self.verdicts.insert(name.to_string(), true);
+        let pure = self.is_pure_node(ast, scripts);
+        self.verdicts.insert(name.to_string(), pure);
+        pure
+    }
+
+    fn is_pure_node(&mut self, node: &AstKind, scripts: &HashMap<String, AstKind>) -> bool {
+        let mut pure = |node: &AstKind| self.is_pure_node(node, scripts);
+        match node {
+            AstKind::FunctionCall { name, arguments } => {
+                is_pure_builtin(name) && arguments.iter().all(|arg| pure(arg))
+            }
+            AstKind::ScriptCall { script, arguments } => {
+                let AstKind::Identifier(name) = &**script else {
+                    return false;
+                };
+                arguments.iter().all(|arg| pure(arg)) && self.is_pure(name, scripts)
+            }
+            AstKind::Trigger { body, .. } => pure(body),
+            AstKind::BinaryExpression { lhs, rhs, .. } => pure(lhs) && pure(rhs),
+            AstKind::ConditionalExpression { lhs, rhs, value } => pure(lhs) && pure(value) && pure(rhs),
+            AstKind::Define { value, .. } => pure(value),
+            AstKind::Assignment { target, value } => pure(target) && pure(value),
+            AstKind::Varp { .. } | AstKind::GameVar(_) => false,
+            AstKind::Return(expr) => pure(expr),
+            AstKind::Cast { expression, .. } => pure(expression),
+            AstKind::Block(statements) => statements.iter().all(pure),
+            AstKind::If { expression, value, return_statement } => {
+                pure(expression) && pure(value) && pure(return_statement)
+            }
+            AstKind::While { condition, body } => pure(condition) && pure(body),
+            AstKind::For { init, condition, step, body } => {
+                pure(init) && pure(condition) && pure(step) && pure(body)
+            }
+            AstKind::InvariantWhile { invariants, condition, body } => {
+                invariants.iter().all(&mut pure) && pure(condition) && pure(body)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Replaces calls to pure scripts whose arguments are all constants with the
+/// value they return, computed by the `Evaluator` at compile time.
+///
+/// Only scripts that are `Purity::is_pure` and don't return a string are
+/// folded, so the call always becomes an int literal. A call that fails or
+/// runs past `FOLD_MAX_STEPS` is left as it is, so the error still surfaces
+/// when the script runs.
+pub struct ConstantCallFolding {
+    scripts: HashMap<String, AstKind>,
+    purity: Purity,
+    evaluator: Evaluator,
+    folded: usize,
+}
+
+impl Default for ConstantCallFolding {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConstantCallFolding {
+    pub fn new() -> Self {
+        let options = VmOptions {
+            max_instructions: FOLD_MAX_STEPS,
+            memoize: false,
+            ..VmOptions::default()
+        };
+        Self {
+            scripts: HashMap::new(),
+            purity: Purity::new(),
+            evaluator: Evaluator::with_options(&options),
+            folded: 0,
+        }
+    }
+    /// Makes a script's AST available as a call target.
+    pub fn add_script(&mut self, name: String, ast: AstKind) {
+        // A new definition can change whether its callers are pure.
+        self.purity.clear();
+        self.evaluator.register_script(name.clone(), ast.clone());
+        self.scripts.insert(name, ast);
+    }
+
+    /// Number of calls folded so far.
+    pub fn folded(&self) -> usize {
+        self.folded
+    }
+
+    pub fn run(&mut self, node: &AstKind) -> AstKind {
+        let boxed = |this: &mut Self, node: &AstKind| Box::new(this.run(node));
+        match node {
+            AstKind::ScriptCall { script, arguments } => {
+                let arguments: Vec<Box<AstKind>> = arguments.iter().map(|arg| boxed(self, arg)).collect();
+                if let Some(value) = self.fold(script, &arguments) {
+                    self.folded += 1;
+                    return AstKind::NumericLiteral(value);
+                }
+                AstKind::ScriptCall { script: script.clone(), arguments }
+            }
+            AstKind::BinaryExpression { lhs, rhs, operator } => AstKind::BinaryExpression {
+                lhs: boxed(self, lhs),
+                rhs: boxed(self, rhs),
+                operator: operator.clone(),
+            },
+            AstKind::ConditionalExpression { lhs, rhs, value } => AstKind::ConditionalExpression {
+                lhs: boxed(self, lhs),
+                rhs: boxed(self, rhs),
+                value: boxed(self, value),
+            },
+            AstKind::FunctionCall { name, arguments } => AstKind::FunctionCall {
+                name: name.clone(),
+                arguments: arguments.iter().map(|arg| boxed(self, arg)).collect(),
+            },
+            AstKind::Cast { target, expression } => AstKind::Cast {
+                target: target.clone(),
+                expression: boxed(self, expression),
+            },
+            AstKind::Define { name, var_type, value } => AstKind::Define {
+                name: name.clone(),
+                var_type: var_type.clone(),
+                value: boxed(self, value),
+            },
+            AstKind::Assignment { target, value } => AstKind::Assignment {
+                target: target.clone(),
+                value: boxed(self, value),
+            },
+            AstKind::Return(expr) => AstKind::Return(boxed(self, expr)),
+            AstKind::Block(statements) => {
+                AstKind::Block(statements.iter().map(|stmt| self.run(stmt)).collect())
+            }
+            AstKind::If { expression, value, return_statement } => AstKind::If {
+                expression: boxed(self, expression),
+                value: boxed(self, value),
+                return_statement: boxed(self, return_statement),
+            },
+            AstKind::While { condition, body } => AstKind::While {
+                condition: boxed(self, condition),
+                body: boxed(self, body),
+            },
+            AstKind::InvariantWhile { invariants, condition, body } => AstKind::InvariantWhile {
+                invariants: invariants.iter().map(|invariant| self.run(invariant)).collect(),
+                condition: boxed(self, condition),
+                body: boxed(self, body),
+            },
+            _ => node.clone(),
+        }
+    }
+
+    fn fold(&mut self, script: &AstKind, arguments: &[Box<AstKind>]) -> Option<i32> {
+        let AstKind::Identifier(name) = script else {
+            return None;
+        };
+        let args = arguments
+            .iter()
+            .map(|arg| match **arg {
+                AstKind::NumericLiteral(value) => Some(value),
+                _ => None,
+            })
+            .collect::<Option<Vec<i32>>>()?;
+        let returns_string = matches!(
+            self.scripts.get(name),
+            Some(AstKind::Trigger { return_type, .. })
+                if matches!(&**return_type, AstKind::Identifier(name) if name == "string")
+        );
+        if returns_string || !self.purity.is_pure(name, &self.scripts) {
+            return None;
+        }
+        self.evaluator.eval_script(name, &args).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Instruction;
+    use crate::test_support;
+
+    const SCALED: &str = "[proc,scaled](int $a, int $b, int $n)(int)\ndef_int $total = 0;\ndef_int $i = 0;\nwhile ($i < $n) {\n    $total = calc($total + calc($a * $b));\n    $i = calc($i + 1);\n}\nreturn($total);\n";
+
+    #[test]
+    fn invariant_product_is_computed_once_per_run() {
+        let body = match &test_support::parse(SCALED).body[0] {
+            AstKind::Trigger { body, .. } => body.clone(),
+            node => panic!("expected a trigger, got {:?}", node),
+        };
+        let mut licm = LoopInvariantMotion::new();
+        licm.run(&body);
+        assert_eq!(licm.hoisted(), 1);
+
+        let mut vm = test_support::vm(SCALED);
+        vm.enable_profiling();
+        assert_eq!(vm.run_script("scaled", &[3, 4, 10]), Ok(120));
+        let bytecode = vm.script("scaled").unwrap();
+        let counts = &vm.profile()["scaled"];
+        let multiplies: Vec<u64> = bytecode
+            .instructions
+            .iter()
+            .zip(counts)
+            .filter(|(instruction, _)| matches!(instruction, Instruction::Multiply))
+            .map(|(_, count)| *count)
+            .collect();
+        assert_eq!(multiplies, [1]);
+    }
+
+    /// Folds the calls in `caller`'s body with every trigger in `source`
+    /// available as a target.
+    fn fold_calls(source: &str, caller: &str) -> (AstKind, usize) {
+        let script = test_support::parse(source);
+        let mut folding = ConstantCallFolding::new();
+        for node in &script.body {
+            folding.add_script(node.script_name().unwrap(), node.clone());
+        }
+        let caller = script.body.iter().find(|node| node.script_name().as_deref() == Some(caller)).unwrap();
+        let AstKind::Trigger { body, .. } = caller else { unreachable!() };
+        (folding.run(body).without_source_lines(), folding.folded())
+    }
+
+    #[test]
+    fn constant_call_to_a_pure_script_becomes_its_result() {
+        let (body, folded) = fold_calls(
+            "[proc,square](int $n)(int)\nreturn(calc($n * $n));\n[proc,area]()(int)\nreturn(~square(4));\n",
+            "area",
+        );
+        assert_eq!(folded, 1);
+        assert_eq!(body, AstKind::Block(vec![AstKind::Return(Box::new(AstKind::NumericLiteral(16)))]));
+    }
+
+    #[test]
+    fn call_that_never_finishes_is_left_alone() {
+        let (body, folded) = fold_calls(
+            "[proc,spin](int $n)(int)\nwhile ($n > 0) {\n    $n = calc($n + 1);\n}\nreturn($n);\n[proc,caller]()(int)\nreturn(~spin(1));\n",
+            "caller",
+        );
+        assert_eq!(folded, 0);
+        assert!(matches!(&body, AstKind::Block(statements) if matches!(&statements[0], AstKind::Return(value) if matches!(**value, AstKind::ScriptCall { .. }))), "{:?}", body);
+    }
+
+    const DEAD: &str = "[proc,dead](int $n)(int)\n\
+        if (1 > 0) {\n    $n = calc($n + 10);\n}\n\
+        if (calc(2 * 3) = 7) {\n    mes(\"never\");\n}\n\
+        while (0) {\n    mes(\"never either\");\n}\n\
+        while ($n < 40) {\n    $n = calc($n + 1);\n}\n\
+        if (0 = 0) {\n    return($n);\n}\n\
+        return(0);\n";
+
+    /// The conditions left in `bytecode`: each ends in a `BranchNot`.
+    fn branches(bytecode: &crate::bytecode::ByteCode) -> usize {
+        bytecode.instructions.iter().filter(|instruction| matches!(instruction, Instruction::BranchNot(_))).count()
+    }
+
+    #[test]
+    fn constant_conditions_drop_dead_branches_and_keep_behaviour() {
+        let body = match &test_support::parse(DEAD).body[0] {
+            AstKind::Trigger { body, .. } => body.clone(),
+            node => panic!("expected a trigger, got {:?}", node),
+        };
+        let mut conditions = ConstantConditions::new();
+        conditions.run(&body);
+        assert_eq!(conditions.removed(), 4);
+
+        let optimized = &test_support::compile(DEAD)[0];
+        assert!(!optimized.strings.iter().any(|string| string.starts_with("never")), "{:?}", optimized.strings);
+        // Only the real loop's condition is left.
+        assert_eq!(branches(optimized), 1, "{:?}", optimized.instructions);
+
+        let mut unoptimized = crate::compiler::Compiler::new();
+        unoptimized.set_opt_level(0);
+        let kept = &test_support::compile_with(&mut unoptimized, DEAD)[0];
+        assert_eq!(branches(kept), 5, "{:?}", kept.instructions);
+
+        let mut vm = test_support::vm(DEAD);
+        let mut evaluator = test_support::evaluator(DEAD);
+        for (n, expected) in [(0, 40), (35, 45)] {
+            assert_eq!(vm.run_script("dead", &[n]), Ok(expected));
+            assert_eq!(evaluator.eval_script("dead", &[n]), Ok(expected));
+        }
+        let mut unoptimized_vm = crate::vm::VM::new();
+        unoptimized_vm.register_script(kept.clone());
+        assert_eq!(unoptimized_vm.run_script("dead", &[0]), Ok(40));
+    }
+
+    #[test]
+    fn a_condition_that_fails_at_run_time_is_kept() {
+        let body = match &test_support::parse("[proc,div]()(int)\nif (calc(1 / 0) = 0) {\n    return(1);\n}\nreturn(0);\n").body[0] {
+            AstKind::Trigger { body, .. } => body.clone(),
+            node => panic!("expected a trigger, got {:?}", node),
+        };
+        let mut conditions = ConstantConditions::new();
+        assert_eq!(conditions.run(&body), *body);
+        assert_eq!(conditions.removed(), 0);
+    }
+}
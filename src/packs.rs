@@ -0,0 +1,116 @@
+//! Script packs: `.zip` archives of scripts that can stand in for a scripts
+//! directory. Their `.rs2` entries, at any depth, are read straight from the
+//! archive and named as paths under it, e.g. `dist/days.zip/day1/main.rs2`.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// Whether `path` is a script pack rather than a directory.
+pub fn is_pack(path: &Path) -> bool {
+    path.is_file() && path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// The names of the `.rs2` entries in an archive, sorted.
+pub fn rs2_entries<R: Read + Seek>(reader: R) -> io::Result<Vec<String>> {
+    let archive = ZipArchive::new(reader)?;
+    let mut names: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && name.ends_with(".rs2"))
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Reads the entry `name` of an archive as text.
+pub fn read_entry<R: Read + Seek>(reader: R, name: &str) -> io::Result<String> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut source = String::new();
+    archive.by_name(name)?.read_to_string(&mut source)?;
+    Ok(source)
+}
+
+/// The paths of the scripts in the pack at `pack`, in name order.
+pub fn scripts(pack: &Path) -> io::Result<Vec<PathBuf>> {
+    Ok(rs2_entries(File::open(pack)?)?.iter().map(|name| pack.join(name)).collect())
+}
+
+/// Splits a path under a pack into the pack and the entry's name.
+fn split(path: &Path) -> Option<(&Path, String)> {
+    let pack = path.ancestors().skip(1).find(|ancestor| is_pack(ancestor))?;
+    let entry = path.strip_prefix(pack).ok()?;
+    let name: Vec<_> = entry.components().map(|part| part.as_os_str().to_string_lossy()).collect();
+    Some((pack, name.join("/")))
+}
+
+/// Reads a script, from disk or, for a path under a pack, from the archive.
+pub fn read_to_string(path: &Path) -> io::Result<String> {
+    match split(path) {
+        Some((pack, name)) => read_entry(File::open(pack)?, &name),
+        None => fs::read_to_string(path),
+    }
+}
+
+/// Whether a script exists at `path`, on disk or in a pack.
+pub fn is_script(path: &Path) -> bool {
+    match split(path) {
+        Some((pack, name)) => File::open(pack).and_then(rs2_entries).is_ok_and(|names| names.contains(&name)),
+        None => path.is_file(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+    use super::*;
+    use crate::test_support;
+
+    /// An archive held in memory with each (name, contents) as an entry.
+    fn zip(entries: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            match name.strip_suffix('/') {
+                Some(dir) => writer.add_directory(dir, SimpleFileOptions::default()).unwrap(),
+                None => {
+                    writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+                    writer.write_all(contents.as_bytes()).unwrap();
+                }
+            }
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn both_scripts_in_an_in_memory_zip_are_found_and_run() {
+        let pack = zip(&[
+            ("c/two.rs2", "[proc,two](int $n)(int)\nreturn(calc(~one($n) * 2));\n"),
+            ("a/", ""),
+            ("a/b/one.rs2", "[proc,one](int $n)(int)\nreturn(calc($n + 1));\n"),
+            ("notes.md", "[proc,not_a_script]()(int)\n"),
+        ]);
+        let names = rs2_entries(pack.clone()).unwrap();
+        assert_eq!(names, ["a/b/one.rs2", "c/two.rs2"]);
+
+        let source: String = names.iter().map(|name| read_entry(pack.clone(), name).unwrap()).collect();
+        assert_eq!(test_support::run(&source, "one", &[4]), Ok(5));
+        assert_eq!(test_support::run(&source, "two", &[4]), Ok(10));
+        assert!(read_entry(pack, "notes.txt").is_err());
+    }
+
+    #[test]
+    fn a_path_under_a_pack_on_disk_reads_from_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let pack = dir.path().join("days.zip");
+        fs::write(&pack, zip(&[("day1/main.rs2", "[proc,main]()(int)\nreturn(1);\n")]).into_inner()).unwrap();
+
+        assert!(is_pack(&pack));
+        assert_eq!(scripts(&pack).unwrap(), [pack.join("day1/main.rs2")]);
+        assert_eq!(read_to_string(&pack.join("day1").join("main.rs2")).unwrap(), "[proc,main]()(int)\nreturn(1);\n");
+        assert!(is_script(&pack.join("day1/main.rs2")));
+        assert!(!is_script(&pack.join("day1/other.rs2")));
+    }
+}
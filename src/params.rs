@@ -0,0 +1,179 @@
+//! Params: typed values attached to objs, npcs and structs by `param=` lines
+//! in their configs, declared by `.param` configs and read by scripts with
+//! `oc_param`, `nc_param` and `struct_param`.
+
+use std::collections::HashMap;
+use crate::host::HostValue;
+use crate::symbols::SymbolTable;
+use crate::types::Type;
+
+/// Parameter and return types of the param lookups. The param itself is
+/// named rather than passed, and the result takes its declared type; see
+/// `Compiler::infer_type`.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "oc_param" => Some((&[Type::Obj, Type::Param], Type::Int)),
+        "nc_param" => Some((&[Type::Npc, Type::Param], Type::Int)),
+        "struct_param" => Some((&[Type::Struct, Type::Param], Type::Int)),
+        _ => None,
+    }
+}
+
+/// The config kind a lookup reads params off.
+fn owner_kind(command: &str) -> &'static str {
+    match command {
+        "oc_param" => "obj",
+        "nc_param" => "npc",
+        _ => "struct",
+    }
+}
+
+/// One `[name]` section of a `.param` config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamConfig {
+    pub name: String,
+    pub ty: Type,
+    /// Returned for owners without a value; without one, such a lookup fails.
+    pub default: Option<HostValue>,
+}
+
+impl ParamConfig {
+    /// Reads the fields of a `.param` section:
+    ///
+    /// ```text
+    /// [examine]
+    /// type=string
+    /// default=Nothing interesting happens.
+    /// ```
+    pub fn parse(name: &str, fields: &[(String, String)], symbols: &SymbolTable) -> Result<Self, String> {
+        let field = |key: &str| fields.iter().find(|(field, _)| field == key).map(|(_, value)| value.as_str());
+        let type_name = field("type").ok_or_else(|| format!("param '{}' has no type", name))?;
+        let ty = Type::from_name(type_name).ok_or_else(|| format!("param '{}': unknown type '{}'", name, type_name))?;
+        let default = field("default")
+            .map(|text| parse_value(name, &ty, text, symbols))
+            .transpose()?;
+        Ok(ParamConfig { name: name.to_string(), ty, default })
+    }
+}
+
+fn parse_value(param: &str, ty: &Type, text: &str, symbols: &SymbolTable) -> Result<HostValue, String> {
+    symbols
+        .parse_value(ty, text)
+        .ok_or_else(|| format!("param '{}': '{}' is not a valid {}", param, text, ty))
+}
+
+/// The declared params and the values attached to each obj, npc and struct.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    configs: HashMap<String, ParamConfig>,
+    /// Keyed by owner kind and id, then param name.
+    values: HashMap<(&'static str, i32), HashMap<String, HostValue>>,
+}
+
+impl Params {
+    pub fn config(&self, name: &str) -> Option<&ParamConfig> {
+        self.configs.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.configs.keys()
+    }
+
+    pub(crate) fn declare(&mut self, config: ParamConfig) {
+        self.configs.entry(config.name.clone()).or_insert(config);
+    }
+
+    /// Attaches a `param=name,value` line from the config of the `kind`
+    /// entry `owner`, called `owner_name`.
+    pub(crate) fn attach(&mut self, kind: &'static str, owner: i32, owner_name: &str, line: &str, symbols: &SymbolTable) -> Result<(), String> {
+        let (name, text) = line
+            .split_once(',')
+            .ok_or_else(|| format!("{} '{}': expected param=name,value, found 'param={}'", kind, owner_name, line))?;
+        let config = self
+            .configs
+            .get(name.trim())
+            .ok_or_else(|| format!("{} '{}': unknown param '{}'", kind, owner_name, name.trim()))?;
+        let value = parse_value(&config.name, &config.ty, text.trim(), symbols)?;
+        self.values.entry((kind, owner)).or_default().insert(config.name.clone(), value);
+        Ok(())
+    }
+
+    /// Runs the lookup `command` for `param` on the entry `owner`: its value,
+    /// or the param's default if it has none.
+    pub fn get(&self, command: &str, owner: i32, param: &str) -> Result<HostValue, String> {
+        let config = self
+            .configs
+            .get(param)
+            .ok_or_else(|| format!("{}: param '{}' is not loaded", command, param))?;
+        let kind = owner_kind(command);
+        self.values
+            .get(&(kind, owner))
+            .and_then(|values| values.get(param))
+            .or(config.default.as_ref())
+            .cloned()
+            .ok_or_else(|| format!("{}: {} {} has no param '{}' and it has no default", command, kind, owner, param))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::compiler::Compiler;
+    use crate::host::{HostValue, RecordingHost};
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    const PARAMS: &str = "[stock]\ntype=int\n\n[keeper]\ntype=string\n\n[discount]\ntype=int\n\n[rarity]\ntype=int\ndefault=1\n";
+
+    const SCRIPTS: &str = "[proc,stock]()(int)\nreturn(struct_param(general_store, stock));\n\
+        [proc,greet]()(int)\nmes(\"Talk to <struct_param(general_store, keeper)>.\");\nreturn(0);\n\
+        [proc,discount]()(int)\nreturn(struct_param(general_store, discount));\n\
+        [proc,rarity](obj $item)(int)\nreturn(oc_param($item, rarity));\n";
+
+    fn symbols() -> SymbolTable {
+        test_support::symbols(&[
+            ("shop.param", PARAMS),
+            ("items.obj", "[logs]\n\n[dragon_axe]\nparam=rarity,9\n"),
+            ("shops.struct", "[general_store]\nparam=stock,30\nparam=keeper,Bob\n"),
+        ])
+    }
+
+    #[test]
+    fn int_and_string_params_read_off_a_struct_on_both_backends() {
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols(), SCRIPTS);
+        assert_eq!(vm.run_script("stock", &[]), Ok(30));
+        assert_eq!(evaluator.eval_script("stock", &[]), Ok(30));
+
+        let host = Rc::new(RefCell::new(RecordingHost::default()));
+        vm.set_host(host.clone());
+        evaluator.set_host(host.clone());
+        vm.run_script("greet", &[]).unwrap();
+        evaluator.eval_script("greet", &[]).unwrap();
+        let said = ("mes".to_string(), vec![HostValue::Str("Talk to Bob.".to_string())]);
+        assert_eq!(host.borrow().calls, [said.clone(), said]);
+
+        let (logs, dragon_axe) = (0, 1);
+        assert_eq!(vm.run_script("rarity", &[dragon_axe]), Ok(9));
+        assert_eq!(vm.run_script("rarity", &[logs]), Ok(1));
+        assert_eq!(evaluator.eval_script("rarity", &[logs]), Ok(1));
+    }
+
+    #[test]
+    fn a_missing_param_without_a_default_fails_on_both_backends() {
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols(), SCRIPTS);
+        let message = "struct_param: struct 0 has no param 'discount' and it has no default";
+        let error = vm.run_script("discount", &[]).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = evaluator.eval_script("discount", &[]).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+    }
+
+    #[test]
+    fn a_string_param_cannot_be_stored_as_an_int() {
+        let mut compiler = Compiler::new();
+        compiler.set_symbols(symbols());
+        let source = "[proc,bad]()(int)\ndef_int $n = struct_param(general_store, keeper);\nreturn($n);\n";
+        assert!(test_support::try_compile_with(&mut compiler, source).is_err());
+    }
+}
@@ -1,14 +1,38 @@
-use crate::error::SyntaxError;
+use crate::annotations::{self, Annotations};
+use crate::compiler;
+use crate::error::{SyntaxError, SyntaxErrors};
+use crate::host;
+use crate::lexer::Lexer;
 use crate::token::{Kind, Token};
+use crate::triggers;
 use crate::types::Type;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+/// How many syntax errors [`Parser::parse_recovering`] collects before it
+/// gives up on a file.
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Script {
     pub body: Vec<AstKind>,
 }
 
-#[derive(Debug, Clone)]
+impl Script {
+    /// Whether two scripts parse to the same program, ignoring where each
+    /// statement sits in the source. Only doc comments reach the AST, so
+    /// other comments are ignored too; this is the comparison a formatter
+    /// must preserve.
+    pub fn structurally_equal(&self, other: &Script) -> bool {
+        self.body.len() == other.body.len()
+            && self
+                .body
+                .iter()
+                .zip(&other.body)
+                .all(|(a, b)| a.without_source_lines() == b.without_source_lines())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AstKind {
     NumericLiteral(i32),
     StringLiteral(String),
@@ -29,13 +53,25 @@ pub enum AstKind {
         name: Box<AstKind>,
         kind: Box<AstKind>,
         args: Vec<Box<AstKind>>,
+        /// Default value of each parameter, in order; `None` for required ones.
+        defaults: Vec<Option<AstKind>>,
         body: Box<AstKind>,
         return_type: Box<AstKind>,
+        /// The `//` comments right above the header, one line each, without
+        /// the slashes; see `Parser::doc_comment`.
+        doc: Option<String>,
+        /// The `// @key value` lines among those comments, which aren't
+        /// part of `doc`; see `annotations`.
+        annotations: Annotations,
     },
     Integer,
+    /// `null`, the absent value of nullable types; see `Type::is_nullable`.
+    Null,
     LocalVar(String),
     ReturnType,
     Return(Box<AstKind>),
+    /// `lhs ? value : rhs`: `value` when the condition `lhs` holds (is not
+    /// 0), else `rhs`. Only the chosen branch is evaluated.
     ConditionalExpression {
         lhs: Box<AstKind>,
         rhs: Box<AstKind>,
@@ -64,21 +100,62 @@ pub enum AstKind {
         script: Box<AstKind>,
         arguments: Vec<Box<AstKind>>,
     },
+    Cast {
+        target: Type,
+        expression: Box<AstKind>,
+    },
+    /// `%name`, a varp or varbit, as parsed; `SymbolTable::resolve` turns
+    /// it into a `Varp`.
+    GameVar(String),
+    /// A resolved `%name`: varp `id`, or with `bits`, only the inclusive
+    /// span of bits a varbit occupies in it.
+    Varp {
+        name: String,
+        id: i32,
+        bits: Option<(u8, u8)>,
+    },
+    /// Marks the source line (1-based) of the statement that follows it in a block.
+    SourceLine(usize),
+    /// `for (init; condition; step) body`, lowered to a `while` by
+    /// `AstKind::lower_loops` before compilation or evaluation.
+    For {
+        init: Box<AstKind>,
+        condition: Box<AstKind>,
+        step: Box<AstKind>,
+        body: Box<AstKind>,
+    },
+    /// A `while` loop whose invariant `Define`s run once, before the first
+    /// iteration. Produced by the optimizer, never by the parser.
+    InvariantWhile {
+        invariants: Vec<AstKind>,
+        condition: Box<AstKind>,
+        body: Box<AstKind>,
+    },
 }
 
 pub struct Parser {
     tokens: Vec<Token>,
     file_path: PathBuf,
+    max_errors: usize,
+    /// The line of the last token consumed, so a comment after code on the
+    /// same line isn't taken for a doc comment.
+    last_line: Option<usize>,
 }
 
 impl Parser {
-    pub(crate) fn new(vec: Vec<Token>, file_name: &PathBuf) -> Self {
+    pub fn new(vec: Vec<Token>, file_name: &Path) -> Self {
         Self {
             tokens: vec,
-            file_path: file_name.clone(),
+            file_path: file_name.to_path_buf(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            last_line: None,
         }
     }
 
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors.max(1);
+    }
+
     fn at(&self) -> &Token {
         let mut index = 0;
         while index < self.tokens.len() {
@@ -98,13 +175,16 @@ impl Parser {
             let token = self.tokens.remove(0);
             match token.kind {
                 Kind::SingleLineComment | Kind::MultiLineComment => continue,
-                _ => return token,
+                _ => {
+                    self.last_line = Some(token.line);
+                    return token;
+                }
             }
         }
         self.tokens[0].clone()  // Return first token if no non-comment tokens found
     }
 
-    pub(crate) fn parse(&mut self) -> Result<Script, SyntaxError> {
+    pub fn parse(&mut self) -> Result<Script, SyntaxError> {
         let mut program = Script { body: Vec::new() };
 
         while !self.is_eof() {
@@ -115,6 +195,55 @@ impl Parser {
         Ok(program)
     }
 
+    /// Parses the whole file, skipping to the next script declaration after
+    /// each error so one mistake doesn't hide the rest. Collection stops once
+    /// `max_errors` have been found; `truncated` records whether there were more.
+    pub fn parse_recovering(&mut self) -> Result<Script, SyntaxErrors> {
+        let mut program = Script { body: Vec::new() };
+        let mut errors = SyntaxErrors { errors: Vec::new(), truncated: false };
+
+        while !self.is_eof() {
+            match self.parse_script_declaration() {
+                Ok(body) => program.body.push(body),
+                Err(e) => {
+                    if errors.errors.len() == self.max_errors {
+                        errors.truncated = true;
+                        break;
+                    }
+                    errors.errors.push(e);
+                    self.synchronize();
+                }
+            }
+        }
+
+        if errors.errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Skips tokens up to the next `[trigger,` that starts a script declaration.
+    fn synchronize(&mut self) {
+        if !self.is_eof() {
+            self.next_token();
+        }
+
+        while !self.is_eof() {
+            let mut upcoming = self
+                .tokens
+                .iter()
+                .filter(|token| !matches!(token.kind, Kind::SingleLineComment | Kind::MultiLineComment))
+                .map(|token| &token.kind);
+            if let (Some(Kind::LBracket), Some(Kind::Trigger | Kind::Identifier), Some(Kind::Comma)) =
+                (upcoming.next(), upcoming.next(), upcoming.next())
+            {
+                return;
+            }
+            self.next_token();
+        }
+    }
+
     fn eat(&mut self, expecting: Kind) -> Result<(), SyntaxError> {
         let current = self.at();
 
@@ -134,107 +263,134 @@ impl Parser {
         Ok(())
     }
 
+    /// The doc comment of the script declaration about to be parsed: the
+    /// run of `//` lines ending on the line right above its `[`, with no
+    /// blank line between and no code before them on their lines. `//
+    /// include:` directives are left out, and `// @key value` annotations
+    /// are returned apart from it.
+    fn doc_comment(&self) -> (Option<String>, Annotations) {
+        let mut annotations = Annotations::new();
+        let Some(header) = self.tokens.iter().position(|token| !matches!(token.kind, Kind::SingleLineComment | Kind::MultiLineComment)) else {
+            return (None, annotations);
+        };
+        let mut next_line = self.tokens[header].line;
+        let mut lines = Vec::new();
+        for token in self.tokens[..header].iter().rev() {
+            if token.kind != Kind::SingleLineComment || token.line + 1 != next_line || Some(token.line) == self.last_line {
+                break;
+            }
+            next_line = token.line;
+            let text = token.value.strip_prefix(' ').unwrap_or(&token.value).trim_end();
+            if let Some((key, value)) = annotations::parse(text) {
+                // Read bottom up, so the first of a repeated key wins.
+                annotations.insert(key, value);
+            } else if !text.trim_start().starts_with("include:") {
+                lines.push(text.to_string());
+            }
+        }
+        lines.reverse();
+        ((!lines.is_empty()).then(|| lines.join("\n")), annotations)
+    }
+
     fn parse_script_declaration(&mut self) -> Result<AstKind, SyntaxError> {
         match self.at().kind {
             Kind::LBracket => {
+                let (doc, annotations) = self.doc_comment();
                 self.eat(Kind::LBracket)?;
                 let kind = self.parse_primary_expression()?;
                 self.eat(Kind::Comma)?;
 
-                let primary_expression = self.parse_primary_expression();
-
-                match primary_expression {
-                    Ok(name) => {
-                        self.eat(Kind::RBracket)?;
-
-                        let mut args: Vec<Box<AstKind>> = Vec::new();
-
-                        // Script declaration args
-                        if self.at().kind == Kind::LParen {
-                            self.eat(Kind::LParen)?;
-
-                            while !self.is_eof() && self.at().kind != Kind::RParen {
-                                if self.at().kind == Kind::Comma {
-                                    self.eat(Kind::Comma)?;
-                                }
-
-                                // Parse type
-                                let arg_type = self.parse_primary_expression()?;
-                                args.push(Box::new(arg_type));
-                                
-                                // Parse variable name
-                                if self.at().kind == Kind::LocalVar {
-                                    let var = self.parse_primary_expression()?;
-                                    args.push(Box::new(var));
-                                } else {
-                                    return Err(SyntaxError::from_token(
-                                        self.file_path.clone(),
-                                        self.at(),
-                                        "Expected local variable name".to_string(),
-                                    ));
-                                }
-                            }
-
-                            self.eat(Kind::RParen)?;
-                        }
-
-                        let mut return_type: Box<AstKind> = Box::new(AstKind::ReturnType);
+                let name = self.parse_trigger_subject().map_err(|_| {
+                    SyntaxError::from_token(
+                        self.file_path.clone(),
+                        self.at(),
+                        "Missing script declaration name. Syntax [trigger,declaration_name]".to_string(),
+                    )
+                })?;
+                self.eat(Kind::RBracket)?;
 
-                        // Script declaration return type
-                        if self.at().kind == Kind::LParen {
-                            self.eat(Kind::LParen)?;
+                let mut args: Vec<Box<AstKind>> = Vec::new();
+                let mut defaults = Vec::new();
 
-                            while !self.is_eof() && self.at().kind != Kind::RParen {
-                                if self.at().kind == Kind::Comma {
-                                    self.eat(Kind::Comma)?;
-                                }
-                                return_type = Box::new(self.parse_primary_expression()?)
-                            }
+                // Script declaration args
+                if self.at().kind == Kind::LParen {
+                    self.eat(Kind::LParen)?;
 
-                            self.eat(Kind::RParen)?;
+                    while !self.is_eof() && self.at().kind != Kind::RParen {
+                        if self.at().kind == Kind::Comma {
+                            self.eat(Kind::Comma)?;
                         }
 
-                        // Parse all statements in the script body
-                        let mut body_statements = Vec::new();
+                        // Parse type
+                        let arg_type = self.parse_primary_expression()?;
+                        args.push(Box::new(arg_type));
                         
-                        // Keep parsing statements until we hit EOF or another trigger
-                        while !self.is_eof() && self.at().kind != Kind::LBracket {
-                            let stmt = self.parse_statement()?;
-                            body_statements.push(stmt);
-                        }
-
-                        let trigger = Box::new(AstKind::Trigger {
-                            name: Box::new(name),
-                            kind: Box::new(kind),
-                            body: Box::new(AstKind::Block(body_statements)),
-                            args,
-                            return_type,
-                        });
-
-                        Ok(*trigger)
-                    }
-                    Err(e) => {
-                        if self.next_token().kind == Kind::Underscore {
-                            // TODO: Fix this logic...
-                            self.eat(Kind::RBracket)?;
-
-                            Ok(AstKind::Trigger {
-                                name: Box::new(AstKind::Identifier("_".to_string())),
-                                kind: Box::new(kind),
-                                body: Box::new(self.parse_statement()?),
-                                args: Vec::new(),
-                                return_type: Box::new(AstKind::ReturnType),
-                            })
+                        // Parse variable name
+                        if self.at().kind == Kind::LocalVar {
+                            let var = self.parse_primary_expression()?;
+                            args.push(Box::new(var));
+                            if self.at().kind == Kind::Equals {
+                                self.eat(Kind::Equals)?;
+                                defaults.push(Some(self.parse_expression()?));
+                            } else if defaults.iter().any(Option::is_some) {
+                                return Err(SyntaxError::from_token(
+                                    self.file_path.clone(),
+                                    self.at(),
+                                    "a parameter without a default can't follow one with a default".to_string(),
+                                ));
+                            } else {
+                                defaults.push(None);
+                            }
                         } else {
-                            Err(SyntaxError::from_token(
+                            return Err(SyntaxError::from_token(
                                 self.file_path.clone(),
                                 self.at(),
-                                "Missing script declaration name. Syntax [trigger,declaration_name]"
-                                    .to_string(),
-                            ))
+                                "Expected local variable name".to_string(),
+                            ));
+                        }
+                    }
+
+                    self.eat(Kind::RParen)?;
+                }
+
+                let mut return_type: Box<AstKind> = Box::new(AstKind::ReturnType);
+
+                // Script declaration return type
+                if self.at().kind == Kind::LParen {
+                    self.eat(Kind::LParen)?;
+
+                    while !self.is_eof() && self.at().kind != Kind::RParen {
+                        if self.at().kind == Kind::Comma {
+                            self.eat(Kind::Comma)?;
                         }
+                        return_type = Box::new(self.parse_primary_expression()?)
                     }
+
+                    self.eat(Kind::RParen)?;
+                }
+
+                // Parse all statements in the script body
+                let mut body_statements = Vec::new();
+                
+                // Keep parsing statements until we hit EOF or another trigger
+                while !self.is_eof() && self.at().kind != Kind::LBracket {
+                    body_statements.push(self.source_line());
+                    let stmt = self.parse_statement()?;
+                    body_statements.push(stmt);
                 }
+
+                let trigger = Box::new(AstKind::Trigger {
+                    name: Box::new(name),
+                    kind: Box::new(kind),
+                    body: Box::new(AstKind::Block(body_statements)),
+                    args,
+                    defaults,
+                    return_type,
+                    doc,
+                    annotations,
+                });
+
+                Ok(*trigger)
             }
             _ => Err(SyntaxError::from_token(
                 self.file_path.clone(),
@@ -297,9 +453,10 @@ impl Parser {
                     let mut statements = Vec::new();
 
                     while !self.is_eof() && self.at().kind != Kind::RBrace {
+                        statements.push(self.source_line());
                         let stmt = self.parse_statement()?;
-                        if let AstKind::Return(value) = stmt {
-                            return_statement = value;
+                        if let AstKind::Return(_) = stmt {
+                            return_statement = Box::new(stmt);
                         } else {
                             statements.push(stmt);
                         }
@@ -308,7 +465,13 @@ impl Parser {
                     self.eat(Kind::RBrace)?;
                     Box::new(AstKind::Block(statements))
                 } else {
-                    Box::new(self.parse_statement()?)
+                    // A body without braces is shaped like the same statement in braces.
+                    let mut statements = vec![self.source_line()];
+                    match self.parse_statement()? {
+                        stmt @ AstKind::Return(_) => return_statement = Box::new(stmt),
+                        stmt => statements.push(stmt),
+                    }
+                    Box::new(AstKind::Block(statements))
                 };
 
                 Ok(AstKind::If {
@@ -328,13 +491,14 @@ impl Parser {
                     let mut statements = Vec::new();
 
                     while !self.is_eof() && self.at().kind != Kind::RBrace {
+                        statements.push(self.source_line());
                         statements.push(self.parse_statement()?);
                     }
 
                     self.eat(Kind::RBrace)?;
                     Box::new(AstKind::Block(statements))
                 } else {
-                    Box::new(self.parse_statement()?)
+                    Box::new(AstKind::Block(vec![self.source_line(), self.parse_statement()?]))
                 };
 
                 Ok(AstKind::While {
@@ -342,20 +506,85 @@ impl Parser {
                     body,
                 })
             }
+            Kind::For => {
+                self.eat(Kind::For)?;
+                self.eat(Kind::LParen)?;
+                let init = self.parse_statement()?;
+                let condition = self.parse_expression()?;
+                self.eat(Kind::Semicolon)?;
+                let step = self.parse_statement()?;
+                self.eat(Kind::RParen)?;
+
+                let body = if self.at().kind == Kind::LBrace {
+                    self.eat(Kind::LBrace)?;
+                    let mut statements = Vec::new();
+
+                    while !self.is_eof() && self.at().kind != Kind::RBrace {
+                        statements.push(self.source_line());
+                        statements.push(self.parse_statement()?);
+                    }
+
+                    self.eat(Kind::RBrace)?;
+                    Box::new(AstKind::Block(statements))
+                } else {
+                    Box::new(self.parse_statement()?)
+                };
+
+                Ok(AstKind::For {
+                    init: Box::new(init),
+                    condition: Box::new(condition),
+                    step: Box::new(step),
+                    body,
+                })
+            }
             Kind::Return => {
                 self.eat(Kind::Return)?;
-                self.eat(Kind::LParen)?;
+                // `return(expr)` is just a parenthesised expression, so this accepts `return expr;` too.
+                if matches!(self.at().kind, Kind::Semicolon | Kind::RBrace | Kind::LBracket | Kind::EOF) {
+                    return Err(SyntaxError::from_token(
+                        self.file_path.clone(),
+                        self.at(),
+                        "return requires a value: return(expr)".to_string(),
+                    ));
+                }
                 let expr = self.parse_expression()?;
-                self.eat(Kind::RParen)?;
                 if self.at().kind == Kind::Semicolon {
                     self.eat(Kind::Semicolon)?;
                 }
                 Ok(AstKind::Return(Box::new(expr)))
             }
-            Kind::LocalVar => {
+            Kind::LocalVar | Kind::GameVar => {
                 let var = self.parse_primary_expression()?;
+                if self.at().kind == Kind::ComparisonOperator && self.at().value == "==" {
+                    return Err(SyntaxError::from_token(
+                        self.file_path.clone(),
+                        self.at(),
+                        "'==' compares values; use '=' to assign".to_string(),
+                    ));
+                }
                 if self.at().kind == Kind::Equals {
                     self.eat(Kind::Equals)?;
+                    // An assignment's value is the value it assigns, so
+                    // `$a = $b = 5;` sets $b to 5 and then $a to $b, as two
+                    // statements. The sigil and the name are separate tokens.
+                    let chained = matches!(self.at().kind, Kind::LocalVar | Kind::GameVar)
+                        && self
+                            .tokens
+                            .iter()
+                            .filter(|token| !matches!(token.kind, Kind::SingleLineComment | Kind::MultiLineComment))
+                            .nth(2)
+                            .is_some_and(|token| token.kind == Kind::Equals);
+                    if chained {
+                        let mut statements = match self.parse_statement()? {
+                            AstKind::Block(statements) => statements,
+                            assignment => vec![assignment],
+                        };
+                        if let Some(AstKind::Assignment { target, .. }) = statements.last() {
+                            let value = target.clone();
+                            statements.push(AstKind::Assignment { target: Box::new(var), value });
+                        }
+                        return Ok(AstKind::Block(statements));
+                    }
                     let value = self.parse_expression()?;
                     if self.at().kind == Kind::Semicolon {
                         self.eat(Kind::Semicolon)?;
@@ -383,7 +612,10 @@ impl Parser {
 
         if self.at().kind == Kind::ComparisonOperator || 
            (self.at().kind == Kind::Equals && (self.tokens.len() > 1 && self.tokens[1].kind != Kind::RParen)) {
-            let operator = self.next_token().value;
+            let mut operator = self.next_token().value;
+            if operator == "==" {
+                operator = "=".to_string();
+            }
             let right = self.parse_additive_expression()?;
             
             left = AstKind::BinaryExpression {
@@ -393,13 +625,27 @@ impl Parser {
             };
         }
 
+        // `condition ? a : b` binds loosest and nests to the right, so
+        // `x ? a : y ? b : c` is `x ? a : (y ? b : c)`.
+        if self.at().kind == Kind::Question {
+            self.eat(Kind::Question)?;
+            let value = self.parse_expression()?;
+            self.eat(Kind::Colon)?;
+            let rhs = self.parse_expression()?;
+            left = AstKind::ConditionalExpression {
+                lhs: Box::new(left),
+                rhs: Box::new(rhs),
+                value: Box::new(value),
+            };
+        }
+
         Ok(left)
     }
 
     fn parse_additive_expression(&mut self) -> Result<AstKind, SyntaxError> {
         let mut left = self.parse_multiplicative_expression()?;
 
-        while self.at().kind == Kind::BinaryOperator {
+        while self.at().kind == Kind::BinaryOperator && (self.at().value == "+" || self.at().value == "-") {
             let operator = self.next_token().value;
             let right = self.parse_multiplicative_expression()?;
             
@@ -418,7 +664,7 @@ impl Parser {
 
         match left {
             Ok(_) => {
-                while !self.is_eof() && self.at().value == "*" || self.at().value == "/" {
+                while self.at().kind == Kind::BinaryOperator && matches!(self.at().value.as_str(), "*" | "/" | "%") {
                     let operator_token = self.next_token();
                     let right = self.parse_primary_expression();
 
@@ -435,6 +681,102 @@ impl Parser {
         }
     }
 
+    /// Turns a string literal's `<expr>` interpolations into a string `+`
+    /// chain, so `"hi <$name>!"` parses as `"hi " + $name + "!"` and the
+    /// type checker requires each expression to be a string. Only a `<`
+    /// followed by a local, game var, script call or command call opens one;
+    /// text tags like `<br>` and `<col=ff0000>` are left as they are.
+    fn interpolate(&self, token: &Token) -> Result<AstKind, SyntaxError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = token.value.as_str();
+        while let Some(open) = rest.find('<') {
+            let inner = &rest[open + 1..];
+            let Some(close) = interpolation_end(inner) else {
+                literal.push_str(&rest[..=open]);
+                rest = inner;
+                continue;
+            };
+            literal.push_str(&rest[..open]);
+            if !literal.is_empty() || parts.is_empty() {
+                parts.push(AstKind::StringLiteral(std::mem::take(&mut literal)));
+            }
+            parts.push(self.parse_interpolation(&inner[..close], token)?);
+            rest = &inner[close + 1..];
+        }
+        if parts.is_empty() {
+            return Ok(AstKind::StringLiteral(token.value.clone()));
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(AstKind::StringLiteral(literal));
+        }
+        let mut parts = parts.into_iter();
+        let first = parts.next().unwrap();
+        Ok(parts.fold(first, |lhs, rhs| AstKind::BinaryExpression {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            operator: "+".to_string(),
+        }))
+    }
+
+    fn parse_interpolation(&self, source: &str, token: &Token) -> Result<AstKind, SyntaxError> {
+        let error = |message: String| {
+            SyntaxError::from_token(self.file_path.clone(), token, format!("in <{}>: {}", source, message))
+        };
+        let tokens = Lexer::new(source, &self.file_path).tokenize().map_err(|e| error(e.message))?;
+        let mut parser = Parser::new(tokens, &self.file_path);
+        let expression = parser.parse_expression().map_err(|e| error(e.message))?;
+        if !parser.is_eof() {
+            return Err(error(format!("unexpected {:?}", parser.at().value)));
+        }
+        Ok(expression)
+    }
+
+    /// Builds a command call, checking the argument count of the commands
+    /// the compiler implements itself.
+    fn command_call(&self, name: Token, mut arguments: Vec<AstKind>) -> Result<AstKind, SyntaxError> {
+        if name.value == "settimer" {
+            // The callback is queued rather than called, so it's kept as the
+            // script's name, like the enum named by enum_getvalue.
+            match arguments.pop() {
+                Some(AstKind::ScriptCall { script, arguments: callback_args }) if arguments.len() == 2 && callback_args.is_empty() => {
+                    arguments.push(*script);
+                    return Ok(AstKind::FunctionCall { name: name.value, arguments: arguments.into_iter().map(Box::new).collect() });
+                }
+                _ => {
+                    return Err(SyntaxError::from_token(
+                        self.file_path.clone(),
+                        &name,
+                        "settimer() takes a timer, a number of ticks and a ~script without arguments".to_string(),
+                    ));
+                }
+            }
+        }
+        let signature = compiler::builtin_signature(&name.value).or_else(|| host::signature(&name.value));
+        if let Some((params, _)) = signature {
+            // db_find's value takes its column's type, so the signature
+            // leaves it out.
+            let expected = params.len() + usize::from(name.value == "db_find");
+            if arguments.len() != expected {
+                return Err(SyntaxError::from_token(
+                    self.file_path.clone(),
+                    &name,
+                    format!(
+                        "{}() takes {} argument(s) but {} were given",
+                        name.value,
+                        expected,
+                        arguments.len()
+                    ),
+                ));
+            }
+        }
+        Ok(AstKind::FunctionCall {
+            name: name.value,
+            arguments: arguments.into_iter().map(Box::new).collect(),
+        })
+    }
+
     fn parse_primary_expression(&mut self) -> Result<AstKind, SyntaxError> {
         match self.at().kind {
             Kind::Number => {
@@ -442,11 +784,16 @@ impl Parser {
                 let value: i32 = token.value.parse().unwrap();
                 Ok(AstKind::NumericLiteral(value))
             }
+            Kind::StringLiteral => {
+                let token = self.next_token();
+                self.interpolate(&token)
+            }
             Kind::Identifier => {
                 let token = self.next_token();
                 if token.value == "calc" {
+                    // calc(...) takes comparisons too; they give 0 or 1.
                     self.eat(Kind::LParen)?;
-                    let expr = self.parse_additive_expression()?;
+                    let expr = self.parse_expression()?;
                     self.eat(Kind::RParen)?;
                     Ok(AstKind::FunctionCall {
                         name: "calc".to_string(),
@@ -460,6 +807,42 @@ impl Parser {
                         name: "abs".to_string(),
                         arguments: vec![Box::new(expr)],
                     })
+                } else if let (Some(target), Kind::LParen, None) =
+                    (Type::from_name(&token.value), &self.at().kind, host::signature(&token.value))
+                {
+                    // Explicit cast, e.g. int($npc). stat(...) is the
+                    // command, not a cast.
+                    self.eat(Kind::LParen)?;
+                    let expr = self.parse_expression()?;
+                    if self.at().kind == Kind::Comma {
+                        // Not a cast after all: coord(level, x, z)
+                        let mut arguments = vec![expr];
+                        while self.at().kind == Kind::Comma {
+                            self.eat(Kind::Comma)?;
+                            arguments.push(self.parse_expression()?);
+                        }
+                        self.eat(Kind::RParen)?;
+                        return self.command_call(token, arguments);
+                    }
+                    self.eat(Kind::RParen)?;
+                    Ok(AstKind::Cast {
+                        target,
+                        expression: Box::new(expr),
+                    })
+                } else if self.at().kind == Kind::LParen {
+                    // Host command, e.g. mes("hello")
+                    self.eat(Kind::LParen)?;
+                    let mut arguments = Vec::new();
+                    while self.at().kind != Kind::RParen {
+                        if !arguments.is_empty() {
+                            self.eat(Kind::Comma)?;
+                        }
+                        arguments.push(self.parse_expression()?);
+                    }
+                    self.eat(Kind::RParen)?;
+                    self.command_call(token, arguments)
+                } else if token.value == "null" {
+                    Ok(AstKind::Null)
                 } else {
                     Ok(AstKind::Identifier(token.value))
                 }
@@ -475,6 +858,11 @@ impl Parser {
                 let identifier = self.next_token();
                 Ok(AstKind::LocalVar(identifier.value))
             }
+            Kind::GameVar => {
+                self.eat(Kind::GameVar)?;
+                let identifier = self.next_token();
+                Ok(AstKind::GameVar(identifier.value))
+            }
             Kind::LParen => {
                 self.eat(Kind::LParen)?;
                 let expr = self.parse_expression()?;
@@ -529,7 +917,9 @@ impl Parser {
             },
             Kind::ScriptCall => {
                 self.eat(Kind::ScriptCall)?;
-                let script_name = self.parse_primary_expression()?;
+                // Take the name as is; parsed as an expression, `name(` would
+                // read as a command call.
+                let script_name = AstKind::Identifier(self.next_token().value);
                 
                 let mut arguments = Vec::new();
                 if self.at().kind == Kind::LParen {
@@ -575,6 +965,23 @@ impl Parser {
         }
     }
 
+    /// Reads a trigger's second field: a script or subject name, `_category`
+    /// or the bare `_` wildcard.
+    fn parse_trigger_subject(&mut self) -> Result<AstKind, SyntaxError> {
+        if self.at().kind != Kind::Underscore {
+            return self.parse_primary_expression();
+        }
+        self.eat(Kind::Underscore)?;
+        if self.at().kind == Kind::Identifier {
+            return Ok(AstKind::Identifier(format!("_{}", self.next_token().value)));
+        }
+        Ok(AstKind::Identifier("_".to_string()))
+    }
+
+    fn source_line(&self) -> AstKind {
+        AstKind::SourceLine(self.at().line + 1)
+    }
+
     fn is_eof(&self) -> bool {
         self.at().kind == Kind::EOF
     }
@@ -625,35 +1032,12 @@ impl Parser {
     }
 
     fn get_type_from_def(&self, def_str: &str) -> Result<Type, SyntaxError> {
-        match def_str {
-            "def_int" => Ok(Type::Int),
-            "def_boolean" => Ok(Type::Boolean),
-            "def_string" => Ok(Type::String),
-            "def_loc" => Ok(Type::Loc),
-            "def_npc" => Ok(Type::Npc),
-            "def_obj" => Ok(Type::Obj),
-            "def_coord" => Ok(Type::Coord),
-            "def_namedobj" => Ok(Type::NamedObj),
-            "def_playeruid" => Ok(Type::PlayerUid),
-            "def_npcuid" => Ok(Type::NpcUid),
-            "def_stat" => Ok(Type::Stat),
-            "def_component" => Ok(Type::Component),
-            "def_interface" => Ok(Type::Interface),
-            "def_inv" => Ok(Type::Inv),
-            "def_enum" => Ok(Type::Enum),
-            "def_struct" => Ok(Type::Struct),
-            "def_param" => Ok(Type::Param),
-            "def_dbtable" => Ok(Type::DbTable),
-            "def_dbrow" => Ok(Type::DbRow),
-            "def_dbcolumn" => Ok(Type::DbColumn),
-            "def_varp" => Ok(Type::Varp),
-            "def_mesanim" => Ok(Type::MesAnim),
-            _ => Err(SyntaxError::from_token(
+        Type::from_def_keyword(def_str)
+            .ok_or_else(|| SyntaxError::from_token(
                 self.file_path.clone(),
                 self.at(),
                 format!("Unknown type definition: {}", def_str),
-            )),
-        }
+            ))
     }
 
     fn get_default_value_for_type(&self, var_type: &Type) -> AstKind {
@@ -661,8 +1045,119 @@ impl Parser {
             Type::Int => AstKind::NumericLiteral(0),
             Type::Boolean => AstKind::NumericLiteral(0), // false
             Type::String => AstKind::StringLiteral(String::new()),
-            // Add default values for other types...
-            _ => AstKind::NumericLiteral(0), // temporary default
+            // Id types default to 0 too; the cast keeps it from tripping the int -> id warning.
+            _ => AstKind::Cast {
+                target: var_type.clone(),
+                expression: Box::new(AstKind::NumericLiteral(0)),
+            },
+        }
+    }
+}
+
+impl AstKind {
+    /// The name a trigger is registered and run under: a proc's own name, or
+    /// `[kind,subject]` for other triggers; see `triggers::script_name`.
+    pub fn script_name(&self) -> Option<String> {
+        let AstKind::Trigger { name, kind, .. } = self else {
+            return None;
+        };
+        let AstKind::Identifier(name) = &**name else {
+            return None;
+        };
+        match &**kind {
+            AstKind::Identifier(kind) => Some(triggers::script_name(kind, name)),
+            _ => Some(name.clone()),
+        }
+    }
+
+    /// The type a trigger declares it returns, e.g. `boolean` for
+    /// `[proc,is_even](int $n)(boolean)`.
+    pub fn return_type(&self) -> Option<Type> {
+        match self {
+            AstKind::Trigger { return_type, .. } => match &**return_type {
+                AstKind::Identifier(type_name) => Type::from_name(type_name),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Rewrites every `for` loop as `{ init; while (condition) { body; step } }`.
+    pub fn lower_loops(&self) -> AstKind {
+        match self {
+            AstKind::For { init, condition, step, body } => AstKind::Block(vec![
+                init.lower_loops(),
+                AstKind::While {
+                    condition: condition.clone(),
+                    body: Box::new(AstKind::Block(vec![body.lower_loops(), (**step).clone()])),
+                },
+            ]),
+            AstKind::Block(statements) => AstKind::Block(statements.iter().map(AstKind::lower_loops).collect()),
+            AstKind::If { expression, value, return_statement } => AstKind::If {
+                expression: expression.clone(),
+                value: Box::new(value.lower_loops()),
+                return_statement: return_statement.clone(),
+            },
+            AstKind::While { condition, body } => AstKind::While {
+                condition: condition.clone(),
+                body: Box::new(body.lower_loops()),
+            },
+            AstKind::Trigger { name, kind, body, args, defaults, return_type, doc, annotations } => AstKind::Trigger {
+                name: name.clone(),
+                kind: kind.clone(),
+                body: Box::new(body.lower_loops()),
+                args: args.clone(),
+                defaults: defaults.clone(),
+                return_type: return_type.clone(),
+                doc: doc.clone(),
+                annotations: annotations.clone(),
+            },
+            node => node.clone(),
+        }
+    }
+
+    /// A copy with every `SourceLine` marker removed. Markers only appear in
+    /// blocks, so only statements that hold blocks are walked.
+    pub fn without_source_lines(&self) -> AstKind {
+        match self {
+            AstKind::Block(statements) => AstKind::Block(
+                statements
+                    .iter()
+                    .filter(|stmt| !matches!(stmt, AstKind::SourceLine(_)))
+                    .map(AstKind::without_source_lines)
+                    .collect(),
+            ),
+            AstKind::Trigger { name, kind, args, defaults, body, return_type, doc, annotations } => AstKind::Trigger {
+                name: name.clone(),
+                kind: kind.clone(),
+                args: args.clone(),
+                defaults: defaults.clone(),
+                body: Box::new(body.without_source_lines()),
+                return_type: return_type.clone(),
+                doc: doc.clone(),
+                annotations: annotations.clone(),
+            },
+            AstKind::If { expression, value, return_statement } => AstKind::If {
+                expression: expression.clone(),
+                value: Box::new(value.without_source_lines()),
+                return_statement: Box::new(return_statement.without_source_lines()),
+            },
+            AstKind::While { condition, body } => AstKind::While {
+                condition: condition.clone(),
+                body: Box::new(body.without_source_lines()),
+            },
+            AstKind::For { init, condition, step, body } => AstKind::For {
+                init: init.clone(),
+                condition: condition.clone(),
+                step: step.clone(),
+                body: Box::new(body.without_source_lines()),
+            },
+            AstKind::InvariantWhile { invariants, condition, body } => AstKind::InvariantWhile {
+                invariants: invariants.clone(),
+                condition: condition.clone(),
+                body: Box::new(body.without_source_lines()),
+            },
+            node => node.clone(),
         }
     }
 }
@@ -681,3 +1176,195 @@ pub enum ConfigType {
     Enum,       // .enum
     Struct      // .struct
 }
+
+/// Where the interpolation opened by a `<` just before `text` ends: the
+/// index of its closing `>`, skipping any inside parentheses. `None` if
+/// `text` doesn't start an expression, e.g. for a `<br>` tag, or if the
+/// interpolation is never closed, in which case it's left as text.
+fn interpolation_end(text: &str) -> Option<usize> {
+    let name_end = text.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(text.len());
+    let opens = match text.chars().next()? {
+        '$' | '%' | '~' => true,
+        _ => name_end > 0 && text[name_end..].starts_with('('),
+    };
+    if !opens {
+        return None;
+    }
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '>' if depth == 0 => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::SymbolTable;
+    use crate::test_support;
+
+    /// The statements of the first trigger in `source`, without the
+    /// source line markers.
+    fn statements(source: &str) -> Vec<AstKind> {
+        match test_support::parse(source).body[0].without_source_lines() {
+            AstKind::Trigger { body, .. } => match *body {
+                AstKind::Block(statements) => statements,
+                body => panic!("expected a block, got {:?}", body),
+            },
+            node => panic!("expected a trigger, got {:?}", node),
+        }
+    }
+
+    fn binary(lhs: AstKind, operator: &str, rhs: AstKind) -> AstKind {
+        AstKind::BinaryExpression { lhs: Box::new(lhs), rhs: Box::new(rhs), operator: operator.to_string() }
+    }
+
+    #[test]
+    fn comparison_binds_looser_than_arithmetic() {
+        let source = "[proc,bigger]()(int)\nif (1 + 1 > 1 * 1) {\n    return(1);\n}\nreturn(0);\n";
+        let AstKind::If { expression, .. } = &statements(source)[0] else {
+            panic!("expected an if");
+        };
+        let one = || AstKind::NumericLiteral(1);
+        assert_eq!(**expression, binary(binary(one(), "+", one()), ">", binary(one(), "*", one())));
+        assert_eq!(test_support::run(source, "bigger", &[]), Ok(1));
+    }
+
+    #[test]
+    fn return_takes_a_value_with_or_without_parentheses() {
+        for source in ["[proc,five]()(int)\nreturn 5;\n", "[proc,five]()(int)\nreturn(5);\n"] {
+            assert_eq!(statements(source), [AstKind::Return(Box::new(AstKind::NumericLiteral(5)))]);
+            assert_eq!(test_support::run(source, "five", &[]), Ok(5));
+        }
+    }
+
+    #[test]
+    fn bare_return_is_a_syntax_error() {
+        let path = PathBuf::from("test.rs2");
+        let tokens = Lexer::new("[proc,nothing]()(int)\nreturn;\n", &path).tokenize().unwrap();
+        let error = Parser::new(tokens, &path).parse().unwrap_err();
+        assert_eq!(error.message, "return requires a value: return(expr)");
+        assert_eq!(error.line + 1, 2);
+    }
+
+    #[test]
+    fn error_recovery_stops_at_the_cap() {
+        let source: String = (0..6).map(|i| format!("[proc,broken{}]()(int)\nreturn;\n\n", i)).collect();
+        let path = PathBuf::from("test.rs2");
+        let tokens = Lexer::new(&source, &path).tokenize().unwrap();
+
+        let mut parser = Parser::new(tokens.clone(), &path);
+        parser.set_max_errors(3);
+        let errors = parser.parse_recovering().unwrap_err();
+        assert_eq!(errors.errors.len(), 3);
+        assert!(errors.truncated);
+        assert!(errors.to_string().ends_with("... and more\n"), "{}", errors);
+
+        let errors = Parser::new(tokens, &path).parse_recovering().unwrap_err();
+        assert_eq!(errors.errors.len(), 6);
+        assert!(!errors.truncated);
+    }
+
+    #[test]
+    fn double_equals_compares_like_equals() {
+        let double = "[proc,is_five](int $x)(int)\nif ($x == 5) {\n    return(1);\n}\nreturn(0);\n";
+        assert_eq!(statements(double), statements(&double.replace("==", "=")));
+        assert_eq!(test_support::run(double, "is_five", &[5]), Ok(1));
+        assert_eq!(test_support::run(double, "is_five", &[4]), Ok(0));
+    }
+
+    #[test]
+    fn double_equals_in_an_assignment_and_triple_equals_are_errors() {
+        let path = PathBuf::from("test.rs2");
+        let tokens = Lexer::new("[proc,set]()(int)\ndef_int $x = 0;\n$x == 5;\nreturn($x);\n", &path).tokenize().unwrap();
+        let error = Parser::new(tokens, &path).parse().unwrap_err();
+        assert_eq!(error.message, "'==' compares values; use '=' to assign");
+
+        let error = Lexer::new("[proc,same](int $x)(int)\nif ($x === 5) {\n    return(1);\n}\nreturn(0);\n", &path).tokenize().unwrap_err();
+        assert!(error.to_string().contains("'===' is not an operator; use '=' to compare values"), "{}", error);
+    }
+
+    #[test]
+    fn layout_and_comments_do_not_change_the_structure() {
+        let pairs = [
+            (
+                "[proc,add](int $a, int $b)(int)\nreturn(calc($a + $b));\n",
+                "[proc,add](int $a,int $b)(int)\n\n    return(calc( $a+$b ));   // sum\n",
+            ),
+            (
+                "[proc,count]()(int)\ndef_int $i = 0;\nwhile ($i < 10) {\n$i = calc($i + 1);\n}\nreturn($i);\n",
+                "[proc,count]()(int)\n// start at zero\ndef_int $i = 0;\nwhile ($i < 10) { $i = calc($i + 1); }\n\nreturn($i);\n",
+            ),
+            (
+                "[proc,greet](string $name)\nif ($name = \"\") {\nmes(\"Hello\");\n}\nmes(\"Hello <$name>\");\n",
+                "[proc,greet](string $name)\nif ($name = \"\")\n{\n\tmes(\"Hello\");\n}\nmes(\"Hello <$name>\");\n",
+            ),
+        ];
+        for (source, reflowed) in pairs {
+            let script = test_support::parse(source);
+            assert!(script.structurally_equal(&script), "{}", source);
+            assert!(script.structurally_equal(&test_support::parse(reflowed)), "{}", reflowed);
+        }
+    }
+
+    #[test]
+    fn a_changed_literal_or_operator_is_a_different_structure() {
+        let script = test_support::parse("[proc,add](int $a)(int)\nreturn(calc($a + 1));\n");
+        for changed in ["[proc,add](int $a)(int)\nreturn(calc($a + 2));\n", "[proc,add](int $a)(int)\nreturn(calc($a - 1));\n"] {
+            assert!(!script.structurally_equal(&test_support::parse(changed)), "{}", changed);
+        }
+        let two_scripts = test_support::parse("[proc,add](int $a)(int)\nreturn(calc($a + 1));\n[proc,one]()(int)\nreturn(1);\n");
+        assert!(!script.structurally_equal(&two_scripts));
+    }
+
+    #[test]
+    fn a_comment_run_right_above_a_header_is_its_doc() {
+        let source = "// include: lib/math.rs2\n\
+            // Squares a number.\n//\n//   $n: any int\n[proc,square](int $n)(int)\nreturn(calc($n * $n));\n\
+            // Not attached: a blank line follows.\n\n[proc,plain]()(int)\nreturn(0); // trailing\n\
+            // Only this line.\n[proc,after]()(int)\nreturn(1);\n";
+        let docs: Vec<Option<String>> = test_support::parse(source)
+            .body
+            .iter()
+            .map(|node| match node {
+                AstKind::Trigger { doc, .. } => doc.clone(),
+                node => panic!("expected a trigger, got {:?}", node),
+            })
+            .collect();
+        assert_eq!(docs, [Some("Squares a number.\n\n  $n: any int".to_string()), None, Some("Only this line.".to_string())]);
+
+        let compiled = test_support::compile(source);
+        assert_eq!(compiled[0].doc.as_deref(), Some("Squares a number.\n\n  $n: any int"));
+        assert_eq!(compiled[1].doc, None);
+    }
+
+    #[test]
+    fn brace_less_if_and_while_bodies_are_one_statement_blocks() {
+        let factorial = |body: &str| format!("[proc,factorial](int $n)(int)\nif ($n <= 1) {}\nreturn(calc($n * ~factorial(calc($n - 1))));\n", body);
+        let climb = |body: &str| {
+            format!(
+                "[proc,climb](int $limit)(int)\ndef_int $i = 0;\nwhile ($i < 100) {}\nreturn($i);\n\
+                [proc,step](int $i, int $limit)(int)\nif ($i > $limit) return(100);\nreturn(1);\n",
+                body
+            )
+        };
+        let cases = [
+            (factorial("return(1);"), factorial("{ return(1); }"), "factorial", vec![5], 120),
+            (climb("$i = calc($i + ~step($i, $limit));"), climb("{ $i = calc($i + ~step($i, $limit)); }"), "climb", vec![7], 108),
+        ];
+        for (brace_less, braced, script, args, expected) in cases {
+            assert_eq!(statements(&brace_less), statements(&braced), "{}", brace_less);
+            let (brace_less_code, braced_code) = (test_support::compile(&brace_less), test_support::compile(&braced));
+            assert_eq!(brace_less_code[0].instructions, braced_code[0].instructions, "{}", brace_less);
+
+            let (mut vm, mut evaluator) = test_support::backends_with(&SymbolTable::new(), &brace_less);
+            assert_eq!(vm.run_script(script, &args), Ok(expected), "{}", brace_less);
+            assert_eq!(evaluator.eval_script(script, &args), Ok(expected), "{}", brace_less);
+        }
+    }
+}
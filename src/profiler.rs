@@ -0,0 +1,167 @@
+//! Per-script profiles of VM runs: the instructions and wall time each
+//! script took by itself and with the scripts it called, how often each
+//! script called each other one, and folded stacks for flamegraph tools.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What one script cost over a profile. Inclusive figures count the scripts
+/// it called; a recursive script's inner calls aren't counted twice.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptStats {
+    pub calls: u64,
+    pub self_instructions: u64,
+    pub inclusive_instructions: u64,
+    pub self_time: Duration,
+    pub inclusive_time: Duration,
+}
+
+/// A script call in progress.
+#[derive(Debug)]
+struct Frame {
+    script: String,
+    /// The VM's instruction count when the call started.
+    start_instructions: usize,
+    started: Instant,
+    /// What the scripts this call called took.
+    child_instructions: u64,
+    child_time: Duration,
+}
+
+/// Built by the VM as scripts are entered and left, once
+/// `VM::enable_call_profile` is called.
+#[derive(Debug, Default)]
+pub struct CallProfile {
+    scripts: HashMap<String, ScriptStats>,
+    /// Calls from one script to another, by caller and callee.
+    edges: HashMap<(String, String), u64>,
+    /// Instructions executed by each call stack, outermost script first,
+    /// joined with `;`.
+    stacks: HashMap<String, u64>,
+    frames: Vec<Frame>,
+}
+
+impl CallProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a call to `script`; `instructions` is the VM's instruction
+    /// count so far.
+    pub fn enter(&mut self, script: &str, instructions: usize) {
+        if let Some(caller) = self.frames.last() {
+            *self.edges.entry((caller.script.clone(), script.to_string())).or_insert(0) += 1;
+        }
+        self.scripts.entry(script.to_string()).or_default().calls += 1;
+        self.frames.push(Frame {
+            script: script.to_string(),
+            start_instructions: instructions,
+            started: Instant::now(),
+            child_instructions: 0,
+            child_time: Duration::ZERO,
+        });
+    }
+
+    /// Ends the innermost call, returned or failed.
+    pub fn exit(&mut self, instructions: usize) {
+        let stack: Vec<&str> = self.frames.iter().map(|frame| frame.script.as_str()).collect();
+        let stack = stack.join(";");
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        let inclusive_instructions = instructions.saturating_sub(frame.start_instructions) as u64;
+        let inclusive_time = frame.started.elapsed();
+        let self_instructions = inclusive_instructions.saturating_sub(frame.child_instructions);
+
+        let recursive = self.frames.iter().any(|outer| outer.script == frame.script);
+        let stats = self.scripts.entry(frame.script).or_default();
+        stats.self_instructions += self_instructions;
+        stats.self_time += inclusive_time.saturating_sub(frame.child_time);
+        if !recursive {
+            stats.inclusive_instructions += inclusive_instructions;
+            stats.inclusive_time += inclusive_time;
+        }
+        if self_instructions > 0 {
+            *self.stacks.entry(stack).or_insert(0) += self_instructions;
+        }
+        if let Some(caller) = self.frames.last_mut() {
+            caller.child_instructions += inclusive_instructions;
+            caller.child_time += inclusive_time;
+        }
+    }
+
+    /// Each script's stats, most self instructions first.
+    pub fn scripts(&self) -> Vec<(&str, &ScriptStats)> {
+        let mut scripts: Vec<_> = self.scripts.iter().map(|(name, stats)| (name.as_str(), stats)).collect();
+        scripts.sort_by(|a, b| b.1.self_instructions.cmp(&a.1.self_instructions).then_with(|| a.0.cmp(b.0)));
+        scripts
+    }
+
+    /// Each caller and callee with the number of calls between them, most
+    /// calls first.
+    pub fn edges(&self) -> Vec<(&str, &str, u64)> {
+        let mut edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|((caller, callee), count)| (caller.as_str(), callee.as_str(), *count))
+            .collect();
+        edges.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| (a.0, a.1).cmp(&(b.0, b.1))));
+        edges
+    }
+
+    /// The profile in the folded-stacks format `flamegraph.pl` and inferno
+    /// read: one `outer;inner count` line per call stack, weighted by
+    /// instructions so the output is the same on every run.
+    pub fn folded_stacks(&self) -> String {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        stacks.into_iter().map(|(stack, count)| format!("{} {}\n", stack, count)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::VmOptions;
+    use crate::test_support;
+    use crate::vm::VM;
+
+    /// top loops over middle `$n` times; middle calls leaf twice.
+    const HIERARCHY: &str = "[proc,top](int $n)(int)\ndef_int $i = 0;\ndef_int $total = 0;\n\
+        while ($i < $n) {\n    $total = calc($total + ~middle($i));\n    $i = calc($i + 1);\n}\nreturn($total);\n\
+        [proc,middle](int $x)(int)\nreturn(calc(~leaf($x) + ~leaf(calc($x + 1))));\n\
+        [proc,leaf](int $y)(int)\nreturn(calc($y * 2));\n";
+
+    #[test]
+    fn a_two_level_hierarchy_attributes_self_and_inclusive_costs() {
+        // Without memoizing, so every call to leaf runs.
+        let mut vm = VM::with_options(&VmOptions { memoize: false, ..VmOptions::default() });
+        for bytecode in test_support::compile(HIERARCHY) {
+            vm.register_script(bytecode);
+        }
+        vm.enable_call_profile();
+        assert_eq!(vm.run_script("top", &[3]), Ok(18));
+        let profile = vm.call_profile().unwrap();
+
+        let scripts = profile.scripts();
+        let stats = |name: &str| scripts.iter().find(|(script, _)| *script == name).map(|(_, stats)| (*stats).clone()).unwrap();
+        let (top, middle, leaf) = (stats("top"), stats("middle"), stats("leaf"));
+        assert_eq!((top.calls, middle.calls, leaf.calls), (1, 3, 6));
+        assert_eq!(leaf.self_instructions, leaf.inclusive_instructions);
+        assert_eq!(middle.inclusive_instructions, middle.self_instructions + leaf.inclusive_instructions);
+        assert_eq!(top.inclusive_instructions, top.self_instructions + middle.self_instructions + leaf.self_instructions);
+        assert!(top.inclusive_time >= top.self_time && top.inclusive_time >= middle.inclusive_time);
+
+        assert_eq!(profile.edges(), [("middle", "leaf", 6), ("top", "middle", 3)]);
+        let folded = profile.folded_stacks();
+        let lines: Vec<&str> = folded.lines().map(|line| line.rsplit_once(' ').unwrap().0).collect();
+        assert_eq!(lines, ["top", "top;middle", "top;middle;leaf"]);
+        assert!(folded.ends_with(&format!("top;middle;leaf {}\n", leaf.self_instructions)), "{}", folded);
+    }
+
+    #[test]
+    fn nothing_is_profiled_unless_enabled() {
+        let mut vm = test_support::vm(HIERARCHY);
+        vm.run_script("top", &[2]).unwrap();
+        assert!(vm.call_profile().is_none());
+    }
+}
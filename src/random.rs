@@ -0,0 +1,113 @@
+//! The `random` command and the generator behind it: the same 48-bit
+//! linear congruential generator as the engine's `java.util.Random`, so a
+//! seed gives the same sequence here as there.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::types::Type;
+
+const MULTIPLIER: u64 = 0x5_DEEC_E66D;
+const ADDEND: u64 = 0xB;
+const MASK: u64 = (1 << 48) - 1;
+
+/// Parameter and return types of `random`.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "random" => Some((&[Type::Int], Type::Int)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Default for Rng {
+    /// Seeded from the clock, for runs that don't ask to be reproducible.
+    fn default() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::new(nanos)
+    }
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: (seed ^ MULTIPLIER) & MASK }
+    }
+
+    fn next(&mut self, bits: u32) -> i32 {
+        self.state = self.state.wrapping_mul(MULTIPLIER).wrapping_add(ADDEND) & MASK;
+        (self.state >> (48 - bits)) as i32
+    }
+
+    /// A uniform value in `0..bound`, for a positive `bound`, drawn as
+    /// `Random.nextInt` draws it.
+    fn next_int(&mut self, bound: i32) -> i32 {
+        if bound & -bound == bound {
+            return ((bound as i64 * self.next(31) as i64) >> 31) as i32;
+        }
+        loop {
+            let bits = self.next(31);
+            let value = bits % bound;
+            // Rejects the last, partial run of `bound` values so every value
+            // is equally likely.
+            if bits.wrapping_sub(value).wrapping_add(bound - 1) >= 0 {
+                return value;
+            }
+        }
+    }
+
+    /// Runs `random(bound)`: a value in `0..bound`. `random(0)` is always 0
+    /// and leaves the sequence where it was; a negative bound is an error.
+    pub fn random(&mut self, bound: i32) -> Result<i32, String> {
+        match bound {
+            0 => Ok(0),
+            bound if bound < 0 => Err(format!("random: bound {} is negative", bound)),
+            bound => Ok(self.next_int(bound)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use crate::vm::VM;
+
+    const ROLL: &str = "[proc,roll](int $n)(int)\nreturn(random($n));\n";
+
+    /// Ten rolls of `random(10)` on a VM seeded with `seed`.
+    fn rolls(seed: u64) -> Vec<i32> {
+        let mut vm = VM::with_seed(seed);
+        for bytecode in test_support::compile(ROLL) {
+            vm.register_script(bytecode);
+        }
+        (0..10).map(|_| vm.run_script("roll", &[10]).unwrap()).collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_sequence() {
+        // java.util.Random(42).nextInt(10), ten times.
+        assert_eq!(rolls(42), [0, 3, 8, 4, 0, 5, 5, 8, 9, 3]);
+        assert_eq!(rolls(42), rolls(42));
+        assert_ne!(rolls(42), rolls(7));
+
+        let mut evaluator = test_support::evaluator(ROLL);
+        evaluator.set_seed(42);
+        let evaluated: Vec<i32> = (0..10).map(|_| evaluator.eval_script("roll", &[10]).unwrap()).collect();
+        assert_eq!(evaluated, rolls(42));
+    }
+
+    #[test]
+    fn a_zero_bound_is_zero_and_a_negative_one_fails() {
+        let mut rng = Rng::new(42);
+        assert_eq!(rng.random(0), Ok(0));
+        // random(0) doesn't advance the generator.
+        assert_eq!(rng.random(10), Ok(0));
+        assert_eq!(rng.random(10), Ok(3));
+        assert!(rng.random(-1).is_err());
+    }
+}
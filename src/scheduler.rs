@@ -0,0 +1,155 @@
+//! Game ticks. The `Scheduler` runs queued scripts once per tick, the way
+//! the engine runs them once per 600ms cycle, and is what fires timers:
+//! `settimer($timer, $ticks, ~callback)` queues `~callback` once `$ticks`
+//! ticks have passed, and `cleartimer($timer)` cancels it. Timers are named
+//! by `.timer` configs; the VM keeps the table of pending ones.
+
+use std::collections::{BTreeMap, VecDeque};
+use crate::types::Type;
+use crate::vm::VM;
+
+/// Parameter and return types of the timer commands. `settimer` also takes
+/// a `~script` after these, which the parser keeps as the script's name.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "settimer" => Some((&[Type::Timer, Type::Int], Type::Int)),
+        "cleartimer" => Some((&[Type::Timer], Type::Int)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PendingTimer {
+    ticks_left: i32,
+    script: String,
+}
+
+/// The timers that have been set and haven't fired or been cleared, by
+/// timer id.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timers {
+    pending: BTreeMap<i32, PendingTimer>,
+}
+
+impl Timers {
+    /// Runs `settimer`: `script` runs on the `ticks`th tick from now. Setting
+    /// a timer that is already pending replaces it.
+    pub fn set(&mut self, timer: i32, ticks: i32, script: &str) -> Result<(), String> {
+        if ticks < 1 {
+            return Err(format!("settimer: {} ticks is not a positive delay", ticks));
+        }
+        self.pending.insert(timer, PendingTimer { ticks_left: ticks, script: script.to_string() });
+        Ok(())
+    }
+
+    /// Runs `cleartimer`; clearing a timer that isn't pending does nothing.
+    pub fn clear(&mut self, timer: i32) {
+        self.pending.remove(&timer);
+    }
+
+    pub fn is_pending(&self, timer: i32) -> bool {
+        self.pending.contains_key(&timer)
+    }
+
+    /// Counts every pending timer down by one tick, and removes and returns
+    /// the scripts of those that are due, in timer id order.
+    pub fn expire(&mut self) -> Vec<String> {
+        for timer in self.pending.values_mut() {
+            timer.ticks_left -= 1;
+        }
+        let due: Vec<i32> = self
+            .pending
+            .iter()
+            .filter(|(_, timer)| timer.ticks_left <= 0)
+            .map(|(id, _)| *id)
+            .collect();
+        due.into_iter().filter_map(|id| self.pending.remove(&id)).map(|timer| timer.script).collect()
+    }
+}
+
+/// Queues scripts and runs them on the VM a tick at a time.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    ticks: u64,
+    queue: VecDeque<String>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ticks run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Queues `script` to run, without arguments, on the next tick.
+    pub fn enqueue(&mut self, script: &str) {
+        self.queue.push_back(script.to_string());
+    }
+
+    /// Runs one tick: counts down the VM's timers, queues the scripts of
+    /// those that fire, then runs everything queued in order. Timers set by
+    /// these scripts start counting on the next tick. Returns each script
+    /// run with its result.
+    pub fn tick(&mut self, vm: &mut VM) -> Vec<(String, Result<i32, String>)> {
+        self.ticks += 1;
+        self.queue.extend(vm.timers_mut().expire());
+        let mut ran = Vec::new();
+        while let Some(script) = self.queue.pop_front() {
+            let result = vm.run_script(&script, &[]);
+            ran.push((script, result));
+        }
+        ran
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,poison]()(int)\nsettimer(poisoned, 3, ~hurt);\nreturn(0);\n\
+        [proc,cure]()(int)\ncleartimer(poisoned);\nreturn(0);\n\
+        [proc,hurt]()(int)\nreturn(5);\n";
+
+    fn vm() -> VM {
+        let symbols = test_support::symbols(&[("effects.timer", "[poisoned]\n")]);
+        test_support::backends_with(&symbols, SCRIPTS).0
+    }
+
+    #[test]
+    fn a_timer_fires_its_callback_once_on_its_tick() {
+        let mut vm = vm();
+        let mut scheduler = Scheduler::new();
+        vm.run_script("poison", &[]).unwrap();
+        assert!(scheduler.tick(&mut vm).is_empty());
+        assert!(scheduler.tick(&mut vm).is_empty());
+        assert_eq!(scheduler.tick(&mut vm), [("hurt".to_string(), Ok(5))]);
+        for _ in 0..3 {
+            assert!(scheduler.tick(&mut vm).is_empty());
+        }
+        assert_eq!(scheduler.ticks(), 6);
+    }
+
+    #[test]
+    fn clearing_a_timer_before_it_expires_stops_it() {
+        let mut vm = vm();
+        let mut scheduler = Scheduler::new();
+        vm.run_script("poison", &[]).unwrap();
+        scheduler.tick(&mut vm);
+        scheduler.tick(&mut vm);
+        vm.run_script("cure", &[]).unwrap();
+        for _ in 0..5 {
+            assert!(scheduler.tick(&mut vm).is_empty());
+        }
+    }
+
+    #[test]
+    fn a_zero_delay_is_an_error() {
+        let mut timers = Timers::default();
+        assert_eq!(timers.set(0, 0, "hurt"), Err("settimer: 0 ticks is not a positive delay".to_string()));
+        assert!(!timers.is_pending(0));
+    }
+}
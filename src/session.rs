@@ -0,0 +1,468 @@
+//! The compile pipeline shared by the CLI and tools built on the library.
+//! `CompileSession` finds, parses and compiles every script, reporting each
+//! step to its subscribers as an `Event`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use crate::bytecode::ByteCode;
+use crate::compiler::{Compiler, DEFAULT_OPT_LEVEL};
+use crate::config::Config;
+use crate::error::CompilerError;
+use crate::includes;
+use crate::lexer::Lexer;
+use crate::packs;
+use crate::parser::{AstKind, Parser, Script, DEFAULT_MAX_ERRORS};
+
+/// A step of a `CompileSession`, in the order they happen: every file is
+/// discovered, then parsed, then each of their triggers compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    FileDiscovered { path: PathBuf },
+    Parsed { file: PathBuf, duration: Duration },
+    Compiled { script: String, instructions: usize },
+    /// A compiler warning about `script`, with the script named in `message`
+    /// as well.
+    DiagnosticEmitted { script: String, message: String },
+    Finished { summary: Summary },
+}
+
+/// What a finished `CompileSession` did.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Summary {
+    pub files: usize,
+    pub scripts: usize,
+    pub instructions: usize,
+    pub diagnostics: usize,
+    pub duration: Duration,
+}
+
+/// A compiled trigger, with the file it came from and its syntax tree.
+#[derive(Debug, Clone)]
+pub struct CompiledScript {
+    pub path: PathBuf,
+    pub node: AstKind,
+    pub bytecode: ByteCode,
+}
+
+/// A closure `CompileSession::subscribe` takes.
+pub type Subscriber<'a> = Box<dyn FnMut(&Event) + 'a>;
+
+/// Compiles every script a `Config` names with one `Compiler`, reporting
+/// progress to the closures passed to `subscribe`.
+pub struct CompileSession<'a> {
+    config: &'a Config,
+    compiler: Compiler,
+    verbose: bool,
+    max_errors: usize,
+    subscribers: Vec<Subscriber<'a>>,
+}
+
+impl<'a> CompileSession<'a> {
+    /// A session compiling with `compiler`, already given its symbols and
+    /// settings.
+    pub fn new(config: &'a Config, compiler: Compiler) -> Self {
+        CompileSession {
+            config,
+            compiler,
+            verbose: false,
+            max_errors: DEFAULT_MAX_ERRORS,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Reports each ignored file and directory while discovering files.
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+
+    /// The number of syntax errors collected per file before giving up.
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors;
+    }
+
+    /// Calls `subscriber` with every event from then on.
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&Event) + 'a) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    pub fn compiler(&self) -> &Compiler {
+        &self.compiler
+    }
+
+    /// Finds, parses and compiles every script, returning each trigger in
+    /// file order. Stops at the first error, without a `Finished` event.
+    pub fn run(&mut self) -> Result<Vec<CompiledScript>, CompilerError> {
+        let start = Instant::now();
+        let paths = get_rs2_files(self.config, self.verbose)?;
+        for path in &paths {
+            self.emit(Event::FileDiscovered { path: path.clone() });
+        }
+
+        let mut declared = HashMap::new();
+        let mut parsed = Vec::new();
+        for path in &paths {
+            let parse_start = Instant::now();
+            let script = process_rs2_file(path, self.max_errors)?;
+            check_duplicates(&mut declared, path, &script, self.config)?;
+            self.emit(Event::Parsed { file: path.clone(), duration: parse_start.elapsed() });
+            parsed.push(script);
+        }
+        // Every trigger is known before any is compiled, so calls into
+        // scripts from later files can be folded.
+        for node in parsed.iter().flat_map(|script| &script.body) {
+            self.compiler.add_source(node);
+        }
+
+        let mut summary = Summary { files: paths.len(), ..Summary::default() };
+        let mut compiled = Vec::new();
+        for (path, script) in paths.iter().zip(parsed) {
+            for node in script.body {
+                let Some(name) = node.script_name() else {
+                    continue;
+                };
+                let warned = self.compiler.warnings().len();
+                self.compiler.set_opt_level(opt_level(self.config, &name));
+                let bytecode = self.compiler.compile_script(name.clone(), &node)?;
+                let warnings = self.compiler.warnings()[warned..].to_vec();
+                for message in warnings {
+                    summary.diagnostics += 1;
+                    self.emit(Event::DiagnosticEmitted { script: name.clone(), message });
+                }
+                summary.scripts += 1;
+                summary.instructions += bytecode.instructions.len();
+                self.emit(Event::Compiled { script: name, instructions: bytecode.instructions.len() });
+                compiled.push(CompiledScript { path: path.clone(), node, bytecode });
+            }
+        }
+
+        summary.duration = start.elapsed();
+        self.emit(Event::Finished { summary });
+        Ok(compiled)
+    }
+
+    fn emit(&mut self, event: Event) {
+        for subscriber in &mut self.subscribers {
+            subscriber(&event);
+        }
+    }
+}
+
+/// The script files to compile: `--file` and what it includes, or every
+/// `.rs2` file under the configured script roots and what those include.
+pub fn get_rs2_files(config: &Config, verbose: bool) -> Result<Vec<PathBuf>, CompilerError> {
+    if let Some(file) = &config.file {
+        if !packs::is_script(file) {
+            return Err(CompilerError::FileNotFound(format!("Script file not found: {}", file.display())));
+        }
+        return includes::expand(std::slice::from_ref(file)).map_err(CompilerError::Include);
+    }
+
+    let existing_roots: Vec<&PathBuf> = config.scripts_dirs.iter().filter(|dir| dir.exists()).collect();
+
+    if existing_roots.is_empty() {
+        return Err(CompilerError::FileNotFound(format!(
+            "Scripts directory not found. Searched: {}\n\nTo fix this:\n1. Create the directory\n2. Add your .rs2 files there\n3. Or set RSC_SCRIPTS_DIR or RSC_SCRIPTS_PATH in your RC file (rsc config edit)",
+            config.scripts_dirs_display()
+        )));
+    }
+
+    let mut found_scripts: Vec<PathBuf> = Vec::new();
+    let mut seen = HashSet::new();
+
+    for scripts_path in &config.scripts_dirs {
+        if !scripts_path.exists() {
+            println!("Warning: Scripts directory not found: {}", scripts_path.display());
+            continue;
+        }
+
+        if packs::is_pack(scripts_path) {
+            let scripts = packs::scripts(scripts_path).map_err(|e| {
+                CompilerError::FileNotFound(format!("Cannot read script pack {}: {}", scripts_path.display(), e))
+            })?;
+            found_scripts.extend(scripts.into_iter().filter(|path| seen.insert(path.clone())));
+            continue;
+        }
+
+        if !scripts_path.is_dir() {
+            return Err(CompilerError::FileNotFound(format!(
+                "Expected {} to be a directory or a .zip script pack",
+                scripts_path.display()
+            )));
+        }
+
+        let ignore = build_ignore_matcher(scripts_path, &config.ignore)?;
+        collect_rs2_files(scripts_path, &ignore, verbose, &mut seen, &mut found_scripts)?;
+    }
+
+    if found_scripts.is_empty() {
+        return Err(CompilerError::FileNotFound(format!(
+            "No .rs2 files found in: {}\n\nTo fix this:\n1. Add your RuneScript (.rs2) files to one of these directories\n2. Or set RSC_SCRIPTS_DIR or RSC_SCRIPTS_PATH in your RC file (rsc config edit)\n3. Example script path: {}/example.rs2",
+            config.scripts_dirs_display(),
+            existing_roots[0].display()
+        )));
+    }
+
+    includes::expand(&found_scripts).map_err(CompilerError::Include)
+}
+
+/// Combines the root's `.rscignore` file (if any) with the configured ignore
+/// patterns. Both use gitignore syntax relative to the script root.
+fn build_ignore_matcher(root: &Path, patterns: &[String]) -> Result<Gitignore, CompilerError> {
+    let mut builder = GitignoreBuilder::new(root);
+
+    for pattern in patterns {
+        builder.add_line(None, pattern).map_err(|e| {
+            CompilerError::FileNotFound(format!("Invalid ignore pattern '{}': {}", pattern, e))
+        })?;
+    }
+
+    let ignore_file = root.join(".rscignore");
+    if ignore_file.is_file() {
+        if let Some(e) = builder.add(&ignore_file) {
+            return Err(CompilerError::FileNotFound(format!(
+                "Invalid ignore file {}: {}",
+                ignore_file.display(), e
+            )));
+        }
+    }
+
+    builder.build().map_err(|e| {
+        CompilerError::FileNotFound(format!("Invalid ignore patterns for {}: {}", root.display(), e))
+    })
+}
+
+/// Recursively collects `.rs2` files under `dir`, skipping ignored paths and
+/// any file already reached through another root or a symlinked directory.
+/// Entries are visited in name order, whatever order the platform lists
+/// them in, so every machine registers scripts in the same order.
+fn collect_rs2_files(
+    dir: &Path,
+    ignore: &Gitignore,
+    verbose: bool,
+    seen: &mut HashSet<PathBuf>,
+    found_scripts: &mut Vec<PathBuf>,
+) -> Result<(), CompilerError> {
+    let files = fs::read_dir(dir).map_err(|e| {
+        CompilerError::FileNotFound(format!(
+            "Cannot access scripts directory: {}\nError: {}",
+            dir.display(), e
+        ))
+    })?;
+    let mut paths: Vec<PathBuf> = files.flatten().map(|entry| entry.path()).collect();
+    paths.sort();
+
+    for path in paths {
+        let is_dir = path.is_dir();
+
+        // An ignored directory skips its whole subtree, as in git, so a
+        // negated pattern can only re-include files whose parent is not ignored.
+        if ignore.matched(&path, is_dir).is_ignore() {
+            if verbose && (is_dir || path.extension().and_then(|ext| ext.to_str()) == Some("rs2")) {
+                println!("Skipping ignored {}: {}", if is_dir { "directory" } else { "file" }, path.display());
+            }
+            continue;
+        }
+
+        if is_dir {
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if seen.insert(canonical) {
+                collect_rs2_files(&path, ignore, verbose, seen, found_scripts)?;
+            }
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some("rs2") {
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if seen.insert(canonical) {
+                found_scripts.push(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lexes and parses one file, collecting up to `max_errors` syntax errors.
+pub fn process_rs2_file(path_buf: &PathBuf, max_errors: usize) -> Result<Script, CompilerError> {
+    let source_code = packs::read_to_string(path_buf)?;
+    let tokens = Lexer::new(&source_code, path_buf).tokenize()?;
+
+    let mut parser = Parser::new(tokens, path_buf);
+    parser.set_max_errors(max_errors);
+    Ok(parser.parse_recovering()?)
+}
+
+/// Parses every file, checking that no two triggers share a name, since the
+/// one registered later would replace the other. With `--allow-duplicates`
+/// the later one wins, with a warning.
+pub fn parse_scripts(paths: &[PathBuf], config: &Config, max_errors: usize) -> Result<Vec<Script>, CompilerError> {
+    let mut declared = HashMap::new();
+    let mut parsed = Vec::new();
+    for path in paths {
+        let script = process_rs2_file(path, max_errors)?;
+        check_duplicates(&mut declared, path, &script, config)?;
+        parsed.push(script);
+    }
+    Ok(parsed)
+}
+
+/// Records the triggers `script` declares in `declared`, failing on one an
+/// earlier file declared unless duplicates are allowed.
+fn check_duplicates<'p>(
+    declared: &mut HashMap<String, &'p PathBuf>,
+    path: &'p PathBuf,
+    script: &Script,
+    config: &Config,
+) -> Result<(), CompilerError> {
+    for name in script.body.iter().filter_map(AstKind::script_name) {
+        if let Some(first) = declared.insert(name.clone(), path) {
+            if !config.allow_duplicates {
+                return Err(CompilerError::DuplicateScript { name, first: first.clone(), second: path.clone() });
+            }
+            println!("Warning: '{}' in {} replaces the one in {}", name, path.display(), first.display());
+        }
+    }
+    Ok(())
+}
+
+/// Parses every file before anything is compiled and registers each trigger
+/// with the compiler, so calls into scripts from later files can be folded.
+pub fn parse_rs2_files(paths: &[PathBuf], compiler: &mut Compiler, config: &Config, max_errors: usize) -> Result<Vec<Script>, CompilerError> {
+    let parsed = parse_scripts(paths, config, max_errors)?;
+    for node in parsed.iter().flat_map(|script| &script.body) {
+        compiler.add_source(node);
+    }
+    Ok(parsed)
+}
+
+/// The optimisation level `script_name` compiles at: its override in
+/// `rsc.toml`, or the default.
+pub fn opt_level(config: &Config, script_name: &str) -> u8 {
+    config
+        .script_overrides(script_name)
+        .and_then(|overrides| overrides.opt_level)
+        .unwrap_or(DEFAULT_OPT_LEVEL)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) -> PathBuf {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn config(scripts_dirs: Vec<PathBuf>) -> Config {
+        Config { scripts_dirs, ..Config::default() }
+    }
+
+    #[test]
+    fn finds_scripts_in_nested_directories_of_every_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let skills = dir.path().join("skills");
+        let quests = dir.path().join("quests");
+        let fishing = write(&skills, "fishing.rs2", "[proc,fish]()(int)\nreturn(1);\n");
+        let cooking = write(&skills, "gathering/food/cooking.rs2", "[proc,cook]()(int)\nreturn(2);\n");
+        let cook_quest = write(&quests, "cook.rs2", "[proc,cook_quest]()(int)\nreturn(3);\n");
+        write(&skills, "notes.txt", "not a script");
+
+        let found = get_rs2_files(&config(vec![skills, quests]), false).unwrap();
+        assert_eq!(found, vec![fishing, cooking, cook_quest]);
+    }
+
+    #[test]
+    fn a_file_reached_through_two_roots_is_found_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("scripts");
+        let top = write(&scripts, "top.rs2", "[proc,top]()(int)\nreturn(1);\n");
+        let inner = write(&scripts, "inner/inner.rs2", "[proc,inner]()(int)\nreturn(2);\n");
+
+        let roots = vec![scripts.clone(), scripts.join("inner"), scripts.clone()];
+        let found = get_rs2_files(&config(roots), false).unwrap();
+        assert_eq!(found, vec![inner, top]);
+    }
+
+    #[test]
+    fn ignore_patterns_exclude_a_subtree_and_negations_re_include_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("scripts");
+        let main = write(&scripts, "main.rs2", "[proc,main]()(int)\nreturn(1);\n");
+        write(&scripts, "broken/half_done.rs2", "[proc,half_done](\n");
+        write(&scripts, "wip/draft.rs2", "[proc,draft]()(int)\nreturn(2);\n");
+        write(&scripts, "wip/old/older.rs2", "[proc,older]()(int)\nreturn(3);\n");
+        let keep = write(&scripts, "wip/keep.rs2", "[proc,keep]()(int)\nreturn(4);\n");
+        write(&scripts, ".rscignore", "wip/*\n!wip/keep.rs2\n");
+
+        let mut config = config(vec![scripts]);
+        config.ignore = vec!["broken/".to_string()];
+        let found = get_rs2_files(&config, false).unwrap();
+        assert_eq!(found, vec![main, keep]);
+    }
+
+    #[test]
+    fn a_proc_declared_in_two_files_is_a_collision_naming_both() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("scripts");
+        let first = write(&scripts, "a.rs2", "[proc,foo]()(int)\nreturn(1);\n");
+        let second = write(&scripts, "b.rs2", "[proc,bar]()(int)\nreturn(2);\n[proc,foo]()(int)\nreturn(3);\n");
+        let paths = vec![first.clone(), second.clone()];
+
+        let error = parse_scripts(&paths, &config(vec![scripts.clone()]), 10).unwrap_err();
+        assert!(
+            matches!(&error, CompilerError::DuplicateScript { name, first: a, second: b } if name == "foo" && *a == first && *b == second),
+            "{:?}",
+            error
+        );
+
+        let mut allowing = config(vec![scripts]);
+        allowing.allow_duplicates = true;
+        assert_eq!(parse_scripts(&paths, &allowing, 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn a_session_reports_each_step_for_a_fixture_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts = dir.path().join("scripts");
+        let helpers = write(&scripts, "a.rs2", "// @deprecated use triple\n[proc,double](int $n)(int)\nreturn(calc($n * 2));\n");
+        let main = write(&scripts, "b.rs2", "[proc,main](int $n)(int)\nreturn(~double($n));\n[proc,one]()(int)\nreturn(1);\n");
+        let config = config(vec![scripts]);
+
+        let mut events = Vec::new();
+        let mut session = CompileSession::new(&config, Compiler::new());
+        session.subscribe(|event| events.push(event.clone()));
+        let compiled = session.run().unwrap();
+        drop(session);
+        let sizes: Vec<usize> = compiled.iter().map(|script| script.bytecode.instructions.len()).collect();
+
+        // Durations vary from run to run, so only their presence is checked.
+        let events: Vec<Event> = events
+            .into_iter()
+            .map(|event| match event {
+                Event::Parsed { file, .. } => Event::Parsed { file, duration: Duration::ZERO },
+                Event::Finished { summary } => Event::Finished { summary: Summary { duration: Duration::ZERO, ..summary } },
+                event => event,
+            })
+            .collect();
+        let warning = "call to deprecated script 'double' at line 2: use triple (in script 'main')".to_string();
+        assert_eq!(
+            events,
+            vec![
+                Event::FileDiscovered { path: helpers.clone() },
+                Event::FileDiscovered { path: main.clone() },
+                Event::Parsed { file: helpers, duration: Duration::ZERO },
+                Event::Parsed { file: main, duration: Duration::ZERO },
+                Event::Compiled { script: "double".to_string(), instructions: sizes[0] },
+                Event::DiagnosticEmitted { script: "main".to_string(), message: warning },
+                Event::Compiled { script: "main".to_string(), instructions: sizes[1] },
+                Event::Compiled { script: "one".to_string(), instructions: sizes[2] },
+                Event::Finished {
+                    summary: Summary { files: 2, scripts: 3, instructions: sizes.iter().sum(), diagnostics: 1, duration: Duration::ZERO },
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! The player's stats, named in scripts and numbered in the engine's order,
+//! and the stat commands the default host implements with `PlayerStats`.
+
+use crate::host::HostValue;
+use crate::types::Type;
+
+/// Every stat, indexed by id.
+pub const NAMES: &[&str] = &[
+    "attack",
+    "defence",
+    "strength",
+    "hitpoints",
+    "ranged",
+    "prayer",
+    "magic",
+    "cooking",
+    "woodcutting",
+    "fletching",
+    "fishing",
+    "firemaking",
+    "crafting",
+    "smithing",
+    "mining",
+    "herblore",
+    "agility",
+    "thieving",
+    "stat18",
+    "stat19",
+    "runecraft",
+];
+
+pub const MAX_LEVEL: i32 = 99;
+pub const MAX_XP: i32 = 200_000_000;
+
+const HITPOINTS: usize = 3;
+const HITPOINTS_LEVEL: i32 = 10;
+
+/// Parameter and return types of the stat commands. Unlike the coord and
+/// conversion commands these go to the host, which owns the player data.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "stat" | "stat_base" => Some((&[Type::Stat], Type::Int)),
+        "stat_advance" => Some((&[Type::Stat, Type::Int], Type::Int)),
+        _ => None,
+    }
+}
+
+/// The least xp a stat needs to reach `level`.
+pub fn xp_for_level(level: i32) -> i32 {
+    let points: f64 = (1..level)
+        .map(|level| (level as f64 + 300.0 * 2f64.powf(level as f64 / 7.0)).floor())
+        .sum();
+    (points / 4.0).floor() as i32
+}
+
+/// The level `xp` reaches, from 1 to `MAX_LEVEL`.
+pub fn level_for_xp(xp: i32) -> i32 {
+    (1..=MAX_LEVEL).rev().find(|level| xp >= xp_for_level(*level)).unwrap_or(1)
+}
+
+/// One player's xp and current level in each stat, kept in memory for runs
+/// without a game server. Stats start at level 1, and hitpoints at 10.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerStats {
+    xp: Vec<i32>,
+    levels: Vec<i32>,
+}
+
+impl Default for PlayerStats {
+    fn default() -> Self {
+        let mut stats = Self {
+            xp: vec![0; NAMES.len()],
+            levels: vec![1; NAMES.len()],
+        };
+        stats.xp[HITPOINTS] = xp_for_level(HITPOINTS_LEVEL);
+        stats.levels[HITPOINTS] = HITPOINTS_LEVEL;
+        stats
+    }
+}
+
+impl PlayerStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn xp(&self, stat: usize) -> i32 {
+        self.xp[stat]
+    }
+
+    /// The current level, which boosts and drains move away from the base.
+    pub fn level(&self, stat: usize) -> i32 {
+        self.levels[stat]
+    }
+
+    /// The level the stat's xp has reached.
+    pub fn base_level(&self, stat: usize) -> i32 {
+        level_for_xp(self.xp[stat])
+    }
+
+    /// Adds xp, capped at `MAX_XP`. Levels gained raise the current level
+    /// by as much as the base.
+    pub fn advance(&mut self, stat: usize, xp: i32) -> Result<(), String> {
+        if xp < 0 {
+            return Err(format!("xp {} is negative", xp));
+        }
+        let before = self.base_level(stat);
+        self.xp[stat] = self.xp[stat].saturating_add(xp).min(MAX_XP);
+        self.levels[stat] += self.base_level(stat) - before;
+        Ok(())
+    }
+
+    /// Runs a stat command for the default host; the parser and type
+    /// checker have already checked the argument count and types.
+    pub fn call(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        let stat = match args.first() {
+            Some(HostValue::Int(id)) if (0..NAMES.len() as i32).contains(id) => *id as usize,
+            Some(other) => return Err(format!("{} is not a stat", other)),
+            None => return Err("no stat given".to_string()),
+        };
+        match (name, &args[1..]) {
+            ("stat", []) => Ok(self.level(stat)),
+            ("stat_base", []) => Ok(self.base_level(stat)),
+            ("stat_advance", [HostValue::Int(xp)]) => self.advance(stat, *xp).map(|()| 0),
+            _ => Err(format!("{}() got {} argument(s)", name, args.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,chop](int $xp)(int)\n\
+        stat_advance(woodcutting, $xp);\n\
+        return(calc(stat(woodcutting) * 100 + stat_base(woodcutting)));\n\
+        [proc,hitpoints]()(int)\nreturn(stat_base(hitpoints));\n";
+
+    #[test]
+    fn the_xp_table_matches_the_engine() {
+        assert_eq!(xp_for_level(1), 0);
+        assert_eq!(xp_for_level(2), 83);
+        assert_eq!(xp_for_level(MAX_LEVEL), 13_034_431);
+        assert_eq!(level_for_xp(82), 1);
+        assert_eq!(level_for_xp(83), 2);
+        assert_eq!(level_for_xp(MAX_XP), MAX_LEVEL);
+    }
+
+    #[test]
+    fn advancing_a_stat_reads_back_from_a_script_on_both_backends() {
+        let mut vm = test_support::vm(SCRIPTS);
+        let mut evaluator = test_support::evaluator(SCRIPTS);
+        // 1000 xp is level 9, which the current level follows.
+        assert_eq!(vm.run_script("chop", &[1000]), Ok(909));
+        assert_eq!(evaluator.eval_script("chop", &[1000]), Ok(909));
+        // Each run's xp adds to the last.
+        assert_eq!(vm.run_script("chop", &[200]), Ok(1010));
+        assert_eq!(evaluator.eval_script("chop", &[200]), Ok(1010));
+        assert_eq!(vm.run_script("hitpoints", &[]), Ok(HITPOINTS_LEVEL));
+    }
+
+    #[test]
+    fn stat_arguments_must_name_a_stat() {
+        for source in ["[proc,bad]()(int)\nreturn(stat(woodcuting));\n", "[proc,bad]()(int)\nreturn(stat(\"attack\"));\n"] {
+            assert!(test_support::try_compile_with(&mut Compiler::new(), source).is_err(), "{}", source);
+        }
+    }
+}
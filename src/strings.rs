@@ -0,0 +1,89 @@
+//! The string commands. Lengths and indices count chars rather than bytes,
+//! so non-ASCII text measures and slices the way it reads. An index outside
+//! the string is an error rather than clamped, as with array indices.
+
+use crate::types::Type;
+
+/// Parameter and return types of the string commands. `tostring`, `toint`
+/// and `compare` live with the conversions.
+pub fn signature(name: &str) -> Option<(&'static [Type], Type)> {
+    match name {
+        "string_length" => Some((&[Type::String], Type::Int)),
+        "substring" => Some((&[Type::String, Type::Int, Type::Int], Type::String)),
+        "string_indexof" => Some((&[Type::String, Type::String], Type::Int)),
+        "append" => Some((&[Type::String, Type::String], Type::String)),
+        "lowercase" | "uppercase" => Some((&[Type::String], Type::String)),
+        _ => None,
+    }
+}
+
+pub fn length(text: &str) -> i32 {
+    text.chars().count() as i32
+}
+
+/// The chars from `start` up to but not including `end`.
+pub fn substring(text: &str, start: i32, end: i32) -> Result<String, String> {
+    let length = length(text);
+    if start < 0 || end < start || end > length {
+        return Err(format!(
+            "substring: {}..{} is outside \"{}\" (0..{})",
+            start, end, text, length
+        ));
+    }
+    Ok(text.chars().skip(start as usize).take((end - start) as usize).collect())
+}
+
+/// The char index where `find` first occurs in `text`, or -1 if it doesn't.
+pub fn index_of(text: &str, find: &str) -> i32 {
+    text.find(find).map_or(-1, |byte| length(&text[..byte]))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::host::{HostValue, RecordingHost};
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[proc,show](string $text)(int)\n\
+        mes(tostring(string_length($text)));\n\
+        mes(substring($text, 3, 7));\n\
+        mes(tostring(string_indexof($text, \"Café\")));\n\
+        mes(tostring(string_indexof($text, \"tea\")));\n\
+        mes(append($text, \"!\"));\n\
+        mes(lowercase($text));\n\
+        mes(uppercase($text));\n\
+        return(0);\n\
+        [proc,slice](string $text, int $start, int $end)(int)\nmes(substring($text, $start, $end));\nreturn(0);\n";
+
+    #[test]
+    fn each_command_counts_chars_of_non_ascii_text_on_both_backends() {
+        let host = Rc::new(RefCell::new(RecordingHost::default()));
+        let mut vm = test_support::vm(SCRIPTS);
+        vm.set_host(host.clone());
+        let mut evaluator = test_support::evaluator(SCRIPTS);
+        evaluator.set_host(host.clone());
+        let args = [HostValue::Str("Fjölnir Café".to_string())];
+
+        vm.run_script_with_args("show", &args).unwrap();
+        evaluator.eval_script_with_args("show", &args).unwrap();
+        let shown: Vec<_> = ["12", "lnir", "8", "-1", "Fjölnir Café!", "fjölnir café", "FJÖLNIR CAFÉ"]
+            .iter()
+            .map(|text| ("mes".to_string(), vec![HostValue::Str(text.to_string())]))
+            .collect();
+        assert_eq!(host.borrow().calls, [shown.clone(), shown].concat());
+    }
+
+    #[test]
+    fn an_out_of_range_substring_fails_on_both_backends() {
+        let message = "substring: 10..13 is outside \"Fjölnir Café\" (0..12)";
+        let args = [HostValue::Str("Fjölnir Café".to_string()), HostValue::Int(10), HostValue::Int(13)];
+        let error = test_support::vm(SCRIPTS).run_script_with_args("slice", &args).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = test_support::evaluator(SCRIPTS).eval_script_with_args("slice", &args).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+
+        let reversed = [HostValue::Str("abc".to_string()), HostValue::Int(2), HostValue::Int(1)];
+        assert!(test_support::vm(SCRIPTS).run_script_with_args("slice", &reversed).is_err());
+    }
+}
@@ -0,0 +1,811 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::appearance::IdkConfig;
+use crate::compiler;
+use crate::db::{self, DbColumn, DbTable};
+use crate::enums::{self, EnumConfig};
+use crate::evaluator::edit_distance;
+use crate::floors::FloConfig;
+use crate::host::{self, HostValue};
+use crate::interfaces::ComponentConfig;
+use crate::inv::{self, InvConfig};
+use crate::params::{ParamConfig, Params};
+use crate::triggers::Subject;
+use crate::parser::AstKind;
+use crate::stats;
+use crate::types::{Type, NULL};
+use crate::varbits::{self, Varbit};
+
+/// The config file extension whose entries name values of `ty`. Types with
+/// one are handles: plain ids at runtime, written by name in scripts.
+fn config_kind(ty: &Type) -> Option<&'static str> {
+    match ty {
+        Type::Npc => Some("npc"),
+        Type::Obj | Type::NamedObj => Some("obj"),
+        Type::Stat => Some("stat"),
+        Type::Inv => Some("inv"),
+        Type::MesAnim => Some("mesanim"),
+        Type::Animation => Some("seq"),
+        Type::Spotanim => Some("spotanim"),
+        Type::IdKit => Some("idk"),
+        Type::Flo => Some("flo"),
+        Type::Interface => Some("interface"),
+        Type::Component => Some("component"),
+        Type::Timer => Some("timer"),
+        Type::Category => Some("category"),
+        Type::Struct => Some("struct"),
+        Type::DbTable => Some("dbtable"),
+        Type::DbColumn => Some("dbcolumn"),
+        _ => None,
+    }
+}
+
+/// Whether values of `ty` are config entries that scripts refer to by name.
+pub fn is_handle(ty: &Type) -> bool {
+    config_kind(ty).is_some()
+}
+
+/// Maps the names of config entries (npcs, objs, varps) and stats to their
+/// ids, and holds the enums scripts read with `enum_getvalue`, the varbits
+/// they read and write as `%name`, the params they read off configs and the
+/// db tables they query.
+#[derive(Debug, Clone)]
+pub struct SymbolTable {
+    ids: HashMap<&'static str, HashMap<String, i32>>,
+    /// The `type=` of each varp that declares one.
+    varp_types: HashMap<i32, Type>,
+    /// Objs declared `stackable=yes`.
+    stackable_objs: HashSet<i32>,
+    /// Indexed by inv id.
+    invs: Vec<InvConfig>,
+    /// Indexed by mesanim id.
+    mesanims: Vec<String>,
+    /// Indexed by component id.
+    components: Vec<ComponentConfig>,
+    /// Indexed by idk id.
+    idkits: Vec<IdkConfig>,
+    /// Indexed by flo id.
+    floors: Vec<FloConfig>,
+    /// The category of each npc and obj that has one, keyed by kind and id.
+    categories: HashMap<(&'static str, i32), String>,
+    enums: HashMap<String, EnumConfig>,
+    varbits: HashMap<String, Varbit>,
+    params: Params,
+    /// Indexed by dbtable id.
+    db_tables: Vec<DbTable>,
+}
+
+/// Holds the stats, which are built in rather than read from configs.
+impl Default for SymbolTable {
+    fn default() -> Self {
+        let mut table = Self {
+            ids: HashMap::new(),
+            varp_types: HashMap::new(),
+            stackable_objs: HashSet::new(),
+            invs: Vec::new(),
+            mesanims: Vec::new(),
+            components: Vec::new(),
+            idkits: Vec::new(),
+            floors: Vec::new(),
+            categories: HashMap::new(),
+            enums: HashMap::new(),
+            varbits: HashMap::new(),
+            params: Params::default(),
+            db_tables: Vec::new(),
+        };
+        for name in stats::NAMES {
+            table.declare("stat", name);
+        }
+        table
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads every `.npc`, `.obj`, `.struct`, `.param`, `.varp`, `.inv`,
+    /// `.mesanim`, `.seq`, `.spotanim`, `.if`, `.idk`, `.flo`, `.timer`,
+    /// `.enum` and `.varbit` file under `dirs`, and every `.csv` or `.tsv` file as a db
+    /// table named after the file. Each `[name]` header in the files other
+    /// than enums and varbits declares an entry, and each `.if` file an
+    /// interface; ids are numbered per kind in order of
+    /// declaration, with files taken in path order. An npc's or obj's
+    /// `category=` declares that category too. Params, then enums, varbits
+    /// and tables, are read last so they can name those entries. Missing directories are skipped.
+    pub fn load(dirs: &[PathBuf]) -> io::Result<Self> {
+        Self::from_files(&read_config_files(dirs)?)
+    }
+
+    /// Builds the table from config files already read, as `load` does from
+    /// the files `read_config_files` returns. Ids follow the order of `files`.
+    pub fn from_files(files: &[(PathBuf, String)]) -> io::Result<Self> {
+        let mut table = Self::new();
+        let mut later = Vec::new();
+        let mut param_configs = Vec::new();
+        let mut param_lines = Vec::new();
+        for (path, contents) in files {
+            let kind = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("npc") => "npc",
+                Some("obj") => "obj",
+                Some("struct") => "struct",
+                Some("param") => "param",
+                Some("varp") => "varp",
+                Some("inv") => "inv",
+                Some("mesanim") => "mesanim",
+                Some("seq") => "seq",
+                Some("spotanim") => "spotanim",
+                Some("if") => "component",
+                Some("idk") => "idk",
+                Some("flo") => "flo",
+                Some("timer") => "timer",
+                _ => {
+                    later.push((path, contents));
+                    continue;
+                }
+            };
+            let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+            // An interface is named after its file, and its components
+            // after it.
+            let interface = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            if kind == "component" {
+                table.declare("interface", interface);
+            }
+            for (name, fields) in sections(contents).map_err(invalid)? {
+                let name = if kind == "component" { format!("{}:{}", interface, name) } else { name };
+                let id = table.declare(kind, &name);
+                table.read_fields(kind, id, &name, &fields).map_err(invalid)?;
+                if matches!(kind, "npc" | "obj" | "struct") {
+                    for (_, line) in fields.iter().filter(|(key, _)| key == "param") {
+                        param_lines.push((path.clone(), kind, id, name.clone(), line.clone()));
+                    }
+                }
+                if kind == "param" {
+                    param_configs.push((path.clone(), name, fields));
+                }
+            }
+        }
+
+        // Params are read once every entry is declared, since their values
+        // may name any of them.
+        let mut params = Params::default();
+        for (path, name, fields) in param_configs {
+            let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+            params.declare(ParamConfig::parse(&name, &fields, &table).map_err(invalid)?);
+        }
+        for (path, kind, id, owner, line) in param_lines {
+            let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+            params.attach(kind, id, &owner, &line, &table).map_err(invalid)?;
+        }
+        table.params = params;
+
+        for (path, contents) in later {
+            let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+            let extension = path.extension().and_then(|ext| ext.to_str());
+            if extension == Some("varbit") {
+                for varbit in varbits::parse(contents, &table).map_err(invalid)? {
+                    table.varbits.insert(varbit.name.clone(), varbit);
+                }
+            } else if let Some(separator) = extension.and_then(|ext| ext.strip_suffix("sv")) {
+                let separator = if separator == "c" { ',' } else { '\t' };
+                let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+                table.load_db_table(name, contents, separator).map_err(invalid)?;
+            } else {
+                for config in enums::parse(contents, &table).map_err(invalid)? {
+                    table.enums.insert(config.name.clone(), config);
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    /// Declares a db table and its columns. Columns of the same name share
+    /// an id across tables, so they must share a type too.
+    fn load_db_table(&mut self, name: &str, contents: &str, separator: char) -> Result<(), String> {
+        if self.lookup(&Type::DbTable, name).is_some() {
+            return Err(format!("table '{}' is declared twice", name));
+        }
+        let mut ids = self.ids.remove("dbcolumn").unwrap_or_default();
+        let parsed = db::parse(name, contents, separator, self, |column| {
+            let next = ids.len() as i32;
+            *ids.entry(column.to_string()).or_insert(next)
+        });
+        self.ids.insert("dbcolumn", ids);
+        let parsed = parsed?;
+        for column in &parsed.columns {
+            if let Some(ty) = self.db_column_type(&column.name).filter(|ty| **ty != column.ty) {
+                return Err(format!(
+                    "table '{}': column '{}' is {}, but another table declares it {}",
+                    name, column.name, column.ty, ty
+                ));
+            }
+        }
+        self.declare("dbtable", name);
+        self.db_tables.push(parsed);
+        Ok(())
+    }
+
+    pub fn varp(&self, name: &str) -> Option<i32> {
+        self.ids.get("varp")?.get(name).copied()
+    }
+
+    /// Records the fields of a newly declared entry that later stages use:
+    /// a varp's `type=` (int by default), npc and obj categories, whether an
+    /// obj is `stackable=`, an inv's size and stacking, an idkit's body part
+    /// and gender, a floor's colour and texture, and a component's text.
+    fn read_fields(&mut self, kind: &'static str, id: i32, name: &str, fields: &[(String, String)]) -> Result<(), String> {
+        let field = |key: &str| fields.iter().find(|(field, _)| field == key).map(|(_, value)| value.as_str());
+        if let ("npc" | "obj", Some(category)) = (kind, field("category")) {
+            self.declare("category", category);
+            self.categories.insert((kind, id), category.to_string());
+        }
+        match kind {
+            "varp" => {
+                if let Some(type_name) = field("type") {
+                    let ty = Type::from_name(type_name).ok_or_else(|| format!("varp '{}': unknown type '{}'", name, type_name))?;
+                    self.varp_types.insert(id, ty);
+                }
+            }
+            "obj" => {
+                if let Some(value) = field("stackable") {
+                    if inv::parse_flag(value).ok_or_else(|| format!("obj '{}': stackable '{}' is not yes or no", name, value))? {
+                        self.stackable_objs.insert(id);
+                    }
+                }
+            }
+            // A name declared twice keeps its first config, as it keeps its id.
+            "inv" if id as usize == self.invs.len() => self.invs.push(InvConfig::parse(name, fields)?),
+            "mesanim" if id as usize == self.mesanims.len() => self.mesanims.push(name.to_string()),
+            "idk" if id as usize == self.idkits.len() => self.idkits.push(IdkConfig::parse(name, fields)?),
+            "flo" if id as usize == self.floors.len() => self.floors.push(FloConfig::parse(name, fields)?),
+            "component" if id as usize == self.components.len() => {
+                let interface = name.split_once(':').and_then(|(interface, _)| self.lookup(&Type::Interface, interface)).unwrap_or(NULL);
+                self.components.push(ComponentConfig::parse(name, interface, fields));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The `category=` of the npc or obj called `name`, for dispatching
+    /// triggers; see `VM::dispatch_trigger`.
+    pub fn category_of(&self, ty: &Type, name: &str) -> Option<&str> {
+        let kind = config_kind(ty)?;
+        let id = self.lookup(ty, name)?;
+        self.categories.get(&(kind, id)).map(String::as_str)
+    }
+
+    pub fn invs(&self) -> &[InvConfig] {
+        &self.invs
+    }
+
+    /// The declared params and the values attached to objs, npcs and
+    /// structs.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// The param with the closest name, if any is close enough to be a
+    /// likely typo.
+    pub fn suggest_param(&self, name: &str) -> Option<&str> {
+        self.params
+            .names()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| candidate.as_str())
+    }
+
+    /// Every db table, indexed by id.
+    pub fn db_tables(&self) -> &[DbTable] {
+        &self.db_tables
+    }
+
+    pub fn db_table(&self, id: i32) -> Option<&DbTable> {
+        usize::try_from(id).ok().and_then(|index| self.db_tables.get(index))
+    }
+
+    /// The db column with dbcolumn id `id`, in whichever table has it.
+    pub fn db_column(&self, id: i32) -> Option<&DbColumn> {
+        self.db_tables.iter().flat_map(|table| &table.columns).find(|column| column.id == id)
+    }
+
+    /// The type of the db column called `name`, in whichever table has it.
+    pub fn db_column_type(&self, name: &str) -> Option<&Type> {
+        self.db_tables.iter().find_map(|table| table.column(name)).map(|column| &column.ty)
+    }
+
+    /// The names of every entry of the handle type `ty`, indexed by id.
+    pub fn names(&self, ty: &Type) -> Vec<String> {
+        let mut names: Vec<(i32, String)> = config_kind(ty)
+            .and_then(|kind| self.ids.get(kind))
+            .map(|ids| ids.iter().map(|(name, id)| (*id, name.clone())).collect())
+            .unwrap_or_default();
+        names.sort();
+        names.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Every floor, indexed by id.
+    pub fn floors(&self) -> &[FloConfig] {
+        &self.floors
+    }
+
+    /// Every idkit, indexed by id.
+    pub fn idkits(&self) -> &[IdkConfig] {
+        &self.idkits
+    }
+
+    /// Every component, indexed by id.
+    pub fn components(&self) -> &[ComponentConfig] {
+        &self.components
+    }
+
+    /// Every mesanim's name, indexed by id.
+    pub fn mesanims(&self) -> &[String] {
+        &self.mesanims
+    }
+
+    pub fn stackable_objs(&self) -> &HashSet<i32> {
+        &self.stackable_objs
+    }
+
+    pub fn varp_type(&self, id: i32) -> Type {
+        self.varp_types.get(&id).cloned().unwrap_or(Type::Int)
+    }
+
+    /// The value of each varp that doesn't start at 0: nullable ones start
+    /// as `null`.
+    pub fn varp_defaults(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.varp_types
+            .iter()
+            .filter(|(_, ty)| ty.is_nullable())
+            .map(|(id, _)| (*id, NULL))
+    }
+
+    pub fn varbit(&self, name: &str) -> Option<&Varbit> {
+        self.varbits.get(name)
+    }
+
+    /// The varp or varbit with the closest name to `%name`, if any is close
+    /// enough to be a likely typo.
+    pub fn suggest_game_var(&self, name: &str) -> Option<&str> {
+        let varps = self.ids.get("varp").into_iter().flat_map(|ids| ids.keys());
+        varps
+            .chain(self.varbits.keys())
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| candidate.as_str())
+    }
+
+    pub fn enum_config(&self, name: &str) -> Option<&EnumConfig> {
+        self.enums.get(name)
+    }
+
+    /// Every loaded enum, for backends that look them up at runtime.
+    pub fn enums(&self) -> impl Iterator<Item = &EnumConfig> {
+        self.enums.values()
+    }
+
+    /// The enum with the closest name, if any is close enough to be a likely
+    /// typo.
+    pub fn suggest_enum(&self, name: &str) -> Option<&str> {
+        self.enums
+            .keys()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| candidate.as_str())
+    }
+
+    /// Reads a config value of type `ty`: strings as written, handles by
+    /// name, booleans as yes/no and anything else as an int.
+    pub(crate) fn parse_value(&self, ty: &Type, text: &str) -> Option<HostValue> {
+        if *ty == Type::String {
+            return Some(HostValue::Str(text.to_string()));
+        }
+        if let Some(id) = self.lookup(ty, text) {
+            return Some(HostValue::Int(id));
+        }
+        match (ty, text) {
+            (Type::Boolean, "true" | "yes") => Some(HostValue::Int(1)),
+            (Type::Boolean, "false" | "no") => Some(HostValue::Int(0)),
+            _ => text.parse().ok().map(HostValue::Int),
+        }
+    }
+
+    /// Adds an entry with the next free id of its kind and returns its id. A
+    /// name declared twice keeps its first id.
+    fn declare(&mut self, kind: &'static str, name: &str) -> i32 {
+        let ids = self.ids.entry(kind).or_default();
+        let next = ids.len() as i32;
+        *ids.entry(name.to_string()).or_insert(next)
+    }
+
+    pub fn lookup(&self, ty: &Type, name: &str) -> Option<i32> {
+        self.ids.get(config_kind(ty)?)?.get(name).copied()
+    }
+
+    /// The entry of the same kind with the closest name, if any is close
+    /// enough to be a likely typo.
+    pub fn suggest(&self, ty: &Type, name: &str) -> Option<&str> {
+        self.ids
+            .get(config_kind(ty)?)?
+            .keys()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2)
+            .min()
+            .map(|(_, candidate)| candidate.as_str())
+    }
+
+    /// Replaces each bare name in a handle-typed position with its id, cast
+    /// to the handle type: initializers and assignments of handle locals,
+    /// defaults of handle parameters, returns from scripts returning a
+    /// handle, comparisons against a handle, and casts.
+    pub fn resolve(&self, ast: &AstKind) -> Result<AstKind, String> {
+        let mut resolver = Resolver {
+            table: self,
+            locals: HashMap::new(),
+            return_type: None,
+        };
+        resolver.statement(ast)
+    }
+}
+
+/// A `[name]` section of a config file, with its `key=value` fields in order.
+pub(crate) type Section = (String, Vec<(String, String)>);
+
+/// Splits a config file into its sections, skipping blank lines and `//`
+/// comments.
+pub(crate) fn sections(contents: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with("//")) {
+        if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            sections.push((name.to_string(), Vec::new()));
+        } else {
+            let (key, value) = line.split_once('=').ok_or_else(|| format!("expected key=value, found '{}'", line))?;
+            let (_, fields) = sections.last_mut().ok_or_else(|| format!("'{}' is outside any [name] section", line))?;
+            fields.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    Ok(sections)
+}
+
+/// Every config file `SymbolTable::load` reads under `dirs`, in path order,
+/// with its contents.
+pub fn read_config_files(dirs: &[PathBuf]) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        collect_config_files(dir, &mut files)?;
+    }
+    files.sort();
+    files
+        .into_iter()
+        .map(|path| {
+            let contents = fs::read_to_string(&path)?;
+            Ok((path, contents))
+        })
+        .collect()
+}
+
+fn collect_config_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_config_files(&path, files)?;
+        } else if matches!(path.extension().and_then(|ext| ext.to_str()), Some("npc" | "obj" | "struct" | "param" | "varp" | "inv" | "mesanim" | "seq" | "spotanim" | "if" | "idk" | "flo" | "timer" | "enum" | "varbit" | "csv" | "tsv")) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+struct Resolver<'a> {
+    table: &'a SymbolTable,
+    locals: HashMap<String, Type>,
+    return_type: Option<Type>,
+}
+
+impl Resolver<'_> {
+    fn statement(&mut self, node: &AstKind) -> Result<AstKind, String> {
+        Ok(match node {
+            AstKind::Trigger { name, kind, args, defaults, body, return_type, doc, annotations } => {
+                // A `_category` subject must name a category some config declares.
+                if let (AstKind::Identifier(_), AstKind::Identifier(subject)) = (&**kind, &**name) {
+                    if let Subject::Category(category) = Subject::parse(subject) {
+                        self.handle(&Type::Category, category)?;
+                    }
+                }
+                let mut resolved_defaults = Vec::new();
+                for (pair, default) in args.chunks(2).zip(defaults) {
+                    let param_type = match pair {
+                        [arg_type, arg_name] => match (&**arg_type, &**arg_name) {
+                            (AstKind::Identifier(type_name), AstKind::LocalVar(var_name)) => {
+                                Type::from_name(type_name).inspect(|ty| {
+                                    self.locals.insert(var_name.trim_start_matches('$').to_string(), ty.clone());
+                                })
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    resolved_defaults.push(match default {
+                        Some(default) => Some(self.expression(default, param_type.as_ref())?),
+                        None => None,
+                    });
+                }
+                self.return_type = match &**return_type {
+                    AstKind::Identifier(type_name) => Type::from_name(type_name),
+                    _ => None,
+                };
+                AstKind::Trigger {
+                    name: name.clone(),
+                    kind: kind.clone(),
+                    args: args.clone(),
+                    defaults: resolved_defaults,
+                    body: Box::new(self.statement(body)?),
+                    return_type: return_type.clone(),
+                    doc: doc.clone(),
+                    annotations: annotations.clone(),
+                }
+            }
+            AstKind::Block(statements) => {
+                AstKind::Block(statements.iter().map(|stmt| self.statement(stmt)).collect::<Result<_, _>>()?)
+            }
+            AstKind::Define { name, var_type, value } => {
+                let value = self.expression(value, Some(var_type))?;
+                self.locals.insert(name.trim_start_matches('$').to_string(), var_type.clone());
+                AstKind::Define {
+                    name: name.clone(),
+                    var_type: var_type.clone(),
+                    value: Box::new(value),
+                }
+            }
+            AstKind::Assignment { target, value } => {
+                let target = self.expression(target, None)?;
+                let target_type = self.static_type(&target);
+                AstKind::Assignment {
+                    target: Box::new(target),
+                    value: Box::new(self.expression(value, target_type.as_ref())?),
+                }
+            }
+            AstKind::Return(expr) => {
+                let return_type = self.return_type.clone();
+                AstKind::Return(Box::new(self.expression(expr, return_type.as_ref())?))
+            }
+            AstKind::If { expression, value, return_statement } => AstKind::If {
+                expression: Box::new(self.expression(expression, None)?),
+                value: Box::new(self.statement(value)?),
+                return_statement: Box::new(self.statement(return_statement)?),
+            },
+            AstKind::While { condition, body } => AstKind::While {
+                condition: Box::new(self.expression(condition, None)?),
+                body: Box::new(self.statement(body)?),
+            },
+            AstKind::For { init, condition, step, body } => AstKind::For {
+                init: Box::new(self.statement(init)?),
+                condition: Box::new(self.expression(condition, None)?),
+                step: Box::new(self.statement(step)?),
+                body: Box::new(self.statement(body)?),
+            },
+            node => self.expression(node, None)?,
+        })
+    }
+
+    fn expression(&mut self, node: &AstKind, expected: Option<&Type>) -> Result<AstKind, String> {
+        Ok(match node {
+            AstKind::Identifier(name) => match expected.filter(|ty| is_handle(ty)) {
+                Some(ty) => self.handle(ty, name)?,
+                None => node.clone(),
+            },
+            AstKind::BinaryExpression { lhs, rhs, operator } => {
+                // A name compared against a handle names an entry of the
+                // handle's type.
+                let (lhs_type, rhs_type) = (self.static_type(lhs), self.static_type(rhs));
+                let lhs = self.expression(lhs, rhs_type.as_ref())?;
+                let rhs = self.expression(rhs, lhs_type.as_ref())?;
+                AstKind::BinaryExpression {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                    operator: operator.clone(),
+                }
+            }
+            AstKind::FunctionCall { name, arguments } if name == "enum_getvalue" => {
+                // The key is a name when the enum's keys are handles.
+                let key_type = self.enum_called(node).map(|config| config.key_type.clone());
+                let mut resolved = Vec::new();
+                for (index, arg) in arguments.iter().enumerate() {
+                    let expected = if index == 1 { key_type.as_ref() } else { None };
+                    resolved.push(Box::new(self.expression(arg, expected)?));
+                }
+                AstKind::FunctionCall {
+                    name: name.clone(),
+                    arguments: resolved,
+                }
+            }
+            AstKind::FunctionCall { name, arguments } if name == "db_find" => {
+                // The value is a name when the column holds handles.
+                let value_type = self.db_column_called(node).cloned();
+                let mut resolved = Vec::new();
+                for (index, arg) in arguments.iter().enumerate() {
+                    let expected = [Type::DbTable, Type::DbColumn].get(index).or(value_type.as_ref());
+                    resolved.push(Box::new(self.expression(arg, expected)?));
+                }
+                AstKind::FunctionCall {
+                    name: name.clone(),
+                    arguments: resolved,
+                }
+            }
+            AstKind::FunctionCall { name, arguments } => {
+                // Stat, inventory, timer and db commands take their handles
+                // by name.
+                let params = compiler::builtin_signature(name)
+                    .or_else(|| host::signature(name))
+                    .map(|(params, _)| params)
+                    .unwrap_or_default();
+                let mut resolved = Vec::new();
+                for (index, arg) in arguments.iter().enumerate() {
+                    resolved.push(Box::new(self.expression(arg, params.get(index))?));
+                }
+                AstKind::FunctionCall {
+                    name: name.clone(),
+                    arguments: resolved,
+                }
+            }
+            AstKind::ConditionalExpression { lhs, rhs, value } => AstKind::ConditionalExpression {
+                lhs: Box::new(self.expression(lhs, None)?),
+                rhs: Box::new(self.expression(rhs, expected)?),
+                value: Box::new(self.expression(value, expected)?),
+            },
+            AstKind::ScriptCall { script, arguments } => AstKind::ScriptCall {
+                script: script.clone(),
+                arguments: arguments.iter().map(|arg| self.expression(arg, None).map(Box::new)).collect::<Result<_, _>>()?,
+            },
+            AstKind::Cast { target, expression } => {
+                // `flo(grass)` names a flo; `int(grass)` names whichever
+                // config entry is called that.
+                let expected = match &**expression {
+                    AstKind::Identifier(name) if !is_handle(target) => self.only_kind_naming(name)?,
+                    _ => Some(target.clone()),
+                };
+                AstKind::Cast {
+                    target: target.clone(),
+                    expression: Box::new(self.expression(expression, expected.as_ref())?),
+                }
+            }
+            AstKind::GameVar(name) => self.game_var(name)?,
+            node => node.clone(),
+        })
+    }
+
+    fn handle(&self, ty: &Type, name: &str) -> Result<AstKind, String> {
+        match self.table.lookup(ty, name) {
+            Some(id) => Ok(AstKind::Cast {
+                target: ty.clone(),
+                expression: Box::new(AstKind::NumericLiteral(id)),
+            }),
+            None => {
+                let mut message = format!("unknown {} '{}'", ty, name);
+                if let Some(suggestion) = self.table.suggest(ty, name) {
+                    message.push_str(&format!("; did you mean '{}'?", suggestion));
+                }
+                Err(message)
+            }
+        }
+    }
+
+    /// The handle type of the one kind of config entry called `name`, if
+    /// any. A name several kinds use is an error, since the cast can't tell
+    /// which is meant.
+    fn only_kind_naming(&self, name: &str) -> Result<Option<Type>, String> {
+        let mut kinds: Vec<(&str, &Type)> = Type::all()
+            .filter_map(|ty| config_kind(ty).filter(|_| self.table.lookup(ty, name).is_some()).map(|kind| (kind, ty)))
+            .collect();
+        kinds.sort_by_key(|(kind, _)| *kind);
+        kinds.dedup_by_key(|(kind, _)| *kind);
+        match kinds.as_slice() {
+            [] => Ok(None),
+            [(_, ty)] => Ok(Some((*ty).clone())),
+            [(_, first), (_, second), ..] => {
+                Err(format!("'{}' names entries of more than one kind; write {}({}) or {}({})", name, first, name, second, name))
+            }
+        }
+    }
+
+    fn game_var(&self, name: &str) -> Result<AstKind, String> {
+        if let Some(varbit) = self.table.varbit(name) {
+            return Ok(AstKind::Varp {
+                name: name.to_string(),
+                id: varbit.varp,
+                bits: Some((varbit.start_bit, varbit.end_bit)),
+            });
+        }
+        if let Some(id) = self.table.varp(name) {
+            return Ok(AstKind::Varp { name: name.to_string(), id, bits: None });
+        }
+        let mut message = format!("unknown varp or varbit '%{}'", name);
+        if let Some(suggestion) = self.table.suggest_game_var(name) {
+            message.push_str(&format!("; did you mean '%{}'?", suggestion));
+        }
+        Err(message)
+    }
+
+    fn static_type(&self, node: &AstKind) -> Option<Type> {
+        match node {
+            AstKind::LocalVar(name) => self.locals.get(name.trim_start_matches('$')).cloned(),
+            AstKind::Cast { target, .. } => Some(target.clone()),
+            AstKind::Varp { id, bits: None, .. } => Some(self.table.varp_type(*id)),
+            AstKind::FunctionCall { name, .. } if name == "db_getfield" => self.db_column_called(node).cloned(),
+            AstKind::FunctionCall { .. } if compiler::param_called(node, self.table).is_some() => {
+                compiler::param_called(node, self.table).map(|config| config.ty.clone())
+            }
+            AstKind::FunctionCall { .. } => self.enum_called(node).map(|config| config.value_type.clone()),
+            _ => None,
+        }
+    }
+
+    /// The type of the column a `db_find` or `db_getfield` call names, if it
+    /// names a loaded one.
+    fn db_column_called(&self, node: &AstKind) -> Option<&Type> {
+        match node {
+            AstKind::FunctionCall { arguments, .. } => match arguments.get(1).map(|arg| &**arg) {
+                Some(AstKind::Identifier(column)) => self.table.db_column_type(column),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The enum an `enum_getvalue` call reads, if it names a loaded one.
+    fn enum_called(&self, node: &AstKind) -> Option<&EnumConfig> {
+        match node {
+            AstKind::FunctionCall { name, arguments } if name == "enum_getvalue" => match arguments.first().map(|arg| &**arg) {
+                Some(AstKind::Identifier(enum_name)) => self.table.enum_config(enum_name),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::test_support;
+    use crate::vm::VM;
+
+    /// A compiler resolving names against a config directory holding
+    /// two npcs and an obj.
+    fn compiler() -> Compiler {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("people.npc"), "[man]\nname=Man\n\n[goblin]\nname=Goblin\n").unwrap();
+        fs::write(dir.path().join("items.obj"), "[coins]\nname=Coins\n").unwrap();
+        let mut compiler = Compiler::new();
+        compiler.set_symbols(SymbolTable::load(&[dir.path().to_path_buf()]).unwrap());
+        compiler
+    }
+
+    #[test]
+    fn npc_and_obj_names_resolve_to_their_ids() {
+        let mut compiler = compiler();
+        let source = "[proc,ids]()(int)\ndef_npc $n = goblin;\ndef_obj $o = coins;\nif ($n = goblin) {\n    return(calc(int($n) * 10 + int($o)));\n}\nreturn(0);\n";
+        let mut vm = VM::new();
+        for bytecode in test_support::compile_with(&mut compiler, source) {
+            vm.register_script(bytecode);
+        }
+        assert_eq!(vm.run_script("ids", &[]), Ok(10));
+    }
+
+    #[test]
+    fn unknown_names_and_mixed_handles_are_compile_errors() {
+        let error = test_support::try_compile_with(&mut compiler(), "[proc,who]()(int)\ndef_npc $n = gobln;\nreturn(0);\n").unwrap_err();
+        assert!(error.to_string().contains("gobln") && error.to_string().contains("goblin"), "{}", error);
+
+        let error = test_support::try_compile_with(&mut compiler(), "[proc,mixed]()(int)\ndef_npc $n = man;\ndef_obj $o = coins;\nif ($n = $o) {\n    return(1);\n}\nreturn(0);\n").unwrap_err();
+        assert!(matches!(&error, crate::error::CompilerError::Type(message) if message.contains("npc") && message.contains("obj")), "{:?}", error);
+    }
+}
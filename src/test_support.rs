@@ -0,0 +1,127 @@
+//! Helpers for unit tests that compile RuneScript held in a string rather
+//! than read from a scripts directory.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use crate::bytecode::ByteCode;
+use crate::compiler::Compiler;
+use crate::config::VmOptions;
+use crate::error::CompilerError;
+use crate::evaluator::Evaluator;
+use crate::host::CommandRegistry;
+use crate::lexer::Lexer;
+use crate::parser::{Parser, Script};
+use crate::symbols::SymbolTable;
+use crate::vm::VM;
+
+/// Parses `source`, panicking on a syntax error.
+pub fn parse(source: &str) -> Script {
+    let path = PathBuf::from("test.rs2");
+    let tokens = Lexer::new(source, &path).tokenize().expect("source should lex");
+    Parser::new(tokens, &path).parse().expect("source should parse")
+}
+
+/// Compiles every trigger in `source` with `compiler`, registering them all
+/// first so calls between them can be folded, as `CompileSession` does.
+pub fn try_compile_with(compiler: &mut Compiler, source: &str) -> Result<Vec<ByteCode>, CompilerError> {
+    let script = parse(source);
+    for node in &script.body {
+        compiler.add_source(node);
+    }
+    script
+        .body
+        .iter()
+        .filter_map(|node| Some(compiler.compile_script(node.script_name()?, node)))
+        .collect()
+}
+
+/// `try_compile_with`, panicking on a compile error.
+pub fn compile_with(compiler: &mut Compiler, source: &str) -> Vec<ByteCode> {
+    try_compile_with(compiler, source).expect("source should compile")
+}
+
+pub fn compile(source: &str) -> Vec<ByteCode> {
+    compile_with(&mut Compiler::new(), source)
+}
+
+/// A VM with every trigger in `source` registered.
+pub fn vm(source: &str) -> VM {
+    let mut vm = VM::new();
+    for bytecode in compile(source) {
+        vm.register_script(bytecode);
+    }
+    vm
+}
+
+/// An Evaluator with every trigger in `source` registered, its symbols
+/// resolved as `DifferentialHarness` does.
+pub fn evaluator(source: &str) -> Evaluator {
+    evaluator_with(&VmOptions::default(), source)
+}
+
+pub fn evaluator_with(options: &VmOptions, source: &str) -> Evaluator {
+    let script = parse(source);
+    let mut compiler = Compiler::new();
+    for node in &script.body {
+        compiler.add_source(node);
+    }
+    let mut evaluator = Evaluator::with_options(options);
+    for node in &script.body {
+        if let Some(name) = node.script_name() {
+            evaluator.register_script(name, compiler.resolve_symbols(node).expect("symbols should resolve"));
+        }
+    }
+    evaluator
+}
+
+/// The symbol table built from config `files`, given as (path, contents).
+pub fn symbols(files: &[(&str, &str)]) -> SymbolTable {
+    let files: Vec<(PathBuf, String)> = files.iter().map(|(path, contents)| (PathBuf::from(path), contents.to_string())).collect();
+    SymbolTable::from_files(&files).expect("configs should load")
+}
+
+/// A VM and an Evaluator, both with `symbols` loaded and every trigger in
+/// `source` registered, compiled and resolved against them.
+pub fn backends_with(symbols: &SymbolTable, source: &str) -> (VM, Evaluator) {
+    let mut compiler = Compiler::new();
+    compiler.set_symbols(symbols.clone());
+    let mut vm = VM::new();
+    vm.load_symbols(symbols);
+    for bytecode in compile_with(&mut compiler, source) {
+        vm.register_script(bytecode);
+    }
+    let mut evaluator = Evaluator::new();
+    evaluator.load_symbols(symbols);
+    for node in &parse(source).body {
+        if let Some(name) = node.script_name() {
+            evaluator.register_script(name, compiler.resolve_symbols(node).expect("symbols should resolve"));
+        }
+    }
+    (vm, evaluator)
+}
+
+/// `backends_with`, each backend given its own default host, which is
+/// returned too so a test can read back what the commands did.
+pub fn hosted_backends(symbols: &SymbolTable, source: &str) -> (VM, Evaluator, Rc<RefCell<CommandRegistry>>, Rc<RefCell<CommandRegistry>>) {
+    let (mut vm, mut evaluator) = backends_with(symbols, source);
+    let (vm_host, evaluator_host) = (Rc::new(RefCell::new(CommandRegistry::new())), Rc::new(RefCell::new(CommandRegistry::new())));
+    // Replacing the host drops the configs `backends_with` loaded into it.
+    vm.set_host(vm_host.clone());
+    vm.load_symbols(symbols);
+    evaluator.set_host(evaluator_host.clone());
+    evaluator.load_symbols(symbols);
+    (vm, evaluator, vm_host, evaluator_host)
+}
+
+/// The error compiling `source` against `symbols`, panicking if it compiles.
+pub fn compile_error(symbols: &SymbolTable, source: &str) -> CompilerError {
+    let mut compiler = Compiler::new();
+    compiler.set_symbols(symbols.clone());
+    try_compile_with(&mut compiler, source).expect_err("source should fail to compile")
+}
+
+/// Runs `script` from `source` on a fresh VM.
+pub fn run(source: &str, script: &str, args: &[i32]) -> Result<i32, String> {
+    vm(source).run_script(script, args)
+}
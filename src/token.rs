@@ -1,9 +1,9 @@
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
-    pub(crate) line: usize,
-    pub(crate) position: usize,
-    pub(crate) kind: Kind,
-    pub(crate) value: String
+    pub line: usize,
+    pub position: usize,
+    pub kind: Kind,
+    pub value: String
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -17,6 +17,8 @@ pub enum Kind {
     RBrace,      // }
     Semicolon,   // ;
     Comma,       // ,
+    Question,    // ? (conditional expression)
+    Colon,       // : (conditional expression)
     
     // Operators
     Equals,      // =
@@ -34,11 +36,14 @@ pub enum Kind {
     Return,     // return
     If,         // if
     While,      // while
+    For,        // for
     
     // Identifiers and literals
     Identifier,  // Regular identifiers
     LocalVar,    // $ prefixed variables
+    GameVar,     // % prefixed varps and varbits
     Number,      // Numeric literals
+    StringLiteral, // "quoted" text, without the quotes
     
     // Comments
     SingleLineComment,  // // comment
@@ -0,0 +1,143 @@
+//! Triggers other than procs, such as `[opnpc1,man]`, run when the engine
+//! dispatches an event rather than when a script calls them. Their subject
+//! is a specific name, `_category` for everything in a category, or a bare
+//! `_` for anything, and the most specific trigger that matches runs.
+
+use std::collections::BTreeMap;
+use serde::Serialize;
+
+/// What a trigger's second field names.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subject<'a> {
+    Name(&'a str),
+    Category(&'a str),
+    Any,
+}
+
+impl<'a> Subject<'a> {
+    pub fn parse(subject: &'a str) -> Self {
+        match subject.strip_prefix('_') {
+            Some("") => Subject::Any,
+            Some(category) => Subject::Category(category),
+            None => Subject::Name(subject),
+        }
+    }
+}
+
+/// The name a non-proc trigger is registered under, e.g. `[opnpc1,_man]`.
+/// Scripts can't call it, so it never collides with a proc's name.
+pub fn script_name(kind: &str, subject: &str) -> String {
+    format!("[{},{}]", kind, subject)
+}
+
+/// The triggers that may handle an event, most specific first: the subject's
+/// own name, then its category if it has one, then the `_` wildcard.
+pub fn dispatch_order(kind: &str, subject_name: &str, subject_category: Option<&str>) -> Vec<String> {
+    let mut names = vec![script_name(kind, subject_name)];
+    if let Some(category) = subject_category {
+        names.push(script_name(kind, &format!("_{}", category)));
+    }
+    names.push(script_name(kind, "_"));
+    names
+}
+
+/// The order `dispatch_order` tries a kind's triggers in, as written into
+/// the dispatch manifest: the subject's name, its category, then `_`.
+pub const SPECIFICITY: &[&str] = &["name", "category", "any"];
+
+/// The triggers of a set of scripts, laid out for an engine to route
+/// events with, without the rest of the compiler. `rsc compile --emit
+/// dispatch` writes it as JSON. Script ids are indexes into `scripts`.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct DispatchManifest {
+    /// See `SPECIFICITY`.
+    pub order: &'static [&'static str],
+    /// Every trigger's script name, e.g. `[opnpc1,man]`, sorted.
+    pub scripts: Vec<String>,
+    pub triggers: BTreeMap<String, KindDispatch>,
+}
+
+/// The triggers of one kind, e.g. every `[opnpc1,...]`, by script id.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct KindDispatch {
+    /// By subject name.
+    pub names: BTreeMap<String, usize>,
+    /// By category, without the leading `_`.
+    pub categories: BTreeMap<String, usize>,
+    /// The `_` wildcard.
+    pub any: Option<usize>,
+}
+
+impl DispatchManifest {
+    /// Builds the manifest from script names as `AstKind::script_name`
+    /// gives them. Procs are left out, as the engine never dispatches them.
+    pub fn new<'a>(script_names: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut triggers: Vec<(&str, &str, &str)> = script_names
+            .into_iter()
+            .filter_map(|name| {
+                let (kind, subject) = name.strip_prefix('[')?.strip_suffix(']')?.split_once(',')?;
+                Some((name, kind, subject))
+            })
+            .collect();
+        triggers.sort_unstable();
+        triggers.dedup();
+        let mut manifest = DispatchManifest { order: SPECIFICITY, ..Default::default() };
+        for (id, (name, kind, subject)) in triggers.into_iter().enumerate() {
+            manifest.scripts.push(name.to_string());
+            let dispatch = manifest.triggers.entry(kind.to_string()).or_default();
+            match Subject::parse(subject) {
+                Subject::Name(name) => {
+                    dispatch.names.insert(name.to_string(), id);
+                }
+                Subject::Category(category) => {
+                    dispatch.categories.insert(category.to_string(), id);
+                }
+                Subject::Any => dispatch.any = Some(id),
+            }
+        }
+        manifest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::host::{HostValue, RecordingHost};
+    use crate::test_support;
+
+    const SCRIPTS: &str = "[opnpc1,man]\nmes(\"man\");\n\
+        [opnpc1,_human]\nmes(\"human\");\n\
+        [opnpc1,_]\nmes(\"anyone\");\n\
+        [proc,man]()(int)\nreturn(7);\n";
+
+    #[test]
+    fn dispatch_runs_the_name_then_the_category_then_the_wildcard() {
+        let symbols = test_support::symbols(&[("people.npc", "[man]\ncategory=human\n\n[woman]\ncategory=human\n\n[goblin]\n")]);
+        let (mut vm, _) = test_support::backends_with(&symbols, SCRIPTS);
+        let host = Rc::new(RefCell::new(RecordingHost::default()));
+        vm.set_host(host.clone());
+
+        for (name, category) in [("man", Some("human")), ("woman", Some("human")), ("goblin", None)] {
+            assert!(vm.dispatch_trigger("opnpc1", name, category, &[]).unwrap().is_some(), "{}", name);
+        }
+        assert_eq!(vm.dispatch_trigger("opnpc2", "man", Some("human"), &[]), Ok(None));
+        // The proc named man is a separate script.
+        assert_eq!(vm.run_script("man", &[]), Ok(7));
+
+        let messages: Vec<_> = ["man", "human", "anyone"]
+            .iter()
+            .map(|text| ("mes".to_string(), vec![HostValue::Str(text.to_string())]))
+            .collect();
+        assert_eq!(host.borrow().calls, messages);
+    }
+
+    #[test]
+    fn an_undeclared_category_is_a_compile_error() {
+        let symbols = test_support::symbols(&[("people.npc", "[man]\ncategory=human\n")]);
+        let mut compiler = crate::compiler::Compiler::new();
+        compiler.set_symbols(symbols);
+        let error = test_support::try_compile_with(&mut compiler, "[opnpc1,_humans]\nmes(\"hi\");\n").unwrap_err();
+        assert!(error.to_string().contains("unknown category 'humans'; did you mean 'human'?"), "{}", error);
+    }
+}
@@ -1,36 +1,194 @@
-#[derive(Debug, PartialEq, Clone)]
-pub enum Type {
-    Int,
-    Boolean,
-    String,
-    Loc,
-    Npc,
-    Obj,
-    Coord,
-    NamedObj,
-    PlayerUid,
-    NpcUid,
-    Stat,
-    Component,
-    Interface,
-    Inv,
-    Enum,
-    Struct,
-    Param,
-    DbTable,
-    DbRow,
-    DbColumn,
-    Varp,
-    MesAnim,
-    Category,     // For categorizing items/npcs
-    Model,        // 3D model reference
-    Animation,    // Animation sequence
-    Sound,        // Sound effect
-    Color,        // RGB color
-    Coord2,       // 2D coordinate (x,y)
-    Coord3,       // 3D coordinate (x,y,z)
-    IdKit,        // Identity kit reference
-    Spotanim,     // Special animation reference
-    Varbit,       // Variable bit reference
-    Timer,        // Timer reference
-} 
\ No newline at end of file
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+/// Declares `Type` together with `TYPES`, the table of script names and
+/// `def_` keywords every lookup uses, so adding a type touches one place.
+macro_rules! types {
+    ($($(#[$doc:meta])* $variant:ident => $name:literal,)*) => {
+        #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+        pub enum Type {
+            $($(#[$doc])* $variant,)*
+        }
+
+        const TYPES: &[(Type, &str, &str)] = &[
+            $((Type::$variant, $name, concat!("def_", $name)),)*
+        ];
+    };
+}
+
+types! {
+    Int => "int",
+    Boolean => "boolean",
+    String => "string",
+    Loc => "loc",
+    Npc => "npc",
+    Obj => "obj",
+    Coord => "coord",
+    NamedObj => "namedobj",
+    PlayerUid => "playeruid",
+    NpcUid => "npcuid",
+    Stat => "stat",
+    Component => "component",
+    Interface => "interface",
+    Inv => "inv",
+    Enum => "enum",
+    Struct => "struct",
+    Param => "param",
+    DbTable => "dbtable",
+    DbRow => "dbrow",
+    DbColumn => "dbcolumn",
+    Varp => "varp",
+    MesAnim => "mesanim",
+    /// For categorizing items/npcs
+    Category => "category",
+    /// 3D model reference
+    Model => "model",
+    /// Animation sequence
+    Animation => "seq",
+    /// Sound effect
+    Sound => "synth",
+    /// RGB color
+    Color => "colour",
+    /// 2D coordinate (x,y)
+    Coord2 => "coord2",
+    /// 3D coordinate (x,y,z)
+    Coord3 => "coord3",
+    /// Identity kit reference
+    IdKit => "idkit",
+    /// Special animation reference
+    Spotanim => "spotanim",
+    /// Variable bit reference
+    Varbit => "varbit",
+    /// Timer reference
+    Timer => "timer",
+    /// Floor reference
+    Flo => "flo",
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The runtime value of `null`.
+pub const NULL: i32 = -1;
+
+/// How a value of one type may be used where another is expected.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Coercion {
+    /// Converted silently.
+    Allowed,
+    /// Converted, but the compiler warns; an explicit cast silences it.
+    Warn,
+    /// Rejected unless written as an explicit cast.
+    Explicit,
+    /// Rejected, even with an explicit cast.
+    Forbidden,
+}
+
+impl Type {
+    /// Looks up a type by its script name, e.g. `int` or `npc`, as used in
+    /// script signatures and casts.
+    pub fn from_name(name: &str) -> Option<Type> {
+        TYPES.iter().find(|(_, type_name, _)| *type_name == name).map(|(ty, _, _)| ty.clone())
+    }
+
+    /// Looks up a type by its declaration keyword, e.g. `def_int`.
+    pub fn from_def_keyword(keyword: &str) -> Option<Type> {
+        TYPES.iter().find(|(_, _, def)| *def == keyword).map(|(ty, _, _)| ty.clone())
+    }
+
+    /// The script name of this type, for diagnostics.
+    pub fn name(&self) -> &'static str {
+        self.entry().1
+    }
+
+    /// The keyword that declares a local of this type, e.g. `def_int`.
+    pub fn def_keyword(&self) -> &'static str {
+        self.entry().2
+    }
+
+    /// Every type, in declaration order.
+    pub fn all() -> impl Iterator<Item = &'static Type> {
+        TYPES.iter().map(|(ty, _, _)| ty)
+    }
+
+    fn entry(&self) -> &'static (Type, &'static str, &'static str) {
+        TYPES
+            .iter()
+            .find(|(ty, _, _)| ty == self)
+            .expect("every Type variant has an entry in TYPES")
+    }
+
+    /// Id types reference config entries (npcs, objs, interfaces, ...) and
+    /// are plain ints at runtime.
+    pub fn is_id(&self) -> bool {
+        !matches!(self, Type::Int | Type::Boolean | Type::String)
+    }
+
+    /// What a required parameter left out of a call holds with
+    /// `vm.lenient_args`: `null` for nullable types, 0 (false) otherwise.
+    /// String parameters get the empty string.
+    pub fn default_value(&self) -> i32 {
+        if self.is_nullable() { NULL } else { 0 }
+    }
+
+    /// Whether `null` is a valid value: true for handles to things that may
+    /// not exist, such as an npc that has despawned.
+    pub fn is_nullable(&self) -> bool {
+        matches!(
+            self,
+            Type::Npc | Type::Obj | Type::NamedObj | Type::PlayerUid | Type::NpcUid | Type::Loc | Type::DbRow
+        )
+    }
+
+    /// The implicit coercion table:
+    ///
+    /// - int <-> boolean: allowed
+    /// - int <-> colour: only with a cast, though an int literal is a colour
+    ///   (see `Compiler::check_value`)
+    /// - int <-> other id types: allowed with a warning
+    /// - id type -> the same id type: allowed
+    /// - anything involving string, or between different id types: forbidden
+    pub fn coercion_to(&self, target: &Type) -> Coercion {
+        match (self, target) {
+            (from, to) if from == to => Coercion::Allowed,
+            (Type::Int, Type::Boolean) | (Type::Boolean, Type::Int) => Coercion::Allowed,
+            (Type::Int, Type::Color) | (Type::Color, Type::Int) => Coercion::Explicit,
+            (Type::Int, to) if to.is_id() => Coercion::Warn,
+            (from, Type::Int) if from.is_id() => Coercion::Warn,
+            _ => Coercion::Forbidden,
+        }
+    }
+
+    /// Whether `<target>(value)` is accepted. Casts only make warned
+    /// coercions explicit; they never allow a forbidden one.
+    pub fn can_cast_to(&self, target: &Type) -> bool {
+        self.coercion_to(target) != Coercion::Forbidden
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use crate::lexer::Lexer;
+    use crate::token::Kind;
+
+    #[test]
+    fn every_type_round_trips_through_the_keyword_table() {
+        let path = PathBuf::from("test.rs2");
+        let lexer = Lexer::new("", &path);
+        for ty in Type::all() {
+            assert_eq!(Type::from_name(ty.name()).as_ref(), Some(ty));
+            assert_eq!(Type::from_def_keyword(ty.def_keyword()).as_ref(), Some(ty));
+            assert_eq!(ty.def_keyword(), format!("def_{}", ty));
+            assert_eq!(lexer.get_keyword_token(&ty.def_keyword().to_string()).unwrap(), Kind::Def, "{}", ty.def_keyword());
+        }
+        let names: HashSet<&str> = Type::all().map(Type::name).collect();
+        assert_eq!(names.len(), TYPES.len(), "two types share a name");
+        assert_eq!(Type::from_def_keyword("def_nothing"), None);
+    }
+}
@@ -0,0 +1,395 @@
+//! `rsc update`: installing a released version of the compiler from the
+//! git checkout it was built from. Releases are tags named `vX.Y.Z`.
+//! Commands run through a `Runner`, so the steps can be driven without
+//! spawning git or the install script.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+/// A release version, from a tag like `v1.2.3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Reads `v1.2.3` or `1.2.3`.
+    pub fn parse(text: &str) -> Option<Version> {
+        let mut parts = text.strip_prefix('v').unwrap_or(text).split('.').map(|part| part.parse().ok());
+        let version = Version { major: parts.next()??, minor: parts.next()??, patch: parts.next()?? };
+        parts.next().is_none().then_some(version)
+    }
+
+    /// The version this binary was built as.
+    pub fn current() -> Version {
+        Version::parse(env!("CARGO_PKG_VERSION")).unwrap_or(Version { major: 0, minor: 0, patch: 0 })
+    }
+
+    /// The git tag naming this version.
+    pub fn tag(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// What a command run by a `Runner` produced.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Output {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the commands an update needs.
+pub trait Runner {
+    /// Runs `program` in the working directory, capturing its output.
+    fn output(&mut self, program: &str, args: &[&str]) -> io::Result<Output>;
+    /// Runs `program` with `envs` set and its output shown as it runs,
+    /// returning whether it succeeded.
+    fn status(&mut self, program: &str, args: &[&str], envs: &[(&str, String)]) -> io::Result<bool>;
+}
+
+/// Spawns the commands for real.
+pub struct SystemRunner;
+
+impl Runner for SystemRunner {
+    fn output(&mut self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let output = Command::new(program).args(args).stdin(Stdio::null()).output()?;
+        Ok(Output {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+
+    fn status(&mut self, program: &str, args: &[&str], envs: &[(&str, String)]) -> io::Result<bool> {
+        let mut command = Command::new(program);
+        command.args(args);
+        for (key, value) in envs {
+            command.env(key, value);
+        }
+        Ok(command.status()?.success())
+    }
+}
+
+/// The install script run once the release is checked out.
+pub struct Install {
+    pub program: String,
+    pub args: Vec<String>,
+    pub envs: Vec<(&'static str, String)>,
+}
+
+/// The version last installed by `rsc update`, saved in the environment's
+/// config directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Installed {
+    pub version: String,
+    pub commit: String,
+    /// Seconds since the Unix epoch.
+    pub installed_at: u64,
+}
+
+impl Installed {
+    /// The saved record, if there is one that reads.
+    pub fn load(path: &Path) -> Option<Installed> {
+        serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// The installed and newest released versions, as `rsc update --check`
+/// reports them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// The recorded version, or else the one this binary was built as.
+    pub installed: Version,
+    pub latest: Version,
+    /// False when there is no `origin` to fetch from, so only tags already
+    /// fetched were considered.
+    pub fetched: bool,
+}
+
+impl Check {
+    pub fn update_available(&self) -> bool {
+        self.latest > self.installed
+    }
+}
+
+/// What `Updater::update` did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Updated {
+    Installed(Installed),
+    /// The newest version was already installed, so nothing ran.
+    UpToDate(Version),
+}
+
+/// Drives an update with `runner`, recording the result at `state_file`.
+pub struct Updater<R: Runner> {
+    runner: R,
+    state_file: PathBuf,
+}
+
+impl<R: Runner> Updater<R> {
+    pub fn new(runner: R, state_file: PathBuf) -> Self {
+        Self { runner, state_file }
+    }
+
+    pub fn runner(&self) -> &R {
+        &self.runner
+    }
+
+    /// Fetches the release tags and compares the newest with the installed
+    /// version, changing nothing.
+    pub fn check(&mut self) -> Result<Check, String> {
+        let fetched = self.fetch_tags()?;
+        let latest = self.releases()?.into_iter().max().ok_or("no released versions (tags like v1.2.3) found")?;
+        Ok(Check { installed: self.installed(), latest, fetched })
+    }
+
+    /// Checks out `pin`, or the newest release, and runs `install` in it.
+    /// Refuses to start if the working tree has uncommitted changes, and
+    /// records the version only once the install succeeds. Without a pin,
+    /// does nothing when the newest release is already installed.
+    pub fn update(&mut self, pin: Option<Version>, install: &Install) -> Result<Updated, String> {
+        let changes = self.git(&["status", "--porcelain", "--untracked-files=no"])?;
+        if !changes.trim().is_empty() {
+            return Err(format!(
+                "the working tree has local changes; commit or stash them, then update again:\n{}",
+                changes.trim_end()
+            ));
+        }
+        self.fetch_tags()?;
+        let releases = self.releases()?;
+        let target = match pin {
+            Some(pin) if releases.contains(&pin) => pin,
+            Some(pin) => {
+                let mut newest = releases;
+                newest.sort_unstable_by(|a, b| b.cmp(a));
+                let newest: Vec<String> = newest.iter().take(5).map(Version::to_string).collect();
+                return Err(match newest.is_empty() {
+                    true => format!("{} is not a released version; there are none yet", pin),
+                    false => format!("{} is not a released version; the newest are {}", pin, newest.join(", ")),
+                });
+            }
+            None => releases.into_iter().max().ok_or("no released versions (tags like v1.2.3) found")?,
+        };
+        if pin.is_none() && Installed::load(&self.state_file).is_some() && self.installed() == target {
+            return Ok(Updated::UpToDate(target));
+        }
+
+        let tag = target.tag();
+        let expected = self.git(&["rev-parse", &format!("{}^{{commit}}", tag)])?.trim().to_string();
+        self.git(&["checkout", "--quiet", &tag])?;
+        let head = self.git(&["rev-parse", "HEAD"])?.trim().to_string();
+        if head != expected {
+            return Err(format!("checked out {} but HEAD is {}, not {}; not installing", tag, head, expected));
+        }
+
+        let args: Vec<&str> = install.args.iter().map(String::as_str).collect();
+        let ran = self.runner.status(&install.program, &args, &install.envs).map_err(|e| format!("couldn't run {}: {}", install.program, e))?;
+        if !ran {
+            return Err(format!("the install script failed; {} is checked out but not installed", tag));
+        }
+        let installed = Installed {
+            version: tag,
+            commit: head,
+            installed_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs()),
+        };
+        installed.save(&self.state_file).map_err(|e| format!("installed, but couldn't record it in {}: {}", self.state_file.display(), e))?;
+        Ok(Updated::Installed(installed))
+    }
+
+    /// The recorded version, or else the one this binary was built as.
+    fn installed(&self) -> Version {
+        Installed::load(&self.state_file)
+            .and_then(|installed| Version::parse(&installed.version))
+            .unwrap_or_else(Version::current)
+    }
+
+    /// Fetches tags from `origin`, if there is one; whether it did.
+    fn fetch_tags(&mut self) -> Result<bool, String> {
+        let has_origin = self.runner.output("git", &["remote", "get-url", "origin"]).is_ok_and(|output| output.success);
+        if has_origin {
+            self.git(&["fetch", "--quiet", "--tags", "origin"])?;
+        }
+        Ok(has_origin)
+    }
+
+    /// Every tag that names a release.
+    fn releases(&mut self) -> Result<Vec<Version>, String> {
+        Ok(self.git(&["tag", "--list", "v*"])?.lines().filter_map(|tag| Version::parse(tag.trim())).collect())
+    }
+
+    /// Runs git, returning its stdout, or an error quoting its stderr.
+    fn git(&mut self, args: &[&str]) -> Result<String, String> {
+        let output = self.runner.output("git", args).map_err(|e| format!("couldn't run git: {}", e))?;
+        if !output.success {
+            return Err(format!("git {} failed: {}", args.join(" "), output.stderr.trim()));
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers git from a fixed checkout: its release tags, the commit
+    /// each names, and what `git status` reports. Records every command.
+    #[derive(Default)]
+    struct MockRunner {
+        origin: bool,
+        tags: Vec<&'static str>,
+        changes: &'static str,
+        head: String,
+        install_succeeds: bool,
+        calls: Vec<String>,
+    }
+
+    impl MockRunner {
+        fn new(tags: &[&'static str]) -> Self {
+            Self { origin: true, tags: tags.to_vec(), install_succeeds: true, ..Self::default() }
+        }
+
+        fn commit(tag: &str) -> String {
+            format!("commit-of-{}", tag)
+        }
+
+        fn ran(&self, prefix: &str) -> bool {
+            self.calls.iter().any(|call| call.starts_with(prefix))
+        }
+    }
+
+    impl Runner for MockRunner {
+        fn output(&mut self, program: &str, args: &[&str]) -> io::Result<Output> {
+            self.calls.push(format!("{} {}", program, args.join(" ")));
+            let ok = |stdout: String| Ok(Output { success: true, stdout, stderr: String::new() });
+            match args {
+                ["remote", "get-url", "origin"] if self.origin => ok("git@example.com:rsc.git\n".to_string()),
+                ["remote", ..] => Ok(Output { success: false, stdout: String::new(), stderr: "error: No such remote 'origin'".to_string() }),
+                ["status", ..] => ok(self.changes.to_string()),
+                ["fetch", ..] => ok(String::new()),
+                ["tag", "--list", "v*"] => ok(self.tags.iter().map(|tag| format!("{}\n", tag)).collect()),
+                ["rev-parse", "HEAD"] => ok(format!("{}\n", self.head)),
+                ["rev-parse", spec] => ok(format!("{}\n", Self::commit(spec.trim_end_matches("^{commit}")))),
+                ["checkout", "--quiet", tag] => {
+                    self.head = Self::commit(tag);
+                    ok(String::new())
+                }
+                _ => panic!("unexpected command: git {}", args.join(" ")),
+            }
+        }
+
+        fn status(&mut self, program: &str, args: &[&str], _envs: &[(&str, String)]) -> io::Result<bool> {
+            self.calls.push(format!("{} {}", program, args.join(" ")));
+            Ok(self.install_succeeds)
+        }
+    }
+
+    fn install() -> Install {
+        Install { program: "./install.sh".to_string(), args: Vec::new(), envs: Vec::new() }
+    }
+
+    fn version(text: &str) -> Version {
+        Version::parse(text).unwrap()
+    }
+
+    #[test]
+    fn check_reports_the_newest_release_and_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("installed.json");
+        Installed { version: "v0.2.0".to_string(), commit: MockRunner::commit("v0.2.0"), installed_at: 0 }.save(&state).unwrap();
+
+        let mut updater = Updater::new(MockRunner::new(&["v0.1.0", "v0.10.0", "v0.2.0", "not-a-release"]), state.clone());
+        let check = updater.check().unwrap();
+        assert_eq!(check, Check { installed: version("v0.2.0"), latest: version("v0.10.0"), fetched: true });
+        assert!(check.update_available());
+        assert!(updater.runner().ran("git fetch --quiet --tags origin"));
+        assert!(!updater.runner().ran("git checkout") && !updater.runner().ran("./install.sh"));
+        assert_eq!(Installed::load(&state).unwrap().version, "v0.2.0");
+
+        // Without an origin only the tags already fetched count.
+        let mut updater = Updater::new(MockRunner { origin: false, ..MockRunner::new(&["v0.1.0"]) }, state);
+        assert!(!updater.check().unwrap().fetched);
+        assert!(!updater.runner().ran("git fetch"));
+    }
+
+    #[test]
+    fn updating_installs_the_newest_release_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("env/installed.json");
+        let mut updater = Updater::new(MockRunner::new(&["v0.1.0", "v0.3.0", "v0.2.0"]), state.clone());
+
+        let Updated::Installed(installed) = updater.update(None, &install()).unwrap() else {
+            panic!("expected an install");
+        };
+        assert_eq!((installed.version.as_str(), installed.commit.as_str()), ("v0.3.0", "commit-of-v0.3.0"));
+        assert_eq!(Installed::load(&state), Some(installed));
+        assert!(updater.runner().ran("git checkout --quiet v0.3.0") && updater.runner().ran("./install.sh"));
+
+        let mut updater = Updater::new(MockRunner::new(&["v0.1.0", "v0.3.0", "v0.2.0"]), state);
+        assert_eq!(updater.update(None, &install()), Ok(Updated::UpToDate(version("v0.3.0"))));
+        assert!(!updater.runner().ran("git checkout") && !updater.runner().ran("./install.sh"));
+    }
+
+    #[test]
+    fn a_pin_installs_that_release_even_when_older() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("installed.json");
+        Installed { version: "v0.3.0".to_string(), commit: MockRunner::commit("v0.3.0"), installed_at: 0 }.save(&state).unwrap();
+        let tags = ["v0.1.0", "v0.3.0", "v0.2.0"];
+
+        let mut updater = Updater::new(MockRunner::new(&tags), state.clone());
+        let updated = updater.update(Some(version("v0.1.0")), &install()).unwrap();
+        assert!(matches!(&updated, Updated::Installed(installed) if installed.version == "v0.1.0"), "{:?}", updated);
+        assert!(updater.runner().ran("git checkout --quiet v0.1.0"));
+        assert_eq!(Installed::load(&state).unwrap().version, "v0.1.0");
+
+        let mut updater = Updater::new(MockRunner::new(&tags), state.clone());
+        let error = updater.update(Some(version("v9.9.9")), &install()).unwrap_err();
+        assert_eq!(error, "v9.9.9 is not a released version; the newest are v0.3.0, v0.2.0, v0.1.0");
+        assert!(!updater.runner().ran("git checkout"));
+        assert_eq!(Installed::load(&state).unwrap().version, "v0.1.0");
+    }
+
+    #[test]
+    fn a_dirty_working_tree_is_refused_before_anything_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("installed.json");
+        let runner = MockRunner { changes: " M src/main.rs\n", ..MockRunner::new(&["v0.1.0"]) };
+        let mut updater = Updater::new(runner, state.clone());
+
+        let error = updater.update(None, &install()).unwrap_err();
+        assert_eq!(error, "the working tree has local changes; commit or stash them, then update again:\n M src/main.rs");
+        assert_eq!(updater.runner().calls, ["git status --porcelain --untracked-files=no"]);
+        assert_eq!(Installed::load(&state), None);
+    }
+
+    #[test]
+    fn a_failed_install_is_not_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("installed.json");
+        let mut updater = Updater::new(MockRunner { install_succeeds: false, ..MockRunner::new(&["v0.1.0"]) }, state.clone());
+        let error = updater.update(None, &install()).unwrap_err();
+        assert_eq!(error, "the install script failed; v0.1.0 is checked out but not installed");
+        assert_eq!(Installed::load(&state), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Varbits name a span of bits within a varp, so several small values
+//! (mostly flags) can share one varp. Bits are numbered from 0, the least
+//! significant, and both ends of a span are inclusive, as in the engine's
+//! `.varbit` configs.
+
+use crate::symbols::{self, SymbolTable};
+
+pub const MAX_BIT: u8 = 31;
+
+/// One `[name]` section of a `.varbit` config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Varbit {
+    pub name: String,
+    /// Id of the varp the bits live in.
+    pub varp: i32,
+    pub start_bit: u8,
+    pub end_bit: u8,
+}
+
+/// The largest value that fits in bits `start_bit..=end_bit`.
+pub fn max_value(start_bit: u8, end_bit: u8) -> u32 {
+    ((1u64 << (end_bit - start_bit + 1)) - 1) as u32
+}
+
+/// Extracts a varbit from the value of its varp.
+pub fn read(varp_value: i32, start_bit: u8, end_bit: u8) -> i32 {
+    ((varp_value as u32 >> start_bit) & max_value(start_bit, end_bit)) as i32
+}
+
+/// The varp value with bits `start_bit..=end_bit` replaced by `value` and
+/// every other bit kept. Values that don't fit in the span are rejected
+/// rather than truncated.
+pub fn write(varp_value: i32, start_bit: u8, end_bit: u8, value: i32) -> Result<i32, String> {
+    let max = max_value(start_bit, end_bit);
+    if value as u32 > max {
+        return Err(format!(
+            "varbit value {} does not fit in bits {}..={} (0..={})",
+            value, start_bit, end_bit, max
+        ));
+    }
+    let cleared = varp_value as u32 & !(max << start_bit);
+    Ok((cleared | (value as u32) << start_bit) as i32)
+}
+
+/// Parses the sections of a `.varbit` file:
+///
+/// ```text
+/// [quest_started]
+/// basevar=quest_flags
+/// startbit=0
+/// endbit=0
+/// ```
+///
+/// `basevar` names a varp declared in a `.varp` file, or gives its id.
+pub fn parse(contents: &str, symbols: &SymbolTable) -> Result<Vec<Varbit>, String> {
+    symbols::sections(contents)?
+        .into_iter()
+        .map(|(name, fields)| build(name, fields, symbols))
+        .collect()
+}
+
+fn build(name: String, fields: Vec<(String, String)>, symbols: &SymbolTable) -> Result<Varbit, String> {
+    let field = |field: &str| -> Result<&str, String> {
+        fields
+            .iter()
+            .find(|(key, _)| key == field)
+            .map(|(_, value)| value.as_str())
+            .ok_or_else(|| format!("varbit '{}' has no {}", name, field))
+    };
+    let bit = |field_name: &str| -> Result<u8, String> {
+        let value = field(field_name)?;
+        value
+            .parse()
+            .ok()
+            .filter(|bit| *bit <= MAX_BIT)
+            .ok_or_else(|| format!("varbit '{}': {} '{}' is not a bit from 0 to {}", name, field_name, value, MAX_BIT))
+    };
+
+    let basevar = field("basevar")?;
+    let varp = symbols
+        .varp(basevar)
+        .or_else(|| basevar.parse().ok())
+        .ok_or_else(|| format!("varbit '{}': unknown varp '{}'", name, basevar))?;
+    let (start_bit, end_bit) = (bit("startbit")?, bit("endbit")?);
+    if start_bit > end_bit {
+        return Err(format!("varbit '{}': startbit {} is after endbit {}", name, start_bit, end_bit));
+    }
+    Ok(Varbit { name, varp, start_bit, end_bit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const VARBITS: &str = "[started]\nbasevar=flags\nstartbit=0\nendbit=0\n\n\
+        [stage]\nbasevar=flags\nstartbit=1\nendbit=4\n\n\
+        [kills]\nbasevar=flags\nstartbit=8\nendbit=15\n";
+
+    const SCRIPTS: &str = "[proc,progress]()(int)\n\
+        %flags = 0;\n%started = 1;\n%stage = 12;\n%kills = 200;\n%stage = 5;\n\
+        return(calc(%started * 100000 + %stage * 1000 + %kills));\n\
+        [proc,flags]()(int)\nreturn(%flags);\n\
+        [proc,overflow]()\n%stage = 16;\n";
+
+    #[test]
+    fn write_keeps_the_bits_outside_the_span() {
+        let varp = write(-1, 4, 7, 0).unwrap();
+        assert_eq!(varp, !0xf0);
+        assert_eq!(read(varp, 4, 7), 0);
+        assert_eq!(read(varp, 0, 3), 15);
+        assert_eq!(read(write(0, 0, MAX_BIT, -1).unwrap(), 0, MAX_BIT), -1);
+        assert!(write(0, 4, 7, 16).is_err());
+    }
+
+    #[test]
+    fn varbits_sharing_a_varp_leave_their_neighbours_alone_on_both_backends() {
+        let symbols = test_support::symbols(&[("state.varp", "[flags]\n"), ("state.varbit", VARBITS)]);
+        let (mut vm, mut evaluator) = test_support::backends_with(&symbols, SCRIPTS);
+
+        assert_eq!(vm.run_script("progress", &[]), Ok(105200));
+        assert_eq!(evaluator.eval_script("progress", &[]), Ok(105200));
+        // started in bit 0, stage 5 in bits 1-4 and 200 kills from bit 8.
+        let packed = 1 | 5 << 1 | 200 << 8;
+        assert_eq!(vm.run_script("flags", &[]), Ok(packed));
+        assert_eq!(evaluator.eval_script("flags", &[]), Ok(packed));
+
+        let message = "varbit value 16 does not fit in bits 1..=4 (0..=15)";
+        let error = vm.run_script("overflow", &[]).unwrap_err();
+        assert!(error.contains(message), "{}", error);
+        let error = evaluator.eval_script("overflow", &[]).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+        assert_eq!(vm.run_script("flags", &[]), Ok(packed));
+    }
+}
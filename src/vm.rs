@@ -1,121 +1,943 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::annotations;
 use crate::bytecode::{ByteCode, Instruction};
+use crate::compiler::{self, ARG_COUNT_LOCAL};
+use crate::config::{ScriptOverrides, VmOptions};
+use crate::convert;
+use crate::dedupe::{self, Dedupe};
+use crate::colour;
+use crate::coord;
+use crate::enums::EnumConfig;
+use crate::params::Params;
+use crate::profiler::CallProfile;
+use crate::host::{Host, HostContext, HostValue};
+use crate::random::Rng;
+use crate::scheduler::Timers;
+use crate::strings;
+use crate::symbols::SymbolTable;
+use crate::triggers;
+use crate::types::Type;
+use crate::varbits;
 
-#[derive(Debug)]
-pub struct VM {
+/// Global stores that outlive a single script invocation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VmState {
+    pub varps: HashMap<i32, i32>,
+    pub varns: HashMap<i32, i32>,
+    pub arrays: HashMap<String, Vec<i32>>,
+    pub script_vars: Vec<i32>,
+}
+
+/// What a VM paused at a breakpoint does next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Pause again at the next source line.
+    Step,
+    /// Stop the run with an error.
+    Abort,
+}
+
+/// Where execution paused: before the first instruction of `line`.
+pub struct Pause<'a> {
+    pub script: &'a str,
+    pub line: usize,
+    pub ip: usize,
+    pub locals: &'a HashMap<String, i32>,
+    pub stack: &'a [i32],
+}
+
+/// The state of the innermost frame when a run failed, for post-mortem
+/// debugging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameDump {
+    pub script: String,
+    /// The instruction that failed.
+    pub ip: usize,
+    /// Its source line, if the script has a source map.
+    pub line: Option<usize>,
+    pub locals: BTreeMap<String, i32>,
+    pub string_locals: BTreeMap<String, String>,
+    pub stack: Vec<i32>,
+    pub string_stack: Vec<String>,
+}
+
+impl fmt::Display for FrameDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => writeln!(f, "In '{}' at line {} (instruction {})", self.script, line, self.ip)?,
+            None => writeln!(f, "In '{}' at instruction {}", self.script, self.ip)?,
+        }
+        writeln!(f, "Locals:")?;
+        if self.locals.is_empty() && self.string_locals.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for (name, value) in &self.locals {
+            writeln!(f, "  ${} = {}", name, value)?;
+        }
+        for (name, value) in &self.string_locals {
+            writeln!(f, "  ${} = {:?}", name, value)?;
+        }
+        writeln!(f, "Stack: {:?}", self.stack)?;
+        if !self.string_stack.is_empty() {
+            writeln!(f, "String stack: {:?}", self.string_stack)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error of a run stopped through `VM::cancel_flag`.
+pub const CANCELLED: &str = "Execution cancelled.";
+
+/// A failed run: the message, and the state of the frame that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub message: String,
+    /// `None` if the run failed before a script started, e.g. because it
+    /// wasn't found.
+    pub frame: Option<Box<FrameDump>>,
+    /// Set only when the VM stopped the run because `VM::cancel_flag` was
+    /// set, never by a script or host command failing with the same message.
+    pub cancelled: bool,
+}
+
+impl VmError {
+    /// Whether the run was stopped through `VM::cancel_flag` rather than
+    /// failing.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for VmError {}
+
+/// Decides what happens when execution reaches a breakpoint.
+pub trait Debugger {
+    fn pause(&mut self, at: &Pause) -> DebugAction;
+}
+
+/// Holds the VM's debugger; lets `VM` keep deriving `Debug`.
+struct DebuggerSlot(Box<dyn Debugger>);
+
+impl fmt::Debug for DebuggerSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Debugger")
+    }
+}
+
+/// Called with a script's name and arguments as it starts running.
+pub type EnterHook = Box<dyn FnMut(&str, &[i32])>;
+
+/// Called with a script's name and result as it returns.
+pub type ExitHook = Box<dyn FnMut(&str, i32)>;
+
+/// Callbacks for tools that follow script calls, e.g. to build flamegraphs.
+#[derive(Default)]
+struct CallHooks {
+    on_enter: Option<EnterHook>,
+    on_exit: Option<ExitHook>,
+}
+
+impl fmt::Debug for CallHooks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("CallHooks")
+    }
+}
+
+/// Key of a memoized script result: the script name and its arguments.
+/// Shared with the Evaluator so both backends cache the same calls.
+pub type MemoKey = (String, Vec<i32>);
+
+/// The state of one script frame: its locals, its stacks and where it is.
+/// Each run and each script call gets a fresh one and puts its caller's
+/// back when it returns, so a run started from inside another, e.g. by a
+/// command registered with `VM::register_command`, leaves the caller's frame
+/// as it was.
+#[derive(Debug, Default)]
+struct ExecContext {
     ip: usize,
     stack: Vec<i32>,
     string_stack: Vec<String>,
     variables: HashMap<String, i32>,
     string_variables: HashMap<String, String>,
-    arrays: HashMap<String, Vec<i32>>,
-    script_vars: Vec<i32>,
-    scripts: HashMap<String, ByteCode>,
     current_script: Option<String>,
+    /// Script and source line of the last instruction executed in this
+    /// frame, so a line only pauses when execution enters it.
+    last_line: Option<(String, usize)>,
+}
+
+impl ExecContext {
+    /// A frame about to run `script`, with its arguments bound to `arg0`,
+    /// `arg1`, ... and their count to `__argc`.
+    fn new(script: &str, args: &[i32], string_args: HashMap<String, String>) -> Self {
+        let mut variables: HashMap<String, i32> = args
+            .iter()
+            .enumerate()
+            .map(|(i, &arg)| (format!("arg{}", i), arg))
+            .collect();
+        variables.insert(ARG_COUNT_LOCAL.to_string(), args.len() as i32);
+        ExecContext {
+            variables,
+            string_variables: string_args,
+            current_script: Some(script.to_string()),
+            ..Self::default()
+        }
+    }
+}
+
+type ScriptCommand = Box<dyn FnMut(&mut VM, &[HostValue]) -> Result<i32, String>>;
+
+/// Commands registered with `VM::register_command`; lets the VM keep
+/// deriving `Debug`.
+#[derive(Default)]
+struct ScriptCommands(HashMap<String, Rc<RefCell<ScriptCommand>>>);
+
+impl fmt::Debug for ScriptCommands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// A script registered under another's body by `VM::dedupe_scripts`.
+#[derive(Debug)]
+struct Alias {
+    /// The name the shared body is stored under.
+    body: String,
+    /// The alias's own line for each instruction, for diagnostics.
+    source_lines: Vec<Option<usize>>,
+}
+
+#[derive(Debug)]
+pub struct VM {
+    /// The frame being executed.
+    ctx: ExecContext,
+    globals: VmState,
+    scripts: HashMap<String, ByteCode>,
     call_stack: Vec<(usize, Option<String>)>,
     instruction_count: usize,
     max_instructions: usize,
-    memo_cache: HashMap<(String, Vec<i32>), i32>,
+    memoize: bool,
+    /// See `VmOptions::lenient_args`.
+    lenient_args: bool,
+    memo_cache: HashMap<MemoKey, i32>,
+    /// Whether each script is free of global reads and writes, directly or
+    /// through the scripts it calls. Only pure scripts are memoized.
+    purity: HashMap<String, bool>,
+    script_overrides: HashMap<String, ScriptOverrides>,
+    /// What each script's annotations set, by name; `script_overrides` win.
+    annotated: HashMap<String, ScriptOverrides>,
+    deadline: Option<(Instant, u64)>,
+    profile: Option<HashMap<String, Vec<u64>>>,
+    host: Host,
+    commands: ScriptCommands,
+    /// Enums read by `enum_getvalue`, loaded once and kept across runs.
+    enums: HashMap<String, EnumConfig>,
+    /// Params read by `oc_param`, `nc_param` and `struct_param`.
+    params: Params,
+    /// What unset varps read as, when not 0.
+    varp_defaults: HashMap<i32, i32>,
+    /// Draws for `random`; kept across runs so repeated runs continue the
+    /// sequence.
+    rng: Rng,
+    /// String arguments for the next `run_script`, set by
+    /// `run_script_with_args`.
+    entry_string_args: HashMap<String, String>,
+    /// Timers set by `settimer`, which `Scheduler::tick` counts down; kept
+    /// across runs.
+    timers: Timers,
+    hooks: CallHooks,
+    breakpoints: HashSet<usize>,
+    debugger: Option<DebuggerSlot>,
+    /// Pause at the next new line regardless of breakpoints.
+    stepping: bool,
+    /// The innermost frame of the failing run, kept for `run_script_with_state`.
+    failed_frame: Option<FrameDump>,
+    /// Whether the failing run was stopped by `cancel_flag`, kept for
+    /// `run_script_with_state`.
+    stopped_by_cancel: bool,
+    call_profile: Option<CallProfile>,
+    /// Scripts `dedupe_scripts` found to duplicate another, by name.
+    aliases: HashMap<String, Alias>,
+    max_call_depth: usize,
+    /// How many script calls deep the current frame is; 0 in the entry script.
+    call_depth: usize,
+    /// Set from outside, e.g. by a Ctrl-C handler, to stop the current run;
+    /// see `cancel_flag`.
+    cancel: Arc<AtomicBool>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     pub fn new() -> Self {
         VM {
-            ip: 0,
-            stack: Vec::new(),
-            string_stack: Vec::new(),
-            variables: HashMap::new(),
-            string_variables: HashMap::new(),
-            arrays: HashMap::new(),
-            script_vars: Vec::new(),
+            ctx: ExecContext::default(),
+            globals: VmState::default(),
             scripts: HashMap::new(),
-            current_script: None,
             call_stack: Vec::new(),
             instruction_count: 0,
             max_instructions: 10_000_000,
+            memoize: true,
+            lenient_args: false,
             memo_cache: HashMap::new(),
+            purity: HashMap::new(),
+            script_overrides: HashMap::new(),
+            annotated: HashMap::new(),
+            deadline: None,
+            profile: None,
+            host: Host::default(),
+            commands: ScriptCommands::default(),
+            enums: HashMap::new(),
+            params: Params::default(),
+            varp_defaults: HashMap::new(),
+            rng: Rng::default(),
+            entry_string_args: HashMap::new(),
+            timers: Timers::default(),
+            hooks: CallHooks::default(),
+            breakpoints: HashSet::new(),
+            debugger: None,
+            stepping: false,
+            failed_frame: None,
+            stopped_by_cancel: false,
+            call_profile: None,
+            aliases: HashMap::new(),
+            max_call_depth: 200,
+            call_depth: 0,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_options(options: &VmOptions) -> Self {
+        let mut vm = Self::new();
+        vm.max_instructions = options.max_instructions;
+        vm.memoize = options.memoize;
+        vm.lenient_args = options.lenient_args;
+        vm.max_call_depth = options.max_call_depth;
+        if let Some(seed) = options.seed {
+            vm.set_seed(seed);
+        }
+        vm
+    }
+
+    /// A VM whose `random` draws are the same on every run with this seed.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut vm = Self::new();
+        vm.set_seed(seed);
+        vm
+    }
+
+    /// Restarts the `random` sequence from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
+
+    pub fn timers(&self) -> &Timers {
+        &self.timers
+    }
+
+    pub fn timers_mut(&mut self) -> &mut Timers {
+        &mut self.timers
+    }
+
+    /// Registers per-script option overrides. Names are matched
+    /// case-insensitively, like script names on the command line.
+    pub fn set_script_overrides(&mut self, overrides: &HashMap<String, ScriptOverrides>) {
+        self.script_overrides = overrides
+            .iter()
+            .map(|(name, overrides)| (name.to_lowercase(), overrides.clone()))
+            .collect();
+    }
+
+    /// The options set for `name` in the config, or else by its
+    /// annotations.
+    fn overrides(&self, name: &str) -> ScriptOverrides {
+        let configured = self.script_overrides.get(&name.to_lowercase()).cloned().unwrap_or_default();
+        match self.annotated.get(name) {
+            Some(annotated) => configured.or(annotated),
+            None => configured,
+        }
+    }
+
+    fn memoize_script(&mut self, name: &str) -> bool {
+        let enabled = self.overrides(name).memoize.unwrap_or(self.memoize);
+        enabled && self.is_pure(name)
+    }
+
+    /// A script's result can only be cached if it depends on nothing but its
+    /// arguments, so any global access, or a call to a script with one, rules
+    /// it out.
+    fn is_pure(&mut self, name: &str) -> bool {
+        if let Some(&pure) = self.purity.get(name) {
+            return pure;
+        }
+        let Some(script) = self.script(name) else {
+            return false;
+        };
+        let instructions = script.instructions.clone();
+        // Recursive calls are assumed pure while the script is being checked.
+        self.purity.insert(name.to_string(), true);
+        let pure = instructions.iter().all(|instruction| match instruction {
+            Instruction::Gosub(callee) | Instruction::GosubWithParams(callee) => self.is_pure(callee),
+            Instruction::Command(..) => false,
+            instruction => !instruction.touches_globals(),
+        });
+        self.purity.insert(name.to_string(), pure);
+        pure
+    }
+
+    /// Makes an enum available to `enum_getvalue`, replacing any of the same
+    /// name.
+    pub fn register_enum(&mut self, config: EnumConfig) {
+        self.enums.insert(config.name.clone(), config);
+    }
+
+    /// Sets what varp `id` reads as until it is first written.
+    pub fn set_varp_default(&mut self, id: i32, value: i32) {
+        self.varp_defaults.insert(id, value);
+    }
+
+    /// Registers the enums, params and varp defaults the compiled scripts
+    /// rely on, and passes the configs on to the host.
+    pub fn load_symbols(&mut self, symbols: &SymbolTable) {
+        for config in symbols.enums() {
+            self.register_enum(config.clone());
+        }
+        self.params = symbols.params().clone();
+        for (id, value) in symbols.varp_defaults() {
+            self.set_varp_default(id, value);
+        }
+        self.host.0.load_symbols(symbols);
+    }
+
+    /// Replaces the default `CommandRegistry` as the target of host commands.
+    pub fn set_host(&mut self, host: impl HostContext + 'static) {
+        self.host = Host(Box::new(host));
+    }
+
+    /// Adds a command that runs with the VM itself rather than in the host,
+    /// so it may run scripts of its own with `run_script` before returning;
+    /// the calling script's frame is kept as it was. Takes precedence over a
+    /// host command of the same name. A command can't call itself, directly
+    /// or through the scripts it runs.
+    pub fn register_command<F>(&mut self, name: &str, command: F)
+    where
+        F: FnMut(&mut VM, &[HostValue]) -> Result<i32, String> + 'static,
+    {
+        self.commands.0.insert(name.to_string(), Rc::new(RefCell::new(Box::new(command))));
+    }
+
+    /// Pauses before the first instruction of `line` in any registered
+    /// script, each time execution enters that line. A line without code
+    /// moves the breakpoint to the next line that has some. Returns the line
+    /// used, or `None` if no later line has code, in which case nothing is set.
+    pub fn add_breakpoint(&mut self, line: usize) -> Option<usize> {
+        let resolved = self
+            .scripts
+            .values()
+            .flat_map(|script| script.source_lines.iter().flatten())
+            .filter(|&&code_line| code_line >= line)
+            .min()
+            .copied()?;
+        self.breakpoints.insert(resolved);
+        Some(resolved)
+    }
+
+    /// Called each time a script starts running, with its arguments. Calls
+    /// answered from the memo cache don't run the script and aren't reported.
+    pub fn set_on_enter(&mut self, on_enter: impl FnMut(&str, &[i32]) + 'static) {
+        self.hooks.on_enter = Some(Box::new(on_enter));
+    }
+
+    /// Called each time a script returns, with its result. A script that
+    /// fails reports no exit; the error ends the whole run.
+    pub fn set_on_exit(&mut self, on_exit: impl FnMut(&str, i32) + 'static) {
+        self.hooks.on_exit = Some(Box::new(on_exit));
+    }
+
+    /// Called at every breakpoint. Without a debugger, breakpoints are ignored.
+    pub fn set_debugger(&mut self, debugger: impl Debugger + 'static) {
+        self.debugger = Some(DebuggerSlot(Box::new(debugger)));
+    }
+
+    /// Hands control to the debugger if `ip` starts a line with a breakpoint,
+    /// or any new line while stepping.
+    fn check_breakpoint(&mut self, script: &str, ip: usize) -> Result<(), String> {
+        if self.debugger.is_none() {
+            return Ok(());
+        }
+        let Some(line) = self.line_at(script, ip) else {
+            return Ok(());
+        };
+        let entered = self.ctx.last_line.as_ref().is_none_or(|(last_script, last)| last_script != script || *last != line);
+        self.ctx.last_line = Some((script.to_string(), line));
+        if !entered || !(self.stepping || self.breakpoints.contains(&line)) {
+            return Ok(());
+        }
+
+        let pause = Pause {
+            script,
+            line,
+            ip,
+            locals: &self.ctx.variables,
+            stack: &self.ctx.stack,
+        };
+        let action = match &mut self.debugger {
+            Some(DebuggerSlot(debugger)) => debugger.pause(&pause),
+            None => DebugAction::Continue,
+        };
+        self.stepping = action == DebugAction::Step;
+        if action == DebugAction::Abort {
+            return Err("Execution aborted by debugger".to_string());
+        }
+        Ok(())
+    }
+
+    /// Starts counting how many times each instruction executes.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(HashMap::new);
+    }
+
+    /// Execution counts per script, indexed by instruction pointer. Empty
+    /// unless `enable_profiling` was called.
+    pub fn profile(&self) -> HashMap<String, Vec<u64>> {
+        self.profile.clone().unwrap_or_default()
+    }
+
+    /// Starts attributing instructions and time to each script, and counting
+    /// calls between scripts; see `CallProfile`.
+    pub fn enable_call_profile(&mut self) {
+        self.call_profile.get_or_insert_with(CallProfile::new);
+    }
+
+    /// The profile of every run since `enable_call_profile` was called.
+    pub fn call_profile(&self) -> Option<&CallProfile> {
+        self.call_profile.as_ref()
+    }
+
+    /// A flag that stops the VM when set, from any thread: the run in
+    /// progress fails with `CANCELLED` at its next instruction, as do later
+    /// runs until the flag is cleared again.
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// Whether the flag from `cancel_flag` is set.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// The bytecode `name` runs, which for an alias left by `dedupe_scripts`
+    /// is the body it shares.
+    pub fn script(&self, name: &str) -> Option<&ByteCode> {
+        self.scripts.get(name).or_else(|| self.aliases.get(name).and_then(|alias| self.scripts.get(&alias.body)))
+    }
+
+    /// The script whose body `name` calls, if `dedupe_scripts` made it an
+    /// alias.
+    pub fn alias_of(&self, name: &str) -> Option<&str> {
+        self.aliases.get(name).map(|alias| alias.body.as_str())
+    }
+
+    /// The source line of instruction `ip` of `script`, in the script's own
+    /// file even when it is an alias.
+    pub fn line_at(&self, script: &str, ip: usize) -> Option<usize> {
+        match self.aliases.get(script) {
+            Some(alias) => alias.source_lines.get(ip).copied().flatten(),
+            None => self.scripts.get(script).and_then(|bytecode| bytecode.line_at(ip)),
+        }
+    }
+
+    /// Keeps one body of each set of registered scripts with the same
+    /// bytecode and registers the others as aliases of it, so calls by any
+    /// of their names run the shared body. Frames, errors and profiles
+    /// still name the script that was called.
+    pub fn dedupe_scripts(&mut self) -> Dedupe {
+        let dedupe = dedupe::find_duplicates(&self.scripts);
+        for (alias, body) in &dedupe.aliases {
+            let Some(bytecode) = self.scripts.remove(alias) else {
+                continue;
+            };
+            self.aliases.insert(alias.clone(), Alias { body: body.clone(), source_lines: bytecode.source_lines });
+        }
+        // Aliases of a script that just became an alias itself follow it.
+        for alias in self.aliases.values_mut() {
+            if let Some(shared) = dedupe.aliases.get(&alias.body) {
+                alias.body = shared.clone();
+            }
         }
+        dedupe
+    }
+
+    /// Number of script bodies stored, which aliases don't add to.
+    pub fn stored_scripts(&self) -> usize {
+        self.scripts.len()
+    }
+
+    fn record_execution(&mut self, script: &str, ip: usize, len: usize) {
+        if let Some(profile) = &mut self.profile {
+            if !profile.contains_key(script) {
+                profile.insert(script.to_string(), vec![0; len]);
+            }
+            if let Some(count) = profile.get_mut(script).and_then(|counts| counts.get_mut(ip)) {
+                *count += 1;
+            }
+        }
+    }
+
+    fn check_limits(&mut self) -> Result<(), String> {
+        if self.is_cancelled() {
+            self.stopped_by_cancel = true;
+            return Err(CANCELLED.to_string());
+        }
+        if self.instruction_count >= self.max_instructions {
+            return Err(format!("Execution exceeded maximum instruction count ({}).", self.max_instructions));
+        }
+        // Reading the clock every instruction is noticeably slow, so only sample it.
+        if let Some((deadline, timeout)) = self.deadline {
+            if self.instruction_count.is_multiple_of(1024) && Instant::now() >= deadline {
+                return Err(format!("Execution exceeded timeout ({}ms).", timeout));
+            }
+        }
+        Ok(())
     }
 
     pub fn register_script(&mut self, bytecode: ByteCode) {
-        self.scripts.insert(bytecode.script_name.clone(), bytecode);
+        self.purity.clear();
+        let name = bytecode.script_name.clone();
+        let (annotated, _) = annotations::overrides(&bytecode.annotations);
+        if annotated == ScriptOverrides::default() {
+            self.annotated.remove(&name);
+        } else {
+            self.annotated.insert(name.clone(), annotated);
+        }
+        self.aliases.remove(&name);
+        if let Some(mut old) = self.scripts.insert(name.clone(), bytecode) {
+            // Scripts sharing the replaced body keep it, now stored under
+            // the first of their names.
+            let mut sharing: Vec<String> =
+                self.aliases.iter().filter(|(_, alias)| alias.body == name).map(|(alias, _)| alias.clone()).collect();
+            sharing.sort();
+            if let Some((heir, rest)) = sharing.split_first() {
+                if let Some(alias) = self.aliases.remove(heir) {
+                    old.source_lines = alias.source_lines;
+                }
+                old.script_name = heir.clone();
+                self.scripts.insert(heir.clone(), old);
+                for alias in rest {
+                    if let Some(alias) = self.aliases.get_mut(alias) {
+                        alias.body = heir.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn globals(&self) -> &VmState {
+        &self.globals
+    }
+
+    pub fn globals_mut(&mut self) -> &mut VmState {
+        &mut self.globals
+    }
+
+    pub fn get_varp(&self, id: i32) -> i32 {
+        self.globals.varps.get(&id).or_else(|| self.varp_defaults.get(&id)).copied().unwrap_or(0)
+    }
+
+    pub fn set_varp(&mut self, id: i32, value: i32) {
+        self.globals.varps.insert(id, value);
+    }
+
+    /// Captures every global store so it can later be rolled back with `restore`.
+    pub fn snapshot(&self) -> VmState {
+        self.globals.clone()
+    }
+
+    /// Rolls the global stores back to `state`. Memoized results may depend on
+    /// globals that have since changed, so the memo cache is dropped as well.
+    pub fn restore(&mut self, state: VmState) {
+        self.globals = state;
+        self.memo_cache.clear();
+    }
+
+    /// Runs the most specific registered trigger for an event, such as
+    /// `opnpc1` on an npc: `[kind,subject_name]`, else the subject's
+    /// `[kind,_category]`, else `[kind,_]`. Returns `None` if none is
+    /// registered, as the engine ignores events nothing handles.
+    pub fn dispatch_trigger(
+        &mut self,
+        kind: &str,
+        subject_name: &str,
+        subject_category: Option<&str>,
+        args: &[i32],
+    ) -> Result<Option<i32>, String> {
+        let Some(name) = triggers::dispatch_order(kind, subject_name, subject_category)
+            .into_iter()
+            .find(|name| self.script(name).is_some())
+        else {
+            return Ok(None);
+        };
+        self.run_script(&name, args).map(Some)
+    }
+
+    /// Like `run_script`, but the arguments may be strings, for scripts with
+    /// `string` parameters. String arguments go to the string locals
+    /// `arg0`, `arg1`, ... by position, and ints to the int ones. Never
+    /// answered from the memo cache, which is keyed by ints alone.
+    pub fn run_script_with_args(&mut self, name: &str, args: &[HostValue]) -> Result<i32, String> {
+        let mut ints = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            match arg {
+                HostValue::Int(value) => ints.push(*value),
+                HostValue::Str(text) => {
+                    ints.push(0);
+                    self.entry_string_args.insert(format!("arg{}", i), text.clone());
+                }
+            }
+        }
+        let result = self.run_script(name, &ints);
+        // Left over if the run failed before starting the script.
+        self.entry_string_args.clear();
+        result
+    }
+
+    /// Like `run_script_with_args`, but a failure carries the locals,
+    /// stacks and position of the frame that failed.
+    pub fn run_script_with_state(&mut self, name: &str, args: &[HostValue]) -> Result<i32, VmError> {
+        self.failed_frame = None;
+        self.stopped_by_cancel = false;
+        self.run_script_with_args(name, args).map_err(|message| VmError {
+            message,
+            frame: self.failed_frame.take().map(Box::new),
+            cancelled: std::mem::take(&mut self.stopped_by_cancel),
+        })
+    }
+
+    /// Keeps the current frame as the one that failed, unless a frame it
+    /// called failed first.
+    fn record_failure(&mut self) {
+        if self.failed_frame.is_some() {
+            return;
+        }
+        let Some(script) = self.ctx.current_script.clone() else {
+            return;
+        };
+        // `ip` has already moved past the failing instruction.
+        let ip = self.ctx.ip.saturating_sub(1);
+        self.failed_frame = Some(FrameDump {
+            line: self.line_at(&script, ip),
+            script,
+            ip,
+            locals: self
+                .ctx
+                .variables
+                .iter()
+                .filter(|(name, _)| !name.starts_with("__"))
+                .map(|(name, value)| (name.clone(), *value))
+                .collect(),
+            string_locals: self.ctx.string_variables.iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+            stack: self.ctx.stack.clone(),
+            string_stack: self.ctx.string_stack.clone(),
+        });
     }
 
     pub fn run_script(&mut self, name: &str, args: &[i32]) -> Result<i32, String> {
         println!("Executing {} with args: {:?}", name, args);
-        
-        // Clear any existing variables
-        self.variables.clear();
-        
-        // Set up arguments
-        for (i, &arg) in args.iter().enumerate() {
-            let arg_name = format!("arg{}", i);
-            println!("Setting {} = {}", arg_name, arg);
-            self.variables.insert(arg_name, arg);
+        for (i, arg) in args.iter().enumerate() {
+            println!("Setting arg{} = {}", i, arg);
         }
 
         // Check memo cache first
         let cache_key = (name.to_string(), args.to_vec());
-        if let Some(&cached_result) = self.memo_cache.get(&cache_key) {
-            return Ok(cached_result);
+        if self.memoize_script(name) && self.entry_string_args.is_empty() {
+            if let Some(&cached_result) = self.memo_cache.get(&cache_key) {
+                return Ok(cached_result);
+            }
         }
 
-        let script = self.scripts.get(name).ok_or_else(|| format!("Script '{}' not found", name))?;
+        let script = self.script(name).ok_or_else(|| format!("Script '{}' not found", name))?;
+        let args = &bind_args(script, args, self.lenient_args)?;
         let instructions = script.instructions.clone();
-        
-        // Save current state
-        let old_ip = self.ip;
-        let old_script = self.current_script.clone();
-        let old_variables = self.variables.clone();
-        let old_stack = self.stack.clone();
-        
-        // Reset instruction pointer and initialize new variables
-        self.ip = 0;
-        self.current_script = Some(name.to_string());
-        self.variables.clear();
-        self.stack.clear();
-        
-        // Initialize script arguments
-        for (i, &arg) in args.iter().enumerate() {
-            let arg_name = format!("arg{}", i);
-            self.variables.insert(arg_name, arg);
+        if let Some(on_enter) = &mut self.hooks.on_enter {
+            on_enter(name, args);
+        }
+        if let Some(profile) = &mut self.call_profile {
+            profile.enter(name, self.instruction_count);
         }
         
+        // Overrides for the entry script govern the whole run.
+        let old_limits = (self.max_instructions, self.deadline);
+        if self.ctx.current_script.is_none() {
+            let overrides = self.overrides(name);
+            if let Some(max_instructions) = overrides.max_instructions {
+                self.max_instructions = max_instructions;
+            }
+            if let Some(timeout) = overrides.timeout {
+                self.deadline = Some((Instant::now() + Duration::from_millis(timeout), timeout));
+            }
+        }
+
+        let string_args = std::mem::take(&mut self.entry_string_args);
+        let caller = std::mem::replace(&mut self.ctx, ExecContext::new(name, args, string_args));
+
         // Execute instructions
         let mut result = Ok(0);
-        while self.ip < instructions.len() {
-            if self.instruction_count >= self.max_instructions {
-                result = Err(format!("Execution exceeded maximum instruction count ({}).", self.max_instructions));
+        while self.ctx.ip < instructions.len() {
+            if let Err(e) = self.check_limits() {
+                result = Err(e);
                 break;
             }
             self.instruction_count += 1;
             
-            let current_ip = self.ip;
-            self.ip += 1;  // Advance instruction pointer by default
+            let current_ip = self.ctx.ip;
+            self.ctx.ip += 1;  // Advance instruction pointer by default
+            self.record_execution(name, current_ip, instructions.len());
+            if let Err(e) = self.check_breakpoint(name, current_ip) {
+                result = Err(e);
+                break;
+            }
             
             match &instructions[current_ip] {
                 Instruction::PushConstantInt(value) => {
                     println!("Pushing constant: {}", value);
-                    self.stack.push(*value);
+                    self.ctx.stack.push(*value);
                 }
                 
                 Instruction::PushIntLocal(name) => {
-                    let value = self.variables.get(name).copied().unwrap_or(0);
+                    let value = self.ctx.variables.get(name).copied().unwrap_or(0);
                     println!("Pushing local {}: {}", name, value);
-                    self.stack.push(value);
+                    self.ctx.stack.push(value);
                 }
                 
                 Instruction::PopIntLocal(name) => {
-                    let value = self.stack.pop().unwrap_or(0);
+                    let value = self.ctx.stack.pop().unwrap_or(0);
                     println!("Popping into local {}: {}", name, value);
-                    self.variables.insert(name.clone(), value);
+                    self.ctx.variables.insert(name.clone(), value);
+                }
+
+                Instruction::PushConstantString(value) => {
+                    self.ctx.string_stack.push(value.clone());
+                }
+
+                Instruction::PushStringConstant(index) => match self.pooled_string(*index) {
+                    Ok(value) => self.ctx.string_stack.push(value),
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                },
+
+                Instruction::PushStringLocal(name) => {
+                    let value = self.ctx.string_variables.get(name).cloned().unwrap_or_default();
+                    self.ctx.string_stack.push(value);
+                }
+
+                Instruction::PopStringLocal(name) => {
+                    let value = self.ctx.string_stack.pop().unwrap_or_default();
+                    self.ctx.string_variables.insert(name.clone(), value);
+                }
+
+                Instruction::JoinString(count) => {
+                    if let Err(e) = self.join_strings(*count) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::PopIntDiscard => {
+                    self.ctx.stack.pop();
+                }
+
+                Instruction::PopStringDiscard => {
+                    self.ctx.string_stack.pop();
+                }
+
+                Instruction::Command(name, signature) => {
+                    if let Err(e) = self.call_command(name, signature) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::PackCoord
+                | Instruction::CoordX
+                | Instruction::CoordZ
+                | Instruction::CoordLevel
+                | Instruction::CoordDistance
+                | Instruction::PackCoord2
+                | Instruction::Coord2X
+                | Instruction::Coord2Y
+                | Instruction::PackCoord3
+                | Instruction::Coord3X
+                | Instruction::Coord3Y
+                | Instruction::Coord3Z => {
+                    if let Err(e) = self.coord_command(&instructions[current_ip]) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::IntToString | Instruction::StringToInt | Instruction::CompareStrings => {
+                    if let Err(e) = self.convert_command(&instructions[current_ip]) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::StringLength
+                | Instruction::Substring
+                | Instruction::StringIndexOf
+                | Instruction::LowerCase
+                | Instruction::UpperCase => {
+                    if let Err(e) = self.string_command(&instructions[current_ip]) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::Random => {
+                    if let Err(e) = self.random() {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::EnumGetValue(name) => {
+                    if let Err(e) = self.enum_get_value(name) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::OcParam(_) | Instruction::NcParam(_) | Instruction::StructParam(_) => {
+                    if let Err(e) = self.param_command(&instructions[current_ip]) {
+                        result = Err(e);
+                        break;
+                    }
                 }
                 
                 Instruction::Add => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     match a.checked_add(b) {
-                        Some(result) => self.stack.push(result),
+                        Some(result) => self.ctx.stack.push(result),
                         None => {
                             result = Err("Integer overflow".to_string());
                             break;
@@ -124,10 +946,10 @@ impl VM {
                 }
                 
                 Instruction::Subtract => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     match a.checked_sub(b) {
-                        Some(result) => self.stack.push(result),
+                        Some(result) => self.ctx.stack.push(result),
                         None => {
                             result = Err("Integer overflow".to_string());
                             break;
@@ -136,90 +958,182 @@ impl VM {
                 }
                 
                 Instruction::Multiply => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     match a.checked_mul(b) {
                         Some(result) => {
                             println!("Multiplying {} * {} = {}", a, b, result);
-                            self.stack.push(result)
+                            self.ctx.stack.push(result)
                         },
                         None => return Err("Integer overflow".to_string()),
                     }
                 }
                 
+                Instruction::Divide => {
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
+                    if b == 0 {
+                        result = Err("Division by zero".to_string());
+                        break;
+                    }
+                    match a.checked_div(b) {
+                        Some(result) => self.ctx.stack.push(result),
+                        None => {
+                            result = Err("Integer overflow".to_string());
+                            break;
+                        }
+                    }
+                }
+
+                Instruction::Modulo => {
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
+                    if b == 0 {
+                        result = Err("Division by zero".to_string());
+                        break;
+                    }
+                    match a.checked_rem(b) {
+                        Some(result) => self.ctx.stack.push(result),
+                        None => {
+                            result = Err("Integer overflow".to_string());
+                            break;
+                        }
+                    }
+                }
+                
+                Instruction::Min | Instruction::Max => self.min_max(&instructions[current_ip]),
+
+                Instruction::Rgb | Instruction::ColorComponent => {
+                    if let Err(e) = self.colour_command(&instructions[current_ip]) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
+                Instruction::SetTimer(_) | Instruction::ClearTimer => {
+                    if let Err(e) = self.timer_command(&instructions[current_ip]) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+
                 Instruction::Abs => {
-                    let value = self.stack.pop().unwrap_or(0);
-                    let result = if value < 0 { -value } else { value };
-                    println!("Abs {} = {}", value, result);
-                    self.stack.push(result);
+                    let value = self.ctx.stack.pop().unwrap_or(0);
+                    match value.checked_abs() {
+                        Some(result) => {
+                            println!("Abs {} = {}", value, result);
+                            self.ctx.stack.push(result);
+                        }
+                        None => {
+                            result = Err("Integer overflow".to_string());
+                            break;
+                        }
+                    }
+                }
+
+                Instruction::PushVarp(id) => {
+                    let value = self.get_varp(*id);
+                    println!("Pushing varp {}: {}", id, value);
+                    self.ctx.stack.push(value);
+                }
+                
+                Instruction::PopVarp(id) => {
+                    let value = self.ctx.stack.pop().unwrap_or(0);
+                    println!("Popping into varp {}: {}", id, value);
+                    self.set_varp(*id, value);
+                }
+
+                Instruction::PushVarbit(id, start_bit, end_bit) => {
+                    let value = varbits::read(self.get_varp(*id), *start_bit, *end_bit);
+                    println!("Pushing varp {} bits {}..={}: {}", id, start_bit, end_bit, value);
+                    self.ctx.stack.push(value);
+                }
+
+                Instruction::PopVarbit(id, start_bit, end_bit) => {
+                    if let Err(e) = self.pop_varbit(*id, *start_bit, *end_bit) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+                
+                Instruction::PushVarn(id) => {
+                    let value = self.globals.varns.get(id).copied().unwrap_or(0);
+                    println!("Pushing varn {}: {}", id, value);
+                    self.ctx.stack.push(value);
+                }
+                
+                Instruction::PopVarn(id) => {
+                    let value = self.ctx.stack.pop().unwrap_or(0);
+                    println!("Popping into varn {}: {}", id, value);
+                    self.globals.varns.insert(*id, value);
                 }
                 
                 Instruction::BranchGreaterThan(pos) => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     println!("Comparing {} > {}", a, b);
                     if a > b {
                         println!("Branch taken to {}", pos);
-                        self.ip = *pos;
+                        self.ctx.ip = *pos;
                     } else {
                         println!("Branch not taken");
                     }
                 }
                 
                 Instruction::BranchGreaterThanOrEquals(pos) => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     println!("Comparing {} >= {}", a, b);
                     if a >= b {
                         println!("Branch taken to {}", pos);
-                        self.ip = *pos;
+                        self.ctx.ip = *pos;
                     } else {
                         println!("Branch not taken");
                     }
                 }
                 
                 Instruction::BranchLessThan(pos) => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     println!("Comparing {} < {}", a, b);
                     if a < b {
                         println!("Branch taken to {}", pos);
-                        self.ip = *pos;
+                        self.ctx.ip = *pos;
                     } else {
                         println!("Branch not taken");
                     }
                 }
                 
                 Instruction::BranchLessThanOrEquals(pos) => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     println!("Comparing {} <= {}", a, b);
                     if a <= b {
                         println!("Branch taken to {}", pos);
-                        self.ip = *pos;
+                        self.ctx.ip = *pos;
                     } else {
                         println!("Branch not taken");
                     }
                 }
                 
                 Instruction::BranchEquals(pos) => {
-                    let b = self.stack.pop().unwrap_or(0);
-                    let a = self.stack.pop().unwrap_or(0);
+                    let b = self.ctx.stack.pop().unwrap_or(0);
+                    let a = self.ctx.stack.pop().unwrap_or(0);
                     println!("Comparing {} = {}", a, b);
                     if a == b {
                         println!("Branch taken to {}", pos);
-                        self.ip = *pos;
+                        self.ctx.ip = *pos;
                     } else {
                         println!("Branch not taken");
                     }
                 }
                 
                 Instruction::BranchNot(pos) => {
-                    let value = self.stack.pop().unwrap_or(0);
+                    let value = self.ctx.stack.pop().unwrap_or(0);
                     println!("Testing condition: {}", value);
                     if value == 0 {
                         println!("Branch taken to {}", pos);
-                        self.ip = *pos;
+                        self.ctx.ip = *pos;
                     } else {
                         println!("Branch not taken");
                     }
@@ -227,110 +1141,25 @@ impl VM {
                 
                 Instruction::Jump(pos) => {
                     println!("Jumping to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
+                }
+
+                Instruction::Switch { low, targets, default } => {
+                    let value = self.ctx.stack.pop().unwrap_or(0);
+                    let target = switch_target(value, *low, targets, *default);
+                    println!("Switch on {} to {}", value, target);
+                    self.ctx.ip = target;
                 }
                 
                 Instruction::GosubWithParams(script_name) => {
-                    // Pop arguments in reverse order (since they were pushed in forward order)
-                    let mut args = Vec::new();
-                    let num_args = self.stack.pop().unwrap_or(0) as usize;
-                    for _ in 0..num_args {
-                        args.push(self.stack.pop().unwrap_or(0));
-                    }
-                    args.reverse(); // Put them back in the right order
-                    
-                    // Debug print
-                    println!("Executing {} with args: {:?}", script_name, args);
-                    
-                    // Check memo cache first
-                    let cache_key = (script_name.clone(), args.clone());
-                    if let Some(&cached_result) = self.memo_cache.get(&cache_key) {
-                        println!("Cache hit for {} with args {:?}: result = {}", script_name, args, cached_result);
-                        self.stack.push(cached_result);
-                        continue;
-                    }
-                    println!("Cache miss for {} with args {:?}", script_name, args);
-
-                    // Save current state
-                    let saved_ip = self.ip;
-                    let saved_script = self.current_script.clone();
-                    let saved_variables = self.variables.clone();
-                    let saved_stack = self.stack.clone();
-                    
-                    // Set up new script execution
-                    self.ip = 0;
-                    self.current_script = Some(script_name.clone());
-                    self.variables.clear();
-                    self.stack.clear();
-                    
-                    // Set up arguments
-                    for (i, &arg) in args.iter().enumerate() {
-                        let arg_name = format!("arg{}", i);
-                        println!("Setting {} = {}", arg_name, arg);
-                        self.variables.insert(arg_name, arg);
-                    }
-                    
-                    // Get the script
-                    let script = match self.scripts.get(script_name) {
-                        Some(script) => script,
-                        None => {
-                            result = Err(format!("Script '{}' not found", script_name));
-                            break;
-                        }
-                    };
-                    
-                    // Execute the script
-                    let mut script_result = Ok(0);
-                    let script_instructions = script.instructions.clone();
-                    while self.ip < script_instructions.len() {
-                        if self.instruction_count >= self.max_instructions {
-                            script_result = Err(format!("Execution exceeded maximum instruction count ({}).", self.max_instructions));
-                            break;
-                        }
-                        self.instruction_count += 1;
-                        
-                        let current_ip = self.ip;
-                        self.ip += 1;
-                        
-                        match &script_instructions[current_ip] {
-                            Instruction::Return => {
-                                let return_value = self.stack.pop().unwrap_or(0);
-                                script_result = Ok(return_value);
-                                break;
-                            }
-                            _ => {
-                                // Handle other instructions recursively
-                                match self.execute_instruction(&script_instructions[current_ip]) {
-                                    Ok(_) => continue,
-                                    Err(e) => {
-                                        script_result = Err(e);
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    
-                    // Restore state
-                    self.ip = saved_ip;
-                    self.current_script = saved_script;
-                    self.variables = saved_variables;
-                    self.stack = saved_stack;
-                    
-                    match script_result {
-                        Ok(value) => {
-                            self.stack.push(value);
-                            self.memo_cache.insert(cache_key, value);
-                        }
-                        Err(e) => {
-                            result = Err(e);
-                            break;
-                        }
+                    if let Err(e) = self.gosub(script_name) {
+                        result = Err(e);
+                        break;
                     }
                 }
                 
                 Instruction::Return => {
-                    let return_value = self.stack.pop().unwrap_or(0);
+                    let return_value = self.ctx.stack.pop().unwrap_or(0);
                     result = Ok(return_value);
                     break;
                 }
@@ -342,132 +1171,593 @@ impl VM {
             }
         }
         
-        // Restore previous state
-        self.ip = old_ip;
-        self.current_script = old_script;
-        self.variables = old_variables;
-        self.stack = old_stack;
-        
+        (self.max_instructions, self.deadline) = old_limits;
+        if result.is_err() {
+            self.record_failure();
+        }
+        if let Some(profile) = &mut self.call_profile {
+            profile.exit(self.instruction_count);
+        }
+        self.ctx = caller;
+
+        if let (Ok(value), Some(on_exit)) = (&result, &mut self.hooks.on_exit) {
+            on_exit(name, *value);
+        }
         result
     }
 
+    /// Pops the top `count` strings and pushes them back joined, first
+    /// pushed first.
+    fn join_strings(&mut self, count: usize) -> Result<(), String> {
+        if count > self.ctx.string_stack.len() {
+            return Err(format!(
+                "JoinString({}) with only {} strings on the stack",
+                count,
+                self.ctx.string_stack.len()
+            ));
+        }
+        let parts = self.ctx.string_stack.split_off(self.ctx.string_stack.len() - count);
+        let joined = parts.concat();
+        println!("Joining {} strings: {:?}", count, joined);
+        self.ctx.string_stack.push(joined);
+        Ok(())
+    }
+
+    /// Pops a host command's arguments, int or string as `signature` says,
+    /// calls it and pushes its result.
+    fn call_command(&mut self, name: &str, signature: &str) -> Result<(), String> {
+        let mut args = Vec::new();
+        for kind in signature.chars().rev() {
+            args.push(match kind {
+                's' => HostValue::Str(self.ctx.string_stack.pop().unwrap_or_default()),
+                _ => HostValue::Int(self.ctx.stack.pop().unwrap_or(0)),
+            });
+        }
+        args.reverse();
+        println!("Calling command {} with args: {:?}", name, args);
+        if compiler::returns_string(name) {
+            let text = self
+                .host
+                .0
+                .call_string(name, &args)
+                .unwrap_or_else(|| Err(format!("Unknown command: {}", name)))
+                .map_err(|message| format!("{}: {}", name, message))?;
+            self.ctx.string_stack.push(text);
+            return Ok(());
+        }
+        let value = match self.commands.0.get(name).cloned() {
+            Some(command) => match command.try_borrow_mut() {
+                Ok(mut command) => command(self, &args),
+                Err(_) => Err("already running".to_string()),
+            },
+            None => self
+                .host
+                .0
+                .call(name, &args)
+                .unwrap_or_else(|| Err(format!("Unknown command: {}", name))),
+        }
+        .map_err(|message| format!("{}: {}", name, message))?;
+        self.ctx.stack.push(value);
+        Ok(())
+    }
+
+    fn coord_command(&mut self, instruction: &Instruction) -> Result<(), String> {
+        let name = instruction.coord_command().ok_or("Not a coord command")?;
+        let (params, _) = coord::signature(name).ok_or("Not a coord command")?;
+        let mut args = vec![0; params.len()];
+        for arg in args.iter_mut().rev() {
+            *arg = self.ctx.stack.pop().unwrap_or(0);
+        }
+        let value = coord::call(name, &args)?;
+        println!("{}{:?} = {}", name, args, value);
+        self.ctx.stack.push(value);
+        Ok(())
+    }
+
+    fn enum_get_value(&mut self, name: &str) -> Result<(), String> {
+        let key = self.ctx.stack.pop().unwrap_or(0);
+        let config = self
+            .enums
+            .get(name)
+            .ok_or_else(|| format!("enum_getvalue: enum '{}' is not loaded", name))?;
+        let value = config.get(key)?;
+        println!("enum_getvalue({}, {}) = {}", name, key, value);
+        self.ctx.stack.push(value);
+        Ok(())
+    }
+
+    fn pop_varbit(&mut self, id: i32, start_bit: u8, end_bit: u8) -> Result<(), String> {
+        let value = self.ctx.stack.pop().unwrap_or(0);
+        let varp = varbits::write(self.get_varp(id), start_bit, end_bit, value)
+            .map_err(|e| format!("{} in varp {}", e, id))?;
+        println!("Popping into varp {} bits {}..={}: {}", id, start_bit, end_bit, value);
+        self.set_varp(id, varp);
+        Ok(())
+    }
+
+    /// Looks up a string literal in the running script's pool.
+    fn pooled_string(&self, index: usize) -> Result<String, String> {
+        self.ctx.current_script
+            .as_ref()
+            .and_then(|name| self.script(name))
+            .and_then(|script| script.strings.get(index))
+            .cloned()
+            .ok_or_else(|| format!("String constant {} not found", index))
+    }
+
+    fn convert_command(&mut self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::IntToString => {
+                let value = self.ctx.stack.pop().unwrap_or(0);
+                println!("tostring({}) = \"{}\"", value, value);
+                self.ctx.string_stack.push(value.to_string());
+            }
+            Instruction::StringToInt => {
+                let text = self.ctx.string_stack.pop().unwrap_or_default();
+                let value = convert::to_int(&text)?;
+                println!("toint(\"{}\") = {}", text, value);
+                self.ctx.stack.push(value);
+            }
+            Instruction::CompareStrings => {
+                let b = self.ctx.string_stack.pop().unwrap_or_default();
+                let a = self.ctx.string_stack.pop().unwrap_or_default();
+                let value = convert::compare(&a, &b);
+                println!("compare(\"{}\", \"{}\") = {}", a, b, value);
+                self.ctx.stack.push(value);
+            }
+            _ => return Err("Not a conversion command".to_string()),
+        }
+        Ok(())
+    }
+
+    fn min_max(&mut self, instruction: &Instruction) {
+        let b = self.ctx.stack.pop().unwrap_or(0);
+        let a = self.ctx.stack.pop().unwrap_or(0);
+        let (command, value) = match instruction {
+            Instruction::Min => ("min", a.min(b)),
+            _ => ("max", a.max(b)),
+        };
+        println!("{}({}, {}) = {}", command, a, b, value);
+        self.ctx.stack.push(value);
+    }
+
+    fn colour_command(&mut self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::Rgb => {
+                let blue = self.ctx.stack.pop().unwrap_or(0);
+                let green = self.ctx.stack.pop().unwrap_or(0);
+                let red = self.ctx.stack.pop().unwrap_or(0);
+                let value = colour::rgb(red, green, blue)?;
+                println!("rgb({}, {}, {}) = 0x{:06X}", red, green, blue, value);
+                self.ctx.stack.push(value);
+            }
+            Instruction::ColorComponent => {
+                let index = self.ctx.stack.pop().unwrap_or(0);
+                let value = self.ctx.stack.pop().unwrap_or(0);
+                let component = colour::component(value, index)?;
+                println!("color_component(0x{:06X}, {}) = {}", value, index, component);
+                self.ctx.stack.push(component);
+            }
+            _ => return Err("Not a colour command".to_string()),
+        }
+        Ok(())
+    }
+
+    fn param_command(&mut self, instruction: &Instruction) -> Result<(), String> {
+        let (command, param) = match instruction {
+            Instruction::OcParam(param) => ("oc_param", param),
+            Instruction::NcParam(param) => ("nc_param", param),
+            Instruction::StructParam(param) => ("struct_param", param),
+            _ => return Err("Not a param command".to_string()),
+        };
+        let owner = self.ctx.stack.pop().unwrap_or(0);
+        let value = self.params.get(command, owner, param)?;
+        println!("{}({}, {}) = {:?}", command, owner, param, value);
+        match value {
+            HostValue::Int(value) => self.ctx.stack.push(value),
+            HostValue::Str(text) => self.ctx.string_stack.push(text),
+        }
+        Ok(())
+    }
+
+    fn timer_command(&mut self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::SetTimer(script) => {
+                let ticks = self.ctx.stack.pop().unwrap_or(0);
+                let timer = self.ctx.stack.pop().unwrap_or(0);
+                self.timers.set(timer, ticks, script)?;
+                println!("settimer({}, {}, ~{})", timer, ticks, script);
+            }
+            Instruction::ClearTimer => {
+                let timer = self.ctx.stack.pop().unwrap_or(0);
+                self.timers.clear(timer);
+                println!("cleartimer({})", timer);
+            }
+            _ => return Err("Not a timer command".to_string()),
+        }
+        self.ctx.stack.push(0);
+        Ok(())
+    }
+
+    fn random(&mut self) -> Result<(), String> {
+        let bound = self.ctx.stack.pop().unwrap_or(0);
+        let value = self.rng.random(bound)?;
+        println!("random({}) = {}", bound, value);
+        self.ctx.stack.push(value);
+        Ok(())
+    }
+
+    fn string_command(&mut self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::StringLength => {
+                let text = self.ctx.string_stack.pop().unwrap_or_default();
+                let value = strings::length(&text);
+                println!("string_length(\"{}\") = {}", text, value);
+                self.ctx.stack.push(value);
+            }
+            Instruction::Substring => {
+                let end = self.ctx.stack.pop().unwrap_or(0);
+                let start = self.ctx.stack.pop().unwrap_or(0);
+                let text = self.ctx.string_stack.pop().unwrap_or_default();
+                let value = strings::substring(&text, start, end)?;
+                println!("substring(\"{}\", {}, {}) = \"{}\"", text, start, end, value);
+                self.ctx.string_stack.push(value);
+            }
+            Instruction::StringIndexOf => {
+                let find = self.ctx.string_stack.pop().unwrap_or_default();
+                let text = self.ctx.string_stack.pop().unwrap_or_default();
+                let value = strings::index_of(&text, &find);
+                println!("string_indexof(\"{}\", \"{}\") = {}", text, find, value);
+                self.ctx.stack.push(value);
+            }
+            Instruction::LowerCase | Instruction::UpperCase => {
+                let text = self.ctx.string_stack.pop().unwrap_or_default();
+                let (command, value) = match instruction {
+                    Instruction::LowerCase => ("lowercase", text.to_lowercase()),
+                    _ => ("uppercase", text.to_uppercase()),
+                };
+                println!("{}(\"{}\") = \"{}\"", command, text, value);
+                self.ctx.string_stack.push(value);
+            }
+            _ => return Err("Not a string command".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Calls `script_name` from the current frame: pops its arguments and
+    /// their count, runs it in a frame of its own and pushes its result.
+    fn gosub(&mut self, script_name: &str) -> Result<(), String> {
+        // Pop arguments in reverse order (since they were pushed in forward order)
+        let mut args = Vec::new();
+        let num_args = self.ctx.stack.pop().unwrap_or(0) as usize;
+        for _ in 0..num_args {
+            args.push(self.ctx.stack.pop().unwrap_or(0));
+        }
+        args.reverse(); // Put them back in the right order
+
+        // Debug print
+        println!("Executing {} with args: {:?}", script_name, args);
+
+        // Check memo cache first
+        let cache_key = (script_name.to_string(), args.clone());
+        let memoize = self.memoize_script(script_name);
+        if memoize {
+            if let Some(&cached_result) = self.memo_cache.get(&cache_key) {
+                println!("Cache hit for {} with args {:?}: result = {}", script_name, args, cached_result);
+                self.ctx.stack.push(cached_result);
+                return Ok(());
+            }
+            println!("Cache miss for {} with args {:?}", script_name, args);
+        }
+
+        for (i, arg) in args.iter().enumerate() {
+            println!("Setting arg{} = {}", i, arg);
+        }
+
+        // Get the script
+        let script = self.script(script_name).ok_or_else(|| format!("Script '{}' not found", script_name))?;
+        let args = bind_args(script, &args, self.lenient_args)?;
+        if self.call_depth >= self.max_call_depth {
+            return Err(format!("Execution exceeded maximum call depth ({}).", self.max_call_depth));
+        }
+        let script_instructions = script.instructions.clone();
+        let caller = std::mem::replace(&mut self.ctx, ExecContext::new(script_name, &args, HashMap::new()));
+        self.call_depth += 1;
+        if let Some(on_enter) = &mut self.hooks.on_enter {
+            on_enter(script_name, &args);
+        }
+        if let Some(profile) = &mut self.call_profile {
+            profile.enter(script_name, self.instruction_count);
+        }
+
+        // Execute the script
+        let mut script_result = Ok(0);
+        while self.ctx.ip < script_instructions.len() {
+            if let Err(e) = self.check_limits() {
+                script_result = Err(e);
+                break;
+            }
+            self.instruction_count += 1;
+
+            let current_ip = self.ctx.ip;
+            self.ctx.ip += 1;
+            self.record_execution(script_name, current_ip, script_instructions.len());
+            if let Err(e) = self.check_breakpoint(script_name, current_ip) {
+                script_result = Err(e);
+                break;
+            }
+
+            match &script_instructions[current_ip] {
+                Instruction::Return => {
+                    let return_value = self.ctx.stack.pop().unwrap_or(0);
+                    script_result = Ok(return_value);
+                    break;
+                }
+                // Everything else, nested calls included, runs through
+                // execute_instruction.
+                instruction => {
+                    if let Err(e) = self.execute_instruction(instruction) {
+                        script_result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if script_result.is_err() {
+            self.record_failure();
+        }
+        if let Some(profile) = &mut self.call_profile {
+            profile.exit(self.instruction_count);
+        }
+        self.ctx = caller;
+        self.call_depth -= 1;
+
+        let value = script_result?;
+        if let Some(on_exit) = &mut self.hooks.on_exit {
+            on_exit(script_name, value);
+        }
+        self.ctx.stack.push(value);
+        if memoize {
+            self.memo_cache.insert(cache_key, value);
+        }
+        Ok(())
+    }
+
     fn execute_instruction(&mut self, instruction: &Instruction) -> Result<(), String> {
         match instruction {
+            Instruction::GosubWithParams(script_name) => self.gosub(script_name)?,
+
             Instruction::PushConstantInt(value) => {
                 println!("Pushing constant: {}", value);
-                self.stack.push(*value);
+                self.ctx.stack.push(*value);
             }
             
             Instruction::PushIntLocal(name) => {
-                let value = self.variables.get(name).copied().unwrap_or(0);
+                let value = self.ctx.variables.get(name).copied().unwrap_or(0);
                 println!("Pushing local {}: {}", name, value);
-                self.stack.push(value);
+                self.ctx.stack.push(value);
             }
             
             Instruction::PopIntLocal(name) => {
-                let value = self.stack.pop().unwrap_or(0);
+                let value = self.ctx.stack.pop().unwrap_or(0);
                 println!("Popping into local {}: {}", name, value);
-                self.variables.insert(name.clone(), value);
+                self.ctx.variables.insert(name.clone(), value);
+            }
+
+            Instruction::PushConstantString(value) => {
+                self.ctx.string_stack.push(value.clone());
+            }
+
+            Instruction::PushStringConstant(index) => {
+                let value = self.pooled_string(*index)?;
+                self.ctx.string_stack.push(value);
+            }
+
+            Instruction::PushStringLocal(name) => {
+                let value = self.ctx.string_variables.get(name).cloned().unwrap_or_default();
+                self.ctx.string_stack.push(value);
+            }
+
+            Instruction::PopStringLocal(name) => {
+                let value = self.ctx.string_stack.pop().unwrap_or_default();
+                self.ctx.string_variables.insert(name.clone(), value);
+            }
+
+            Instruction::JoinString(count) => self.join_strings(*count)?,
+            Instruction::Command(name, signature) => self.call_command(name, signature)?,
+            Instruction::PackCoord
+            | Instruction::CoordX
+            | Instruction::CoordZ
+            | Instruction::CoordLevel
+            | Instruction::CoordDistance
+            | Instruction::PackCoord2
+            | Instruction::Coord2X
+            | Instruction::Coord2Y
+            | Instruction::PackCoord3
+            | Instruction::Coord3X
+            | Instruction::Coord3Y
+            | Instruction::Coord3Z => self.coord_command(instruction)?,
+            Instruction::IntToString | Instruction::StringToInt | Instruction::CompareStrings => {
+                self.convert_command(instruction)?
+            }
+            Instruction::StringLength
+            | Instruction::Substring
+            | Instruction::StringIndexOf
+            | Instruction::LowerCase
+            | Instruction::UpperCase => self.string_command(instruction)?,
+            Instruction::Random => self.random()?,
+            Instruction::EnumGetValue(name) => self.enum_get_value(name)?,
+
+            Instruction::PopIntDiscard => {
+                self.ctx.stack.pop();
+            }
+
+            Instruction::PopStringDiscard => {
+                self.ctx.string_stack.pop();
             }
             
             Instruction::Add => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 match a.checked_add(b) {
-                    Some(result) => self.stack.push(result),
+                    Some(result) => self.ctx.stack.push(result),
                     None => return Err("Integer overflow".to_string()),
                 }
             }
             
             Instruction::Subtract => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 match a.checked_sub(b) {
-                    Some(result) => self.stack.push(result),
+                    Some(result) => self.ctx.stack.push(result),
                     None => return Err("Integer overflow".to_string()),
                 }
             }
             
             Instruction::Multiply => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
-                println!("Multiplying {} * {} = {}", a, b, a * b);
-                self.stack.push(a * b);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
+                match a.checked_mul(b) {
+                    Some(result) => self.ctx.stack.push(result),
+                    None => return Err("Integer overflow".to_string()),
+                }
+            }
+            
+            Instruction::Divide => {
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
+                if b == 0 {
+                    return Err("Division by zero".to_string());
+                }
+                match a.checked_div(b) {
+                    Some(result) => self.ctx.stack.push(result),
+                    None => return Err("Integer overflow".to_string()),
+                }
+            }
+
+            Instruction::Modulo => {
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
+                if b == 0 {
+                    return Err("Division by zero".to_string());
+                }
+                match a.checked_rem(b) {
+                    Some(result) => self.ctx.stack.push(result),
+                    None => return Err("Integer overflow".to_string()),
+                }
             }
             
+            Instruction::Min | Instruction::Max => self.min_max(instruction),
+            Instruction::Rgb | Instruction::ColorComponent => self.colour_command(instruction)?,
+            Instruction::OcParam(_) | Instruction::NcParam(_) | Instruction::StructParam(_) => self.param_command(instruction)?,
+            Instruction::SetTimer(_) | Instruction::ClearTimer => self.timer_command(instruction)?,
+
             Instruction::Abs => {
-                let value = self.stack.pop().unwrap_or(0);
-                let result = if value < 0 { -value } else { value };
+                let value = self.ctx.stack.pop().unwrap_or(0);
+                let result = value.checked_abs().ok_or("Integer overflow")?;
                 println!("Abs {} = {}", value, result);
-                self.stack.push(result);
+                self.ctx.stack.push(result);
+            }
+
+            Instruction::PushVarp(id) => {
+                let value = self.get_varp(*id);
+                println!("Pushing varp {}: {}", id, value);
+                self.ctx.stack.push(value);
+            }
+            
+            Instruction::PopVarp(id) => {
+                let value = self.ctx.stack.pop().unwrap_or(0);
+                println!("Popping into varp {}: {}", id, value);
+                self.set_varp(*id, value);
+            }
+
+            Instruction::PushVarbit(id, start_bit, end_bit) => {
+                let value = varbits::read(self.get_varp(*id), *start_bit, *end_bit);
+                println!("Pushing varp {} bits {}..={}: {}", id, start_bit, end_bit, value);
+                self.ctx.stack.push(value);
+            }
+
+            Instruction::PopVarbit(id, start_bit, end_bit) => self.pop_varbit(*id, *start_bit, *end_bit)?,
+            
+            Instruction::PushVarn(id) => {
+                let value = self.globals.varns.get(id).copied().unwrap_or(0);
+                println!("Pushing varn {}: {}", id, value);
+                self.ctx.stack.push(value);
+            }
+            
+            Instruction::PopVarn(id) => {
+                let value = self.ctx.stack.pop().unwrap_or(0);
+                println!("Popping into varn {}: {}", id, value);
+                self.globals.varns.insert(*id, value);
             }
             
             Instruction::BranchGreaterThan(pos) => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 println!("Comparing {} > {}", a, b);
                 if a > b {
                     println!("Branch taken to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
                 } else {
                     println!("Branch not taken");
                 }
             }
             
             Instruction::BranchGreaterThanOrEquals(pos) => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 println!("Comparing {} >= {}", a, b);
                 if a >= b {
                     println!("Branch taken to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
                 } else {
                     println!("Branch not taken");
                 }
             }
             
             Instruction::BranchLessThan(pos) => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 println!("Comparing {} < {}", a, b);
                 if a < b {
                     println!("Branch taken to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
                 } else {
                     println!("Branch not taken");
                 }
             }
             
             Instruction::BranchLessThanOrEquals(pos) => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 println!("Comparing {} <= {}", a, b);
                 if a <= b {
                     println!("Branch taken to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
                 } else {
                     println!("Branch not taken");
                 }
             }
             
             Instruction::BranchEquals(pos) => {
-                let b = self.stack.pop().unwrap_or(0);
-                let a = self.stack.pop().unwrap_or(0);
+                let b = self.ctx.stack.pop().unwrap_or(0);
+                let a = self.ctx.stack.pop().unwrap_or(0);
                 println!("Comparing {} = {}", a, b);
                 if a == b {
                     println!("Branch taken to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
                 } else {
                     println!("Branch not taken");
                 }
             }
             
             Instruction::BranchNot(pos) => {
-                let value = self.stack.pop().unwrap_or(0);
+                let value = self.ctx.stack.pop().unwrap_or(0);
                 println!("Testing condition: {}", value);
                 if value == 0 {
                     println!("Branch taken to {}", pos);
-                    self.ip = *pos;
+                    self.ctx.ip = *pos;
                 } else {
                     println!("Branch not taken");
                 }
@@ -475,7 +1765,14 @@ impl VM {
             
             Instruction::Jump(pos) => {
                 println!("Jumping to {}", pos);
-                self.ip = *pos;
+                self.ctx.ip = *pos;
+            }
+
+            Instruction::Switch { low, targets, default } => {
+                let value = self.ctx.stack.pop().unwrap_or(0);
+                let target = switch_target(value, *low, targets, *default);
+                println!("Switch on {} to {}", value, target);
+                self.ctx.ip = target;
             }
             
             _ => {
@@ -487,18 +1784,18 @@ impl VM {
     }
 
     fn call_script(&mut self, script_name: &str) -> Result<(), String> {
-        if !self.scripts.contains_key(script_name) {
+        if self.script(script_name).is_none() {
             return Err(format!("Script not found: {}", script_name));
         }
         
         // Save current instruction pointer and script
-        if self.current_script.is_some() {
-            self.call_stack.push((self.ip, self.current_script.clone()));
+        if self.ctx.current_script.is_some() {
+            self.call_stack.push((self.ctx.ip, self.ctx.current_script.clone()));
         }
         
         // Reset instruction pointer for new script
-        self.ip = 0;
-        self.current_script = Some(script_name.to_string());
+        self.ctx.ip = 0;
+        self.ctx.current_script = Some(script_name.to_string());
         
         Ok(())
     }
@@ -506,7 +1803,7 @@ impl VM {
     fn execute_bytecode(&mut self) -> Result<i32, String> {
         self.instruction_count = 0;
         
-        while let Some(ref script_name) = self.current_script.clone() {
+        while let Some(ref script_name) = self.ctx.current_script.clone() {
             self.instruction_count += 1;
             if self.instruction_count > self.max_instructions {
                 return Err(format!("Execution exceeded maximum instruction count ({}).", self.max_instructions));
@@ -515,9 +1812,9 @@ impl VM {
             let result = self.run_script(script_name, &[]);
             
             if let Some((return_ip, return_script)) = self.call_stack.pop() {
-                self.ip = return_ip;
-                self.current_script = return_script;
-                if self.call_stack.is_empty() && self.current_script.is_none() {
+                self.ctx.ip = return_ip;
+                self.ctx.current_script = return_script;
+                if self.call_stack.is_empty() && self.ctx.current_script.is_none() {
                     // Main script finished
                     return result;
                 }
@@ -527,6 +1824,245 @@ impl VM {
             return result;
         }
         
-        Ok(self.stack.pop().unwrap_or(0))
+        Ok(self.ctx.stack.pop().unwrap_or(0))
+    }
+}
+
+/// Where a `Switch` on `value` jumps.
+fn switch_target(value: i32, low: i32, targets: &[usize], default: usize) -> usize {
+    value
+        .checked_sub(low)
+        .and_then(|index| usize::try_from(index).ok())
+        .and_then(|index| targets.get(index))
+        .copied()
+        .unwrap_or(default)
+}
+
+/// Checks a call's arguments against the script's parameters. A call
+/// passing more than there are fails, as does one leaving out a parameter
+/// without a default, unless `lenient`, in which case those parameters get
+/// their type's default.
+fn bind_args(script: &ByteCode, args: &[i32], lenient: bool) -> Result<Vec<i32>, String> {
+    if args.len() > script.params.len() {
+        return Err(format!(
+            "Script '{}' takes at most {} argument(s) but {} were given",
+            script.script_name,
+            script.params.len(),
+            args.len()
+        ));
+    }
+    if args.len() >= script.required_args {
+        return Ok(args.to_vec());
+    }
+    if !lenient {
+        return Err(format!(
+            "Script '{}' requires at least {} argument(s) but {} were given",
+            script.script_name, script.required_args, args.len()
+        ));
+    }
+    let missing = &script.params[args.len()..script.required_args];
+    Ok(args.iter().copied().chain(missing.iter().map(Type::default_value)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    const SUM_TO: &str = "[proc,sum_to](int $n)(int)\ndef_int $total = 0;\ndef_int $i = 0;\nwhile ($i < $n) {\n    $i = calc($i + 1);\n    $total = calc($total + $i);\n}\nreturn($total);\n";
+
+    /// A VM with `options` and every trigger in `source` registered.
+    fn vm_with(options: &VmOptions, source: &str) -> VM {
+        let mut vm = VM::with_options(options);
+        for bytecode in test_support::compile(source) {
+            vm.register_script(bytecode);
+        }
+        vm
+    }
+
+    #[test]
+    fn restore_rolls_back_globals_changed_after_snapshot() {
+        let mut vm = VM::new();
+        vm.set_varp(3, 10);
+        vm.globals_mut().arrays.insert("totals".to_string(), vec![1, 2]);
+        let snapshot = vm.snapshot();
+
+        vm.set_varp(3, 99);
+        vm.globals_mut().arrays.get_mut("totals").unwrap().push(3);
+        assert_eq!(vm.get_varp(3), 99);
+
+        vm.restore(snapshot);
+        assert_eq!(vm.get_varp(3), 10);
+        assert_eq!(vm.globals().arrays["totals"], vec![1, 2]);
+    }
+
+    #[test]
+    fn script_override_raises_the_instruction_budget() {
+        let options = VmOptions { max_instructions: 1_000, ..VmOptions::default() };
+        let mut vm = vm_with(&options, SUM_TO);
+        let error = vm.run_script("sum_to", &[500]).unwrap_err();
+        assert!(error.contains("exceeded maximum instruction count (1000)"), "{}", error);
+
+        let overrides = ScriptOverrides { max_instructions: Some(100_000), ..ScriptOverrides::default() };
+        vm.set_script_overrides(&HashMap::from([("sum_to".to_string(), overrides)]));
+        assert_eq!(vm.run_script("sum_to", &[500]), Ok(125_250));
+    }
+
+    /// Each pause's line and `$i`.
+    type Pauses = Rc<RefCell<Vec<(usize, Option<i32>)>>>;
+
+    /// Records each pause, then carries on.
+    struct Recorder(Pauses);
+
+    impl Debugger for Recorder {
+        fn pause(&mut self, at: &Pause) -> DebugAction {
+            self.0.borrow_mut().push((at.line, at.locals.get("i").copied()));
+            DebugAction::Continue
+        }
+    }
+
+    #[test]
+    fn breakpoint_in_a_loop_pauses_on_each_iteration() {
+        let mut vm = test_support::vm(SUM_TO);
+        let pauses = Rc::new(RefCell::new(Vec::new()));
+        vm.set_debugger(Recorder(Rc::clone(&pauses)));
+        // Line 5 is `$i = calc($i + 1);`, the first line of the loop body.
+        assert_eq!(vm.add_breakpoint(5), Some(5));
+        assert_eq!(vm.add_breakpoint(100), None);
+
+        assert_eq!(vm.run_script("sum_to", &[3]), Ok(6));
+        assert_eq!(*pauses.borrow(), vec![(5, Some(0)), (5, Some(1)), (5, Some(2))]);
+    }
+
+    #[test]
+    fn trailing_parameter_takes_its_default_when_left_out() {
+        let source = "[proc,scale](int $a, int $b = 10)(int)\nreturn(calc($a * $b));\n";
+        assert_eq!(test_support::run(source, "scale", &[3, 2]), Ok(6));
+        assert_eq!(test_support::run(source, "scale", &[3]), Ok(30));
+        assert_eq!(test_support::evaluator(source).eval_script("scale", &[3]), Ok(30));
+
+        assert_eq!(
+            test_support::run(source, "scale", &[]),
+            Err("Script 'scale' requires at least 1 argument(s) but 0 were given".to_string())
+        );
+    }
+
+    #[test]
+    fn division_truncates_and_remainder_takes_the_dividends_sign() {
+        let source = "[proc,divide](int $a, int $b)(int)\nreturn(calc($a / $b));\n\
+            [proc,modulo](int $a, int $b)(int)\nreturn(calc($a % $b));\n\
+            [proc,constant]()(int)\nreturn(calc(calc(calc(0 - 7) / 2) * 10 + calc(calc(0 - 7) % 2)));\n";
+        let mut evaluator = test_support::evaluator(source);
+        for (a, b, quotient, remainder) in [(7, 2, 3, 1), (-7, 2, -3, -1), (7, -2, -3, 1), (-7, -2, 3, -1)] {
+            assert_eq!(test_support::run(source, "divide", &[a, b]), Ok(quotient), "{} / {}", a, b);
+            assert_eq!(test_support::run(source, "modulo", &[a, b]), Ok(remainder), "{} % {}", a, b);
+            assert_eq!(evaluator.eval_script("divide", &[a, b]), Ok(quotient), "{} / {}", a, b);
+            assert_eq!(evaluator.eval_script("modulo", &[a, b]), Ok(remainder), "{} % {}", a, b);
+        }
+        assert_eq!(test_support::run(source, "constant", &[]), Ok(-31));
+    }
+
+    #[test]
+    fn three_string_parts_join_in_one_instruction() {
+        let source = "[proc,greet](string $name)(int)\nmes(\"Hello, \" + $name + \"!\");\nreturn(0);";
+        let bytecode = test_support::compile(source);
+        let joins: Vec<&Instruction> = bytecode[0].instructions.iter().filter(|i| matches!(i, Instruction::JoinString(_))).collect();
+        assert_eq!(joins, vec![&Instruction::JoinString(3)]);
+
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&messages);
+        let mut vm = test_support::vm(source);
+        vm.register_command("mes", move |_, args| {
+            recorded.borrow_mut().extend(args.iter().cloned());
+            Ok(0)
+        });
+        vm.run_script_with_args("greet", &[HostValue::Str("Bob".to_string())]).unwrap();
+        assert_eq!(*messages.borrow(), vec![HostValue::Str("Hello, Bob!".to_string())]);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn enter_and_exit_hooks_see_a_call_to_a_helper() {
+        let source = "[proc,main](int $n)(int)\nreturn(calc(~helper($n) + 1));\n[proc,helper](int $n)(int)\nreturn(calc($n * 2));\n";
+        let mut vm = test_support::vm(source);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let entered = Rc::clone(&events);
+        vm.set_on_enter(move |name, args| entered.borrow_mut().push(format!("enter {} {:?}", name, args)));
+        let exited = Rc::clone(&events);
+        vm.set_on_exit(move |name, result| exited.borrow_mut().push(format!("exit {} {}", name, result)));
+
+        assert_eq!(vm.run_script("main", &[5]), Ok(11));
+        assert_eq!(*events.borrow(), ["enter main [5]", "enter helper [5]", "exit helper 10", "exit main 11"]);
+    }
+
+    #[test]
+    fn a_command_can_run_a_script_without_clobbering_its_callers_frame() {
+        let source = "[proc,outer](int $n)(int)\n\
+            def_int $before = calc($n * 100);\n\
+            def_string $label = \"kept\";\n\
+            def_int $inner = fire(calc($n + 1));\n\
+            mes($label);\n\
+            return(calc($before + $inner * 10 + $n));\n\
+            [proc,inner](int $n)(int)\ndef_int $before = 7;\ndef_string $label = \"inner\";\nreturn(calc($n + $before - 7));\n\
+            [proc,again]()(int)\nreturn(fire(1));\n";
+        let mut vm = test_support::vm(source);
+        vm.register_command("fire", |vm, args| match args {
+            [HostValue::Int(n)] if *n > 1 => vm.run_script("inner", &[*n]),
+            _ => vm.run_script("again", &[]),
+        });
+        let messages = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&messages);
+        vm.register_command("mes", move |_, args| {
+            recorded.borrow_mut().extend(args.iter().cloned());
+            Ok(0)
+        });
+
+        // $before is 300 and $n 3 after inner(4) ran with its own locals.
+        assert_eq!(vm.run_script("outer", &[3]), Ok(343));
+        assert_eq!(*messages.borrow(), [HostValue::Str("kept".to_string())]);
+
+        // fire(1) runs again, which calls fire while it is still running.
+        let error = vm.run_script("outer", &[0]).unwrap_err();
+        assert!(error.contains("already running"), "{}", error);
+        assert_eq!(vm.run_script("outer", &[3]), Ok(343));
+    }
+
+    #[test]
+    fn a_divide_by_zero_keeps_the_failing_frames_locals() {
+        let source = "[proc,outer](int $n)(int)\nreturn(~scale($n, 0));\n\
+            [proc,scale](int $a, int $b)(int)\ndef_string $label = \"scaled\";\ndef_int $scaled = calc($a * 10);\nreturn(calc($scaled / $b));\n";
+        let mut vm = test_support::vm(source);
+        let error = vm.run_script_with_state("outer", &[HostValue::Int(7)]).unwrap_err();
+        assert!(error.message.contains("zero"), "{}", error.message);
+
+        let frame = error.frame.expect("the failing frame should be kept");
+        assert_eq!(frame.script, "scale");
+        assert_eq!(frame.line, Some(6));
+        assert_eq!((frame.locals.get("a"), frame.locals.get("b"), frame.locals.get("scaled")), (Some(&7), Some(&0), Some(&70)));
+        assert_eq!(frame.string_locals.get("label").map(String::as_str), Some("scaled"));
+        let dump = frame.to_string();
+        assert!(dump.starts_with("In 'scale' at line 6"), "{}", dump);
+        assert!(dump.contains("  $scaled = 70\n") && dump.contains("  $label = \"scaled\"\n"), "{}", dump);
+    }
+
+    #[test]
+    fn too_few_args_fail_unless_lenient_and_too_many_always_fail() {
+        let source = "[proc,add](int $a, int $b)(int)\nreturn(calc($a + $b));\n\
+            [proc,call_add]()(int)\nreturn(~add(5));\n\
+            [proc,greet](int $n, string $name)(int)\nreturn(string_length($name));\n";
+        let strict = VmOptions::default();
+        let lenient = VmOptions { lenient_args: true, ..VmOptions::default() };
+
+        for (options, expected) in [(&strict, None), (&lenient, Some(5))] {
+            let mut vm = vm_with(options, source);
+            let mut evaluator = test_support::evaluator_with(options, source);
+            assert_eq!(vm.run_script("add", &[5]).ok(), expected);
+            assert_eq!(evaluator.eval_script("add", &[5]).ok(), expected);
+            assert_eq!(vm.run_script("call_add", &[]).ok(), expected);
+            assert_eq!(evaluator.eval_script("call_add", &[]).ok(), expected);
+            assert!(vm.run_script("add", &[1, 2, 3]).is_err());
+            assert!(evaluator.eval_script("add", &[1, 2, 3]).is_err());
+        }
+        // A missing string parameter defaults to "".
+        assert_eq!(vm_with(&lenient, source).run_script("greet", &[1]), Ok(0));
+    }
+}
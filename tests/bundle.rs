@@ -0,0 +1,69 @@
+mod common;
+
+use common::{result, stdout, Project};
+
+const FIB: &str = include_str!("../data/scripts/fib.rs2");
+
+#[test]
+fn a_bundle_from_another_compiler_build_is_refused() {
+    let project = Project::new();
+    project.script("fib", FIB);
+    let output = project.rsc(&["bundle", "fib", "--out", "fib.rsx"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let output = project.rsc(&["run", "--bundle", "fib.rsx", "10"]);
+    assert_eq!(result(&output).as_deref(), Some("55"), "{}", stdout(&output));
+
+    let bundle = std::fs::read_to_string(project.path().join("fib.rsx")).unwrap();
+    let stamp = format!("\"compiler\":\"{}\",\"codegen\":", env!("CARGO_PKG_VERSION"));
+    assert!(bundle.contains(&stamp), "{}", &bundle[..200.min(bundle.len())]);
+    for (field, stale) in [("compiler", serde_json::json!("0.0.0-old")), ("codegen", serde_json::json!(0))] {
+        let mut value: serde_json::Value = serde_json::from_str(&bundle).unwrap();
+        value[field] = stale;
+        project.file("stale.rsx", &value.to_string());
+
+        let output = project.rsc(&["run", "--bundle", "stale.rsx", "10"]);
+        assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+        assert_eq!(result(&output), None, "{}", stdout(&output));
+        let stdout = stdout(&output);
+        assert!(stdout.contains("stale.rsx was compiled by rsc"), "{}", stdout);
+        assert!(stdout.contains("bundle it again with this version"), "{}", stdout);
+    }
+}
+
+#[test]
+fn a_multi_proc_bundle_runs_from_an_empty_directory_and_home() {
+    let project = Project::new();
+    project.file("rsc.toml", "scripts_dir = \"scripts\"\nconfigs = [\"configs\"]\n\n[vm]\nmax_instructions = 2000\n");
+    project.file("configs/items.obj", "[bronze_sword]\n[iron_sword]\n");
+    project.file("configs/lookups.enum", "[weapon_damage]\ninputtype=obj\noutputtype=int\nval=bronze_sword,5\nval=iron_sword,8\n");
+    project.script("day1", "[proc,day1](int $n)(int)\nreturn(calc(~hits($n) + ~bonus));\n");
+    project.script(
+        "combat/hits",
+        "[proc,hits](int $n)(int)\ndef_int $total = 0;\ndef_int $i = 0;\nwhile ($i < $n) {\n    $total = calc($total + ~damage(obj(iron_sword)));\n    $i = calc($i + 1);\n}\nreturn($total);\n\
+        [proc,damage](obj $weapon)(int)\nreturn(enum_getvalue(weapon_damage, $weapon));\n",
+    );
+    project.script("bonus", "[proc,bonus]()(int)\nreturn(~damage(obj(bronze_sword)));\n");
+    let output = project.rsc(&["run", "day1", "3"]);
+    assert_eq!(result(&output).as_deref(), Some("29"), "{}", stdout(&output));
+    let output = project.rsc(&["bundle", "day1", "--out", "day1.rsx"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    // Nothing but the bundle: no rsc.toml, scripts or configs, and a HOME
+    // with no ~/.rsc.
+    let elsewhere = tempfile::tempdir().unwrap();
+    std::fs::create_dir(elsewhere.path().join(".git")).unwrap();
+    std::fs::create_dir(elsewhere.path().join("home")).unwrap();
+    std::fs::copy(project.path().join("day1.rsx"), elsewhere.path().join("day1.rsx")).unwrap();
+    let run = |args: &[&str]| {
+        project.command().current_dir(elsewhere.path()).env("HOME", elsewhere.path().join("home")).args(args).output().unwrap()
+    };
+
+    let output = run(&["run", "--bundle", "day1.rsx", "3"]);
+    assert_eq!(result(&output).as_deref(), Some("29"), "{}", stdout(&output));
+    let output = run(&["run", "--bundle", "day1.rsx", "10"]);
+    assert_eq!(result(&output).as_deref(), Some("85"), "{}", stdout(&output));
+    // The bundled [vm] options still apply.
+    let output = run(&["run", "--bundle", "day1.rsx", "1000"]);
+    assert_eq!(result(&output), None, "{}", stdout(&output));
+    assert!(stdout(&output).contains("maximum instruction count (2000)"), "{}", stdout(&output));
+}
@@ -0,0 +1,77 @@
+//! A scratch project for driving the `rsc` binary: a temp directory with an
+//! `rsc.toml` pointing at its `scripts` directory and its own HOME, so runs
+//! never read or write the user's `~/.rsc`.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+pub struct Project {
+    dir: TempDir,
+}
+
+impl Project {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        // Stops the search for project config files at the temp directory.
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::create_dir(dir.path().join("scripts")).unwrap();
+        fs::create_dir(dir.path().join("home")).unwrap();
+        let project = Self { dir };
+        project.file("rsc.toml", "scripts_dir = \"scripts\"\n");
+        project
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn home(&self) -> PathBuf {
+        self.path().join("home")
+    }
+
+    /// Writes `contents` to `path` within the project, creating directories.
+    pub fn file(&self, path: &str, contents: &str) -> PathBuf {
+        let path = self.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Writes `scripts/<name>.rs2`.
+    pub fn script(&self, name: &str, contents: &str) -> PathBuf {
+        self.file(&format!("scripts/{}.rs2", name), contents)
+    }
+
+    /// `rsc` run in the project directory.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_runescript-compiler"));
+        command
+            .current_dir(self.path())
+            .env("HOME", self.home())
+            .env_remove("RSC_ENV")
+            .env_remove("RSC_SCRIPTS_PATH")
+            .env_remove("RUST_BACKTRACE");
+        command
+    }
+
+    pub fn rsc(&self, args: &[&str]) -> Output {
+        self.command().args(args).output().unwrap()
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// The value after `Result: ` in a run's output.
+pub fn result(output: &Output) -> Option<String> {
+    stdout(output).lines().find_map(|line| line.strip_prefix("Result: ")).map(str::to_string)
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use common::{stdout, Project};
+
+#[test]
+fn the_dispatch_manifest_lists_specific_category_and_wildcard_triggers() {
+    let project = Project::new();
+    project.file("rsc.toml", "scripts_dir = \"scripts\"\nconfigs = [\"configs\"]\n");
+    project.file("configs/animals.npc", "[man]\ncategory=human\n\n[woman]\ncategory=human\n\n[cow]\n");
+    project.script(
+        "npcs",
+        "[opnpc1,man]\nmes(\"man\");\n\
+        [opnpc1,_human]\nmes(\"human\");\n\
+        [opnpc1,_]\nmes(\"anyone\");\n\
+        [opnpc3,cow]\nmes(\"moo\");\n\
+        [proc,helper]()(int)\nreturn(1);\n",
+    );
+
+    let output = project.rsc(&["compile", "--emit", "dispatch", "-o", "triggers.json"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("wrote triggers.json"), "{}", stdout(&output));
+    let manifest = std::fs::read_to_string(project.path().join("triggers.json")).unwrap();
+    assert_eq!(
+        manifest,
+        r#"{
+  "order": [
+    "name",
+    "category",
+    "any"
+  ],
+  "scripts": [
+    "[opnpc1,_]",
+    "[opnpc1,_human]",
+    "[opnpc1,man]",
+    "[opnpc3,cow]"
+  ],
+  "triggers": {
+    "opnpc1": {
+      "names": {
+        "man": 2
+      },
+      "categories": {
+        "human": 1
+      },
+      "any": 0
+    },
+    "opnpc3": {
+      "names": {
+        "cow": 3
+      },
+      "categories": {},
+      "any": null
+    }
+  }
+}"#
+    );
+}
+
+/// The same fixture, written in `order`.
+fn fixture(order: &[usize]) -> Project {
+    let files = [
+        ("b", "[proc,foo]()(int)\nreturn(2);\n"),
+        ("a", "[proc,foo]()(int)\nreturn(1);\n"),
+        ("npcs/man", "[opnpc1,man]\nmes(\"hello\");\n"),
+        ("npcs/any", "[opnpc1,_]\nmes(\"anyone\");\n"),
+        ("main", "[proc,main](int $n)(int)\nreturn(calc(~foo + $n));\n"),
+    ];
+    let project = Project::new();
+    // Enough overrides that a map kept in hash order would come out
+    // differently from one run to the next.
+    let overrides: String = ["main", "foo", "helper", "fib", "other", "last"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| format!("\n[scripts.{}]\nmax_instructions = {}\nmemoize = false\n", name, 100_000 + i))
+        .collect();
+    project.file("rsc.toml", &format!("scripts_dir = \"scripts\"\nconfigs = [\"configs\"]\n{}", overrides));
+    project.file("configs/people.npc", "[man]\n");
+    for &index in order {
+        project.script(files[index].0, files[index].1);
+    }
+    project
+}
+
+#[test]
+fn two_compiles_of_the_same_sources_write_byte_identical_artifacts() {
+    let projects = [fixture(&[0, 1, 2, 3, 4]), fixture(&[4, 3, 2, 1, 0])];
+    let mut artifacts = Vec::new();
+    for project in &projects {
+        for args in [
+            &["compile", "--emit", "dispatch", "-o", "dispatch.json", "--allow-duplicates"][..],
+            &["compile", "--emit", "bytecode-text", "-o", "scripts.rscasm", "--allow-duplicates"],
+            &["bundle", "main", "--out", "main.rsx", "--allow-duplicates"],
+            &["bundle", "main", "--out", "again.rsx", "--allow-duplicates"],
+        ] {
+            let output = project.rsc(args);
+            assert!(output.status.success(), "{:?}: {}", args, stdout(&output));
+        }
+        let read = |name: &str| std::fs::read(project.path().join(name)).unwrap();
+        assert!(read("main.rsx") == read("again.rsx"), "two bundles of one project differ");
+        artifacts.push([read("dispatch.json"), read("scripts.rscasm"), read("main.rsx")]);
+        // The later of the two declarations wins, whatever order the files were made in.
+        assert_eq!(common::result(&project.rsc(&["run", "main", "1", "--allow-duplicates"])).as_deref(), Some("3"));
+    }
+    for (first, second) in artifacts[0].iter().zip(&artifacts[1]) {
+        assert!(first == second, "{}\n---\n{}", String::from_utf8_lossy(first), String::from_utf8_lossy(second));
+        let text = String::from_utf8_lossy(first);
+        for project in &projects {
+            assert!(!text.contains(&*project.path().to_string_lossy()), "{}", text);
+        }
+    }
+}
+
+#[test]
+fn check_reports_lints_and_fails_only_on_a_denied_one() {
+    let project = Project::new();
+    project.script("same", "[proc,same](int $n)(int)\nif ($n = $n) {\n    return(1);\n}\nreturn(0);\n");
+
+    let output = project.rsc(&["check"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("warning[L0004]"), "{}", stdout(&output));
+
+    project.file("rsc.toml", "scripts_dir = \"scripts\"\n\n[lints.self-comparison]\nlevel = \"deny\"\n");
+    let output = project.rsc(&["check"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    assert!(stdout(&output).contains("error[L0004]"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("same.rs2:2"), "{}", stdout(&output));
+}
+
+#[test]
+fn scripts_emitted_as_text_run_with_the_same_results() {
+    let project = Project::new();
+    project.script("fib", include_str!("../data/scripts/fib.rs2"));
+    let output = project.rsc(&["compile", "--emit", "bytecode-text"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+
+    for args in [&["fib", "10"][..], &["factorial", "5"], &["ackermann", "2", "3"], &["is_even", "7"], &["mccarthy91", "50"]] {
+        let from_source = common::result(&project.rsc(&[&["run"][..], args].concat()));
+        let output = project.rsc(&[&["run", "--asm", "scripts.rscasm"][..], args].concat());
+        assert!(from_source.is_some(), "{:?}", args);
+        assert_eq!(common::result(&output), from_source, "{:?}: {}", args, stdout(&output));
+    }
+
+    // The text is what runs: an edit to it shows, and the sources aren't read.
+    let text = std::fs::read_to_string(project.path().join("scripts.rscasm")).unwrap();
+    let start = text.find(".script sum_to_n\n").unwrap();
+    let end = text[start + 1..].find(".script ").map_or(text.len(), |end| start + 1 + end);
+    let edited = text[start..end].replacen("PushConstantInt(0)\n    PopIntLocal(\"result\")", "PushConstantInt(1000)\n    PopIntLocal(\"result\")", 1);
+    project.file("edited.rscasm", &format!("{}{}{}", &text[..start], edited, &text[end..]));
+    std::fs::remove_file(project.path().join("scripts/fib.rs2")).unwrap();
+    let output = project.rsc(&["run", "--asm", "edited.rscasm", "sum_to_n", "10"]);
+    assert_eq!(common::result(&output).as_deref(), Some("1055"), "{}", stdout(&output));
+
+    let output = project.rsc(&["run", "--asm", "edited.rscasm", "nowhere"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("Script 'nowhere' not found in edited.rscasm"), "{}", stdout(&output));
+}
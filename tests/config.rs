@@ -0,0 +1,157 @@
+mod common;
+
+use std::fs;
+use common::{result, stdout, Project};
+use runescript_compiler::config::Config;
+use runescript_compiler::error::ConfigError;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Held by tests that point this process's HOME at their project, so they
+/// don't see each other's. Tests that spawn rsc pass HOME to it instead.
+static HOME: Mutex<()> = Mutex::new(());
+
+fn set_home(home: &Path) {
+    std::env::set_var("HOME", home);
+    std::env::remove_var("RSC_ENV");
+}
+
+#[test]
+fn migrated_config_toml_reloads_the_old_settings() {
+    let project = Project::new();
+    fs::remove_file(project.path().join("rsc.toml")).unwrap();
+    project.script("double", "[proc,double](int $n)(int)\nreturn(calc($n * 2));\n");
+    let scripts = project.path().join("scripts");
+    let env_dir = project.home().join(".rsc").join("default");
+    let json = project.file(
+        "home/.rsc/default/config.json",
+        r#"{"install_dir": "/opt/rsc", "scripts_dir": "/nowhere", "env_name": "default", "dump_limit": 7}"#,
+    );
+    let rc = project.file(
+        "home/.rsc/default/rscrc",
+        &format!("export RSC_SCRIPTS_DIR=\"{}\"\nexport RSC_MAX_INSTRUCTIONS=5000\nalias twice='rsc run double'\n", scripts.display()),
+    );
+
+    let output = project.rsc(&["config", "migrate"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let migrated: toml::Value = toml::from_str(&fs::read_to_string(env_dir.join("config.toml")).unwrap()).unwrap();
+    assert_eq!(migrated["dump_limit"].as_integer(), Some(7));
+    assert_eq!(migrated["vm"]["max_instructions"].as_integer(), Some(5000));
+
+    // Only config.toml is left to supply the scripts directory and alias.
+    fs::remove_file(json).unwrap();
+    fs::remove_file(rc).unwrap();
+    let output = project.rsc(&["twice", "21"]);
+    assert_eq!(result(&output).as_deref(), Some("42"), "{}", stdout(&output));
+    assert!(!env_dir.join("config.json").exists());
+}
+
+#[test]
+fn every_settable_key_persists_through_save_and_reload() {
+    let _home = HOME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let project = Project::new();
+    set_home(&project.home());
+
+    let scripts = std::env::join_paths([project.path().join("a"), project.path().join("b")]).unwrap();
+    let settings = [
+        ("install_dir", "/opt/rsc"),
+        ("scripts_dir", scripts.to_str().unwrap()),
+        ("ignore", "wip/,*.bak"),
+        ("constants", "/data/constants.txt"),
+        ("configs", "/data/configs"),
+        ("strict_conditions", "true"),
+        ("dump_limit", "12"),
+        ("vm.max_instructions", "777"),
+        ("vm.memoize", "false"),
+        ("vm.max_call_depth", "64"),
+        ("vm.lenient_args", "true"),
+        ("vm.dedupe_scripts", "true"),
+        ("defines.DEBUG", "1"),
+        ("env_vars.EDITOR", "vim"),
+        ("scripts.fib.max_instructions", "5000"),
+        ("scripts.fib.memoize", "false"),
+        ("scripts.fib.opt_level", "2"),
+        ("scripts.fib.timeout", "250"),
+    ];
+
+    let defaults = Config::default();
+    let mut config = Config::default();
+    for (key, value) in settings {
+        config.set(key, value).unwrap_or_else(|e| panic!("{}: {}", key, e));
+        assert_ne!(config.get(key), defaults.get(key), "{} didn't change", key);
+    }
+    config.save().unwrap();
+
+    let reloaded = Config::load_user();
+    for (key, _) in settings {
+        assert_eq!(reloaded.get(key), config.get(key), "{}", key);
+    }
+
+    let error = config.set("vm.max_instructionz", "5").unwrap_err();
+    assert!(matches!(error, ConfigError::UnknownKey(key) if key == "vm.max_instructionz"));
+    assert!(config.set("dump_limit", "lots").is_err());
+}
+
+#[test]
+fn environments_inherit_through_two_levels_and_cycles_are_errors() {
+    let _home = HOME.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let project = Project::new();
+    set_home(&project.home());
+    project.file(
+        "home/.rsc/base/config.toml",
+        "install_dir = \"/opt/rsc\"\nscripts_dir = \"/srv/scripts\"\ndump_limit = 5\n\n[vm]\nmax_instructions = 1000\nmemoize = false\n",
+    );
+    project.file("home/.rsc/staging/config.toml", "inherits = \"base\"\ndump_limit = 10\n\n[vm]\nmax_instructions = 2000\n");
+    project.file("home/.rsc/dev/config.toml", "inherits = \"staging\"\nstrict_conditions = true\n");
+
+    let dev = Config::load_env("dev").unwrap();
+    assert_eq!(dev.env_chain, ["dev", "staging", "base"]);
+    assert_eq!(dev.env_name, "dev");
+    assert!(dev.strict_conditions);
+    assert_eq!(dev.dump_limit, 10);
+    // [vm] is merged key by key: one key from staging, one from base.
+    assert_eq!(dev.vm.max_instructions, 2000);
+    assert!(!dev.vm.memoize);
+    assert_eq!(dev.scripts_dirs, [Path::new("/srv/scripts")]);
+
+    project.file("home/.rsc/base/config.toml", "inherits = \"dev\"\n");
+    let error = Config::load_env("dev").unwrap_err();
+    assert!(matches!(&error, ConfigError::InheritanceCycle(chain) if chain == &["dev", "staging", "base", "dev"]), "{}", error);
+}
+
+#[test]
+fn help_in_a_fresh_home_writes_no_config() {
+    let project = Project::new();
+    let config = project.home().join(".rsc").join("default").join("config.json");
+
+    let output = project.rsc(&["--help"]);
+    assert!(output.status.success());
+    assert!(!project.home().join(".rsc").exists());
+
+    let output = project.rsc(&["run", "--no-such-flag"]);
+    assert!(!output.status.success());
+    assert!(!config.exists());
+
+    project.script("one", "[proc,one]()(int)\nreturn(1);\n");
+    let output = project.rsc(&["run", "one"]);
+    assert_eq!(result(&output).as_deref(), Some("1"));
+    assert!(config.exists());
+}
+
+#[test]
+fn validate_reports_a_missing_scripts_dir_and_a_malformed_export() {
+    let project = Project::new();
+    let output = project.rsc(&["config", "validate"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("is valid"), "{}", stdout(&output));
+
+    project.file("rsc.toml", "scripts_dir = \"missing\"\n");
+    project.file("home/.rsc/default/rscrc", "export RSC_MAX_INSTRUCTIONS\nalias twice='rsc run double'\n");
+    let output = project.rsc(&["config", "validate"]);
+    let report = stdout(&output);
+    assert_eq!(output.status.code(), Some(1), "{}", report);
+    assert!(report.contains("Found 2 problem(s)"), "{}", report);
+    assert!(report.contains(&format!("scripts_dir {} does not exist", project.path().join("missing").display())), "{}", report);
+    assert!(report.contains("line 1: malformed export 'export RSC_MAX_INSTRUCTIONS'"), "{}", report);
+    assert!(report.contains("fix: write it as export NAME=value"), "{}", report);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use common::{stdout, Project};
+use runescript_compiler::config::VmOptions;
+use runescript_compiler::differential::{self, DifferentialHarness, Grid};
+use runescript_compiler::lexer::Lexer;
+use runescript_compiler::parser::Parser;
+
+const LOOP: &str = "[proc,count](int $n)(int)\ndef_int $i = 0;\nwhile ($i < $n) {\n    $i = calc($i + 1);\n}\nreturn($i);\n";
+
+/// Every script in the fixture directory, registered with one harness.
+fn fixture_harness(dir: &Path) -> DifferentialHarness {
+    let options = VmOptions { max_instructions: differential::DEFAULT_MAX_INSTRUCTIONS, ..VmOptions::default() };
+    let mut harness = DifferentialHarness::new(&options);
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "rs2"));
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixture scripts in {}", dir.display());
+
+    let mut scripts = Vec::new();
+    for path in &paths {
+        let source = fs::read_to_string(path).unwrap();
+        let tokens = Lexer::new(&source, path).tokenize().unwrap();
+        scripts.push(Parser::new(tokens, path).parse().unwrap());
+    }
+    for node in scripts.iter().flat_map(|script| &script.body) {
+        harness.add_source(node);
+    }
+    for node in scripts.iter().flat_map(|script| &script.body) {
+        harness.add_script(node, 1).unwrap();
+    }
+    harness
+}
+
+#[test]
+fn backends_agree_on_every_fixture_script() {
+    // The Evaluator recurses natively for each script call, so deep calls
+    // like ackermann's need more stack than a test thread gets by default.
+    let checks = thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(|| {
+            let mut harness = fixture_harness(&Path::new(env!("CARGO_MANIFEST_DIR")).join("data").join("scripts"));
+            let names: Vec<String> = harness.scripts().iter().map(|(name, _)| name.clone()).collect();
+            names
+                .into_iter()
+                .map(|name| {
+                    let check = harness.check_script(&name, &mut Grid::default());
+                    (name, check)
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    assert!(checks.iter().any(|(name, _)| name == "fib"));
+    for (name, check) in checks {
+        assert!(check.compared > 0, "{} was not run", name);
+        if let Some(divergence) = check.divergence {
+            panic!("{}", divergence);
+        }
+    }
+}
+
+#[test]
+fn one_backend_hitting_its_limit_is_inconclusive() {
+    let project = Project::new();
+    project.script("count", LOOP);
+
+    // The VM runs out of instructions long before the Evaluator, which
+    // counts only loop iterations, runs out of steps.
+    let output = project.rsc(&["test", "--differential", "count", "--values", "30000"]);
+    let report = stdout(&output);
+    assert!(output.status.success(), "{}", report);
+    assert!(report.contains("1 calls  ok (1 inconclusive: one engine hit its limits)"), "{}", report);
+    assert!(!report.contains("DIVERGED"), "{}", report);
+}
+
+#[test]
+fn values_may_be_negative() {
+    let project = Project::new();
+    project.script("count", LOOP);
+
+    let output = project.rsc(&["test", "--differential", "count", "--values", "-7,-1,0"]);
+    let report = stdout(&output);
+    assert!(output.status.success(), "{}", report);
+    assert!(report.contains("values [-7, -1, 0]"), "{}", report);
+    assert!(report.contains("3 calls  ok"), "{}", report);
+}
+
+#[test]
+fn coverage_reports_the_line_of_an_untaken_branch() {
+    let project = Project::new();
+    project.script("clamp", "[proc,clamp](int $a)(int)\nif ($a > 100) {\n    $a = 100;\n}\nreturn($a);\n");
+
+    let output = project.rsc(&["test", "--coverage", "--lcov", "out.info"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    let stdout = stdout(&output);
+    assert!(stdout.contains("clamp                     66.7%  (2/3 lines)"), "{}", stdout);
+    assert!(stdout.contains("      #####:    3:     $a = 100;"), "{}", stdout);
+
+    let lcov = fs::read_to_string(project.path().join("out.info")).unwrap();
+    assert!(lcov.contains("DA:3,0\n") && lcov.contains("LH:2\n"), "{}", lcov);
+}
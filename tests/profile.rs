@@ -0,0 +1,27 @@
+mod common;
+
+use common::{stdout, Project};
+
+#[test]
+fn profile_hot_ranks_the_inner_loop_first() {
+    let project = Project::new();
+    project.script(
+        "hot",
+        "[proc,hot](int $n)(int)\ndef_int $total = 0;\ndef_int $i = 0;\nwhile ($i < $n) {\n    $i = calc($i + 1);\n    $total = calc($total + $i);\n}\nreturn($total);\n",
+    );
+
+    let output = project.rsc(&["profile-hot", "hot", "200"]);
+    let stdout = stdout(&output);
+    assert!(output.status.success(), "{}", stdout);
+    let report: Vec<&str> = stdout.lines().skip_while(|line| !line.starts_with("Hottest lines")).skip(1).collect();
+
+    // The loop's three lines come first and account for nearly every instruction.
+    let loop_share: f64 = report[..3]
+        .iter()
+        .map(|line| {
+            assert!(["hot.rs2:4 ", "hot.rs2:5 ", "hot.rs2:6 "].iter().any(|at| line.contains(at)), "{}", stdout);
+            line.split_whitespace().nth(1).unwrap().trim_end_matches('%').parse::<f64>().unwrap()
+        })
+        .sum();
+    assert!(loop_share > 99.0, "{}", stdout);
+}
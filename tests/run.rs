@@ -0,0 +1,179 @@
+mod common;
+
+use common::{result, stderr, stdout, Project};
+
+const FIB: &str = include_str!("../data/scripts/fib.rs2");
+
+#[test]
+fn internal_panic_is_reported_with_a_nonzero_exit() {
+    let project = Project::new();
+    // An int literal too large for i32 still panics in the parser.
+    project.script("huge", "[proc,huge]()(int)\nreturn(99999999999);\n");
+
+    let output = project.rsc(&["run", "huge"]);
+    assert_eq!(output.status.code(), Some(101));
+    let stderr = stderr(&output);
+    assert!(stderr.contains("Internal compiler error while processing script 'huge'"), "{}", stderr);
+    assert!(stderr.contains("This is a bug in the RuneScript Compiler, not in your script."), "{}", stderr);
+    assert!(!stderr.contains("stack backtrace"), "{}", stderr);
+    assert_eq!(result(&output), None);
+}
+
+#[test]
+fn vm_and_interpreter_agree_on_fib_and_factorial() {
+    let project = Project::new();
+    project.script("fib", FIB);
+
+    for (script, arg, expected) in [("fib", "15", "610"), ("factorial", "6", "720")] {
+        let vm = project.rsc(&["run", script, arg]);
+        let interp = project.rsc(&["run", script, arg, "--backend", "interp"]);
+        assert_eq!(result(&vm).as_deref(), Some(expected), "{}", stdout(&vm));
+        assert_eq!(result(&interp).as_deref(), Some(expected), "{}", stdout(&interp));
+    }
+}
+
+#[test]
+fn a_large_scripts_dump_is_cut_short_with_a_notice() {
+    let project = Project::new();
+    let body = "$x = calc($x + 1);\n".repeat(100);
+    project.script("long", &format!("[proc,long]()(int)\ndef_int $x = 0;\n{}return($x);\n", body));
+
+    let output = project.rsc(&["run", "long"]);
+    assert_eq!(result(&output).as_deref(), Some("100"), "{}", stdout(&output));
+    let stdout = stdout(&output);
+    let total = project.rsc(&["disasm", "long"]);
+    let listing = common::stdout(&total);
+    let instructions = listing.lines().filter(|line| line.get(4..6) == Some(": ")).count();
+    assert!(instructions > 200, "{}", listing);
+    assert!(!listing.contains("more;"), "{}", listing);
+    assert!(stdout.contains("0199: "), "{}", stdout);
+    assert!(!stdout.contains("0200: "), "{}", stdout);
+    let notice = format!("... ({} more; `rsc disasm long` prints them all)", instructions - 200);
+    assert!(stdout.contains(&notice), "{}", stdout);
+
+    project.file("rsc.toml", "scripts_dir = \"scripts\"\ndump_limit = 5\n");
+    let stdout = common::stdout(&project.rsc(&["run", "long"]));
+    assert!(stdout.contains("0004: ") && !stdout.contains("0005: "), "{}", stdout);
+    assert!(stdout.contains(&format!("... ({} more;", instructions - 5)), "{}", stdout);
+}
+
+#[test]
+fn typed_args_run_a_script_with_int_and_string_parameters() {
+    let project = Project::new();
+    project.script("greet", "[proc,greet](int $n, string $name, string $suffix = \"!\")(int)\nmes(\"Hello <$name><$suffix>\");\nreturn(calc($n * 2));\n");
+
+    for backend in ["vm", "interp"] {
+        let output = project.rsc(&["run", "greet", "--arg", "int:5", "--arg", "string:Bob", "--backend", backend]);
+        assert_eq!(result(&output).as_deref(), Some("10"), "{}", stdout(&output));
+        assert!(stdout(&output).contains("Hello Bob!"), "{}", stdout(&output));
+
+        let output = project.rsc(&["run", "greet", "--arg", "int:5", "--arg", "string:Bob", "--arg", "string:?", "--backend", backend]);
+        assert!(stdout(&output).contains("Hello Bob?"), "{}", stdout(&output));
+    }
+
+    let output = project.rsc(&["run", "greet", "--arg", "string:5", "--arg", "string:Bob"]);
+    assert!(stdout(&output).contains("Error: $n is int, not a string; pass it as int:N"), "{}", stdout(&output));
+    assert_eq!(result(&output), None);
+}
+
+#[test]
+fn a_boolean_script_prints_true_or_false_unless_bool_as_int() {
+    let project = Project::new();
+    project.script("is_big", "[proc,is_big](int $n)(boolean)\nreturn(calc($n > 10));\n");
+
+    for backend in ["vm", "interp"] {
+        let output = project.rsc(&["run", "is_big", "11", "--backend", backend]);
+        assert_eq!(result(&output).as_deref(), Some("true"), "{}", stdout(&output));
+        let output = project.rsc(&["run", "is_big", "3", "--backend", backend]);
+        assert_eq!(result(&output).as_deref(), Some("false"), "{}", stdout(&output));
+    }
+    let output = project.rsc(&["run", "is_big", "11", "--bool-as-int"]);
+    assert_eq!(result(&output).as_deref(), Some("1"), "{}", stdout(&output));
+}
+
+#[test]
+fn a_file_runs_with_helpers_that_come_only_from_its_includes() {
+    let project = Project::new();
+    project.script("day1/main", "// include: ../lib/math.rs2\n[proc,main](int $n)(int)\nreturn(calc(~square($n) + ~one));\n");
+    project.script("lib/math", "// include: util.rs2\n[proc,square](int $n)(int)\nreturn(calc($n * $n));\n");
+    project.script("lib/util", "[proc,one]()(int)\nreturn(9);\n");
+    project.script("broken", "[proc,broken]()(int)\nreturn(;\n");
+
+    for backend in ["vm", "interp"] {
+        let output = project.rsc(&["run", "main", "4", "--file", "scripts/day1/main.rs2", "--backend", backend]);
+        assert_eq!(result(&output).as_deref(), Some("25"), "{}", stdout(&output));
+    }
+    // Without --file, every script is compiled, the broken one too.
+    assert_eq!(result(&project.rsc(&["run", "main", "4"])), None);
+
+    let graph = stdout(&project.rsc(&["graph"]));
+    assert!(graph.lines().any(|line| line.contains("day1/main.rs2 -> ") && line.ends_with("lib/math.rs2")), "{}", graph);
+    assert!(graph.lines().any(|line| line.contains("lib/math.rs2 -> ") && line.ends_with("lib/util.rs2")), "{}", graph);
+}
+
+#[test]
+fn a_proc_in_two_files_is_refused_unless_duplicates_are_allowed() {
+    let project = Project::new();
+    project.script("a", "[proc,foo]()(int)\nreturn(1);\n");
+    project.script("b", "[proc,foo]()(int)\nreturn(2);\n");
+
+    let output = project.rsc(&["run", "foo"]);
+    assert_eq!(result(&output), None, "{}", stdout(&output));
+    let message = format!("{}{}", stdout(&output), stderr(&output));
+    assert!(message.contains("DuplicateScript") && message.contains("a.rs2") && message.contains("b.rs2"), "{}", message);
+
+    let output = project.rsc(&["run", "foo", "--allow-duplicates"]);
+    assert_eq!(result(&output).as_deref(), Some("2"), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Warning: "), "{}", stdout(&output));
+}
+
+#[test]
+fn dump_locals_on_error_prints_the_failing_frame() {
+    let project = Project::new();
+    project.script("scale", "[proc,scale](int $a, int $b)(int)\ndef_int $scaled = calc($a * 10);\nreturn(calc($scaled / $b));\n");
+
+    let output = project.rsc(&["run", "scale", "7", "0", "--dump-locals-on-error"]);
+    assert_eq!(result(&output), None);
+    let stdout = stdout(&output);
+    assert!(stdout.contains("In 'scale' at line 3"), "{}", stdout);
+    assert!(stdout.contains("  $scaled = 70"), "{}", stdout);
+
+    let output = project.rsc(&["run", "scale", "7", "0"]);
+    assert!(!common::stdout(&output).contains("In 'scale' at line"), "{}", common::stdout(&output));
+}
+
+#[test]
+fn compare_engines_reports_agreement_and_a_shared_failure() {
+    let project = Project::new();
+    project.script("double", "[proc,double](int $n)(int)\nreturn(calc($n * 2));\n");
+    project.script("sq", "[proc,sq](int $n)(int)\nreturn(calc($n * $n));\n[proc,big]()(int)\nreturn(~sq(46341));\n");
+
+    let output = project.rsc(&["run", "double", "4", "--compare-engines"]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert!(stdout(&output).contains("Engines agree. Result: 8"), "{}", stdout(&output));
+
+    // A called script's overflow fails on both backends alike.
+    let output = project.rsc(&["run", "big", "--compare-engines"]);
+    assert!(stdout(&output).contains("Engines agree. Both failed: Integer overflow"), "{}", stdout(&output));
+}
+
+#[test]
+fn compare_engines_reports_a_divergence_and_exits_with_1() {
+    let project = Project::new();
+    project.file("rsc.toml", "scripts_dir = \"scripts\"\nconfigs = [\"configs\"]\n");
+    project.file("configs/items.obj", "[bronze_sword]\n[iron_sword]\n");
+    project.file("configs/lookups.enum", "[weapon_damage]\ninputtype=obj\noutputtype=int\nval=bronze_sword,5\nval=iron_sword,8\n");
+    // A bare obj name passed to a script call: the interpreter resolves it
+    // to the obj, while the VM's call passes the caller's int instead.
+    project.script(
+        "damage",
+        "[proc,total](int $n)(int)\nreturn(calc($n + ~damage(iron_sword)));\n[proc,damage](obj $weapon)(int)\nreturn(enum_getvalue(weapon_damage, $weapon));\n",
+    );
+
+    let output = project.rsc(&["run", "total", "3", "--compare-engines"]);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout(&output));
+    let stdout = stdout(&output);
+    assert!(stdout.contains("Engines disagree: total[3] diverged:"), "{}", stdout);
+    assert!(stdout.contains("  vm:     Other error: enum_getvalue: key 3 is not in enum 'weapon_damage'"), "{}", stdout);
+    assert!(stdout.contains("  interp: 8"), "{}", stdout);
+}